@@ -0,0 +1,142 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use fann::{
+    cache::{no_cache, DistanceCache},
+    distances::{
+        ndarray::{NdProvider, ND_DOT_DISTANCE, ND_L2_DISTANCE},
+        normed::{NormedEmbed, NORM_COSINE_DISTANCE},
+        vec::{VEC_DOT_DISTANCE, VEC_L2_DISTANCE},
+    },
+    info::no_info,
+    kmed::FannTree,
+    Distance, Embedding, LocalDistance, NearestNeighbors, Tree,
+};
+use ndarray::{Array2, ArrayView2};
+
+const DIM: usize = 32;
+const QUERIES: usize = 16;
+
+fn random_arr(rows: usize, cols: usize, seed: u64) -> Array2<f64> {
+    let mut state = seed;
+    Array2::from_shape_simple_fn((rows, cols), || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((state >> 33) as f64) / (1u64 << 31) as f64 - 0.5
+    })
+}
+
+fn build_tree(arr: ArrayView2<f64>, max_node_size: Option<usize>) -> FannTree {
+    let provider = NdProvider::new(arr.view(), ND_DOT_DISTANCE);
+    FannTree::build(
+        &provider,
+        max_node_size,
+        None,
+        &mut DistanceCache::new(100000),
+        &mut no_info(),
+    )
+}
+
+fn bench_build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build");
+    group.sample_size(10);
+    for size in [250, 500, 1000, 2000] {
+        let arr = random_arr(size, DIM, 1);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &arr, |b, arr| {
+            b.iter(|| build_tree(arr.view(), Some(10)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("search");
+    for size in [1000, 5000] {
+        let arr = random_arr(size, DIM, 2);
+        let queries = random_arr(QUERIES, DIM, 3);
+        let tree = build_tree(arr.view(), Some(10));
+        group.bench_with_input(BenchmarkId::new("tree", size), &arr, |b, arr| {
+            b.iter(|| {
+                queries.rows().into_iter().for_each(|query| {
+                    let provider = NdProvider::new(arr.view(), ND_DOT_DISTANCE);
+                    let embed = Embedding::as_embedding(query.view());
+                    let ldist = LocalDistance::new(&provider, &embed);
+                    black_box(FannTree::get_closest(&tree, 10, &ldist, &mut no_info()));
+                })
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("brute", size), &arr, |b, arr| {
+            b.iter(|| {
+                queries.rows().into_iter().for_each(|query| {
+                    let provider = NdProvider::new(arr.view(), ND_DOT_DISTANCE);
+                    let embed = Embedding::as_embedding(query.view());
+                    black_box(provider.get_closest(&embed, 10, &mut no_info()));
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_cache(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cache");
+    group.sample_size(10);
+    let arr = random_arr(1000, DIM, 4);
+    let provider = NdProvider::new(arr.view(), ND_L2_DISTANCE);
+    group.bench_function("none", |b| {
+        b.iter(|| FannTree::build(&provider, Some(10), None, &mut no_cache(), &mut no_info()))
+    });
+    for cap in [1000, 100000] {
+        group.bench_with_input(BenchmarkId::new("lru", cap), &cap, |b, &cap| {
+            b.iter(|| {
+                FannTree::build(
+                    &provider,
+                    Some(10),
+                    None,
+                    &mut DistanceCache::new(cap),
+                    &mut no_info(),
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_distances(c: &mut Criterion) {
+    let mut group = c.benchmark_group("distance");
+    let arr = random_arr(2, 256, 5);
+    let a = Embedding::as_embedding(arr.row(0));
+    let b = Embedding::as_embedding(arr.row(1));
+    group.bench_function("nd_dot", |bench| {
+        bench.iter(|| ND_DOT_DISTANCE.distance_cmp(black_box(&a), black_box(&b)))
+    });
+    group.bench_function("nd_l2", |bench| {
+        bench.iter(|| ND_L2_DISTANCE.distance_cmp(black_box(&a), black_box(&b)))
+    });
+    let na = Embedding::as_embedding(NormedEmbed::new(arr.row(0)));
+    let nb = Embedding::as_embedding(NormedEmbed::new(arr.row(1)));
+    group.bench_function("norm_cosine", |bench| {
+        bench.iter(|| NORM_COSINE_DISTANCE.distance_cmp(black_box(&na), black_box(&nb)))
+    });
+    let va = arr.row(0).to_vec();
+    let vb = arr.row(1).to_vec();
+    let va = Embedding::as_embedding(&va);
+    let vb = Embedding::as_embedding(&vb);
+    group.bench_function("vec_dot", |bench| {
+        bench.iter(|| VEC_DOT_DISTANCE.distance_cmp(black_box(&va), black_box(&vb)))
+    });
+    group.bench_function("vec_l2", |bench| {
+        bench.iter(|| VEC_L2_DISTANCE.distance_cmp(black_box(&va), black_box(&vb)))
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_build,
+    bench_search,
+    bench_cache,
+    bench_distances
+);
+criterion_main!(benches);
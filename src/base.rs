@@ -1,32 +1,65 @@
-use std::marker::PhantomData;
+use std::{fmt, marker::PhantomData};
 
 use blake2::Blake2s256;
 use digest::Digest;
 use serde::{Deserialize, Serialize};
 
-use crate::info::Info;
+use crate::{
+    ids::{ExternalId, IdMap, IdMapError},
+    info::Info,
+};
+
+#[cfg(not(feature = "f32"))]
+pub type DistanceValue = f64;
+#[cfg(feature = "f32")]
+pub type DistanceValue = f32;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-pub struct DistanceCmp(f64);
+pub struct DistanceCmp(DistanceValue);
 
 impl DistanceCmp {
     pub fn zero() -> Self {
         DistanceCmp(0.0)
     }
 
-    pub fn of(v: f64) -> Self {
+    pub fn of(v: DistanceValue) -> Self {
+        debug_assert!(v >= 0.0, "distance {v} is negative or NaN");
+        DistanceCmp(v)
+    }
+
+    // for comparison values that can be negative like raw dot products or
+    // log-probabilities
+    pub fn of_signed(v: DistanceValue) -> Self {
+        debug_assert!(!v.is_nan(), "distance is NaN");
         DistanceCmp(v)
     }
 
-    pub fn to(&self) -> f64 {
+    // counts like hamming or edit distances are stored exactly, every integer
+    // up to 2^53 (2^24 with f32) is representable and the tree bounds only add
+    // and subtract, so comparisons between counts never round
+    pub fn of_count(count: u32) -> Self {
+        debug_assert!(
+            count as DistanceValue as u32 == count,
+            "count {count} is not exactly representable",
+        );
+        DistanceCmp(count as DistanceValue)
+    }
+
+    pub fn to(&self) -> DistanceValue {
         self.0
     }
 
+    pub fn to_count(&self) -> Option<u32> {
+        let value = self.0;
+        (value >= 0.0 && value.fract() == 0.0 && value <= u32::MAX as DistanceValue)
+            .then_some(value as u32)
+    }
+
     pub fn combine<F>(&self, other: &Self, map: F) -> Self
     where
-        F: FnOnce(f64, f64) -> f64,
+        F: FnOnce(DistanceValue, DistanceValue) -> DistanceValue,
     {
-        DistanceCmp::of(map(self.to(), other.to()))
+        DistanceCmp::of_signed(map(self.to(), other.to()))
     }
 }
 
@@ -50,10 +83,81 @@ impl Ord for DistanceCmp {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Neighbor {
+    pub index: usize,
+    pub distance: DistanceValue,
+}
+
+impl From<(usize, DistanceValue)> for Neighbor {
+    fn from((index, distance): (usize, DistanceValue)) -> Self {
+        Neighbor { index, distance }
+    }
+}
+
+impl From<Neighbor> for (usize, DistanceValue) {
+    fn from(value: Neighbor) -> Self {
+        (value.index, value.distance)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub neighbors: Vec<Neighbor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ids: Option<Vec<ExternalId>>,
+}
+
+impl SearchResult {
+    pub fn len(&self) -> usize {
+        self.neighbors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.neighbors.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Neighbor> {
+        self.neighbors.iter()
+    }
+
+    pub fn indices(&self) -> Vec<usize> {
+        self.neighbors.iter().map(|n| n.index).collect()
+    }
+
+    pub fn to_tuples(&self) -> Vec<(usize, DistanceValue)> {
+        self.neighbors.iter().map(|&n| n.into()).collect()
+    }
+
+    pub fn with_ids(mut self, ids: &IdMap) -> Result<Self, IdMapError> {
+        self.ids = Some(ids.resolve(self.neighbors.iter().map(|n| n.index))?);
+        Ok(self)
+    }
+}
+
+impl From<Vec<(usize, DistanceValue)>> for SearchResult {
+    fn from(value: Vec<(usize, DistanceValue)>) -> Self {
+        SearchResult {
+            neighbors: value.into_iter().map(Neighbor::from).collect(),
+            ids: None,
+        }
+    }
+}
+
+impl IntoIterator for SearchResult {
+    type Item = Neighbor;
+    type IntoIter = std::vec::IntoIter<Neighbor>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.neighbors.into_iter()
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Embedding<T> {
     pub embed: T,
     pub index: Option<usize>,
+    pub generation: u64,
 }
 
 impl<T> Embedding<T> {
@@ -61,18 +165,132 @@ impl<T> Embedding<T> {
         Embedding {
             embed,
             index: Some(index),
+            generation: 0,
         }
     }
 
     pub fn as_embedding(embed: T) -> Embedding<T> {
-        Embedding { embed, index: None }
+        Embedding {
+            embed,
+            index: None,
+            generation: 0,
+        }
+    }
+
+    pub fn with_generation(self, generation: u64) -> Embedding<T> {
+        Embedding { generation, ..self }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InvalidEmbeddingError {
+    pub index: usize,
+    pub position: usize,
+    pub value: f64,
+}
+
+impl InvalidEmbeddingError {
+    pub fn check<I>(index: usize, values: I) -> Result<(), InvalidEmbeddingError>
+    where
+        I: IntoIterator<Item = f64>,
+    {
+        match values
+            .into_iter()
+            .enumerate()
+            .find(|(_, value)| !value.is_finite())
+        {
+            Some((position, value)) => Err(InvalidEmbeddingError {
+                index,
+                position,
+                value,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for InvalidEmbeddingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "embedding {index} has non-finite value {value} at position {position}",
+            index = self.index,
+            value = self.value,
+            position = self.position,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DimensionMismatch {
+    pub expected: usize,
+    pub actual: usize,
+}
+
+impl DimensionMismatch {
+    pub fn check(expected: usize, actual: usize) -> Result<(), DimensionMismatch> {
+        if expected == actual {
+            Ok(())
+        } else {
+            Err(DimensionMismatch { expected, actual })
+        }
+    }
+}
+
+impl fmt::Display for DimensionMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "expected embedding of length {expected} got {actual}",
+            expected = self.expected,
+            actual = self.actual,
+        )
     }
 }
 
 pub trait Distance<T> {
     fn distance_cmp(&self, a: &Embedding<T>, b: &Embedding<T>) -> DistanceCmp;
-    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> f64;
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue;
     fn name(&self) -> &str;
+
+    fn is_signed(&self) -> bool {
+        false
+    }
+
+    // semi-metrics break the triangle inequality so trees cannot prune
+    fn is_metric(&self) -> bool {
+        true
+    }
+
+    // tree bounds add and subtract distances so they are computed on the
+    // metric the comparison values are a monotone transform of, like the
+    // root of squared l2
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    // signed distances never prune but their radii still go through here
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        DistanceCmp::of_signed(value)
+    }
+
+    // distances that only depend on the dot product and the squared norms
+    // can be computed in bulk from a single matrix-vector product
+    fn dot_batch(&self) -> DotBatch {
+        DotBatch::Unsupported
+    }
+
+    // only called if dot_batch is supported, the norms are zero for Dot
+    fn dot_distance_cmp(&self, _dot: f64, _norm_a: f64, _norm_b: f64) -> DistanceCmp {
+        unreachable!("{name} has no dot product form", name = self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DotBatch {
+    Unsupported,
+    Dot,
+    DotAndNorms,
 }
 
 pub trait EmbeddingProvider<'a, D, T>
@@ -84,13 +302,88 @@ where
     fn distance(&self) -> D;
 
     fn get(&'a self, index: usize) -> Embedding<T> {
-        Embedding::wrap(self.get_embed(index), index)
+        Embedding::wrap(self.get_embed(index), index).with_generation(self.generation())
+    }
+
+    fn generation(&self) -> u64 {
+        0
+    }
+
+    fn len(&self) -> usize {
+        self.all().len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.all().is_empty()
+    }
+
+    fn iter_embeddings(&'a self) -> impl Iterator<Item = Embedding<T>> + 'a
+    where
+        Self: Sized,
+        D: 'a,
+    {
+        self.all().map(|index| self.get(index))
+    }
+
+    fn medoid_of<C, I>(&'a self, ixs: &[usize], cache: &mut C, info: &mut I) -> usize
+    where
+        Self: Sized,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        crate::clustering::medoid(self, ixs, cache, info)
     }
 
     fn hash_embed<H>(&self, index: usize, hasher: &mut H)
     where
         H: Digest;
 
+    // hands all rows to op at once, providers that can fetch several rows
+    // more cheaply than one at a time should override this
+    fn with_embeds<R, O>(&'a self, indices: &[usize], op: O) -> R
+    where
+        O: FnOnce(&[Embedding<T>]) -> R,
+    {
+        let embeds: Vec<Embedding<T>> = indices.iter().map(|&ix| self.get(ix)).collect();
+        op(&embeds)
+    }
+
+    fn dist_batch(&'a self, query: &Embedding<T>, indices: &[usize]) -> Vec<DistanceCmp> {
+        let distance = self.distance();
+        self.with_embeds(indices, |embeds| {
+            embeds
+                .iter()
+                .map(|embed| distance.distance_cmp(query, embed))
+                .collect()
+        })
+    }
+
+    fn check_embed(&self, _index: usize) -> Result<(), InvalidEmbeddingError> {
+        Ok(())
+    }
+
+    // providers without a fixed length per embedding return None
+    fn dim(&self) -> Option<usize> {
+        None
+    }
+
+    fn ids(&self) -> Option<&IdMap> {
+        None
+    }
+
+    fn check_dim(&self, _embed: &Embedding<T>) -> Result<(), DimensionMismatch> {
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), InvalidEmbeddingError> {
+        self.all().try_for_each(|ix| self.check_embed(ix))
+    }
+
+    fn validate_dims(&'a self) -> Result<(), DimensionMismatch> {
+        self.all().try_for_each(|ix| self.check_dim(&self.get(ix)))
+    }
+
     fn compute_hash(&self) -> String {
         let mut hasher = Blake2s256::new();
         let all = self.all();
@@ -106,15 +399,25 @@ where
 pub struct Key {
     lower_index: usize,
     upper_index: usize,
+    generation: u64,
 }
 
 impl Key {
     pub fn new(index_a: usize, index_b: usize) -> Self {
+        Self::with_generation(index_a, index_b, 0)
+    }
+
+    pub fn with_generation(index_a: usize, index_b: usize, generation: u64) -> Self {
         Key {
             lower_index: index_a.min(index_b),
             upper_index: index_a.max(index_b),
+            generation,
         }
     }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
 }
 
 pub trait Cache {
@@ -141,7 +444,7 @@ pub trait Cache {
             (None, _) => compute(a, b),
             (_, None) => compute(a, b),
             (Some(index_a), Some(index_b)) => {
-                let key = Key::new(index_a, index_b);
+                let key = Key::with_generation(index_a, index_b, a.generation.max(b.generation));
                 match self.get(&key) {
                     Some(res) => {
                         info.log_cache_access(false);
@@ -149,7 +452,7 @@ pub trait Cache {
                     }
                     None => {
                         let res = compute(a, b);
-                        self.put(Key::new(index_a, index_b), res);
+                        self.put(key, res);
                         res
                     }
                 }
@@ -171,7 +474,7 @@ pub trait Cache {
     {
         info.log_dist(&a.index);
         info.log_dist(&b.index);
-        self.cached_dist(&a, &b, |a, b| distance.distance_cmp(a, b), info)
+        self.cached_dist(a, b, |a, b| distance.distance_cmp(a, b), info)
     }
 }
 
@@ -200,6 +503,11 @@ where
         }
     }
 
+    pub fn try_new(provider: &'a E, embed: &'a Embedding<T>) -> Result<Self, DimensionMismatch> {
+        provider.check_dim(embed)?;
+        Ok(Self::new(provider, embed))
+    }
+
     pub fn distance_cmp<I>(&self, index: usize, info: &mut I) -> DistanceCmp
     where
         I: Info,
@@ -209,10 +517,88 @@ where
         distance.distance_cmp(self.embed, &self.provider.get(index))
     }
 
-    pub fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> f64 {
+    pub fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
         let distance = self.provider.distance();
         distance.finalize_distance(dist_cmp)
     }
+
+    pub fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        self.provider.distance().to_metric(dist_cmp)
+    }
+
+    pub fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        self.provider.distance().metric_cmp(value)
+    }
+
+    pub fn distance_batch<I>(&self, indices: &[usize], info: &mut I) -> Vec<DistanceCmp>
+    where
+        I: Info,
+    {
+        indices.iter().for_each(|ix| info.log_dist(&Some(*ix)));
+        self.provider.dist_batch(self.embed, indices)
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.provider.distance().is_signed()
+    }
+
+    pub fn is_metric(&self) -> bool {
+        self.provider.distance().is_metric()
+    }
+
+    pub fn embed(&self) -> &Embedding<T> {
+        self.embed
+    }
+}
+
+pub trait ScoreTransform {
+    fn score(&self, distance: DistanceValue) -> DistanceValue;
+
+    fn apply(&self, results: &mut [(usize, DistanceValue)]) {
+        results
+            .iter_mut()
+            .for_each(|(_, distance)| *distance = self.score(*distance));
+    }
+}
+
+impl<F> ScoreTransform for F
+where
+    F: Fn(DistanceValue) -> DistanceValue,
+{
+    fn score(&self, distance: DistanceValue) -> DistanceValue {
+        self(distance)
+    }
+}
+
+// undoes the exp(-x) of the dot distances
+#[derive(Debug, Clone, Copy)]
+pub struct DotSimilarity;
+
+impl ScoreTransform for DotSimilarity {
+    fn score(&self, distance: DistanceValue) -> DistanceValue {
+        -distance.ln()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Temperature(pub DistanceValue);
+
+impl ScoreTransform for Temperature {
+    fn score(&self, distance: DistanceValue) -> DistanceValue {
+        distance / self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Clamp {
+    pub min: DistanceValue,
+    pub max: DistanceValue,
+}
+
+impl ScoreTransform for Clamp {
+    fn score(&self, distance: DistanceValue) -> DistanceValue {
+        distance.clamp(self.min, self.max)
+    }
 }
 
 pub trait NearestNeighbors<'a, T>
@@ -224,7 +610,64 @@ where
         other: &'a Embedding<T>,
         count: usize,
         info: &mut I,
-    ) -> Vec<(usize, f64)>
+    ) -> Vec<(usize, DistanceValue)>
     where
         I: Info;
+
+    fn get_closest_result<I>(
+        &self,
+        other: &'a Embedding<T>,
+        count: usize,
+        info: &mut I,
+    ) -> SearchResult
+    where
+        I: Info,
+    {
+        self.get_closest(other, count, info).into()
+    }
+
+    fn check_query(&self, _other: &Embedding<T>) -> Result<(), DimensionMismatch> {
+        Ok(())
+    }
+
+    fn try_get_closest<I>(
+        &self,
+        other: &'a Embedding<T>,
+        count: usize,
+        info: &mut I,
+    ) -> Result<Vec<(usize, DistanceValue)>, DimensionMismatch>
+    where
+        I: Info,
+    {
+        self.check_query(other)?;
+        Ok(self.get_closest(other, count, info))
+    }
+
+    // results keep their distance order even if the transform reverses it
+    fn get_closest_scored<I, S>(
+        &self,
+        other: &'a Embedding<T>,
+        count: usize,
+        info: &mut I,
+        transform: &S,
+    ) -> Vec<(usize, DistanceValue)>
+    where
+        I: Info,
+        S: ScoreTransform + ?Sized,
+    {
+        let mut res = self.get_closest(other, count, info);
+        transform.apply(&mut res);
+        res
+    }
+}
+
+pub trait StreamingNeighbors<'a, T>
+where
+    T: 'a,
+{
+    type Stream<'s>: Iterator<Item = (usize, DistanceValue)>
+    where
+        Self: 's;
+
+    fn get_closest_stream<'s>(&'s self, other: &'a Embedding<T>) -> Self::Stream<'s>;
 }
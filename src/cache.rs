@@ -1,26 +1,89 @@
 use std::num::NonZeroUsize;
 
 use crate::{Cache, DistanceCmp, Key};
+use log::warn;
 use lru::LruCache;
 
+// below this hit rate after a full turnover the cache only costs time
+const THRASHING_HIT_RATE: f64 = 0.05;
+
 pub struct DistanceCache {
     lru: LruCache<Key, DistanceCmp>,
+    generation: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+    warned: bool,
 }
 
 impl DistanceCache {
     pub fn new(cap: usize) -> Self {
         DistanceCache {
             lru: LruCache::new(NonZeroUsize::new(cap).unwrap()),
+            generation: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+            warned: false,
+        }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn hits_miss(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    fn check_thrashing(&mut self) {
+        let cap = self.lru.cap().get() as u64;
+        if self.warned || self.evictions < cap {
+            return;
+        }
+        let hit_rate = self.hits as f64 / (self.hits + self.misses).max(1) as f64;
+        if hit_rate < THRASHING_HIT_RATE {
+            warn!(
+                "distance cache is thrashing: hit rate {:.2}% after {} evictions with capacity {}",
+                hit_rate * 100.0,
+                self.evictions,
+                cap,
+            );
+            self.warned = true;
+        }
+    }
+
+    fn sync_generation(&mut self, key: &Key) {
+        if key.generation() > self.generation {
+            // entries of older generations can never be hit again
+            self.lru.clear();
+            self.generation = key.generation();
         }
     }
 }
 
 impl Cache for DistanceCache {
     fn get(&mut self, key: &Key) -> Option<DistanceCmp> {
-        self.lru.get(key).map(|&res| res)
+        self.sync_generation(key);
+        let res = self.lru.get(key).copied();
+        if res.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        res
     }
 
     fn put(&mut self, key: Key, value: DistanceCmp) {
+        self.sync_generation(&key);
+        if self.lru.len() == self.lru.cap().get() && !self.lru.contains(&key) {
+            self.evictions += 1;
+            self.check_thrashing();
+        }
         self.lru.put(key, value);
     }
 }
@@ -0,0 +1,311 @@
+use std::{collections::VecDeque, fmt};
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::{
+    distances::backend::{assign_nearest, DistanceBackend},
+    info::Info,
+    Cache, Distance, DistanceCmp, DistanceValue, EmbeddingProvider,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct KMedoidsParams {
+    pub max_rounds: usize,
+    pub history: usize,
+}
+
+impl Default for KMedoidsParams {
+    fn default() -> Self {
+        KMedoidsParams {
+            max_rounds: 1000,
+            history: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    pub medoid: usize,
+    pub members: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct KMedoids {
+    pub clusters: Vec<Cluster>,
+    pub rounds: usize,
+    pub converged: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KMedoidsError {
+    NoClusters,
+    InitMedoids { expected: usize, actual: usize },
+    UnknownMedoid(usize),
+}
+
+impl fmt::Display for KMedoidsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KMedoidsError::NoClusters => write!(f, "cannot split points into zero clusters"),
+            KMedoidsError::InitMedoids { expected, actual } => {
+                write!(f, "expected {expected} initial medoids but got {actual}")
+            }
+            KMedoidsError::UnknownMedoid(index) => {
+                write!(f, "initial medoid {index} is not one of the points")
+            }
+        }
+    }
+}
+
+impl std::error::Error for KMedoidsError {}
+
+pub fn medoid<'a, E, D, T, C, I>(
+    provider: &'a E,
+    all_ixs: &[usize],
+    cache: &mut C,
+    info: &mut I,
+) -> usize
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+    C: Cache,
+    I: Info,
+{
+    // partial sums only grow if no distance is negative
+    let early_exit = !provider.distance().is_signed();
+    let (res_ix, _) = all_ixs.iter().fold(
+        (None, DistanceCmp::of(DistanceValue::INFINITY)),
+        |best, &ix| {
+            let (best_ix, best_dist) = best;
+            let embed = provider.get(ix);
+            let cur_dist: DistanceCmp = all_ixs.iter().fold(DistanceCmp::zero(), |res, &oix| {
+                if oix == ix || (early_exit && res > best_dist) {
+                    res
+                } else {
+                    let oembed = provider.get(oix);
+                    res.combine(
+                        &cache.cached_distance(&embed, &oembed, provider.distance(), info),
+                        |cur, dist| cur + dist,
+                    )
+                }
+            });
+            if best_ix.is_none() || cur_dist < best_dist {
+                (Some(ix), cur_dist)
+            } else {
+                best
+            }
+        },
+    );
+    res_ix.unwrap()
+}
+
+pub fn kmedoids<'a, E, D, T, C, I>(
+    provider: &'a E,
+    k_num: usize,
+    params: KMedoidsParams,
+    cache: &mut C,
+    info: &mut I,
+) -> Result<KMedoids, KMedoidsError>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+    C: Cache,
+    I: Info,
+{
+    kmedoids_of(
+        provider,
+        provider.all().collect(),
+        None,
+        k_num,
+        params,
+        cache,
+        info,
+    )
+}
+
+pub fn kmedoids_of<'a, E, D, T, C, I>(
+    provider: &'a E,
+    all_ixs: Vec<usize>,
+    init_medoids: Option<Vec<usize>>,
+    k_num: usize,
+    params: KMedoidsParams,
+    cache: &mut C,
+    info: &mut I,
+) -> Result<KMedoids, KMedoidsError>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+    C: Cache,
+    I: Info,
+{
+    let assign = |members: &[usize], medoids: &[usize], cache: &mut C, info: &mut I| {
+        members
+            .iter()
+            .map(|&ix| {
+                let embed = provider.get(ix);
+                (0..medoids.len())
+                    .min_by(|&a, &b| {
+                        let dist_a = cache.cached_distance(
+                            &embed,
+                            &provider.get(medoids[a]),
+                            provider.distance(),
+                            info,
+                        );
+                        let dist_b = cache.cached_distance(
+                            &embed,
+                            &provider.get(medoids[b]),
+                            provider.distance(),
+                            info,
+                        );
+                        dist_a.cmp(&dist_b)
+                    })
+                    .unwrap()
+            })
+            .collect()
+    };
+    if let Some(medoids) = &init_medoids {
+        if medoids.len() != k_num.min(all_ixs.len()) {
+            return Err(KMedoidsError::InitMedoids {
+                expected: k_num.min(all_ixs.len()),
+                actual: medoids.len(),
+            });
+        }
+        if let Some(&ix) = medoids.iter().find(|ix| !all_ixs.contains(ix)) {
+            return Err(KMedoidsError::UnknownMedoid(ix));
+        }
+    }
+    if all_ixs.len() <= k_num {
+        return Ok(singletons(&all_ixs));
+    }
+    // every point needs a medoid to be assigned to
+    if k_num == 0 {
+        return Err(KMedoidsError::NoClusters);
+    }
+    let medoids = init_medoids.unwrap_or_else(|| all_ixs[..k_num].to_vec());
+    Ok(kmedoids_assigned(
+        provider, all_ixs, medoids, params, cache, info, assign,
+    ))
+}
+
+// assigning points to medoids is the bulk of the work so it can be batched
+pub fn kmedoids_with<'a, B, E, D, T, C, I>(
+    provider: &'a E,
+    all_ixs: Vec<usize>,
+    k_num: usize,
+    params: KMedoidsParams,
+    backend: &B,
+    cache: &mut C,
+    info: &mut I,
+) -> Result<KMedoids, KMedoidsError>
+where
+    B: DistanceBackend<'a, E, D, T>,
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+    C: Cache,
+    I: Info,
+{
+    if all_ixs.len() <= k_num {
+        return Ok(singletons(&all_ixs));
+    }
+    if k_num == 0 {
+        return Err(KMedoidsError::NoClusters);
+    }
+    let medoids = all_ixs[..k_num].to_vec();
+    Ok(kmedoids_assigned(
+        provider,
+        all_ixs,
+        medoids,
+        params,
+        cache,
+        info,
+        |members: &[usize], medoids: &[usize], _: &mut C, _: &mut I| {
+            assign_nearest(backend, provider, members, medoids)
+        },
+    ))
+}
+
+fn singletons(all_ixs: &[usize]) -> KMedoids {
+    KMedoids {
+        clusters: all_ixs
+            .iter()
+            .map(|&ix| Cluster {
+                medoid: ix,
+                members: Vec::from([ix]),
+            })
+            .collect(),
+        rounds: 0,
+        converged: true,
+    }
+}
+
+fn kmedoids_assigned<'a, E, D, T, C, I, F>(
+    provider: &'a E,
+    all_ixs: Vec<usize>,
+    init_medoids: Vec<usize>,
+    params: KMedoidsParams,
+    cache: &mut C,
+    info: &mut I,
+    mut assign: F,
+) -> KMedoids
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+    C: Cache,
+    I: Info,
+    F: FnMut(&[usize], &[usize], &mut C, &mut I) -> Vec<usize>,
+{
+    let buff_size = params.history.max(1);
+    let mut rounds = 0;
+    let mut buff: VecDeque<Vec<usize>> = VecDeque::with_capacity(buff_size);
+    buff.push_front(init_medoids);
+    let mut done = false;
+    loop {
+        rounds += 1;
+        let medoids: Vec<usize> = buff.front().unwrap().clone();
+        let mut res: Vec<Cluster> = medoids
+            .iter()
+            .map(|&ix| Cluster {
+                medoid: ix,
+                members: Vec::from([ix]),
+            })
+            .collect();
+        let members: Vec<usize> = all_ixs
+            .iter()
+            .copied()
+            .filter(|ix| !medoids.contains(ix))
+            .collect();
+        let assigned = assign(&members, &medoids, cache, info);
+        members
+            .into_iter()
+            .zip(assigned)
+            .for_each(|(ix, pos)| res[pos].members.push(ix));
+        if done || rounds >= params.max_rounds {
+            return KMedoids {
+                clusters: res,
+                rounds,
+                converged: done,
+            };
+        }
+        let new_ms: Vec<usize> = res
+            .iter()
+            .map(|cluster| medoid(provider, &cluster.members, cache, info))
+            .collect();
+        #[cfg(feature = "parallel")]
+        let seen = buff.par_iter().any(|old_ms| *old_ms == new_ms);
+        #[cfg(not(feature = "parallel"))]
+        let seen = buff.iter().any(|old_ms| *old_ms == new_ms);
+        if seen {
+            done = true;
+        }
+        while buff.len() >= buff_size {
+            buff.pop_back();
+        }
+        buff.push_front(new_ms);
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use arrow_array::{
+    cast::AsArray, types::Float64Type, Array, ArrayRef, ListArray, StructArray, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Fields};
+use datafusion::{
+    arrow::buffer::{NullBuffer, OffsetBuffer},
+    common::{exec_err, plan_err, Result, ScalarValue},
+    logical_expr::{
+        ColumnarValue, ScalarFunctionArgs, ScalarUDF, ScalarUDFImpl, Signature, Volatility,
+    },
+    prelude::SessionContext,
+};
+use ndarray::ArrayView1;
+
+use crate::{index::OwnedNdIndex, kmed::QueryContext, DistanceValue};
+
+#[cfg(not(feature = "f32"))]
+type DistanceArray = arrow_array::Float64Array;
+#[cfg(feature = "f32")]
+type DistanceArray = arrow_array::Float32Array;
+
+#[cfg(not(feature = "f32"))]
+const DISTANCE_TYPE: DataType = DataType::Float64;
+#[cfg(feature = "f32")]
+const DISTANCE_TYPE: DataType = DataType::Float32;
+
+pub struct AnnSearch {
+    name: String,
+    index: Arc<OwnedNdIndex>,
+    signature: Signature,
+}
+
+impl AnnSearch {
+    pub fn new(name: &str, index: Arc<OwnedNdIndex>) -> Self {
+        AnnSearch {
+            name: name.to_string(),
+            index,
+            signature: Signature::any(2, Volatility::Immutable),
+        }
+    }
+
+    fn neighbor_fields() -> Fields {
+        Fields::from(vec![
+            Field::new("index", DataType::UInt64, false),
+            Field::new("distance", DISTANCE_TYPE, false),
+        ])
+    }
+
+    fn search(&self, queries: &ArrayRef, count: usize) -> Result<ArrayRef> {
+        let dim = self.index.dim();
+        let value = |row: usize| -> Result<ArrayRef> {
+            match queries.data_type() {
+                DataType::FixedSizeList(_, _) => Ok(queries.as_fixed_size_list().value(row)),
+                DataType::List(_) => Ok(queries.as_list::<i32>().value(row)),
+                DataType::LargeList(_) => Ok(queries.as_list::<i64>().value(row)),
+                other => exec_err!("query must be a list of f64 got {other}"),
+            }
+        };
+        let mut lengths: Vec<usize> = Vec::with_capacity(queries.len());
+        let mut valid: Vec<bool> = Vec::with_capacity(queries.len());
+        let mut indices: Vec<u64> = Vec::with_capacity(queries.len() * count);
+        let mut distances: Vec<DistanceValue> = Vec::with_capacity(queries.len() * count);
+        let mut ctx = QueryContext::with_capacity(count);
+        for row in 0..queries.len() {
+            if queries.is_null(row) {
+                lengths.push(0);
+                valid.push(false);
+                continue;
+            }
+            let embed = value(row)?;
+            let Some(embed) = embed.as_primitive_opt::<Float64Type>() else {
+                return exec_err!("query values must be f64");
+            };
+            if embed.len() != dim {
+                let actual = embed.len();
+                return exec_err!("expected query of length {dim} got {actual}");
+            }
+            let res = self.index.query_with_ctx(
+                ArrayView1::from(embed.values().as_ref()),
+                count,
+                &mut ctx,
+            );
+            lengths.push(res.len());
+            valid.push(true);
+            res.iter().for_each(|&(ix, dist)| {
+                indices.push(ix as u64);
+                distances.push(dist);
+            });
+        }
+        let neighbors = StructArray::new(
+            Self::neighbor_fields(),
+            vec![
+                Arc::new(UInt64Array::from(indices)),
+                Arc::new(DistanceArray::from(distances)),
+            ],
+            None,
+        );
+        Ok(Arc::new(ListArray::new(
+            Arc::new(Field::new_struct("item", Self::neighbor_fields(), false)),
+            OffsetBuffer::from_lengths(lengths),
+            Arc::new(neighbors),
+            Some(NullBuffer::from(valid)),
+        )))
+    }
+}
+
+impl fmt::Debug for AnnSearch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnnSearch")
+            .field("name", &self.name)
+            .field("len", &self.index.len())
+            .field("dim", &self.index.dim())
+            .finish()
+    }
+}
+
+impl PartialEq for AnnSearch {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && Arc::ptr_eq(&self.index, &other.index)
+    }
+}
+
+impl Eq for AnnSearch {}
+
+impl Hash for AnnSearch {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+    }
+}
+
+impl ScalarUDFImpl for AnnSearch {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::new_list(
+            DataType::Struct(Self::neighbor_fields()),
+            false,
+        ))
+    }
+
+    fn invoke_with_args(&self, args: ScalarFunctionArgs) -> Result<ColumnarValue> {
+        let name = &self.name;
+        let count = match &args.args[1] {
+            ColumnarValue::Scalar(value) => match value.cast_to(&DataType::UInt64)? {
+                ScalarValue::UInt64(Some(count)) => count as usize,
+                _ => return plan_err!("{name} count must not be null"),
+            },
+            ColumnarValue::Array(_) => {
+                return plan_err!("{name} count must be a constant");
+            }
+        };
+        let queries = args.args[0].to_array(args.number_rows)?;
+        Ok(ColumnarValue::Array(self.search(&queries, count)?))
+    }
+}
+
+pub fn register_ann_search(ctx: &SessionContext, name: &str, index: Arc<OwnedNdIndex>) {
+    ctx.register_udf(ScalarUDF::from(AnnSearch::new(name, index)));
+}
@@ -0,0 +1,116 @@
+use crate::{
+    graph::knn_lists, info::Info, kmed::FannTree, Distance, DistanceValue, EmbeddingProvider,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct DensityEstimate {
+    pub k_distance: DistanceValue,
+    pub lrd: DistanceValue,
+    pub lof: DistanceValue,
+}
+
+fn k_distance(neighbors: &[(usize, DistanceValue)]) -> DistanceValue {
+    neighbors
+        .last()
+        .map_or(DistanceValue::INFINITY, |&(_, dist)| dist)
+}
+
+fn reachability_density(
+    neighbors: &[(usize, DistanceValue)],
+    k_distances: &[DistanceValue],
+    offset: usize,
+) -> DistanceValue {
+    if neighbors.is_empty() {
+        return 0.0;
+    }
+    let reach: DistanceValue = neighbors
+        .iter()
+        .map(|&(ix, dist)| dist.max(k_distances[ix - offset]))
+        .sum();
+    neighbors.len() as DistanceValue / reach
+}
+
+fn outlier_factor(
+    neighbors: &[(usize, DistanceValue)],
+    lrds: &[DistanceValue],
+    offset: usize,
+    lrd: DistanceValue,
+) -> DistanceValue {
+    if neighbors.is_empty() {
+        return 1.0;
+    }
+    let mean = neighbors
+        .iter()
+        .map(|&(ix, _)| lrds[ix - offset])
+        .sum::<DistanceValue>()
+        / neighbors.len() as DistanceValue;
+    // duplicates make the density infinite on both sides
+    if mean.is_infinite() && lrd.is_infinite() {
+        1.0
+    } else {
+        mean / lrd
+    }
+}
+
+fn estimates(
+    neighbors: &[Vec<(usize, DistanceValue)>],
+    k_distances: &[DistanceValue],
+    lrds: &[DistanceValue],
+    offset: usize,
+) -> Vec<DensityEstimate> {
+    neighbors
+        .iter()
+        .map(|neighbors| {
+            let lrd = reachability_density(neighbors, k_distances, offset);
+            DensityEstimate {
+                k_distance: k_distance(neighbors),
+                lrd,
+                lof: outlier_factor(neighbors, lrds, offset, lrd),
+            }
+        })
+        .collect()
+}
+
+pub fn self_density<'a, E, D, T, I>(
+    tree: &FannTree,
+    provider: &'a E,
+    k: usize,
+    info: &mut I,
+) -> Vec<DensityEstimate>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+    I: Info,
+{
+    let offset = provider.all().start;
+    let neighbors = knn_lists(tree, provider, k, info);
+    let k_distances: Vec<DistanceValue> = neighbors.iter().map(|n| k_distance(n)).collect();
+    let lrds: Vec<DistanceValue> = neighbors
+        .iter()
+        .map(|n| reachability_density(n, &k_distances, offset))
+        .collect();
+    estimates(&neighbors, &k_distances, &lrds, offset)
+}
+
+pub fn knn_density<'a, E, EQ, D, T, I>(
+    tree: &FannTree,
+    provider: &'a E,
+    queries: &'a EQ,
+    k: usize,
+    info: &mut I,
+) -> Vec<DensityEstimate>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    EQ: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+    I: Info,
+{
+    let offset = provider.all().start;
+    let indexed = self_density(tree, provider, k, info);
+    let k_distances: Vec<DistanceValue> = indexed.iter().map(|est| est.k_distance).collect();
+    let lrds: Vec<DistanceValue> = indexed.iter().map(|est| est.lrd).collect();
+    let neighbors = tree.join(provider, queries, k, info);
+    estimates(&neighbors, &k_distances, &lrds, offset)
+}
@@ -1,2 +1,63 @@
+use crate::{DistanceCmp, DistanceValue};
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod backend;
+pub mod bits;
+pub mod braycurtis;
+pub mod chain;
+pub mod composite;
+pub mod disk;
+pub mod dynamic;
+pub mod geo;
+#[cfg(feature = "half")]
+pub mod half;
+pub mod int8;
+pub mod lazy;
+pub mod matrix;
+pub mod minkowski;
+pub mod mips;
+pub mod name;
 pub mod ndarray;
+pub mod normed;
+#[cfg(feature = "parquet")]
+pub mod polars;
+pub mod registry;
+pub mod scalar;
+#[cfg(feature = "simd")]
+pub mod simd;
+pub mod sparse;
+pub mod stats;
+pub mod strings;
+pub mod transform;
 pub mod vec;
+pub mod weighted;
+
+// dimensions where both values are zero do not contribute
+pub fn canberra_term(a: f64, b: f64) -> f64 {
+    let denom = a.abs() + b.abs();
+    if denom > 0.0 {
+        (a - b).abs() / denom
+    } else {
+        0.0
+    }
+}
+
+// for comparison values that are squared metrics like l2
+pub fn sqrt_metric(dist_cmp: &DistanceCmp) -> DistanceValue {
+    dist_cmp.to().sqrt()
+}
+
+pub fn squared_cmp(value: DistanceValue) -> DistanceCmp {
+    DistanceCmp::of(value * value)
+}
+
+// 1 - cos is half the squared chord between the normalized vectors and the
+// chord is a metric
+pub fn chord_metric(dist_cmp: &DistanceCmp) -> DistanceValue {
+    (2.0 * dist_cmp.to()).sqrt()
+}
+
+pub fn chord_cmp(value: DistanceValue) -> DistanceCmp {
+    DistanceCmp::of(value * value / 2.0)
+}
@@ -0,0 +1,209 @@
+use std::{
+    fs::File,
+    io::{Read, Seek},
+    path::Path,
+};
+
+use arrow_array::{
+    cast::AsArray,
+    types::{Float32Type, Float64Type},
+    Array, ArrowNativeTypeOp, ArrowPrimitiveType, PrimitiveArray, RecordBatch,
+};
+use arrow_ipc::reader::{FileReader, StreamReader};
+use arrow_schema::ArrowError;
+use digest::Digest;
+use ndarray::ArrayView1;
+
+use crate::{
+    distances::scalar::Scalar, DimensionMismatch, Distance, Embedding, EmbeddingProvider,
+    InvalidEmbeddingError,
+};
+
+pub trait ArrowScalar: Scalar + ArrowNativeTypeOp {
+    type Arrow: ArrowPrimitiveType<Native = Self>;
+}
+
+impl ArrowScalar for f64 {
+    type Arrow = Float64Type;
+}
+
+impl ArrowScalar for f32 {
+    type Arrow = Float32Type;
+}
+
+// rows point into the buffers of the record batches, nothing is copied
+pub struct ArrowProvider<D, F = f64>
+where
+    F: ArrowScalar,
+{
+    buffers: Vec<PrimitiveArray<F::Arrow>>,
+    starts: Vec<usize>,
+    rows: usize,
+    dim: usize,
+    distance: D,
+}
+
+impl<D, F> ArrowProvider<D, F>
+where
+    F: ArrowScalar,
+{
+    fn column_values(
+        batch: &RecordBatch,
+        column: &str,
+    ) -> Result<(usize, PrimitiveArray<F::Arrow>), ArrowError> {
+        let array = batch
+            .column_by_name(column)
+            .ok_or_else(|| ArrowError::SchemaError(format!("missing column: {column}")))?;
+        let list = array.as_fixed_size_list_opt().ok_or_else(|| {
+            ArrowError::SchemaError(format!("{column} must be a fixed size list"))
+        })?;
+        if list.null_count() > 0 || list.values().null_count() > 0 {
+            return Err(ArrowError::InvalidArgumentError(format!(
+                "{column} must not contain nulls"
+            )));
+        }
+        let values = list
+            .values()
+            .as_primitive_opt::<F::Arrow>()
+            .ok_or_else(|| {
+                ArrowError::SchemaError(format!(
+                    "{column} values must be {data_type}",
+                    data_type = F::Arrow::DATA_TYPE,
+                ))
+            })?;
+        let dim = list.value_length() as usize;
+        // sliced lists share the values of the full array
+        let start = if list.is_empty() {
+            0
+        } else {
+            list.value_offset(0) as usize
+        };
+        Ok((dim, values.slice(start, list.len() * dim)))
+    }
+
+    pub fn from_batches<'b, I>(batches: I, column: &str, distance: D) -> Result<Self, ArrowError>
+    where
+        I: IntoIterator<Item = &'b RecordBatch>,
+    {
+        let mut dim = None;
+        let mut buffers = Vec::new();
+        let mut starts = Vec::new();
+        let mut rows = 0;
+        for batch in batches {
+            let (batch_dim, values) = Self::column_values(batch, column)?;
+            if *dim.get_or_insert(batch_dim) != batch_dim {
+                return Err(ArrowError::SchemaError(format!(
+                    "{column} changes length from {expected} to {batch_dim}",
+                    expected = dim.unwrap_or(0),
+                )));
+            }
+            if batch.num_rows() > 0 {
+                starts.push(rows);
+                buffers.push(values);
+                rows += batch.num_rows();
+            }
+        }
+        Ok(ArrowProvider {
+            buffers,
+            starts,
+            rows,
+            dim: dim.unwrap_or(0),
+            distance,
+        })
+    }
+
+    pub fn from_batch(batch: &RecordBatch, column: &str, distance: D) -> Result<Self, ArrowError> {
+        Self::from_batches([batch], column, distance)
+    }
+
+    pub fn from_ipc_file<P>(path: P, column: &str, distance: D) -> Result<Self, ArrowError>
+    where
+        P: AsRef<Path>,
+    {
+        Self::from_ipc_reader(File::open(path)?, column, distance)
+    }
+
+    pub fn from_ipc_reader<R>(reader: R, column: &str, distance: D) -> Result<Self, ArrowError>
+    where
+        R: Read + Seek,
+    {
+        let batches: Vec<RecordBatch> =
+            FileReader::try_new_buffered(reader, None)?.collect::<Result<_, _>>()?;
+        Self::from_batches(&batches, column, distance)
+    }
+
+    pub fn from_ipc_stream<R>(reader: R, column: &str, distance: D) -> Result<Self, ArrowError>
+    where
+        R: Read,
+    {
+        let batches: Vec<RecordBatch> =
+            StreamReader::try_new_buffered(reader, None)?.collect::<Result<_, _>>()?;
+        Self::from_batches(&batches, column, distance)
+    }
+
+    pub fn try_from_batches<'b, I>(
+        batches: I,
+        column: &str,
+        distance: D,
+    ) -> Result<Self, ArrowError>
+    where
+        I: IntoIterator<Item = &'b RecordBatch>,
+    {
+        let provider = Self::from_batches(batches, column, distance)?;
+        (0..provider.rows)
+            .try_for_each(|ix| provider.check_row(ix))
+            .map_err(|err| ArrowError::InvalidArgumentError(err.to_string()))?;
+        Ok(provider)
+    }
+
+    fn row(&self, index: usize) -> &[F] {
+        let batch = self.starts.partition_point(|&start| start <= index) - 1;
+        let offset = (index - self.starts[batch]) * self.dim;
+        &self.buffers[batch].values()[offset..offset + self.dim]
+    }
+
+    fn check_row(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        InvalidEmbeddingError::check(index, self.row(index).iter().map(|v| v.to_f64()))
+    }
+
+    pub fn batch_count(&self) -> usize {
+        self.buffers.len()
+    }
+}
+
+impl<'a, D, F> EmbeddingProvider<'a, D, ArrayView1<'a, F>> for ArrowProvider<D, F>
+where
+    D: Distance<ArrayView1<'a, F>> + Copy,
+    F: ArrowScalar,
+{
+    fn get_embed(&'a self, index: usize) -> ArrayView1<'a, F> {
+        ArrayView1::from(self.row(index))
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        0..self.rows
+    }
+
+    fn distance(&self) -> D {
+        self.distance
+    }
+
+    fn hash_embed<H>(&self, index: usize, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        self.row(index).iter().for_each(|v| v.hash_into(hasher));
+    }
+
+    fn check_embed(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        self.check_row(index)
+    }
+
+    fn dim(&self) -> Option<usize> {
+        Some(self.dim)
+    }
+
+    fn check_dim(&self, embed: &Embedding<ArrayView1<'a, F>>) -> Result<(), DimensionMismatch> {
+        DimensionMismatch::check(self.dim, embed.embed.len())
+    }
+}
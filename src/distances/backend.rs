@@ -0,0 +1,101 @@
+use crate::{Distance, DistanceCmp, DistanceValue, Embedding, EmbeddingProvider};
+
+pub trait DistanceBackend<'a, E, D, T>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+{
+    fn query_distances(
+        &self,
+        provider: &'a E,
+        query: &Embedding<T>,
+        ixs: &[usize],
+    ) -> Vec<DistanceCmp>;
+
+    // row major with one row of cols per entry in rows
+    fn cross_distances(&self, provider: &'a E, rows: &[usize], cols: &[usize]) -> Vec<DistanceCmp> {
+        rows.iter()
+            .flat_map(|&row| self.query_distances(provider, &provider.get(row), cols))
+            .collect()
+    }
+}
+
+// device backends (wgpu, cuda) live outside of the crate and implement
+// DistanceBackend themselves, they need a device to be built and tested
+// against this one which ci does not have
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuBackend;
+
+impl<'a, E, D, T> DistanceBackend<'a, E, D, T> for CpuBackend
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+{
+    fn query_distances(
+        &self,
+        provider: &'a E,
+        query: &Embedding<T>,
+        ixs: &[usize],
+    ) -> Vec<DistanceCmp> {
+        let distance = provider.distance();
+        ixs.iter()
+            .map(|&ix| distance.distance_cmp(query, &provider.get(ix)))
+            .collect()
+    }
+}
+
+pub fn rerank<'a, B, E, D, T>(
+    backend: &B,
+    provider: &'a E,
+    query: &Embedding<T>,
+    candidates: &[usize],
+    count: usize,
+) -> Vec<(usize, DistanceValue)>
+where
+    B: DistanceBackend<'a, E, D, T>,
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+{
+    let mut res: Vec<(DistanceCmp, usize)> = backend
+        .query_distances(provider, query, candidates)
+        .into_iter()
+        .zip(candidates.iter().copied())
+        .collect();
+    res.sort_unstable();
+    let distance = provider.distance();
+    res.into_iter()
+        .take(count)
+        .map(|(dist, ix)| (ix, distance.finalize_distance(&dist)))
+        .collect()
+}
+
+pub fn assign_nearest<'a, B, E, D, T>(
+    backend: &B,
+    provider: &'a E,
+    ixs: &[usize],
+    medoids: &[usize],
+) -> Vec<usize>
+where
+    B: DistanceBackend<'a, E, D, T>,
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+{
+    if medoids.is_empty() {
+        return Vec::new();
+    }
+    backend
+        .cross_distances(provider, ixs, medoids)
+        .chunks(medoids.len())
+        .map(|row| {
+            row.iter()
+                .enumerate()
+                .min_by_key(|&(_, dist)| dist)
+                .map(|(pos, _)| pos)
+                .unwrap()
+        })
+        .collect()
+}
@@ -0,0 +1,190 @@
+use digest::Digest;
+use ndarray::{ArrayView1, ArrayView2};
+
+use crate::{
+    distances::dynamic::DynDistance, info::Info, DimensionMismatch, Distance, DistanceCmp,
+    DistanceValue, Embedding, EmbeddingProvider, NearestNeighbors,
+};
+
+const WORD_BITS: usize = u64::BITS as usize;
+
+#[derive(Debug, Clone, Copy)]
+pub struct HammingDistance {}
+
+pub const HAMMING_DISTANCE: HammingDistance = HammingDistance {};
+
+impl<'a> Distance<&'a [u64]> for HammingDistance {
+    fn distance_cmp(&self, a: &Embedding<&'a [u64]>, b: &Embedding<&'a [u64]>) -> DistanceCmp {
+        let res: u32 = a
+            .embed
+            .iter()
+            .zip(b.embed.iter())
+            .map(|(&cur_a, &cur_b)| (cur_a ^ cur_b).count_ones())
+            .sum();
+        DistanceCmp::of_count(res)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "hamming"
+    }
+}
+
+pub fn bit_distance<'a>(name: &str) -> Option<DynDistance<'a, &'a [u64]>> {
+    match name {
+        "hamming" => Some(DynDistance::new(&HAMMING_DISTANCE)),
+        _ => None,
+    }
+}
+
+fn word_count(bits: usize) -> usize {
+    bits.div_ceil(WORD_BITS)
+}
+
+pub fn pack_bits<I>(bits: I) -> Vec<u64>
+where
+    I: IntoIterator<Item = bool>,
+{
+    let mut words = Vec::new();
+    bits.into_iter().enumerate().for_each(|(pos, bit)| {
+        if pos % WORD_BITS == 0 {
+            words.push(0);
+        }
+        if bit {
+            *words.last_mut().unwrap() |= 1 << (pos % WORD_BITS);
+        }
+    });
+    words
+}
+
+// positive values become set bits
+pub fn pack_signs(values: ArrayView1<f64>) -> Vec<u64> {
+    pack_bits(values.iter().map(|&v| v > 0.0))
+}
+
+pub struct BitProvider<D> {
+    words: Vec<u64>,
+    bits: usize,
+    rows: usize,
+    distance: D,
+}
+
+impl<D> BitProvider<D> {
+    pub fn from_rows(
+        rows: &[Vec<u64>],
+        bits: usize,
+        distance: D,
+    ) -> Result<Self, DimensionMismatch> {
+        let row_words = word_count(bits);
+        // bits past the end of the embedding must not count
+        let tail_mask = match bits % WORD_BITS {
+            0 => u64::MAX,
+            rest => (1 << rest) - 1,
+        };
+        let mut words = Vec::with_capacity(rows.len() * row_words);
+        for row in rows {
+            DimensionMismatch::check(row_words, row.len())?;
+            words.extend_from_slice(row);
+            if let Some(last) = words.last_mut().filter(|_| row_words > 0) {
+                *last &= tail_mask;
+            }
+        }
+        Ok(BitProvider {
+            words,
+            bits,
+            rows: rows.len(),
+            distance,
+        })
+    }
+
+    pub fn from_signs(arr: ArrayView2<f64>, distance: D) -> Self {
+        BitProvider {
+            words: arr.rows().into_iter().flat_map(pack_signs).collect(),
+            bits: arr.shape()[1],
+            rows: arr.shape()[0],
+            distance,
+        }
+    }
+
+    pub fn bits(&self) -> usize {
+        self.bits
+    }
+
+    fn row_words(&self) -> usize {
+        word_count(self.bits)
+    }
+
+    fn row(&self, index: usize) -> &[u64] {
+        let row_words = self.row_words();
+        &self.words[index * row_words..(index + 1) * row_words]
+    }
+}
+
+impl<'a, D> EmbeddingProvider<'a, D, &'a [u64]> for BitProvider<D>
+where
+    D: Distance<&'a [u64]> + Copy,
+{
+    fn get_embed(&'a self, index: usize) -> &'a [u64] {
+        self.row(index)
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        0..self.rows
+    }
+
+    fn distance(&self) -> D {
+        self.distance
+    }
+
+    fn hash_embed<H>(&self, index: usize, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        self.row(index)
+            .iter()
+            .for_each(|v| hasher.update(v.to_be_bytes()));
+    }
+
+    fn dim(&self) -> Option<usize> {
+        Some(self.row_words())
+    }
+
+    fn check_dim(&self, embed: &Embedding<&'a [u64]>) -> Result<(), DimensionMismatch> {
+        DimensionMismatch::check(self.row_words(), embed.embed.len())
+    }
+}
+
+impl<'a, D> NearestNeighbors<'a, &'a [u64]> for BitProvider<D>
+where
+    D: for<'r> Distance<&'r [u64]>,
+{
+    fn check_query(&self, other: &Embedding<&'a [u64]>) -> Result<(), DimensionMismatch> {
+        DimensionMismatch::check(self.row_words(), other.embed.len())
+    }
+
+    fn get_closest<I>(
+        &self,
+        other: &Embedding<&'a [u64]>,
+        count: usize,
+        _info: &mut I,
+    ) -> Vec<(usize, DistanceValue)>
+    where
+        I: Info,
+    {
+        let mut dists: Vec<(usize, DistanceCmp)> = (0..self.rows)
+            .map(|ix| {
+                let val = Embedding::wrap(self.row(ix), ix);
+                (ix, self.distance.distance_cmp(&val, other))
+            })
+            .collect();
+        dists.sort_unstable_by_key(|(_, a)| *a);
+        dists
+            .iter()
+            .take(count)
+            .map(|(ix, dist)| (*ix, self.distance.finalize_distance(dist)))
+            .collect()
+    }
+}
@@ -0,0 +1,82 @@
+use ndarray::ArrayView1;
+
+use crate::{distances::scalar::Scalar, Distance, DistanceCmp, DistanceValue, Embedding};
+
+#[derive(Debug, Clone, Copy)]
+pub struct BrayCurtisDistance {}
+
+pub const BRAY_CURTIS_DISTANCE: BrayCurtisDistance = BrayCurtisDistance {};
+
+impl BrayCurtisDistance {
+    // the denominator uses magnitudes so negative values cannot cancel out,
+    // for abundance data this is the usual sum of both samples
+    fn ratio<I>(&self, pairs: I) -> DistanceCmp
+    where
+        I: Iterator<Item = (f64, f64)>,
+    {
+        let (diff, total) = pairs.fold((0.0, 0.0), |(diff, total), (cur_a, cur_b)| {
+            (
+                diff + (cur_a - cur_b).abs(),
+                total + cur_a.abs() + cur_b.abs(),
+            )
+        });
+        let res: f64 = if total > 0.0 { diff / total } else { 0.0 };
+        DistanceCmp::of(res as DistanceValue)
+    }
+}
+
+impl<'a, F> Distance<ArrayView1<'a, F>> for BrayCurtisDistance
+where
+    F: Scalar,
+{
+    fn distance_cmp(
+        &self,
+        a: &Embedding<ArrayView1<'a, F>>,
+        b: &Embedding<ArrayView1<'a, F>>,
+    ) -> DistanceCmp {
+        self.ratio(
+            a.embed
+                .iter()
+                .zip(b.embed.iter())
+                .map(|(cur_a, cur_b)| (cur_a.to_f64(), cur_b.to_f64())),
+        )
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "braycurtis"
+    }
+
+    fn is_metric(&self) -> bool {
+        false
+    }
+}
+
+impl<F> Distance<&Vec<F>> for BrayCurtisDistance
+where
+    F: Scalar,
+{
+    fn distance_cmp(&self, a: &Embedding<&Vec<F>>, b: &Embedding<&Vec<F>>) -> DistanceCmp {
+        self.ratio(
+            a.embed
+                .iter()
+                .zip(b.embed.iter())
+                .map(|(cur_a, cur_b)| (cur_a.to_f64(), cur_b.to_f64())),
+        )
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "braycurtis"
+    }
+
+    fn is_metric(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,166 @@
+use std::ops::Range;
+
+use digest::Digest;
+
+use crate::{
+    DimensionMismatch, Distance, DistanceCmp, Embedding, EmbeddingProvider, InvalidEmbeddingError,
+};
+
+// the parts are laid out one after another, row i of part p has the index
+// starts[p] + i
+pub struct ChainProvider<'a, E, D> {
+    parts: Vec<&'a E>,
+    starts: Vec<usize>,
+    total: usize,
+    distance: D,
+    shared_distance: bool,
+}
+
+impl<'a, E, D> ChainProvider<'a, E, D>
+where
+    D: Copy,
+{
+    pub fn new<T>(parts: Vec<&'a E>, distance: D) -> Self
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T>,
+    {
+        let mut starts = Vec::with_capacity(parts.len());
+        let mut total = 0;
+        parts.iter().for_each(|part| {
+            starts.push(total);
+            total += part.len();
+        });
+        // batches can only be handed to the parts if they compute the same
+        // distance as the chain
+        let shared_distance = parts
+            .iter()
+            .all(|part| part.distance().name() == distance.name());
+        ChainProvider {
+            parts,
+            starts,
+            total,
+            distance,
+            shared_distance,
+        }
+    }
+
+    pub fn try_new<T>(parts: Vec<&'a E>, distance: D) -> Result<Self, DimensionMismatch>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T>,
+    {
+        let mut dims = parts.iter().filter_map(|part| part.dim());
+        if let Some(expected) = dims.next() {
+            dims.try_for_each(|dim| DimensionMismatch::check(expected, dim))?;
+        }
+        Ok(Self::new(parts, distance))
+    }
+
+    pub fn part_count(&self) -> usize {
+        self.parts.len()
+    }
+
+    pub fn part(&self, part: usize) -> &'a E {
+        self.parts[part]
+    }
+
+    pub fn part_range(&self, part: usize) -> Range<usize> {
+        let end = self.starts.get(part + 1).copied().unwrap_or(self.total);
+        self.starts[part]..end
+    }
+
+    // returns the part and the index within that part
+    pub fn locate(&self, index: usize) -> (usize, usize) {
+        let part = self.starts.partition_point(|&start| start <= index) - 1;
+        (part, index - self.starts[part])
+    }
+}
+
+impl<'a, E, D, T> EmbeddingProvider<'a, D, T> for ChainProvider<'a, E, D>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+{
+    fn get_embed(&'a self, index: usize) -> T {
+        let (part, local) = self.locate(index);
+        let inner = self.parts[part];
+        inner.get_embed(inner.all().start + local)
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        0..self.total
+    }
+
+    fn distance(&self) -> D {
+        self.distance
+    }
+
+    // any update to a part changes the sum
+    fn generation(&self) -> u64 {
+        self.parts
+            .iter()
+            .fold(0, |acc: u64, part| acc.wrapping_add(part.generation()))
+    }
+
+    fn hash_embed<H>(&self, index: usize, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        let (part, local) = self.locate(index);
+        let inner = self.parts[part];
+        inner.hash_embed(inner.all().start + local, hasher)
+    }
+
+    fn dist_batch(&'a self, query: &Embedding<T>, indices: &[usize]) -> Vec<DistanceCmp> {
+        if !self.shared_distance {
+            let distance = self.distance;
+            return self.with_embeds(indices, |embeds| {
+                embeds
+                    .iter()
+                    .map(|embed| distance.distance_cmp(query, embed))
+                    .collect()
+            });
+        }
+        let mut by_part: Vec<(Vec<usize>, Vec<usize>)> = vec![Default::default(); self.parts.len()];
+        indices.iter().enumerate().for_each(|(pos, &ix)| {
+            let (part, local) = self.locate(ix);
+            let (positions, locals) = &mut by_part[part];
+            positions.push(pos);
+            locals.push(self.parts[part].all().start + local);
+        });
+        let mut res = vec![DistanceCmp::zero(); indices.len()];
+        by_part
+            .iter()
+            .enumerate()
+            .filter(|(_, (positions, _))| !positions.is_empty())
+            .for_each(|(part, (positions, locals))| {
+                let dists = self.parts[part].dist_batch(query, locals);
+                positions
+                    .iter()
+                    .zip(dists)
+                    .for_each(|(&pos, dist)| res[pos] = dist);
+            });
+        res
+    }
+
+    fn check_embed(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        let (part, local) = self.locate(index);
+        let inner = self.parts[part];
+        // errors report the index within the chain
+        inner
+            .check_embed(inner.all().start + local)
+            .map_err(|err| InvalidEmbeddingError { index, ..err })
+    }
+
+    fn dim(&self) -> Option<usize> {
+        self.parts.iter().find_map(|part| part.dim())
+    }
+
+    fn check_dim(&self, embed: &Embedding<T>) -> Result<(), DimensionMismatch> {
+        match self.parts.first() {
+            Some(part) => part.check_dim(embed),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use digest::Digest;
+
+use crate::{
+    distances::{dynamic::DynDistance, name::DistanceName},
+    Distance, DistanceCmp, DistanceValue, Embedding,
+};
+
+// the parts are borrowed since distances have to be Copy
+pub struct CompositeDistance<'c, T> {
+    parts: &'c [(DynDistance<'c, T>, f64)],
+    name: DistanceName,
+}
+
+impl<'c, T> CompositeDistance<'c, T> {
+    pub fn new(parts: &'c [(DynDistance<'c, T>, f64)]) -> Self {
+        let name = DistanceName::digest("composite", |hasher| {
+            parts.iter().for_each(|(distance, weight)| {
+                hasher.update(distance.name().as_bytes());
+                hasher.update(weight.to_be_bytes());
+            })
+        });
+        CompositeDistance { parts, name }
+    }
+
+    pub fn parts(&self) -> &'c [(DynDistance<'c, T>, f64)] {
+        self.parts
+    }
+}
+
+impl<'c, T> Clone for CompositeDistance<'c, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'c, T> Copy for CompositeDistance<'c, T> {}
+
+impl<'c, T> Distance<T> for CompositeDistance<'c, T> {
+    fn distance_cmp(&self, a: &Embedding<T>, b: &Embedding<T>) -> DistanceCmp {
+        // comparison values of different metrics are not on the same scale
+        // so the metric values get combined, for cosine this is the chord
+        let res: DistanceValue = self
+            .parts
+            .iter()
+            .map(|(distance, weight)| {
+                let dist = distance.to_metric(&distance.distance_cmp(a, b));
+                dist * *weight as DistanceValue
+            })
+            .sum();
+        DistanceCmp::of_signed(res)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn is_signed(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|(distance, weight)| distance.is_signed() || *weight < 0.0)
+    }
+
+    // a non-negative sum of metrics is a metric again
+    fn is_metric(&self) -> bool {
+        self.parts.iter().all(|(distance, _)| distance.is_metric())
+    }
+}
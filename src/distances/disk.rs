@@ -0,0 +1,590 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    num::NonZeroUsize,
+    ops::Deref,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use digest::Digest;
+use lru::LruCache;
+
+use crate::{
+    distances::{
+        chord_cmp, chord_metric, dynamic::DynDistance, normed::InnerProduct, scalar::Scalar,
+        sqrt_metric, squared_cmp,
+    },
+    io::npy::{npy_layout, NpyError},
+    DimensionMismatch, Distance, DistanceCmp, DistanceValue, Embedding, EmbeddingProvider,
+    InvalidEmbeddingError,
+};
+
+#[derive(Debug)]
+pub enum DiskError {
+    IoError(std::io::Error),
+    FormatError(String),
+    OutOfRange { index: usize, rows: usize },
+}
+
+impl From<std::io::Error> for DiskError {
+    fn from(value: std::io::Error) -> Self {
+        DiskError::IoError(value)
+    }
+}
+
+impl From<NpyError> for DiskError {
+    fn from(value: NpyError) -> Self {
+        match value {
+            NpyError::IoError(err) => DiskError::IoError(err),
+            NpyError::FormatError(msg) => DiskError::FormatError(msg),
+        }
+    }
+}
+
+impl fmt::Display for DiskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiskError::IoError(err) => write!(f, "{err}"),
+            DiskError::FormatError(msg) => write!(f, "invalid row file: {msg}"),
+            DiskError::OutOfRange { index, rows } => {
+                write!(f, "row {index} out of range for {rows} rows")
+            }
+        }
+    }
+}
+
+// anything that can produce a single row on request, a file or a key value
+// store
+pub trait RowSource<F> {
+    fn rows(&self) -> usize;
+    fn dim(&self) -> usize;
+    fn fetch(&self, index: usize, row: &mut Vec<F>) -> Result<(), DiskError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowFormat {
+    F32Le,
+    F32Be,
+    F64Le,
+    F64Be,
+}
+
+impl RowFormat {
+    pub fn size(&self) -> usize {
+        match self {
+            RowFormat::F32Le | RowFormat::F32Be => 4,
+            RowFormat::F64Le | RowFormat::F64Be => 8,
+        }
+    }
+
+    fn decode(&self, chunk: &[u8]) -> f64 {
+        match self {
+            RowFormat::F32Le => f32::from_le_bytes(chunk.try_into().unwrap()) as f64,
+            RowFormat::F32Be => f32::from_be_bytes(chunk.try_into().unwrap()) as f64,
+            RowFormat::F64Le => f64::from_le_bytes(chunk.try_into().unwrap()),
+            RowFormat::F64Be => f64::from_be_bytes(chunk.try_into().unwrap()),
+        }
+    }
+}
+
+// rows of a file are dim values apart from each other, every row starts
+// stride bytes after the previous one
+pub struct FileRows {
+    file: Mutex<File>,
+    offset: u64,
+    stride: u64,
+    rows: usize,
+    dim: usize,
+    format: RowFormat,
+    // every row is preceded by its dimension as a little endian i32
+    prefixed: bool,
+}
+
+impl FileRows {
+    // a headerless file of consecutive rows starting at offset
+    pub fn open_raw<P>(
+        path: P,
+        offset: u64,
+        dim: usize,
+        format: RowFormat,
+    ) -> Result<Self, DiskError>
+    where
+        P: AsRef<Path>,
+    {
+        if dim == 0 {
+            return Err(DiskError::FormatError(
+                "dimension must be positive".to_string(),
+            ));
+        }
+        let file = File::open(path)?;
+        let stride = (dim * format.size()) as u64;
+        let len = file.metadata()?.len();
+        if len < offset || !(len - offset).is_multiple_of(stride) {
+            return Err(DiskError::FormatError(format!(
+                "{len} bytes are not a whole number of rows of {stride} bytes"
+            )));
+        }
+        Ok(FileRows {
+            file: Mutex::new(file),
+            offset,
+            stride,
+            rows: ((len - offset) / stride) as usize,
+            dim,
+            format,
+            prefixed: false,
+        })
+    }
+
+    pub fn open_npy<P>(path: P) -> Result<Self, DiskError>
+    where
+        P: AsRef<Path>,
+    {
+        let layout = npy_layout(&path)?;
+        let format = match (layout.value_size, layout.big_endian) {
+            (4, false) => RowFormat::F32Le,
+            (4, true) => RowFormat::F32Be,
+            (8, false) => RowFormat::F64Le,
+            _ => RowFormat::F64Be,
+        };
+        Ok(FileRows {
+            file: Mutex::new(File::open(path)?),
+            offset: layout.offset,
+            stride: (layout.cols * layout.value_size) as u64,
+            rows: layout.rows,
+            dim: layout.cols,
+            format,
+            prefixed: false,
+        })
+    }
+
+    // every row is prefixed by its dimension which has to be the same for
+    // all rows. only the first prefix is read here, the others are checked
+    // whenever their row is fetched
+    pub fn open_fvecs<P>(path: P) -> Result<Self, DiskError>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut prefix = [0u8; 4];
+        file.read_exact(&mut prefix)?;
+        let dim = i32::from_le_bytes(prefix);
+        if dim <= 0 {
+            return Err(DiskError::FormatError(format!("invalid dimension {dim}")));
+        }
+        let dim = dim as usize;
+        let stride = (4 + dim * 4) as u64;
+        if !len.is_multiple_of(stride) {
+            return Err(DiskError::FormatError(format!(
+                "{len} bytes are not a whole number of rows of {stride} bytes"
+            )));
+        }
+        Ok(FileRows {
+            file: Mutex::new(file),
+            offset: 4,
+            stride,
+            rows: (len / stride) as usize,
+            dim,
+            format: RowFormat::F32Le,
+            prefixed: true,
+        })
+    }
+
+    pub fn format(&self) -> RowFormat {
+        self.format
+    }
+}
+
+impl<F> RowSource<F> for FileRows
+where
+    F: Scalar,
+{
+    fn rows(&self) -> usize {
+        self.rows
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn fetch(&self, index: usize, row: &mut Vec<F>) -> Result<(), DiskError> {
+        if index >= self.rows {
+            return Err(DiskError::OutOfRange {
+                index,
+                rows: self.rows,
+            });
+        }
+        let size = self.format.size();
+        let prefix = if self.prefixed { 4 } else { 0 };
+        let mut buff = vec![0u8; prefix + self.dim * size];
+        {
+            let mut file = self.file.lock().unwrap();
+            let start = self.offset + index as u64 * self.stride - prefix as u64;
+            file.seek(SeekFrom::Start(start))?;
+            file.read_exact(&mut buff)?;
+        }
+        let (prefix, values) = buff.split_at(prefix);
+        if !prefix.is_empty() {
+            let dim = i32::from_le_bytes(prefix.try_into().unwrap());
+            if dim as i64 != self.dim as i64 {
+                return Err(DiskError::FormatError(format!(
+                    "row {index} has dimension {dim} instead of {expected}",
+                    expected = self.dim,
+                )));
+            }
+        }
+        row.clear();
+        row.extend(
+            values
+                .chunks_exact(size)
+                .map(|chunk| F::from_f64(self.format.decode(chunk))),
+        );
+        Ok(())
+    }
+}
+
+// rows are shared with the cache so handing one out never copies the values
+#[derive(Debug, Clone)]
+pub struct DiskRow<F> {
+    values: Arc<[F]>,
+}
+
+impl<F> DiskRow<F>
+where
+    F: Scalar,
+{
+    pub fn new(values: Vec<F>) -> Self {
+        DiskRow {
+            values: values.into(),
+        }
+    }
+
+    fn dot(&self, other: &Self) -> f64 {
+        F::dot_slice(&self.values, &other.values)
+    }
+}
+
+impl<F> Deref for DiskRow<F> {
+    type Target = [F];
+
+    fn deref(&self) -> &Self::Target {
+        &self.values
+    }
+}
+
+impl<F> From<Vec<F>> for DiskRow<F>
+where
+    F: Scalar,
+{
+    fn from(value: Vec<F>) -> Self {
+        DiskRow::new(value)
+    }
+}
+
+impl<F> InnerProduct for DiskRow<F>
+where
+    F: Scalar,
+{
+    fn inner_product(&self, other: &Self) -> f64 {
+        self.dot(other)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DiskDotDistance {}
+
+pub const DISK_DOT_DISTANCE: DiskDotDistance = DiskDotDistance {};
+
+impl<F> Distance<DiskRow<F>> for DiskDotDistance
+where
+    F: Scalar,
+{
+    fn distance_cmp(&self, a: &Embedding<DiskRow<F>>, b: &Embedding<DiskRow<F>>) -> DistanceCmp {
+        DistanceCmp::of((-a.embed.dot(&b.embed)).exp() as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "dot"
+    }
+
+    fn is_metric(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DiskL2Distance {}
+
+pub const DISK_L2_DISTANCE: DiskL2Distance = DiskL2Distance {};
+
+impl<F> Distance<DiskRow<F>> for DiskL2Distance
+where
+    F: Scalar,
+{
+    fn distance_cmp(&self, a: &Embedding<DiskRow<F>>, b: &Embedding<DiskRow<F>>) -> DistanceCmp {
+        DistanceCmp::of(F::l2_slice(&a.embed, &b.embed) as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to().sqrt()
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        sqrt_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        squared_cmp(value)
+    }
+
+    fn name(&self) -> &str {
+        "l2"
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DiskL1Distance {}
+
+pub const DISK_L1_DISTANCE: DiskL1Distance = DiskL1Distance {};
+
+impl<F> Distance<DiskRow<F>> for DiskL1Distance
+where
+    F: Scalar,
+{
+    fn distance_cmp(&self, a: &Embedding<DiskRow<F>>, b: &Embedding<DiskRow<F>>) -> DistanceCmp {
+        let res: f64 = a
+            .embed
+            .iter()
+            .zip(b.embed.iter())
+            .map(|(cur_a, cur_b)| (cur_a.to_f64() - cur_b.to_f64()).abs())
+            .sum();
+        DistanceCmp::of(res as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "l1"
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DiskCosineDistance {}
+
+pub const DISK_COSINE_DISTANCE: DiskCosineDistance = DiskCosineDistance {};
+
+impl<F> Distance<DiskRow<F>> for DiskCosineDistance
+where
+    F: Scalar,
+{
+    fn distance_cmp(&self, a: &Embedding<DiskRow<F>>, b: &Embedding<DiskRow<F>>) -> DistanceCmp {
+        let denom = (a.embed.dot(&a.embed) * b.embed.dot(&b.embed)).sqrt();
+        let sim = if denom > 0.0 {
+            a.embed.dot(&b.embed) / denom
+        } else {
+            0.0
+        };
+        DistanceCmp::of((1.0 - sim).max(0.0) as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "cosine"
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        chord_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        chord_cmp(value)
+    }
+}
+
+pub fn disk_distance<'a, F>(name: &str) -> Option<DynDistance<'a, DiskRow<F>>>
+where
+    F: Scalar,
+{
+    match name {
+        "cosine" => Some(DynDistance::new(&DISK_COSINE_DISTANCE)),
+        "dot" => Some(DynDistance::new(&DISK_DOT_DISTANCE)),
+        "l1" => Some(DynDistance::new(&DISK_L1_DISTANCE)),
+        "l2" => Some(DynDistance::new(&DISK_L2_DISTANCE)),
+        _ => None,
+    }
+}
+
+// only the most recently used rows are kept in memory, everything else is
+// fetched from the source again when it is needed
+pub struct DiskProvider<S, D, F = f64> {
+    source: S,
+    cache: Mutex<LruCache<usize, DiskRow<F>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    distance: D,
+}
+
+impl<S, D, F> DiskProvider<S, D, F>
+where
+    S: RowSource<F>,
+    F: Scalar,
+{
+    pub fn new(source: S, cap: NonZeroUsize, distance: D) -> Self {
+        DiskProvider {
+            source,
+            cache: Mutex::new(LruCache::new(cap)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            distance,
+        }
+    }
+
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    pub fn hits_miss(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    pub fn cached(&self) -> usize {
+        self.cache.lock().unwrap().len()
+    }
+
+    pub fn clear_cache(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    pub fn try_row(&self, index: usize) -> Result<DiskRow<F>, DiskError> {
+        if let Some(row) = self.cache.lock().unwrap().get(&index) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(row.clone());
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        // the lock is not held while reading so other threads can still use
+        // the cached rows
+        let mut values = Vec::with_capacity(self.source.dim());
+        self.source.fetch(index, &mut values)?;
+        let row = DiskRow::new(values);
+        self.cache.lock().unwrap().put(index, row.clone());
+        Ok(row)
+    }
+
+    // takes the cache lock once for the whole batch and once more to store
+    // the rows that had to be read
+    pub fn try_rows(&self, indices: &[usize]) -> Result<Vec<DiskRow<F>>, DiskError> {
+        let mut res: Vec<Option<DiskRow<F>>> = {
+            let mut cache = self.cache.lock().unwrap();
+            indices.iter().map(|ix| cache.get(ix).cloned()).collect()
+        };
+        let missing = res.iter().filter(|row| row.is_none()).count() as u64;
+        self.hits
+            .fetch_add(indices.len() as u64 - missing, Ordering::Relaxed);
+        self.misses.fetch_add(missing, Ordering::Relaxed);
+        let mut fetched = Vec::with_capacity(missing as usize);
+        res.iter_mut()
+            .zip(indices)
+            .filter(|(row, _)| row.is_none())
+            .try_for_each(|(row, &ix)| {
+                let mut values = Vec::with_capacity(self.source.dim());
+                self.source.fetch(ix, &mut values)?;
+                let fresh = DiskRow::new(values);
+                fetched.push((ix, fresh.clone()));
+                *row = Some(fresh);
+                Ok::<(), DiskError>(())
+            })?;
+        if !fetched.is_empty() {
+            let mut cache = self.cache.lock().unwrap();
+            fetched.into_iter().for_each(|(ix, row)| {
+                cache.put(ix, row);
+            });
+        }
+        Ok(res.into_iter().map(|row| row.unwrap()).collect())
+    }
+
+    // the provider interface has no way to report a failed read
+    pub fn row(&self, index: usize) -> DiskRow<F> {
+        self.try_row(index)
+            .unwrap_or_else(|err| panic!("cannot read row {index}: {err}"))
+    }
+
+    // reads every row once without filling the cache
+    pub fn try_validate(&self) -> Result<(), DiskError> {
+        let mut values = Vec::with_capacity(self.source.dim());
+        (0..self.source.rows()).try_for_each(|ix| {
+            self.source.fetch(ix, &mut values)?;
+            InvalidEmbeddingError::check(ix, values.iter().map(|v| v.to_f64()))
+                .map_err(|err| DiskError::FormatError(err.to_string()))
+        })
+    }
+}
+
+impl<'a, S, D, F> EmbeddingProvider<'a, D, DiskRow<F>> for DiskProvider<S, D, F>
+where
+    S: RowSource<F>,
+    D: Distance<DiskRow<F>> + Copy,
+    F: Scalar,
+{
+    fn get_embed(&'a self, index: usize) -> DiskRow<F> {
+        self.row(index)
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        0..self.source.rows()
+    }
+
+    fn distance(&self) -> D {
+        self.distance
+    }
+
+    fn with_embeds<R, O>(&'a self, indices: &[usize], op: O) -> R
+    where
+        O: FnOnce(&[Embedding<DiskRow<F>>]) -> R,
+    {
+        let rows = self
+            .try_rows(indices)
+            .unwrap_or_else(|err| panic!("cannot read rows: {err}"));
+        let embeds: Vec<Embedding<DiskRow<F>>> = rows
+            .into_iter()
+            .zip(indices)
+            .map(|(row, &ix)| Embedding::wrap(row, ix))
+            .collect();
+        op(&embeds)
+    }
+
+    fn hash_embed<H>(&self, index: usize, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        self.row(index).iter().for_each(|&v| v.hash_into(hasher));
+    }
+
+    fn check_embed(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        InvalidEmbeddingError::check(index, self.row(index).iter().map(|v| v.to_f64()))
+    }
+
+    fn dim(&self) -> Option<usize> {
+        Some(self.source.dim())
+    }
+
+    fn check_dim(&self, embed: &Embedding<DiskRow<F>>) -> Result<(), DimensionMismatch> {
+        DimensionMismatch::check(self.source.dim(), embed.embed.len())
+    }
+}
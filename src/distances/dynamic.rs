@@ -0,0 +1,57 @@
+use crate::{Distance, DistanceCmp, DistanceValue, DotBatch, Embedding};
+
+pub struct DynDistance<'d, T> {
+    distance: &'d (dyn Distance<T> + Sync),
+}
+
+impl<'d, T> DynDistance<'d, T> {
+    pub fn new(distance: &'d (dyn Distance<T> + Sync)) -> Self {
+        DynDistance { distance }
+    }
+}
+
+impl<'d, T> Clone for DynDistance<'d, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'d, T> Copy for DynDistance<'d, T> {}
+
+impl<'d, T> Distance<T> for DynDistance<'d, T> {
+    fn distance_cmp(&self, a: &Embedding<T>, b: &Embedding<T>) -> DistanceCmp {
+        self.distance.distance_cmp(a, b)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        self.distance.finalize_distance(dist_cmp)
+    }
+
+    fn name(&self) -> &str {
+        self.distance.name()
+    }
+
+    fn is_signed(&self) -> bool {
+        self.distance.is_signed()
+    }
+
+    fn is_metric(&self) -> bool {
+        self.distance.is_metric()
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        self.distance.to_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        self.distance.metric_cmp(value)
+    }
+
+    fn dot_batch(&self) -> DotBatch {
+        self.distance.dot_batch()
+    }
+
+    fn dot_distance_cmp(&self, dot: f64, norm_a: f64, norm_b: f64) -> DistanceCmp {
+        self.distance.dot_distance_cmp(dot, norm_a, norm_b)
+    }
+}
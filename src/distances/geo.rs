@@ -0,0 +1,128 @@
+use digest::Digest;
+
+use crate::{
+    info::Info, Distance, DistanceCmp, DistanceValue, Embedding, EmbeddingProvider,
+    InvalidEmbeddingError, NearestNeighbors,
+};
+
+pub const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+// points are (latitude, longitude) in degrees
+pub type GeoPoint = (f64, f64);
+
+#[derive(Debug, Clone, Copy)]
+pub struct HaversineDistance {}
+
+pub const HAVERSINE_DISTANCE: HaversineDistance = HaversineDistance {};
+
+impl Distance<GeoPoint> for HaversineDistance {
+    fn distance_cmp(&self, a: &Embedding<GeoPoint>, b: &Embedding<GeoPoint>) -> DistanceCmp {
+        let (lat_a, lon_a) = a.embed;
+        let (lat_b, lon_b) = b.embed;
+        let (lat_a, lat_b) = (lat_a.to_radians(), lat_b.to_radians());
+        let half_lat = (lat_b - lat_a) / 2.0;
+        let half_lon = (lon_b - lon_a).to_radians() / 2.0;
+        let hav = half_lat.sin().powi(2) + lat_a.cos() * lat_b.cos() * half_lon.sin().powi(2);
+        // the great circle distance is a metric so the tree bounds hold
+        // exactly when comparing in kilometers
+        let res = 2.0 * EARTH_RADIUS_KM * hav.sqrt().min(1.0).asin();
+        DistanceCmp::of(res as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "haversine"
+    }
+}
+
+pub struct GeoProvider<'a, D>
+where
+    D: Distance<GeoPoint>,
+{
+    points: &'a [GeoPoint],
+    distance: D,
+}
+
+impl<'a, D> GeoProvider<'a, D>
+where
+    D: Distance<GeoPoint>,
+{
+    pub fn new(points: &'a [GeoPoint], distance: D) -> Self {
+        GeoProvider { points, distance }
+    }
+
+    pub fn try_new(points: &'a [GeoPoint], distance: D) -> Result<Self, InvalidEmbeddingError> {
+        let provider = Self::new(points, distance);
+        (0..points.len()).try_for_each(|ix| provider.check_point(ix))?;
+        Ok(provider)
+    }
+
+    fn check_point(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        let (lat, lon) = self.points[index];
+        InvalidEmbeddingError::check(index, [lat, lon])
+    }
+}
+
+impl<'a, D> EmbeddingProvider<'a, D, GeoPoint> for GeoProvider<'a, D>
+where
+    D: Distance<GeoPoint> + Copy,
+{
+    fn get_embed(&'a self, index: usize) -> GeoPoint {
+        self.points[index]
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        0..self.points.len()
+    }
+
+    fn distance(&self) -> D {
+        self.distance
+    }
+
+    fn hash_embed<H>(&self, index: usize, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        let (lat, lon) = self.points[index];
+        hasher.update(lat.to_be_bytes());
+        hasher.update(lon.to_be_bytes());
+    }
+
+    fn check_embed(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        self.check_point(index)
+    }
+}
+
+impl<'a, D> NearestNeighbors<'a, GeoPoint> for GeoProvider<'a, D>
+where
+    D: Distance<GeoPoint>,
+{
+    fn get_closest<I>(
+        &self,
+        other: &Embedding<GeoPoint>,
+        count: usize,
+        _info: &mut I,
+    ) -> Vec<(usize, DistanceValue)>
+    where
+        I: Info,
+    {
+        let mut dists: Vec<(usize, DistanceCmp)> = self
+            .points
+            .iter()
+            .enumerate()
+            .map(|(ix, &cur)| {
+                let val = Embedding::wrap(cur, ix);
+                (ix, self.distance.distance_cmp(&val, other))
+            })
+            .collect();
+        dists.sort_unstable_by_key(|(_, a)| *a);
+        dists
+            .iter()
+            .take(count)
+            .map(|(ix, dist)| (*ix, self.distance.finalize_distance(dist)))
+            .collect()
+    }
+}
@@ -0,0 +1,362 @@
+use ::half::{bf16, f16};
+use digest::Digest;
+use ndarray::ArrayView2;
+
+use crate::{
+    distances::{chord_cmp, chord_metric, dynamic::DynDistance, sqrt_metric, squared_cmp},
+    info::Info,
+    DimensionMismatch, Distance, DistanceCmp, DistanceValue, Embedding, EmbeddingProvider,
+    InvalidEmbeddingError, NearestNeighbors,
+};
+
+pub trait HalfFloat: Copy + Send + Sync {
+    fn from_f64(value: f64) -> Self;
+    fn to_f32(self) -> f32;
+    fn to_bits(self) -> u16;
+    fn from_bits(bits: u16) -> Self;
+}
+
+impl HalfFloat for f16 {
+    fn from_f64(value: f64) -> Self {
+        f16::from_f64(value)
+    }
+
+    fn to_f32(self) -> f32 {
+        f16::to_f32(self)
+    }
+
+    fn to_bits(self) -> u16 {
+        f16::to_bits(self)
+    }
+
+    fn from_bits(bits: u16) -> Self {
+        f16::from_bits(bits)
+    }
+}
+
+impl HalfFloat for bf16 {
+    fn from_f64(value: f64) -> Self {
+        bf16::from_f64(value)
+    }
+
+    fn to_f32(self) -> f32 {
+        bf16::to_f32(self)
+    }
+
+    fn to_bits(self) -> u16 {
+        bf16::to_bits(self)
+    }
+
+    fn from_bits(bits: u16) -> Self {
+        bf16::from_bits(bits)
+    }
+}
+
+pub fn to_half<H, I>(values: I) -> Vec<H>
+where
+    H: HalfFloat,
+    I: IntoIterator<Item = f64>,
+{
+    values.into_iter().map(H::from_f64).collect()
+}
+
+// values are upconverted per pair and accumulated in f32
+fn upconvert<'b, H>(a: &'b [H], b: &'b [H]) -> impl Iterator<Item = (f32, f32)> + 'b
+where
+    H: HalfFloat,
+{
+    a.iter()
+        .zip(b.iter())
+        .map(|(&cur_a, &cur_b)| (cur_a.to_f32(), cur_b.to_f32()))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HalfDotDistance {}
+
+pub const HALF_DOT_DISTANCE: HalfDotDistance = HalfDotDistance {};
+
+impl<'a, H> Distance<&'a [H]> for HalfDotDistance
+where
+    H: HalfFloat,
+{
+    fn distance_cmp(&self, a: &Embedding<&'a [H]>, b: &Embedding<&'a [H]>) -> DistanceCmp {
+        let res: f32 = upconvert(a.embed, b.embed)
+            .map(|(cur_a, cur_b)| cur_a * cur_b)
+            .sum();
+        DistanceCmp::of((-(res as f64)).exp() as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "dot"
+    }
+
+    fn is_metric(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HalfL2Distance {}
+
+pub const HALF_L2_DISTANCE: HalfL2Distance = HalfL2Distance {};
+
+impl<'a, H> Distance<&'a [H]> for HalfL2Distance
+where
+    H: HalfFloat,
+{
+    fn distance_cmp(&self, a: &Embedding<&'a [H]>, b: &Embedding<&'a [H]>) -> DistanceCmp {
+        let res: f32 = upconvert(a.embed, b.embed)
+            .map(|(cur_a, cur_b)| (cur_a - cur_b) * (cur_a - cur_b))
+            .sum();
+        DistanceCmp::of(res as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to().sqrt()
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        sqrt_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        squared_cmp(value)
+    }
+
+    fn name(&self) -> &str {
+        "l2"
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HalfL1Distance {}
+
+pub const HALF_L1_DISTANCE: HalfL1Distance = HalfL1Distance {};
+
+impl<'a, H> Distance<&'a [H]> for HalfL1Distance
+where
+    H: HalfFloat,
+{
+    fn distance_cmp(&self, a: &Embedding<&'a [H]>, b: &Embedding<&'a [H]>) -> DistanceCmp {
+        let res: f32 = upconvert(a.embed, b.embed)
+            .map(|(cur_a, cur_b)| (cur_a - cur_b).abs())
+            .sum();
+        DistanceCmp::of(res as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "l1"
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HalfCosineDistance {}
+
+pub const HALF_COSINE_DISTANCE: HalfCosineDistance = HalfCosineDistance {};
+
+impl<'a, H> Distance<&'a [H]> for HalfCosineDistance
+where
+    H: HalfFloat,
+{
+    fn distance_cmp(&self, a: &Embedding<&'a [H]>, b: &Embedding<&'a [H]>) -> DistanceCmp {
+        let (dot, norm_a, norm_b) = upconvert(a.embed, b.embed).fold(
+            (0.0, 0.0, 0.0),
+            |(dot, norm_a, norm_b), (cur_a, cur_b)| {
+                (
+                    dot + cur_a * cur_b,
+                    norm_a + cur_a * cur_a,
+                    norm_b + cur_b * cur_b,
+                )
+            },
+        );
+        let denom: f32 = (norm_a * norm_b).sqrt();
+        let sim = if denom > 0.0 { dot / denom } else { 0.0 };
+        DistanceCmp::of((1.0 - sim).max(0.0) as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "cosine"
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        chord_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        chord_cmp(value)
+    }
+}
+
+pub fn half_distance<'a, H>(name: &str) -> Option<DynDistance<'a, &'a [H]>>
+where
+    H: HalfFloat + 'a,
+{
+    match name {
+        "cosine" => Some(DynDistance::new(&HALF_COSINE_DISTANCE)),
+        "dot" => Some(DynDistance::new(&HALF_DOT_DISTANCE)),
+        "l1" => Some(DynDistance::new(&HALF_L1_DISTANCE)),
+        "l2" => Some(DynDistance::new(&HALF_L2_DISTANCE)),
+        _ => None,
+    }
+}
+
+pub struct HalfProvider<H, D> {
+    data: Vec<H>,
+    dim: usize,
+    rows: usize,
+    distance: D,
+}
+
+impl<H, D> HalfProvider<H, D>
+where
+    H: HalfFloat,
+{
+    pub fn from_array(arr: ArrayView2<f64>, distance: D) -> Self {
+        HalfProvider {
+            data: to_half(arr.iter().copied()),
+            dim: arr.shape()[1],
+            rows: arr.shape()[0],
+            distance,
+        }
+    }
+
+    pub fn from_vecs(embeddings: &[Vec<f64>], distance: D) -> Self {
+        let dim = embeddings.first().map_or(0, |first| first.len());
+        HalfProvider {
+            data: embeddings
+                .iter()
+                .flat_map(|embed| embed.iter().copied().map(H::from_f64))
+                .collect(),
+            dim,
+            rows: embeddings.len(),
+            distance,
+        }
+    }
+
+    // data that is already in half precision is taken as is, a flat buffer
+    // of rows with dim values each
+    pub fn from_raw(data: Vec<H>, dim: usize, distance: D) -> Result<Self, DimensionMismatch> {
+        let rows = data.len().checked_div(dim).unwrap_or(0);
+        DimensionMismatch::check(rows * dim, data.len())?;
+        Ok(HalfProvider {
+            data,
+            dim,
+            rows,
+            distance,
+        })
+    }
+
+    pub fn from_bits(bits: &[u16], dim: usize, distance: D) -> Result<Self, DimensionMismatch> {
+        Self::from_raw(
+            bits.iter().map(|&v| H::from_bits(v)).collect(),
+            dim,
+            distance,
+        )
+    }
+
+    // values outside the half range become infinite
+    pub fn try_from_array(
+        arr: ArrayView2<f64>,
+        distance: D,
+    ) -> Result<Self, InvalidEmbeddingError> {
+        let provider = Self::from_array(arr, distance);
+        (0..provider.rows).try_for_each(|ix| provider.check_row(ix))?;
+        Ok(provider)
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn row(&self, index: usize) -> &[H] {
+        &self.data[index * self.dim..(index + 1) * self.dim]
+    }
+
+    fn check_row(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        InvalidEmbeddingError::check(index, self.row(index).iter().map(|v| v.to_f32() as f64))
+    }
+}
+
+impl<'a, H, D> EmbeddingProvider<'a, D, &'a [H]> for HalfProvider<H, D>
+where
+    H: HalfFloat + 'a,
+    D: Distance<&'a [H]> + Copy,
+{
+    fn get_embed(&'a self, index: usize) -> &'a [H] {
+        self.row(index)
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        0..self.rows
+    }
+
+    fn distance(&self) -> D {
+        self.distance
+    }
+
+    fn hash_embed<Hs>(&self, index: usize, hasher: &mut Hs)
+    where
+        Hs: Digest,
+    {
+        self.row(index)
+            .iter()
+            .for_each(|v| hasher.update(v.to_bits().to_be_bytes()));
+    }
+
+    fn check_embed(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        self.check_row(index)
+    }
+
+    fn dim(&self) -> Option<usize> {
+        Some(self.dim)
+    }
+
+    fn check_dim(&self, embed: &Embedding<&'a [H]>) -> Result<(), DimensionMismatch> {
+        DimensionMismatch::check(self.dim, embed.embed.len())
+    }
+}
+
+impl<'a, H, D> NearestNeighbors<'a, &'a [H]> for HalfProvider<H, D>
+where
+    H: HalfFloat + 'a,
+    D: for<'r> Distance<&'r [H]>,
+{
+    fn check_query(&self, other: &Embedding<&'a [H]>) -> Result<(), DimensionMismatch> {
+        DimensionMismatch::check(self.dim, other.embed.len())
+    }
+
+    fn get_closest<I>(
+        &self,
+        other: &Embedding<&'a [H]>,
+        count: usize,
+        _info: &mut I,
+    ) -> Vec<(usize, DistanceValue)>
+    where
+        I: Info,
+    {
+        let mut dists: Vec<(usize, DistanceCmp)> = (0..self.rows)
+            .map(|ix| {
+                let val = Embedding::wrap(self.row(ix), ix);
+                (ix, self.distance.distance_cmp(&val, other))
+            })
+            .collect();
+        dists.sort_unstable_by_key(|(_, a)| *a);
+        dists
+            .iter()
+            .take(count)
+            .map(|(ix, dist)| (*ix, self.distance.finalize_distance(dist)))
+            .collect()
+    }
+}
@@ -0,0 +1,308 @@
+use digest::Digest;
+use ndarray::{ArrayView1, ArrayView2};
+
+use crate::{
+    distances::{dynamic::DynDistance, sqrt_metric, squared_cmp},
+    info::Info,
+    DimensionMismatch, Distance, DistanceCmp, DistanceValue, Embedding, EmbeddingProvider,
+    NearestNeighbors,
+};
+
+// a row is approximated as scale * (values - zero), the integer sum and the
+// squared norm are kept so distances only need one integer dot product
+#[derive(Debug, Clone, Copy)]
+pub struct Quantized<'a> {
+    pub values: &'a [i8],
+    pub scale: f32,
+    pub zero: i32,
+    pub sum: i32,
+    pub norm_sq: f32,
+}
+
+impl Quantized<'_> {
+    // i32 accumulation does not overflow below 130k dimensions
+    fn int_dot(&self, other: &Quantized) -> i32 {
+        self.values
+            .iter()
+            .zip(other.values.iter())
+            .map(|(&cur_a, &cur_b)| cur_a as i32 * cur_b as i32)
+            .sum()
+    }
+
+    fn dot(&self, other: &Quantized) -> f64 {
+        let count = self.values.len() as i64;
+        let (zero_a, zero_b) = (self.zero as i64, other.zero as i64);
+        let res = self.int_dot(other) as i64 - zero_b * self.sum as i64 - zero_a * other.sum as i64
+            + count * zero_a * zero_b;
+        res as f64 * self.scale as f64 * other.scale as f64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QuantizedRow {
+    pub values: Vec<i8>,
+    pub scale: f32,
+    pub zero: i32,
+    sum: i32,
+    norm_sq: f32,
+}
+
+impl QuantizedRow {
+    pub fn new(values: Vec<i8>, scale: f32, zero: i32) -> Self {
+        let sum = values.iter().map(|&v| v as i32).sum();
+        let norm_sq = values
+            .iter()
+            .map(|&v| {
+                let cur = scale as f64 * (v as i32 - zero) as f64;
+                cur * cur
+            })
+            .sum::<f64>() as f32;
+        QuantizedRow {
+            values,
+            scale,
+            zero,
+            sum,
+            norm_sq,
+        }
+    }
+
+    pub fn view(&self) -> Quantized<'_> {
+        Quantized {
+            values: &self.values,
+            scale: self.scale,
+            zero: self.zero,
+            sum: self.sum,
+            norm_sq: self.norm_sq,
+        }
+    }
+
+    pub fn dequantize(&self) -> Vec<f64> {
+        self.values
+            .iter()
+            .map(|&v| self.scale as f64 * (v as i32 - self.zero) as f64)
+            .collect()
+    }
+}
+
+// maps the range of the row onto [-128, 127]
+pub fn quantize(row: ArrayView1<f64>) -> QuantizedRow {
+    let (min, max) = row.iter().fold((0.0, 0.0), |(min, max): (f64, f64), &v| {
+        (min.min(v), max.max(v))
+    });
+    let scale = if max > min { (max - min) / 255.0 } else { 1.0 };
+    let zero = (-128.0 - min / scale).round().clamp(-128.0, 127.0) as i32;
+    let values = row
+        .iter()
+        .map(|&v| ((v / scale).round() + zero as f64).clamp(-128.0, 127.0) as i8)
+        .collect();
+    QuantizedRow::new(values, scale as f32, zero)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct I8DotDistance {}
+
+pub const I8_DOT_DISTANCE: I8DotDistance = I8DotDistance {};
+
+impl<'a> Distance<Quantized<'a>> for I8DotDistance {
+    fn distance_cmp(
+        &self,
+        a: &Embedding<Quantized<'a>>,
+        b: &Embedding<Quantized<'a>>,
+    ) -> DistanceCmp {
+        DistanceCmp::of((-a.embed.dot(&b.embed)).exp() as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "dot"
+    }
+
+    fn is_metric(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct I8L2Distance {}
+
+pub const I8_L2_DISTANCE: I8L2Distance = I8L2Distance {};
+
+impl<'a> Distance<Quantized<'a>> for I8L2Distance {
+    fn distance_cmp(
+        &self,
+        a: &Embedding<Quantized<'a>>,
+        b: &Embedding<Quantized<'a>>,
+    ) -> DistanceCmp {
+        let res = a.embed.norm_sq as f64 + b.embed.norm_sq as f64 - 2.0 * a.embed.dot(&b.embed);
+        // the expansion can dip below zero for near duplicates
+        DistanceCmp::of(res.max(0.0) as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to().sqrt()
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        sqrt_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        squared_cmp(value)
+    }
+
+    fn name(&self) -> &str {
+        "l2"
+    }
+}
+
+pub fn i8_distance<'a>(name: &str) -> Option<DynDistance<'a, Quantized<'a>>> {
+    match name {
+        "dot" => Some(DynDistance::new(&I8_DOT_DISTANCE)),
+        "l2" => Some(DynDistance::new(&I8_L2_DISTANCE)),
+        _ => None,
+    }
+}
+
+pub struct I8Provider<D> {
+    values: Vec<i8>,
+    scales: Vec<f32>,
+    zeros: Vec<i32>,
+    sums: Vec<i32>,
+    norms: Vec<f32>,
+    dim: usize,
+    distance: D,
+}
+
+impl<D> I8Provider<D> {
+    pub fn quantize(arr: ArrayView2<f64>, distance: D) -> Self {
+        let rows: Vec<QuantizedRow> = arr.rows().into_iter().map(quantize).collect();
+        Self::from_rows(&rows, arr.shape()[1], distance)
+    }
+
+    fn from_rows(rows: &[QuantizedRow], dim: usize, distance: D) -> Self {
+        I8Provider {
+            values: rows
+                .iter()
+                .flat_map(|row| row.values.iter().copied())
+                .collect(),
+            scales: rows.iter().map(|row| row.scale).collect(),
+            zeros: rows.iter().map(|row| row.zero).collect(),
+            sums: rows.iter().map(|row| row.sum).collect(),
+            norms: rows.iter().map(|row| row.norm_sq).collect(),
+            dim,
+            distance,
+        }
+    }
+
+    // for data that was quantized elsewhere
+    pub fn from_parts(
+        values: Vec<i8>,
+        scales: Vec<f32>,
+        zeros: Vec<i32>,
+        dim: usize,
+        distance: D,
+    ) -> Result<Self, DimensionMismatch> {
+        DimensionMismatch::check(scales.len(), zeros.len())?;
+        DimensionMismatch::check(scales.len() * dim, values.len())?;
+        let rows: Vec<QuantizedRow> = values
+            .chunks(dim.max(1))
+            .zip(scales.iter().zip(zeros.iter()))
+            .map(|(row, (&scale, &zero))| QuantizedRow::new(row.to_vec(), scale, zero))
+            .collect();
+        Ok(Self::from_rows(&rows, dim, distance))
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn scale(&self, index: usize) -> f32 {
+        self.scales[index]
+    }
+
+    pub fn zero(&self, index: usize) -> i32 {
+        self.zeros[index]
+    }
+
+    fn row(&self, index: usize) -> Quantized<'_> {
+        Quantized {
+            values: &self.values[index * self.dim..(index + 1) * self.dim],
+            scale: self.scales[index],
+            zero: self.zeros[index],
+            sum: self.sums[index],
+            norm_sq: self.norms[index],
+        }
+    }
+}
+
+impl<'a, D> EmbeddingProvider<'a, D, Quantized<'a>> for I8Provider<D>
+where
+    D: Distance<Quantized<'a>> + Copy,
+{
+    fn get_embed(&'a self, index: usize) -> Quantized<'a> {
+        self.row(index)
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        0..self.scales.len()
+    }
+
+    fn distance(&self) -> D {
+        self.distance
+    }
+
+    fn hash_embed<H>(&self, index: usize, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        let row = self.row(index);
+        row.values
+            .iter()
+            .for_each(|v| hasher.update(v.to_be_bytes()));
+        hasher.update(row.scale.to_be_bytes());
+        hasher.update(row.zero.to_be_bytes());
+    }
+
+    fn dim(&self) -> Option<usize> {
+        Some(self.dim)
+    }
+
+    fn check_dim(&self, embed: &Embedding<Quantized<'a>>) -> Result<(), DimensionMismatch> {
+        DimensionMismatch::check(self.dim, embed.embed.values.len())
+    }
+}
+
+impl<'a, D> NearestNeighbors<'a, Quantized<'a>> for I8Provider<D>
+where
+    D: for<'r> Distance<Quantized<'r>>,
+{
+    fn check_query(&self, other: &Embedding<Quantized<'a>>) -> Result<(), DimensionMismatch> {
+        DimensionMismatch::check(self.dim, other.embed.values.len())
+    }
+
+    fn get_closest<I>(
+        &self,
+        other: &Embedding<Quantized<'a>>,
+        count: usize,
+        _info: &mut I,
+    ) -> Vec<(usize, DistanceValue)>
+    where
+        I: Info,
+    {
+        let mut dists: Vec<(usize, DistanceCmp)> = (0..self.scales.len())
+            .map(|ix| {
+                let val = Embedding::wrap(self.row(ix), ix);
+                (ix, self.distance.distance_cmp(&val, other))
+            })
+            .collect();
+        dists.sort_unstable_by_key(|(_, a)| *a);
+        dists
+            .iter()
+            .take(count)
+            .map(|(ix, dist)| (*ix, self.distance.finalize_distance(dist)))
+            .collect()
+    }
+}
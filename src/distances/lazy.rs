@@ -0,0 +1,128 @@
+use std::cell::OnceCell;
+
+use digest::Digest;
+
+use crate::{DimensionMismatch, Distance, Embedding, EmbeddingProvider, InvalidEmbeddingError};
+
+pub trait Embedder<I> {
+    fn embed_batch(&self, items: &[&I]) -> Vec<Vec<f64>>;
+}
+
+impl<I, F> Embedder<I> for F
+where
+    F: Fn(&I) -> Vec<f64>,
+{
+    fn embed_batch(&self, items: &[&I]) -> Vec<Vec<f64>> {
+        items.iter().map(|item| self(item)).collect()
+    }
+}
+
+pub struct LazyProvider<I, M, D>
+where
+    M: Embedder<I>,
+{
+    items: Vec<I>,
+    embeds: Vec<OnceCell<Vec<f64>>>,
+    embedder: M,
+    distance: D,
+    batch_size: usize,
+}
+
+impl<I, M, D> LazyProvider<I, M, D>
+where
+    M: Embedder<I>,
+{
+    pub fn new(items: Vec<I>, embedder: M, distance: D, batch_size: usize) -> Self {
+        let embeds = items.iter().map(|_| OnceCell::new()).collect();
+        LazyProvider {
+            items,
+            embeds,
+            embedder,
+            distance,
+            batch_size: batch_size.max(1),
+        }
+    }
+
+    pub fn push(&mut self, item: I) -> usize {
+        self.items.push(item);
+        self.embeds.push(OnceCell::new());
+        self.items.len() - 1
+    }
+
+    pub fn item(&self, index: usize) -> &I {
+        &self.items[index]
+    }
+
+    pub fn embedded_count(&self) -> usize {
+        self.embeds
+            .iter()
+            .filter(|cell| cell.get().is_some())
+            .count()
+    }
+
+    fn fill(&self, index: usize) {
+        let end = (index + self.batch_size).min(self.items.len());
+        let missing: Vec<usize> = (index..end)
+            .filter(|&ix| self.embeds[ix].get().is_none())
+            .collect();
+        let items: Vec<&I> = missing.iter().map(|&ix| &self.items[ix]).collect();
+        let embeds = self.embedder.embed_batch(&items);
+        assert_eq!(embeds.len(), missing.len(), "embedder returned wrong count");
+        missing.into_iter().zip(embeds).for_each(|(ix, embed)| {
+            let _ = self.embeds[ix].set(embed);
+        });
+    }
+
+    fn embedding(&self, index: usize) -> &Vec<f64> {
+        if self.embeds[index].get().is_none() {
+            self.fill(index);
+        }
+        self.embeds[index].get().unwrap()
+    }
+}
+
+impl<'a, I, M, D> EmbeddingProvider<'a, D, &'a Vec<f64>> for LazyProvider<I, M, D>
+where
+    M: Embedder<I>,
+    D: Distance<&'a Vec<f64>> + Copy,
+{
+    fn get_embed(&'a self, index: usize) -> &'a Vec<f64> {
+        self.embedding(index)
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        0..self.items.len()
+    }
+
+    fn distance(&self) -> D {
+        self.distance
+    }
+
+    fn hash_embed<H>(&self, index: usize, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        self.embedding(index)
+            .iter()
+            .for_each(|v| hasher.update(v.to_be_bytes()));
+    }
+
+    fn check_embed(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        InvalidEmbeddingError::check(index, self.embedding(index).iter().copied())
+    }
+
+    fn dim(&self) -> Option<usize> {
+        self.embeds
+            .first()
+            .and_then(|cell| cell.get())
+            .map(|first| first.len())
+    }
+
+    fn check_dim(&self, embed: &Embedding<&'a Vec<f64>>) -> Result<(), DimensionMismatch> {
+        // only known once the first item has been embedded
+        match self.embeds.first().and_then(|cell| cell.get()) {
+            Some(first) => DimensionMismatch::check(first.len(), embed.embed.len()),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::marker::PhantomData;
+
+use digest::Digest;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::{
+    DimensionMismatch, Distance, DistanceCmp, DistanceValue, Embedding, EmbeddingProvider,
+    InvalidEmbeddingError,
+};
+
+// distances are assumed to be symmetric so only the upper triangle including
+// the diagonal is stored
+#[derive(Debug, Clone)]
+pub struct DistanceMatrix {
+    values: Vec<DistanceCmp>,
+    start: usize,
+    size: usize,
+    generation: u64,
+}
+
+impl DistanceMatrix {
+    pub fn compute<'a, E, D, T>(provider: &'a E) -> Self
+    where
+        E: EmbeddingProvider<'a, D, T> + Sync,
+        D: Distance<T> + Copy + Sync,
+        T: 'a,
+    {
+        let all = provider.all();
+        let distance = provider.distance();
+        let row_dists = |row: usize| {
+            let embed = provider.get(row);
+            (row..all.end).map(move |col| distance.distance_cmp(&embed, &provider.get(col)))
+        };
+        #[cfg(feature = "parallel")]
+        let values = all
+            .clone()
+            .into_par_iter()
+            .flat_map_iter(row_dists)
+            .collect();
+        #[cfg(not(feature = "parallel"))]
+        let values = all.clone().flat_map(row_dists).collect();
+        DistanceMatrix {
+            values,
+            start: all.start,
+            size: all.len(),
+            generation: provider.generation(),
+        }
+    }
+
+    pub fn get(&self, index_a: usize, index_b: usize) -> Option<DistanceCmp> {
+        let (row, col) = (
+            index_a.min(index_b).checked_sub(self.start)?,
+            index_a.max(index_b).checked_sub(self.start)?,
+        );
+        if col >= self.size {
+            return None;
+        }
+        let offset = row * self.size - row * row.saturating_sub(1) / 2;
+        Some(self.values[offset + col - row])
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+// like the distance cache, indexed embeddings are assumed to come from the
+// provider the matrix was computed for, everything else uses the inner distance
+pub struct MatrixDistance<'m, D> {
+    inner: D,
+    matrix: &'m DistanceMatrix,
+}
+
+impl<D> Clone for MatrixDistance<'_, D>
+where
+    D: Copy,
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<D> Copy for MatrixDistance<'_, D> where D: Copy {}
+
+impl<'m, D> MatrixDistance<'m, D> {
+    pub fn new(inner: D, matrix: &'m DistanceMatrix) -> Self {
+        MatrixDistance { inner, matrix }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+}
+
+impl<D, T> Distance<T> for MatrixDistance<'_, D>
+where
+    D: Distance<T>,
+{
+    fn distance_cmp(&self, a: &Embedding<T>, b: &Embedding<T>) -> DistanceCmp {
+        let is_current =
+            a.generation == self.matrix.generation && b.generation == self.matrix.generation;
+        match (a.index, b.index) {
+            (Some(index_a), Some(index_b)) if is_current => self
+                .matrix
+                .get(index_a, index_b)
+                .unwrap_or_else(|| self.inner.distance_cmp(a, b)),
+            _ => self.inner.distance_cmp(a, b),
+        }
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        self.inner.finalize_distance(dist_cmp)
+    }
+
+    // the inner name keeps trees built from the matrix valid for the inner provider
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn is_signed(&self) -> bool {
+        self.inner.is_signed()
+    }
+
+    fn is_metric(&self) -> bool {
+        self.inner.is_metric()
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        self.inner.to_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        self.inner.metric_cmp(value)
+    }
+}
+
+pub struct MatrixProvider<'a, E, D> {
+    inner: &'a E,
+    matrix: &'a DistanceMatrix,
+    distance_type: PhantomData<D>,
+}
+
+impl<'a, E, D> MatrixProvider<'a, E, D> {
+    pub fn new(inner: &'a E, matrix: &'a DistanceMatrix) -> Self {
+        MatrixProvider {
+            inner,
+            matrix,
+            distance_type: PhantomData,
+        }
+    }
+
+    pub fn matrix(&self) -> &'a DistanceMatrix {
+        self.matrix
+    }
+}
+
+impl<'a, E, D, T> EmbeddingProvider<'a, MatrixDistance<'a, D>, T> for MatrixProvider<'a, E, D>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+{
+    fn get_embed(&'a self, index: usize) -> T {
+        self.inner.get_embed(index)
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        self.inner.all()
+    }
+
+    fn distance(&self) -> MatrixDistance<'a, D> {
+        MatrixDistance::new(self.inner.distance(), self.matrix)
+    }
+
+    fn generation(&self) -> u64 {
+        self.inner.generation()
+    }
+
+    fn hash_embed<H>(&self, index: usize, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        self.inner.hash_embed(index, hasher)
+    }
+
+    fn check_embed(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        self.inner.check_embed(index)
+    }
+
+    fn dim(&self) -> Option<usize> {
+        self.inner.dim()
+    }
+
+    fn check_dim(&self, embed: &Embedding<T>) -> Result<(), DimensionMismatch> {
+        self.inner.check_dim(embed)
+    }
+}
@@ -0,0 +1,114 @@
+use ndarray::ArrayView1;
+
+use crate::{distances::name::DistanceName, Distance, DistanceCmp, DistanceValue, Embedding};
+
+// the name includes p so trees built for one exponent do not attach to
+// providers using another
+#[derive(Debug, Clone, Copy)]
+pub struct MinkowskiDistance {
+    p: f64,
+    name: DistanceName,
+}
+
+impl MinkowskiDistance {
+    pub fn new(p: f64) -> Self {
+        // below 1 the triangle inequality no longer holds
+        let p = if p >= 1.0 { p } else { 1.0 };
+        MinkowskiDistance {
+            p,
+            name: DistanceName::new(format_args!("minkowski-{p:?}")),
+        }
+    }
+
+    pub fn p(&self) -> f64 {
+        self.p
+    }
+
+    fn sum<I>(&self, diffs: I) -> DistanceCmp
+    where
+        I: Iterator<Item = f64>,
+    {
+        let res: f64 = if self.p == 1.0 {
+            diffs.map(f64::abs).sum()
+        } else if self.p == 2.0 {
+            diffs.map(|diff| diff * diff).sum()
+        } else {
+            diffs.map(|diff| diff.abs().powf(self.p)).sum()
+        };
+        DistanceCmp::of(res as DistanceValue)
+    }
+
+    fn root(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        if self.p == 1.0 {
+            dist_cmp.to()
+        } else {
+            dist_cmp.to().powf(1.0 / self.p as DistanceValue)
+        }
+    }
+
+    fn power(&self, value: DistanceValue) -> DistanceCmp {
+        if self.p == 1.0 {
+            DistanceCmp::of(value)
+        } else {
+            DistanceCmp::of(value.powf(self.p as DistanceValue))
+        }
+    }
+}
+
+impl<'a> Distance<ArrayView1<'a, f64>> for MinkowskiDistance {
+    fn distance_cmp(
+        &self,
+        a: &Embedding<ArrayView1<'a, f64>>,
+        b: &Embedding<ArrayView1<'a, f64>>,
+    ) -> DistanceCmp {
+        self.sum(
+            a.embed
+                .iter()
+                .zip(b.embed.iter())
+                .map(|(&cur_a, &cur_b)| cur_a - cur_b),
+        )
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        self.root(dist_cmp)
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        self.root(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        self.power(value)
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+}
+
+impl Distance<&Vec<f64>> for MinkowskiDistance {
+    fn distance_cmp(&self, a: &Embedding<&Vec<f64>>, b: &Embedding<&Vec<f64>>) -> DistanceCmp {
+        self.sum(
+            a.embed
+                .iter()
+                .zip(b.embed.iter())
+                .map(|(&cur_a, &cur_b)| cur_a - cur_b),
+        )
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        self.root(dist_cmp)
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        self.root(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        self.power(value)
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+}
@@ -0,0 +1,66 @@
+use ndarray::{concatenate, s, Array1, Array2, ArrayView1, ArrayView2, Axis};
+
+use crate::DistanceValue;
+
+// maximum inner product search reduced to L2 search: every row gets an extra
+// dimension sqrt(M^2 - |x|^2) and queries get a zero so that
+// |x' - q'|^2 = M^2 + |q|^2 - 2 x.q and the nearest neighbor is the one with
+// the largest dot product. the usual L2 tree bounds stay valid
+#[derive(Debug, Clone, Copy)]
+pub struct MipsTransform {
+    max_norm: f64,
+}
+
+impl MipsTransform {
+    pub fn fit(arr: ArrayView2<f64>) -> Self {
+        let max_norm = arr
+            .rows()
+            .into_iter()
+            .map(|row| row.dot(&row).sqrt())
+            .fold(0.0, f64::max);
+        MipsTransform { max_norm }
+    }
+
+    pub fn max_norm(&self) -> f64 {
+        self.max_norm
+    }
+
+    fn extra(&self, row: ArrayView1<f64>) -> f64 {
+        // rows at the maximum can round to slightly negative values
+        (self.max_norm * self.max_norm - row.dot(&row))
+            .max(0.0)
+            .sqrt()
+    }
+
+    pub fn augment(&self, arr: ArrayView2<f64>) -> Array2<f64> {
+        let (rows, cols) = arr.dim();
+        let mut res = Array2::zeros((rows, cols + 1));
+        res.slice_mut(s![.., ..cols]).assign(&arr);
+        res.column_mut(cols)
+            .iter_mut()
+            .zip(arr.rows())
+            .for_each(|(extra, row)| *extra = self.extra(row));
+        res
+    }
+
+    pub fn augment_query(&self, query: ArrayView1<f64>) -> Array1<f64> {
+        concatenate(Axis(0), &[query, ArrayView1::from(&[0.0])]).unwrap()
+    }
+
+    // recovers the dot product from an L2 distance in the augmented space
+    pub fn to_dot(&self, dist: DistanceValue, query: ArrayView1<f64>) -> DistanceValue {
+        let norms = (self.max_norm * self.max_norm + query.dot(&query)) as DistanceValue;
+        (norms - dist * dist) / 2.0
+    }
+
+    pub fn to_dots(
+        &self,
+        neighbors: &[(usize, DistanceValue)],
+        query: ArrayView1<f64>,
+    ) -> Vec<(usize, DistanceValue)> {
+        neighbors
+            .iter()
+            .map(|&(ix, dist)| (ix, self.to_dot(dist, query)))
+            .collect()
+    }
+}
@@ -0,0 +1,54 @@
+use std::{fmt, io::Write};
+
+use blake2::Blake2s256;
+use digest::Digest;
+
+const NAME_CAPACITY: usize = 40;
+
+// distance names that depend on parameters but still need to be Copy
+#[derive(Debug, Clone, Copy)]
+pub struct DistanceName {
+    buf: [u8; NAME_CAPACITY],
+    len: usize,
+}
+
+impl DistanceName {
+    pub fn new(args: fmt::Arguments) -> Self {
+        let mut buf = [0; NAME_CAPACITY];
+        let mut cursor = &mut buf[..];
+        // overlong names are cut off at the capacity
+        let _ = cursor.write_fmt(args);
+        let len = NAME_CAPACITY - cursor.len();
+        DistanceName { buf, len }
+    }
+
+    // parameters too large to spell out are identified by a short hash
+    pub fn hashed<I>(prefix: &str, values: I) -> Self
+    where
+        I: IntoIterator<Item = f64>,
+    {
+        Self::digest(prefix, |hasher| {
+            values
+                .into_iter()
+                .for_each(|v| hasher.update(v.to_be_bytes()))
+        })
+    }
+
+    pub fn digest<F>(prefix: &str, feed: F) -> Self
+    where
+        F: FnOnce(&mut Blake2s256),
+    {
+        let mut hasher = Blake2s256::new();
+        feed(&mut hasher);
+        let digest = hasher.finalize();
+        let hash: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+        Self::new(format_args!("{prefix}-{hash}"))
+    }
+
+    pub fn as_str(&self) -> &str {
+        match std::str::from_utf8(&self.buf[..self.len]) {
+            Ok(name) => name,
+            Err(err) => std::str::from_utf8(&self.buf[..err.valid_up_to()]).unwrap(),
+        }
+    }
+}
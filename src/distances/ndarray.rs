@@ -1,29 +1,61 @@
+use std::{
+    collections::BinaryHeap,
+    sync::{Arc, OnceLock},
+};
+
 use digest::Digest;
-use ndarray::{Array1, ArrayView1, ArrayView2};
+use ndarray::{s, Array2, ArrayView1, ArrayView2, Axis, CowArray};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
-use crate::{info::Info, Distance, DistanceCmp, Embedding, EmbeddingProvider, NearestNeighbors};
+use crate::{
+    distances::{
+        braycurtis::BRAY_CURTIS_DISTANCE, canberra_term, chord_cmp, chord_metric,
+        dynamic::DynDistance, name::DistanceName, scalar::Scalar, sqrt_metric, squared_cmp,
+    },
+    info::Info,
+    DimensionMismatch, Distance, DistanceCmp, DistanceValue, DotBatch, Embedding,
+    EmbeddingProvider, InvalidEmbeddingError, NearestNeighbors,
+};
 
+// exp(-dot) is not a metric so trees visit every node, use
+// mips::MipsTransform with L2 for maximum inner product search that prunes
 #[derive(Debug, Clone, Copy)]
 pub struct NdDotDistance {}
 
 pub const ND_DOT_DISTANCE: NdDotDistance = NdDotDistance {};
 
-impl<'a> Distance<ArrayView1<'a, f64>> for NdDotDistance {
+impl<'a, F> Distance<ArrayView1<'a, F>> for NdDotDistance
+where
+    F: Scalar,
+{
     fn distance_cmp(
         &self,
-        a: &Embedding<ArrayView1<'a, f64>>,
-        b: &Embedding<ArrayView1<'a, f64>>,
+        a: &Embedding<ArrayView1<'a, F>>,
+        b: &Embedding<ArrayView1<'a, F>>,
     ) -> DistanceCmp {
-        DistanceCmp::of((-a.embed.dot(&b.embed)).exp())
+        DistanceCmp::of((-a.embed.dot(&b.embed).to_f64()).exp() as DistanceValue)
     }
 
-    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> f64 {
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
         dist_cmp.to()
     }
 
     fn name(&self) -> &str {
         "dot"
     }
+
+    fn is_metric(&self) -> bool {
+        false
+    }
+
+    fn dot_batch(&self) -> DotBatch {
+        DotBatch::Dot
+    }
+
+    fn dot_distance_cmp(&self, dot: f64, _norm_a: f64, _norm_b: f64) -> DistanceCmp {
+        DistanceCmp::of((-dot).exp() as DistanceValue)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -31,48 +63,282 @@ pub struct NdL2Distance {}
 
 pub const ND_L2_DISTANCE: NdL2Distance = NdL2Distance {};
 
-impl<'a> Distance<ArrayView1<'a, f64>> for NdL2Distance {
+impl<'a, F> Distance<ArrayView1<'a, F>> for NdL2Distance
+where
+    F: Scalar,
+{
     fn distance_cmp(
         &self,
-        a: &Embedding<ArrayView1<'a, f64>>,
-        b: &Embedding<ArrayView1<'a, f64>>,
+        a: &Embedding<ArrayView1<'a, F>>,
+        b: &Embedding<ArrayView1<'a, F>>,
     ) -> DistanceCmp {
         let diff = &a.embed - &b.embed.view();
-        let res = (&diff * &diff).sum();
-        DistanceCmp::of(res)
+        let res = (&diff * &diff).sum().to_f64();
+        DistanceCmp::of(res as DistanceValue)
     }
 
-    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> f64 {
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
         dist_cmp.to().sqrt()
     }
 
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        sqrt_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        squared_cmp(value)
+    }
+
     fn name(&self) -> &str {
         "l2"
     }
+
+    fn dot_batch(&self) -> DotBatch {
+        DotBatch::DotAndNorms
+    }
+
+    fn dot_distance_cmp(&self, dot: f64, norm_a: f64, norm_b: f64) -> DistanceCmp {
+        // the expansion can dip below zero for near duplicates
+        let res = (norm_a + norm_b - 2.0 * dot).max(0.0);
+        DistanceCmp::of(res as DistanceValue)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NdL1Distance {}
+
+pub const ND_L1_DISTANCE: NdL1Distance = NdL1Distance {};
+
+impl<'a, F> Distance<ArrayView1<'a, F>> for NdL1Distance
+where
+    F: Scalar,
+{
+    fn distance_cmp(
+        &self,
+        a: &Embedding<ArrayView1<'a, F>>,
+        b: &Embedding<ArrayView1<'a, F>>,
+    ) -> DistanceCmp {
+        let res: f64 = a
+            .embed
+            .iter()
+            .zip(b.embed.iter())
+            .map(|(&cur_a, &cur_b)| (cur_a.to_f64() - cur_b.to_f64()).abs())
+            .sum();
+        DistanceCmp::of(res as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "l1"
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NdCanberraDistance {}
+
+pub const ND_CANBERRA_DISTANCE: NdCanberraDistance = NdCanberraDistance {};
+
+impl<'a, F> Distance<ArrayView1<'a, F>> for NdCanberraDistance
+where
+    F: Scalar,
+{
+    fn distance_cmp(
+        &self,
+        a: &Embedding<ArrayView1<'a, F>>,
+        b: &Embedding<ArrayView1<'a, F>>,
+    ) -> DistanceCmp {
+        let res: f64 = a
+            .embed
+            .iter()
+            .zip(b.embed.iter())
+            .map(|(&cur_a, &cur_b)| canberra_term(cur_a.to_f64(), cur_b.to_f64()))
+            .sum();
+        DistanceCmp::of(res as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "canberra"
+    }
+}
+
+fn cosine_cmp(dot: f64, norm_a: f64, norm_b: f64) -> DistanceCmp {
+    let denom = (norm_a * norm_b).sqrt();
+    let sim = if denom > 0.0 { dot / denom } else { 0.0 };
+    DistanceCmp::of((1.0 - sim).max(0.0) as DistanceValue)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NdCosineDistance {}
+
+pub const ND_COSINE_DISTANCE: NdCosineDistance = NdCosineDistance {};
+
+impl<'a, F> Distance<ArrayView1<'a, F>> for NdCosineDistance
+where
+    F: Scalar,
+{
+    fn distance_cmp(
+        &self,
+        a: &Embedding<ArrayView1<'a, F>>,
+        b: &Embedding<ArrayView1<'a, F>>,
+    ) -> DistanceCmp {
+        cosine_cmp(
+            a.embed.dot(&b.embed).to_f64(),
+            a.embed.dot(&a.embed).to_f64(),
+            b.embed.dot(&b.embed).to_f64(),
+        )
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "cosine"
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        chord_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        chord_cmp(value)
+    }
+
+    fn dot_batch(&self) -> DotBatch {
+        DotBatch::DotAndNorms
+    }
+
+    fn dot_distance_cmp(&self, dot: f64, norm_a: f64, norm_b: f64) -> DistanceCmp {
+        cosine_cmp(dot, norm_a, norm_b)
+    }
+}
+
+// the name carries a hash of the matrix so trees only attach to providers
+// using the same covariance
+#[derive(Debug, Clone, Copy)]
+pub struct NdMahalanobisDistance<'m> {
+    inv_cov: ArrayView2<'m, f64>,
+    name: DistanceName,
+}
+
+impl<'m> NdMahalanobisDistance<'m> {
+    pub fn new(inv_cov: ArrayView2<'m, f64>) -> Result<Self, DimensionMismatch> {
+        DimensionMismatch::check(inv_cov.shape()[0], inv_cov.shape()[1])?;
+        Ok(NdMahalanobisDistance {
+            inv_cov,
+            name: DistanceName::hashed("mahalanobis", inv_cov.iter().copied()),
+        })
+    }
+
+    pub fn dim(&self) -> usize {
+        self.inv_cov.shape()[0]
+    }
+}
+
+impl<'a, 'm> Distance<ArrayView1<'a, f64>> for NdMahalanobisDistance<'m> {
+    fn distance_cmp(
+        &self,
+        a: &Embedding<ArrayView1<'a, f64>>,
+        b: &Embedding<ArrayView1<'a, f64>>,
+    ) -> DistanceCmp {
+        let diff = &a.embed - &b.embed;
+        let res = diff.dot(&self.inv_cov.dot(&diff));
+        // rounding can push a semi-definite form slightly below zero
+        DistanceCmp::of(res.max(0.0) as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to().sqrt()
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        sqrt_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        squared_cmp(value)
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+}
+
+pub fn nd_distance<'a, F>(name: &str) -> Option<DynDistance<'a, ArrayView1<'a, F>>>
+where
+    F: Scalar,
+{
+    match name {
+        "braycurtis" => Some(DynDistance::new(&BRAY_CURTIS_DISTANCE)),
+        "canberra" => Some(DynDistance::new(&ND_CANBERRA_DISTANCE)),
+        "cosine" => Some(DynDistance::new(&ND_COSINE_DISTANCE)),
+        "dot" => Some(DynDistance::new(&ND_DOT_DISTANCE)),
+        "l1" => Some(DynDistance::new(&ND_L1_DISTANCE)),
+        "l2" => Some(DynDistance::new(&ND_L2_DISTANCE)),
+        _ => None,
+    }
 }
 
-pub struct NdProvider<'a, D>
+pub struct NdProvider<'a, D, F = f64>
 where
-    D: Distance<ArrayView1<'a, f64>>,
+    D: Distance<ArrayView1<'a, F>>,
+    F: Scalar,
 {
-    arr: ArrayView2<'a, f64>,
+    arr: ArrayView2<'a, F>,
     distance: D,
+    generation: u64,
+    norms: Option<&'a [f64]>,
 }
 
-impl<'a, D> NdProvider<'a, D>
+impl<'a, D, F> NdProvider<'a, D, F>
 where
-    D: Distance<ArrayView1<'a, f64>>,
+    D: Distance<ArrayView1<'a, F>>,
+    F: Scalar,
 {
-    pub fn new(arr: ArrayView2<'a, f64>, distance: D) -> Self {
-        NdProvider { arr, distance }
+    pub fn new(arr: ArrayView2<'a, F>, distance: D) -> Self {
+        Self::with_generation(arr, distance, 0)
+    }
+
+    pub fn with_generation(arr: ArrayView2<'a, F>, distance: D, generation: u64) -> Self {
+        NdProvider {
+            arr,
+            distance,
+            generation,
+            norms: None,
+        }
+    }
+
+    // squared row norms from row_norms so batches do not recompute them
+    pub fn with_norms(mut self, norms: &'a [f64]) -> Self {
+        debug_assert_eq!(norms.len(), self.arr.shape()[0]);
+        self.norms = Some(norms);
+        self
+    }
+
+    pub fn try_new(arr: ArrayView2<'a, F>, distance: D) -> Result<Self, InvalidEmbeddingError> {
+        let provider = Self::new(arr, distance);
+        (0..provider.arr.shape()[0]).try_for_each(|ix| provider.check_row(ix))?;
+        Ok(provider)
+    }
+
+    fn check_row(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        InvalidEmbeddingError::check(index, self.arr.row(index).iter().map(|v| v.to_f64()))
     }
 }
 
-impl<'a, D> EmbeddingProvider<'a, D, ArrayView1<'a, f64>> for NdProvider<'a, D>
+impl<'a, D, F> EmbeddingProvider<'a, D, ArrayView1<'a, F>> for NdProvider<'a, D, F>
 where
-    D: Distance<ArrayView1<'a, f64>> + Copy,
+    D: Distance<ArrayView1<'a, F>> + Copy,
+    F: Scalar,
 {
-    fn get_embed(&'a self, index: usize) -> ArrayView1<'a, f64> {
+    fn get_embed(&'a self, index: usize) -> ArrayView1<'a, F> {
         self.arr.row(index)
     }
 
@@ -84,37 +350,333 @@ where
         self.distance
     }
 
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
     fn hash_embed<H>(&self, index: usize, hasher: &mut H)
     where
         H: Digest,
     {
-        self.arr
-            .row(index)
+        self.arr.row(index).iter().for_each(|v| v.hash_into(hasher));
+    }
+
+    fn check_embed(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        self.check_row(index)
+    }
+
+    fn dim(&self) -> Option<usize> {
+        Some(self.arr.shape()[1])
+    }
+
+    fn check_dim(&self, embed: &Embedding<ArrayView1<'a, F>>) -> Result<(), DimensionMismatch> {
+        DimensionMismatch::check(self.arr.shape()[1], embed.embed.len())
+    }
+
+    fn dist_batch(
+        &'a self,
+        query: &Embedding<ArrayView1<'a, F>>,
+        indices: &[usize],
+    ) -> Vec<DistanceCmp> {
+        let distance = self.distance;
+        if distance.dot_batch() == DotBatch::Unsupported {
+            return indices
+                .iter()
+                .map(|&ix| distance.distance_cmp(query, &self.get(ix)))
+                .collect();
+        }
+        dot_batch(self.arr, &distance, query.embed, indices, self.norms)
+    }
+}
+
+pub fn row_norms<F>(arr: ArrayView2<F>) -> Vec<f64>
+where
+    F: Scalar,
+{
+    arr.rows()
+        .into_iter()
+        .map(|row| row.dot(&row).to_f64())
+        .collect()
+}
+
+// children that are stored next to each other after a reorder are used as a
+// view, other rows are gathered so all products are one matrix-vector product
+fn dot_batch<D, F, T>(
+    arr: ArrayView2<F>,
+    distance: &D,
+    query: ArrayView1<F>,
+    indices: &[usize],
+    norms: Option<&[f64]>,
+) -> Vec<DistanceCmp>
+where
+    D: Distance<T>,
+    F: Scalar,
+{
+    let Some(&first) = indices.first() else {
+        return Vec::new();
+    };
+    let is_contiguous = indices.windows(2).all(|pair| pair[1] == pair[0] + 1);
+    let rows = if is_contiguous {
+        CowArray::from(arr.slice(s![first..first + indices.len(), ..]))
+    } else {
+        CowArray::from(arr.select(Axis(0), indices))
+    };
+    let dots = rows.dot(&query);
+    if distance.dot_batch() == DotBatch::Dot {
+        return dots
             .iter()
-            .for_each(|v| hasher.update(v.to_be_bytes()));
+            .map(|dot| distance.dot_distance_cmp(dot.to_f64(), 0.0, 0.0))
+            .collect();
     }
+    let query_norm = query.dot(&query).to_f64();
+    dots.iter()
+        .zip(indices)
+        .zip(rows.rows())
+        .map(|((dot, &ix), row)| {
+            let norm = match norms {
+                Some(norms) => norms[ix],
+                None => row.dot(&row).to_f64(),
+            };
+            distance.dot_distance_cmp(dot.to_f64(), query_norm, norm)
+        })
+        .collect()
+}
+
+const BRUTE_FORCE_CHUNK: usize = 4096;
+
+fn chunk_closest<'r, D, F>(
+    arr: ArrayView2<'r, F>,
+    distance: &D,
+    other: &Embedding<ArrayView1<'r, F>>,
+    chunk: usize,
+    count: usize,
+) -> Vec<(DistanceCmp, usize)>
+where
+    D: Distance<ArrayView1<'r, F>>,
+    F: Scalar,
+{
+    let start = chunk * BRUTE_FORCE_CHUNK;
+    let end = (start + BRUTE_FORCE_CHUNK).min(arr.shape()[0]);
+    let mut heap: BinaryHeap<(DistanceCmp, usize)> = BinaryHeap::with_capacity(count + 1);
+    (start..end).for_each(|ix| {
+        let row = Embedding::wrap(arr.index_axis_move(Axis(0), ix), ix);
+        let dist = distance.distance_cmp(&row, other);
+        if heap.len() < count {
+            heap.push((dist, ix));
+        } else if heap.peek().is_some_and(|&(worst, _)| dist < worst) {
+            heap.pop();
+            heap.push((dist, ix));
+        }
+    });
+    heap.into_vec()
+}
+
+fn brute_force_closest<'r, D, F>(
+    arr: ArrayView2<'r, F>,
+    distance: &D,
+    other: &Embedding<ArrayView1<'r, F>>,
+    count: usize,
+) -> Vec<(usize, DistanceValue)>
+where
+    D: Distance<ArrayView1<'r, F>> + Sync,
+    F: Scalar,
+{
+    let chunks = arr.shape()[0].div_ceil(BRUTE_FORCE_CHUNK);
+    #[cfg(feature = "parallel")]
+    let mut dists: Vec<(DistanceCmp, usize)> = (0..chunks)
+        .into_par_iter()
+        .flat_map_iter(|chunk| chunk_closest(arr, distance, other, chunk, count))
+        .collect();
+    #[cfg(not(feature = "parallel"))]
+    let mut dists: Vec<(DistanceCmp, usize)> = (0..chunks)
+        .flat_map(|chunk| chunk_closest(arr, distance, other, chunk, count))
+        .collect();
+    dists.sort_unstable();
+    dists
+        .iter()
+        .take(count)
+        .map(|(dist, ix)| (*ix, distance.finalize_distance(dist)))
+        .collect()
 }
 
-impl<'a> NearestNeighbors<'a, ArrayView1<'a, f64>> for NdProvider<'a, NdDotDistance> {
+impl<'a, D, F> NearestNeighbors<'a, ArrayView1<'a, F>> for NdProvider<'a, D, F>
+where
+    D: Distance<ArrayView1<'a, F>> + Copy + Sync,
+    F: Scalar,
+{
+    fn check_query(&self, other: &Embedding<ArrayView1<'a, F>>) -> Result<(), DimensionMismatch> {
+        self.check_dim(other)
+    }
+
     fn get_closest<I>(
         &self,
-        other: &Embedding<ArrayView1<'a, f64>>,
+        other: &Embedding<ArrayView1<'a, F>>,
         count: usize,
         _info: &mut I,
-    ) -> Vec<(usize, f64)>
+    ) -> Vec<(usize, DistanceValue)>
     where
         I: Info,
     {
-        let dists: Array1<DistanceCmp> = self
-            .arr
-            .dot(&other.embed)
-            .map(|v| DistanceCmp::of((-v).exp()));
-        let mut indices: Vec<usize> = (0..dists.len()).collect();
-        indices.sort_unstable_by_key(|&ix| dists[ix]);
-        indices
-            .iter()
-            .take(count)
-            .map(|&ix| (ix, self.distance.finalize_distance(&dists[ix])))
-            .collect()
+        brute_force_closest(self.arr, &self.distance, other, count)
+    }
+}
+
+// owns its rows so it can be stored next to its trees, cloning shares the
+// rows instead of copying them
+#[derive(Debug, Clone)]
+pub struct OwnedNdProvider<D, F = f64> {
+    arr: Arc<Array2<F>>,
+    distance: D,
+    generation: u64,
+    // computed once on the first batch that needs them and shared by clones
+    norms: Arc<OnceLock<Vec<f64>>>,
+}
+
+impl<D, F> OwnedNdProvider<D, F>
+where
+    F: Scalar,
+{
+    pub fn new(arr: Array2<F>, distance: D) -> Self {
+        Self::from_shared(Arc::new(arr), distance)
+    }
+
+    pub fn from_shared(arr: Arc<Array2<F>>, distance: D) -> Self {
+        Self::with_generation(arr, distance, 0)
+    }
+
+    pub fn with_generation(arr: Arc<Array2<F>>, distance: D, generation: u64) -> Self {
+        let norms = Arc::new(OnceLock::new());
+        OwnedNdProvider {
+            arr,
+            distance,
+            generation,
+            norms,
+        }
+    }
+
+    pub fn try_new(arr: Array2<F>, distance: D) -> Result<Self, InvalidEmbeddingError> {
+        let provider = Self::new(arr, distance);
+        (0..provider.arr.shape()[0]).try_for_each(|ix| provider.check_row(ix))?;
+        Ok(provider)
+    }
+
+    fn check_row(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        InvalidEmbeddingError::check(index, self.arr.row(index).iter().map(|v| v.to_f64()))
+    }
+
+    pub fn shared(&self) -> &Arc<Array2<F>> {
+        &self.arr
+    }
+
+    pub fn view<'a>(&'a self) -> NdProvider<'a, D, F>
+    where
+        D: Distance<ArrayView1<'a, F>> + Copy,
+    {
+        let view = NdProvider::with_generation(self.arr.view(), self.distance, self.generation);
+        match self.norms() {
+            Some(norms) => view.with_norms(norms),
+            None => view,
+        }
+    }
+
+    fn norms<'a>(&'a self) -> Option<&'a [f64]>
+    where
+        D: Distance<ArrayView1<'a, F>>,
+    {
+        (self.distance.dot_batch() == DotBatch::DotAndNorms).then(|| {
+            self.norms
+                .get_or_init(|| row_norms(self.arr.view()))
+                .as_slice()
+        })
+    }
+}
+
+impl<'a, D, F> EmbeddingProvider<'a, D, ArrayView1<'a, F>> for OwnedNdProvider<D, F>
+where
+    D: Distance<ArrayView1<'a, F>> + Copy,
+    F: Scalar,
+{
+    fn get_embed(&'a self, index: usize) -> ArrayView1<'a, F> {
+        self.arr.row(index)
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        0..self.arr.shape()[0]
+    }
+
+    fn distance(&self) -> D {
+        self.distance
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn hash_embed<H>(&self, index: usize, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        self.arr.row(index).iter().for_each(|v| v.hash_into(hasher));
+    }
+
+    fn check_embed(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        self.check_row(index)
+    }
+
+    fn dim(&self) -> Option<usize> {
+        Some(self.arr.shape()[1])
+    }
+
+    fn check_dim(&self, embed: &Embedding<ArrayView1<'a, F>>) -> Result<(), DimensionMismatch> {
+        DimensionMismatch::check(self.arr.shape()[1], embed.embed.len())
+    }
+
+    fn dist_batch(
+        &'a self,
+        query: &Embedding<ArrayView1<'a, F>>,
+        indices: &[usize],
+    ) -> Vec<DistanceCmp> {
+        let distance = self.distance;
+        if distance.dot_batch() == DotBatch::Unsupported {
+            return indices
+                .iter()
+                .map(|&ix| distance.distance_cmp(query, &self.get(ix)))
+                .collect();
+        }
+        dot_batch(
+            self.arr.view(),
+            &distance,
+            query.embed,
+            indices,
+            self.norms(),
+        )
+    }
+}
+
+impl<'a, D, F> NearestNeighbors<'a, ArrayView1<'a, F>> for OwnedNdProvider<D, F>
+where
+    D: for<'r> Distance<ArrayView1<'r, F>> + Copy + Sync,
+    F: Scalar + 'a,
+{
+    fn check_query(&self, other: &Embedding<ArrayView1<'a, F>>) -> Result<(), DimensionMismatch> {
+        DimensionMismatch::check(self.arr.shape()[1], other.embed.len())
+    }
+
+    fn get_closest<I>(
+        &self,
+        other: &Embedding<ArrayView1<'a, F>>,
+        count: usize,
+        _info: &mut I,
+    ) -> Vec<(usize, DistanceValue)>
+    where
+        I: Info,
+    {
+        // views are invariant so the query is reborrowed to match the rows
+        let other = Embedding {
+            embed: other.embed.view(),
+            index: other.index,
+            generation: other.generation,
+        };
+        brute_force_closest(self.arr.view(), &self.distance, &other, count)
     }
 }
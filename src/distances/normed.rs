@@ -0,0 +1,355 @@
+use std::marker::PhantomData;
+
+use digest::Digest;
+use ndarray::{Array1, Array2, ArrayView1, ArrayView2, Axis};
+
+use crate::{
+    distances::{chord_cmp, chord_metric, dynamic::DynDistance, scalar::Scalar},
+    DimensionMismatch, Distance, DistanceCmp, DistanceValue, Embedding, EmbeddingProvider,
+    InvalidEmbeddingError,
+};
+
+pub trait InnerProduct {
+    fn inner_product(&self, other: &Self) -> f64;
+
+    fn norm(&self) -> f64 {
+        self.inner_product(self).sqrt()
+    }
+}
+
+impl<F> InnerProduct for ArrayView1<'_, F>
+where
+    F: Scalar,
+{
+    fn inner_product(&self, other: &Self) -> f64 {
+        self.dot(other).to_f64()
+    }
+}
+
+impl<F> InnerProduct for &Vec<F>
+where
+    F: Scalar,
+{
+    fn inner_product(&self, other: &Self) -> f64 {
+        F::dot_slice(self, other)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Normed<T> {
+    pub embed: T,
+    pub norm: f64,
+}
+
+pub type NormedEmbed<'a> = Normed<ArrayView1<'a, f64>>;
+
+impl<T> Normed<T>
+where
+    T: InnerProduct,
+{
+    pub fn new(embed: T) -> Self {
+        let norm = embed.norm();
+        Normed { embed, norm }
+    }
+
+    fn dot(&self, other: &Normed<T>) -> f64 {
+        self.embed.inner_product(&other.embed)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NormDotDistance {}
+
+pub const NORM_DOT_DISTANCE: NormDotDistance = NormDotDistance {};
+
+impl<T> Distance<Normed<T>> for NormDotDistance
+where
+    T: InnerProduct,
+{
+    fn distance_cmp(&self, a: &Embedding<Normed<T>>, b: &Embedding<Normed<T>>) -> DistanceCmp {
+        DistanceCmp::of((-a.embed.dot(&b.embed)).exp() as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "dot"
+    }
+
+    fn is_metric(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NormCosineDistance {}
+
+pub const NORM_COSINE_DISTANCE: NormCosineDistance = NormCosineDistance {};
+
+impl<T> Distance<Normed<T>> for NormCosineDistance
+where
+    T: InnerProduct,
+{
+    fn distance_cmp(&self, a: &Embedding<Normed<T>>, b: &Embedding<Normed<T>>) -> DistanceCmp {
+        let denom = a.embed.norm * b.embed.norm;
+        let sim = if denom > 0.0 {
+            a.embed.dot(&b.embed) / denom
+        } else {
+            0.0
+        };
+        DistanceCmp::of((1.0 - sim).max(0.0) as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "cosine"
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        chord_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        chord_cmp(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NormAngularDistance {}
+
+pub const NORM_ANGULAR_DISTANCE: NormAngularDistance = NormAngularDistance {};
+
+impl<T> Distance<Normed<T>> for NormAngularDistance
+where
+    T: InnerProduct,
+{
+    fn distance_cmp(&self, a: &Embedding<Normed<T>>, b: &Embedding<Normed<T>>) -> DistanceCmp {
+        let denom = a.embed.norm * b.embed.norm;
+        let sim = if denom > 0.0 {
+            a.embed.dot(&b.embed) / denom
+        } else {
+            0.0
+        };
+        // the angle is a metric itself so no mapping is needed for pruning
+        let angle = sim.clamp(-1.0, 1.0).acos() / std::f64::consts::PI;
+        DistanceCmp::of(angle as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "angular"
+    }
+}
+
+pub fn norm_distance<'a, T>(name: &str) -> Option<DynDistance<'a, Normed<T>>>
+where
+    T: InnerProduct,
+{
+    match name {
+        "angular" => Some(DynDistance::new(&NORM_ANGULAR_DISTANCE)),
+        "cosine" => Some(DynDistance::new(&NORM_COSINE_DISTANCE)),
+        "dot" => Some(DynDistance::new(&NORM_DOT_DISTANCE)),
+        _ => None,
+    }
+}
+
+pub struct NdNormProvider<'a, D>
+where
+    D: Distance<NormedEmbed<'a>>,
+{
+    arr: ArrayView2<'a, f64>,
+    norms: Array1<f64>,
+    normalized: Option<Array2<f64>>,
+    distance: D,
+}
+
+impl<'a, D> NdNormProvider<'a, D>
+where
+    D: Distance<NormedEmbed<'a>>,
+{
+    fn compute_norms(arr: &ArrayView2<'a, f64>) -> Array1<f64> {
+        arr.rows()
+            .into_iter()
+            .map(|row| row.dot(&row).sqrt())
+            .collect()
+    }
+
+    pub fn new(arr: ArrayView2<'a, f64>, distance: D) -> Self {
+        NdNormProvider {
+            norms: Self::compute_norms(&arr),
+            arr,
+            normalized: None,
+            distance,
+        }
+    }
+
+    pub fn with_normalized(arr: ArrayView2<'a, f64>, distance: D) -> Self {
+        let norms = Self::compute_norms(&arr);
+        let mut normalized = arr.to_owned();
+        normalized
+            .axis_iter_mut(Axis(0))
+            .zip(norms.iter())
+            .filter(|(_, &norm)| norm > 0.0)
+            .for_each(|(mut row, &norm)| row /= norm);
+        NdNormProvider {
+            arr,
+            norms,
+            normalized: Some(normalized),
+            distance,
+        }
+    }
+
+    pub fn norm(&self, index: usize) -> f64 {
+        self.norms[index]
+    }
+
+    pub fn norms(&self) -> ArrayView1<'_, f64> {
+        self.norms.view()
+    }
+
+    pub fn is_normalized(&self) -> bool {
+        self.normalized.is_some()
+    }
+}
+
+impl<'a, D> EmbeddingProvider<'a, D, NormedEmbed<'a>> for NdNormProvider<'a, D>
+where
+    D: Distance<NormedEmbed<'a>> + Copy,
+{
+    fn get_embed(&'a self, index: usize) -> NormedEmbed<'a> {
+        match &self.normalized {
+            Some(normalized) => NormedEmbed {
+                embed: normalized.row(index),
+                norm: if self.norms[index] > 0.0 { 1.0 } else { 0.0 },
+            },
+            None => NormedEmbed {
+                embed: self.arr.row(index),
+                norm: self.norms[index],
+            },
+        }
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        0..self.arr.shape()[0]
+    }
+
+    fn distance(&self) -> D {
+        self.distance
+    }
+
+    fn hash_embed<H>(&self, index: usize, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        self.arr
+            .row(index)
+            .iter()
+            .for_each(|v| hasher.update(v.to_be_bytes()));
+    }
+
+    fn check_embed(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        InvalidEmbeddingError::check(index, self.arr.row(index).iter().copied())
+    }
+
+    fn dim(&self) -> Option<usize> {
+        Some(self.arr.shape()[1])
+    }
+
+    fn check_dim(&self, embed: &Embedding<NormedEmbed<'a>>) -> Result<(), DimensionMismatch> {
+        DimensionMismatch::check(self.arr.shape()[1], embed.embed.embed.len())
+    }
+}
+
+// caches the norms of any provider whose rows support inner products, the
+// distances above then never recompute norms during a traversal
+pub struct NormalizedProvider<'a, E, DI, D> {
+    inner: &'a E,
+    start: usize,
+    norms: Vec<f64>,
+    distance: D,
+    inner_distance: PhantomData<DI>,
+}
+
+impl<'a, E, DI, D> NormalizedProvider<'a, E, DI, D> {
+    pub fn new<T>(inner: &'a E, distance: D) -> Self
+    where
+        E: EmbeddingProvider<'a, DI, T>,
+        DI: Distance<T> + Copy,
+        T: InnerProduct,
+    {
+        let all = inner.all();
+        NormalizedProvider {
+            inner,
+            start: all.start,
+            norms: all.map(|ix| inner.get_embed(ix).norm()).collect(),
+            distance,
+            inner_distance: PhantomData,
+        }
+    }
+
+    pub fn norm(&self, index: usize) -> f64 {
+        self.norms[index - self.start]
+    }
+
+    pub fn inner(&self) -> &'a E {
+        self.inner
+    }
+}
+
+impl<'a, E, DI, D, T> EmbeddingProvider<'a, D, Normed<T>> for NormalizedProvider<'a, E, DI, D>
+where
+    E: EmbeddingProvider<'a, DI, T>,
+    DI: Distance<T> + Copy,
+    D: Distance<Normed<T>> + Copy,
+    T: Clone,
+{
+    fn get_embed(&'a self, index: usize) -> Normed<T> {
+        Normed {
+            embed: self.inner.get_embed(index),
+            norm: self.norm(index),
+        }
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        self.inner.all()
+    }
+
+    fn distance(&self) -> D {
+        self.distance
+    }
+
+    fn generation(&self) -> u64 {
+        self.inner.generation()
+    }
+
+    fn hash_embed<H>(&self, index: usize, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        self.inner.hash_embed(index, hasher)
+    }
+
+    fn check_embed(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        self.inner.check_embed(index)
+    }
+
+    fn dim(&self) -> Option<usize> {
+        self.inner.dim()
+    }
+
+    fn check_dim(&self, embed: &Embedding<Normed<T>>) -> Result<(), DimensionMismatch> {
+        self.inner.check_dim(&Embedding {
+            embed: embed.embed.embed.clone(),
+            index: embed.index,
+            generation: embed.generation,
+        })
+    }
+}
@@ -0,0 +1,343 @@
+use digest::Digest;
+use polars::prelude::{
+    DataFrame, Float32Type, Float64Type, PolarsDataType, PolarsError, PolarsNumericType,
+};
+
+use crate::{
+    distances::{
+        chord_cmp, chord_metric, dynamic::DynDistance, normed::InnerProduct, scalar::Scalar,
+        sqrt_metric, squared_cmp,
+    },
+    DimensionMismatch, Distance, DistanceCmp, DistanceValue, Embedding, EmbeddingProvider,
+    InvalidEmbeddingError,
+};
+
+pub trait DfScalar: Scalar {
+    type Polars: PolarsNumericType<Native = Self>;
+}
+
+impl DfScalar for f64 {
+    type Polars = Float64Type;
+}
+
+impl DfScalar for f32 {
+    type Polars = Float32Type;
+}
+
+// a row is spread over the columns of the frame, every column is one
+// dimension
+#[derive(Debug, Clone, Copy)]
+pub struct DfRow<'a, F> {
+    pub columns: &'a [&'a [F]],
+    pub row: usize,
+}
+
+impl<F> DfRow<'_, F>
+where
+    F: Scalar,
+{
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = F> + '_ {
+        self.columns.iter().map(|column| column[self.row])
+    }
+
+    pub fn to_vec(&self) -> Vec<F> {
+        self.iter().collect()
+    }
+
+    fn dot(&self, other: &Self) -> f64 {
+        self.iter()
+            .zip(other.iter())
+            .map(|(cur_a, cur_b)| cur_a.to_f64() * cur_b.to_f64())
+            .sum()
+    }
+}
+
+impl<F> InnerProduct for DfRow<'_, F>
+where
+    F: Scalar,
+{
+    fn inner_product(&self, other: &Self) -> f64 {
+        self.dot(other)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DfDotDistance {}
+
+pub const DF_DOT_DISTANCE: DfDotDistance = DfDotDistance {};
+
+impl<'a, F> Distance<DfRow<'a, F>> for DfDotDistance
+where
+    F: Scalar,
+{
+    fn distance_cmp(
+        &self,
+        a: &Embedding<DfRow<'a, F>>,
+        b: &Embedding<DfRow<'a, F>>,
+    ) -> DistanceCmp {
+        DistanceCmp::of((-a.embed.dot(&b.embed)).exp() as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "dot"
+    }
+
+    fn is_metric(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DfL2Distance {}
+
+pub const DF_L2_DISTANCE: DfL2Distance = DfL2Distance {};
+
+impl<'a, F> Distance<DfRow<'a, F>> for DfL2Distance
+where
+    F: Scalar,
+{
+    fn distance_cmp(
+        &self,
+        a: &Embedding<DfRow<'a, F>>,
+        b: &Embedding<DfRow<'a, F>>,
+    ) -> DistanceCmp {
+        let res: f64 = a
+            .embed
+            .iter()
+            .zip(b.embed.iter())
+            .map(|(cur_a, cur_b)| {
+                let diff = cur_a.to_f64() - cur_b.to_f64();
+                diff * diff
+            })
+            .sum();
+        DistanceCmp::of(res as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to().sqrt()
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        sqrt_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        squared_cmp(value)
+    }
+
+    fn name(&self) -> &str {
+        "l2"
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DfL1Distance {}
+
+pub const DF_L1_DISTANCE: DfL1Distance = DfL1Distance {};
+
+impl<'a, F> Distance<DfRow<'a, F>> for DfL1Distance
+where
+    F: Scalar,
+{
+    fn distance_cmp(
+        &self,
+        a: &Embedding<DfRow<'a, F>>,
+        b: &Embedding<DfRow<'a, F>>,
+    ) -> DistanceCmp {
+        let res: f64 = a
+            .embed
+            .iter()
+            .zip(b.embed.iter())
+            .map(|(cur_a, cur_b)| (cur_a.to_f64() - cur_b.to_f64()).abs())
+            .sum();
+        DistanceCmp::of(res as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "l1"
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DfCosineDistance {}
+
+pub const DF_COSINE_DISTANCE: DfCosineDistance = DfCosineDistance {};
+
+impl<'a, F> Distance<DfRow<'a, F>> for DfCosineDistance
+where
+    F: Scalar,
+{
+    fn distance_cmp(
+        &self,
+        a: &Embedding<DfRow<'a, F>>,
+        b: &Embedding<DfRow<'a, F>>,
+    ) -> DistanceCmp {
+        let denom = (a.embed.dot(&a.embed) * b.embed.dot(&b.embed)).sqrt();
+        let sim = if denom > 0.0 {
+            a.embed.dot(&b.embed) / denom
+        } else {
+            0.0
+        };
+        DistanceCmp::of((1.0 - sim).max(0.0) as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "cosine"
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        chord_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        chord_cmp(value)
+    }
+}
+
+pub fn df_distance<'a, F>(name: &str) -> Option<DynDistance<'a, DfRow<'a, F>>>
+where
+    F: Scalar,
+{
+    match name {
+        "cosine" => Some(DynDistance::new(&DF_COSINE_DISTANCE)),
+        "dot" => Some(DynDistance::new(&DF_DOT_DISTANCE)),
+        "l1" => Some(DynDistance::new(&DF_L1_DISTANCE)),
+        "l2" => Some(DynDistance::new(&DF_L2_DISTANCE)),
+        _ => None,
+    }
+}
+
+// rows point into the column buffers of the frame, nothing is copied
+pub struct DfProvider<'a, D, F = f64> {
+    columns: Vec<&'a [F]>,
+    rows: usize,
+    distance: D,
+}
+
+impl<'a, D, F> DfProvider<'a, D, F>
+where
+    F: DfScalar,
+{
+    // an empty selection uses every column, each column must be a single
+    // chunk without nulls so DataFrame::rechunk might be needed first
+    pub fn from_frame(
+        df: &'a DataFrame,
+        columns: &[&str],
+        distance: D,
+    ) -> Result<Self, PolarsError> {
+        let names = if columns.is_empty() {
+            df.get_column_names()
+        } else {
+            columns.to_vec()
+        };
+        let columns = names
+            .into_iter()
+            .map(|name| {
+                let column = df.column(name)?.unpack::<F::Polars>().map_err(|_| {
+                    PolarsError::SchemaMisMatch(
+                        format!("{name} must be {dtype}", dtype = F::Polars::get_dtype()).into(),
+                    )
+                })?;
+                if column.null_count() > 0 {
+                    return Err(PolarsError::ComputeError(
+                        format!("{name} must not contain nulls").into(),
+                    ));
+                }
+                column.cont_slice().map_err(|_| {
+                    PolarsError::ComputeError(
+                        format!("{name} consists of several chunks, rechunk the frame first")
+                            .into(),
+                    )
+                })
+            })
+            .collect::<Result<Vec<&'a [F]>, PolarsError>>()?;
+        Ok(DfProvider {
+            columns,
+            rows: df.height(),
+            distance,
+        })
+    }
+
+    pub fn try_from_frame(
+        df: &'a DataFrame,
+        columns: &[&str],
+        distance: D,
+    ) -> Result<Self, PolarsError> {
+        let provider = Self::from_frame(df, columns, distance)?;
+        (0..provider.rows)
+            .try_for_each(|ix| provider.check_row(ix))
+            .map_err(|err| PolarsError::ComputeError(err.to_string().into()))?;
+        Ok(provider)
+    }
+
+    fn check_row(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        InvalidEmbeddingError::check(
+            index,
+            self.columns.iter().map(|column| column[index].to_f64()),
+        )
+    }
+
+    pub fn row(&self, index: usize) -> DfRow<'_, F> {
+        DfRow {
+            columns: &self.columns,
+            row: index,
+        }
+    }
+}
+
+impl<'a, D, F> EmbeddingProvider<'a, D, DfRow<'a, F>> for DfProvider<'a, D, F>
+where
+    D: Distance<DfRow<'a, F>> + Copy,
+    F: DfScalar,
+{
+    fn get_embed(&'a self, index: usize) -> DfRow<'a, F> {
+        self.row(index)
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        0..self.rows
+    }
+
+    fn distance(&self) -> D {
+        self.distance
+    }
+
+    fn hash_embed<H>(&self, index: usize, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        self.row(index).iter().for_each(|v| v.hash_into(hasher));
+    }
+
+    fn check_embed(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        self.check_row(index)
+    }
+
+    fn dim(&self) -> Option<usize> {
+        Some(self.columns.len())
+    }
+
+    fn check_dim(&self, embed: &Embedding<DfRow<'a, F>>) -> Result<(), DimensionMismatch> {
+        DimensionMismatch::check(self.columns.len(), embed.embed.len())
+    }
+}
@@ -0,0 +1,98 @@
+use std::{collections::HashMap, fmt};
+
+use ndarray::ArrayView1;
+
+use crate::{
+    distances::{dynamic::DynDistance, ndarray::nd_distance, scalar::Scalar, vec::vec_distance},
+    Distance,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownDistance {
+    pub name: String,
+}
+
+impl fmt::Display for UnknownDistance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown distance: {name}", name = self.name)
+    }
+}
+
+type Constructor<'a, T> = Box<dyn Fn() -> DynDistance<'a, T> + Send + Sync + 'a>;
+type Lookup<'a, T> = fn(&str) -> Option<DynDistance<'a, T>>;
+
+// turns the distance names stored in trees back into distances, registered
+// distances take precedence over the built-in lookup
+pub struct DistanceRegistry<'a, T> {
+    constructors: HashMap<String, Constructor<'a, T>>,
+    lookup: Option<Lookup<'a, T>>,
+}
+
+impl<'a, T> DistanceRegistry<'a, T> {
+    pub fn new() -> Self {
+        DistanceRegistry {
+            constructors: HashMap::new(),
+            lookup: None,
+        }
+    }
+
+    pub fn with_lookup(lookup: Lookup<'a, T>) -> Self {
+        DistanceRegistry {
+            constructors: HashMap::new(),
+            lookup: Some(lookup),
+        }
+    }
+
+    // the key is the name the distance reports so it matches what trees store
+    pub fn register<F>(&mut self, constructor: F)
+    where
+        F: Fn() -> DynDistance<'a, T> + Send + Sync + 'a,
+    {
+        let name = constructor().name().to_string();
+        self.constructors.insert(name, Box::new(constructor));
+    }
+
+    pub fn resolve(&self, name: &str) -> Result<DynDistance<'a, T>, UnknownDistance> {
+        self.constructors
+            .get(name)
+            .map(|constructor| constructor())
+            .or_else(|| self.lookup.and_then(|lookup| lookup(name)))
+            .ok_or_else(|| UnknownDistance {
+                name: name.to_string(),
+            })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.resolve(name).is_ok()
+    }
+
+    pub fn registered(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.constructors.keys().map(|name| name.as_str()).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+impl<T> Default for DistanceRegistry<'_, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, F> DistanceRegistry<'a, ArrayView1<'a, F>>
+where
+    F: Scalar,
+{
+    pub fn nd() -> Self {
+        Self::with_lookup(nd_distance::<F>)
+    }
+}
+
+impl<'a, F> DistanceRegistry<'a, &'a Vec<F>>
+where
+    F: Scalar,
+{
+    pub fn vec() -> Self {
+        Self::with_lookup(vec_distance::<F>)
+    }
+}
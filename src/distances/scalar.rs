@@ -0,0 +1,90 @@
+use std::fmt;
+
+use digest::Digest;
+use ndarray::LinalgScalar;
+
+#[cfg(feature = "simd")]
+use crate::distances::simd;
+
+// element types that providers can store, distances are accumulated in f64
+// except for dot products which use the native type for speed
+pub trait Scalar: LinalgScalar + PartialOrd + fmt::Debug + Send + Sync {
+    fn to_f64(self) -> f64;
+    fn from_f64(value: f64) -> Self;
+    fn hash_into<H>(self, hasher: &mut H)
+    where
+        H: Digest;
+
+    fn dot_slice(a: &[Self], b: &[Self]) -> f64 {
+        debug_assert_eq!(a.len(), b.len());
+        a.iter()
+            .zip(b.iter())
+            .map(|(&cur_a, &cur_b)| cur_a.to_f64() * cur_b.to_f64())
+            .sum()
+    }
+
+    fn l2_slice(a: &[Self], b: &[Self]) -> f64 {
+        debug_assert_eq!(a.len(), b.len());
+        a.iter()
+            .zip(b.iter())
+            .map(|(&cur_a, &cur_b)| {
+                let diff = cur_a.to_f64() - cur_b.to_f64();
+                diff * diff
+            })
+            .sum()
+    }
+}
+
+impl Scalar for f64 {
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn hash_into<H>(self, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        hasher.update(self.to_be_bytes());
+    }
+
+    #[cfg(feature = "simd")]
+    fn dot_slice(a: &[Self], b: &[Self]) -> f64 {
+        simd::dot_f64(a, b)
+    }
+
+    #[cfg(feature = "simd")]
+    fn l2_slice(a: &[Self], b: &[Self]) -> f64 {
+        simd::l2_f64(a, b)
+    }
+}
+
+impl Scalar for f32 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn hash_into<H>(self, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        hasher.update(self.to_be_bytes());
+    }
+
+    #[cfg(feature = "simd")]
+    fn dot_slice(a: &[Self], b: &[Self]) -> f64 {
+        simd::dot_f32(a, b)
+    }
+
+    #[cfg(feature = "simd")]
+    fn l2_slice(a: &[Self], b: &[Self]) -> f64 {
+        simd::l2_f32(a, b)
+    }
+}
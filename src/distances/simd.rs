@@ -0,0 +1,163 @@
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+// independent accumulators so the fallback is not bound by add latency
+const LANES: usize = 8;
+
+fn lanes_sum<F, M>(a: &[F], b: &[F], term: M) -> f64
+where
+    F: Copy,
+    M: Fn(F, F) -> f64,
+{
+    let len = a.len().min(b.len());
+    let (a, b) = (&a[..len], &b[..len]);
+    let mut acc = [0.0; LANES];
+    let (chunks_a, chunks_b) = (a.chunks_exact(LANES), b.chunks_exact(LANES));
+    let rest: f64 = chunks_a
+        .remainder()
+        .iter()
+        .zip(chunks_b.remainder().iter())
+        .map(|(&cur_a, &cur_b)| term(cur_a, cur_b))
+        .sum();
+    chunks_a.zip(chunks_b).for_each(|(cur_a, cur_b)| {
+        for lane in 0..LANES {
+            acc[lane] += term(cur_a[lane], cur_b[lane]);
+        }
+    });
+    acc.iter().sum::<f64>() + rest
+}
+
+#[cfg(target_arch = "x86_64")]
+fn has_avx2() -> bool {
+    // the result is cached by std so this is a single load per call
+    is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma")
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+unsafe fn reduce(acc: __m256d) -> f64 {
+    let mut res = [0.0; 4];
+    _mm256_storeu_pd(res.as_mut_ptr(), acc);
+    res.iter().sum()
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+unsafe fn avx_f64(a: &[f64], b: &[f64], diff: bool) -> f64 {
+    let len = a.len().min(b.len());
+    let (ptr_a, ptr_b) = (a.as_ptr(), b.as_ptr());
+    let (mut acc_0, mut acc_1) = (_mm256_setzero_pd(), _mm256_setzero_pd());
+    let mut pos = 0;
+    while pos + 8 <= len {
+        let (mut cur_a0, cur_b0) = (
+            _mm256_loadu_pd(ptr_a.add(pos)),
+            _mm256_loadu_pd(ptr_b.add(pos)),
+        );
+        let (mut cur_a1, cur_b1) = (
+            _mm256_loadu_pd(ptr_a.add(pos + 4)),
+            _mm256_loadu_pd(ptr_b.add(pos + 4)),
+        );
+        if diff {
+            cur_a0 = _mm256_sub_pd(cur_a0, cur_b0);
+            cur_a1 = _mm256_sub_pd(cur_a1, cur_b1);
+            acc_0 = _mm256_fmadd_pd(cur_a0, cur_a0, acc_0);
+            acc_1 = _mm256_fmadd_pd(cur_a1, cur_a1, acc_1);
+        } else {
+            acc_0 = _mm256_fmadd_pd(cur_a0, cur_b0, acc_0);
+            acc_1 = _mm256_fmadd_pd(cur_a1, cur_b1, acc_1);
+        }
+        pos += 8;
+    }
+    let mut res = reduce(_mm256_add_pd(acc_0, acc_1));
+    for (&cur_a, &cur_b) in a[pos..len].iter().zip(b[pos..len].iter()) {
+        res += if diff {
+            (cur_a - cur_b) * (cur_a - cur_b)
+        } else {
+            cur_a * cur_b
+        };
+    }
+    res
+}
+
+// f32 inputs are widened to f64 lanes to keep the accumulation precision
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+unsafe fn avx_f32(a: &[f32], b: &[f32], diff: bool) -> f64 {
+    let len = a.len().min(b.len());
+    let (ptr_a, ptr_b) = (a.as_ptr(), b.as_ptr());
+    let (mut acc_0, mut acc_1) = (_mm256_setzero_pd(), _mm256_setzero_pd());
+    let mut pos = 0;
+    while pos + 8 <= len {
+        let (mut cur_a0, cur_b0) = (
+            _mm256_cvtps_pd(_mm_loadu_ps(ptr_a.add(pos))),
+            _mm256_cvtps_pd(_mm_loadu_ps(ptr_b.add(pos))),
+        );
+        let (mut cur_a1, cur_b1) = (
+            _mm256_cvtps_pd(_mm_loadu_ps(ptr_a.add(pos + 4))),
+            _mm256_cvtps_pd(_mm_loadu_ps(ptr_b.add(pos + 4))),
+        );
+        if diff {
+            cur_a0 = _mm256_sub_pd(cur_a0, cur_b0);
+            cur_a1 = _mm256_sub_pd(cur_a1, cur_b1);
+            acc_0 = _mm256_fmadd_pd(cur_a0, cur_a0, acc_0);
+            acc_1 = _mm256_fmadd_pd(cur_a1, cur_a1, acc_1);
+        } else {
+            acc_0 = _mm256_fmadd_pd(cur_a0, cur_b0, acc_0);
+            acc_1 = _mm256_fmadd_pd(cur_a1, cur_b1, acc_1);
+        }
+        pos += 8;
+    }
+    let mut res = reduce(_mm256_add_pd(acc_0, acc_1));
+    for (&cur_a, &cur_b) in a[pos..len].iter().zip(b[pos..len].iter()) {
+        let (cur_a, cur_b) = (cur_a as f64, cur_b as f64);
+        res += if diff {
+            (cur_a - cur_b) * (cur_a - cur_b)
+        } else {
+            cur_a * cur_b
+        };
+    }
+    res
+}
+
+pub fn dot_f64(a: &[f64], b: &[f64]) -> f64 {
+    debug_assert_eq!(a.len(), b.len());
+    #[cfg(target_arch = "x86_64")]
+    if has_avx2() {
+        // SAFETY: the cpu supports the enabled target features
+        return unsafe { avx_f64(a, b, false) };
+    }
+    lanes_sum(a, b, |cur_a, cur_b| cur_a * cur_b)
+}
+
+pub fn l2_f64(a: &[f64], b: &[f64]) -> f64 {
+    debug_assert_eq!(a.len(), b.len());
+    #[cfg(target_arch = "x86_64")]
+    if has_avx2() {
+        // SAFETY: the cpu supports the enabled target features
+        return unsafe { avx_f64(a, b, true) };
+    }
+    lanes_sum(a, b, |cur_a, cur_b| (cur_a - cur_b) * (cur_a - cur_b))
+}
+
+pub fn dot_f32(a: &[f32], b: &[f32]) -> f64 {
+    debug_assert_eq!(a.len(), b.len());
+    #[cfg(target_arch = "x86_64")]
+    if has_avx2() {
+        // SAFETY: the cpu supports the enabled target features
+        return unsafe { avx_f32(a, b, false) };
+    }
+    lanes_sum(a, b, |cur_a, cur_b| cur_a as f64 * cur_b as f64)
+}
+
+pub fn l2_f32(a: &[f32], b: &[f32]) -> f64 {
+    debug_assert_eq!(a.len(), b.len());
+    #[cfg(target_arch = "x86_64")]
+    if has_avx2() {
+        // SAFETY: the cpu supports the enabled target features
+        return unsafe { avx_f32(a, b, true) };
+    }
+    lanes_sum(a, b, |cur_a, cur_b| {
+        let diff = cur_a as f64 - cur_b as f64;
+        diff * diff
+    })
+}
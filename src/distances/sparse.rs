@@ -0,0 +1,384 @@
+use std::{cmp::Ordering, fmt};
+
+use digest::Digest;
+use ndarray::ArrayView1;
+
+use crate::{
+    distances::{chord_cmp, chord_metric, dynamic::DynDistance, normed::InnerProduct},
+    info::Info,
+    DimensionMismatch, Distance, DistanceCmp, DistanceValue, Embedding, EmbeddingProvider,
+    InvalidEmbeddingError, NearestNeighbors,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct SparseRow<'a> {
+    pub indices: &'a [u32],
+    pub values: &'a [f64],
+    pub norm: f64,
+}
+
+impl SparseRow<'_> {
+    // both index lists are sorted so a single merge pass suffices
+    fn dot(&self, other: &SparseRow) -> f64 {
+        let (mut pos_a, mut pos_b) = (0, 0);
+        let mut res = 0.0;
+        while pos_a < self.indices.len() && pos_b < other.indices.len() {
+            match self.indices[pos_a].cmp(&other.indices[pos_b]) {
+                Ordering::Less => pos_a += 1,
+                Ordering::Greater => pos_b += 1,
+                Ordering::Equal => {
+                    res += self.values[pos_a] * other.values[pos_b];
+                    pos_a += 1;
+                    pos_b += 1;
+                }
+            }
+        }
+        res
+    }
+}
+
+impl InnerProduct for SparseRow<'_> {
+    fn inner_product(&self, other: &Self) -> f64 {
+        self.dot(other)
+    }
+
+    fn norm(&self) -> f64 {
+        self.norm
+    }
+}
+
+fn norm(values: &[f64]) -> f64 {
+    values.iter().map(|v| v * v).sum::<f64>().sqrt()
+}
+
+#[derive(Debug, Clone)]
+pub struct SparseVec {
+    indices: Vec<u32>,
+    values: Vec<f64>,
+    norm: f64,
+}
+
+impl SparseVec {
+    pub fn new<I>(entries: I) -> Self
+    where
+        I: IntoIterator<Item = (u32, f64)>,
+    {
+        let mut entries: Vec<(u32, f64)> = entries.into_iter().filter(|(_, v)| *v != 0.0).collect();
+        entries.sort_unstable_by_key(|(ix, _)| *ix);
+        entries.dedup_by(|cur, prev| {
+            // repeated indices are summed up
+            let same = cur.0 == prev.0;
+            if same {
+                prev.1 += cur.1;
+            }
+            same
+        });
+        let (indices, values): (Vec<u32>, Vec<f64>) = entries.into_iter().unzip();
+        let norm = norm(&values);
+        SparseVec {
+            indices,
+            values,
+            norm,
+        }
+    }
+
+    pub fn from_dense(row: ArrayView1<f64>) -> Self {
+        Self::new(row.iter().enumerate().map(|(ix, &v)| (ix as u32, v)))
+    }
+
+    pub fn nnz(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn view(&self) -> SparseRow<'_> {
+        SparseRow {
+            indices: &self.indices,
+            values: &self.values,
+            norm: self.norm,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SparseDotDistance {}
+
+pub const SPARSE_DOT_DISTANCE: SparseDotDistance = SparseDotDistance {};
+
+impl<'a> Distance<SparseRow<'a>> for SparseDotDistance {
+    fn distance_cmp(
+        &self,
+        a: &Embedding<SparseRow<'a>>,
+        b: &Embedding<SparseRow<'a>>,
+    ) -> DistanceCmp {
+        DistanceCmp::of((-a.embed.dot(&b.embed)).exp() as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "dot"
+    }
+
+    fn is_metric(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SparseCosineDistance {}
+
+pub const SPARSE_COSINE_DISTANCE: SparseCosineDistance = SparseCosineDistance {};
+
+impl<'a> Distance<SparseRow<'a>> for SparseCosineDistance {
+    fn distance_cmp(
+        &self,
+        a: &Embedding<SparseRow<'a>>,
+        b: &Embedding<SparseRow<'a>>,
+    ) -> DistanceCmp {
+        let denom = a.embed.norm * b.embed.norm;
+        let sim = if denom > 0.0 {
+            a.embed.dot(&b.embed) / denom
+        } else {
+            0.0
+        };
+        DistanceCmp::of((1.0 - sim).max(0.0) as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "cosine"
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        chord_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        chord_cmp(value)
+    }
+}
+
+pub fn sparse_distance<'a>(name: &str) -> Option<DynDistance<'a, SparseRow<'a>>> {
+    match name {
+        "cosine" => Some(DynDistance::new(&SPARSE_COSINE_DISTANCE)),
+        "dot" => Some(DynDistance::new(&SPARSE_DOT_DISTANCE)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SparseFormatError {
+    LengthMismatch(DimensionMismatch),
+    BadOffsets { row: usize },
+    IndexOutOfBounds { row: usize, index: u32 },
+    Unsorted { row: usize },
+}
+
+impl fmt::Display for SparseFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SparseFormatError::LengthMismatch(err) => write!(
+                f,
+                "sparse arrays differ in length: expected {expected} got {actual}",
+                expected = err.expected,
+                actual = err.actual,
+            ),
+            SparseFormatError::BadOffsets { row } => {
+                write!(f, "row offsets are out of order at row {row}")
+            }
+            SparseFormatError::IndexOutOfBounds { row, index } => {
+                write!(f, "row {row} has index {index} outside the dimension")
+            }
+            SparseFormatError::Unsorted { row } => {
+                write!(f, "row {row} has unsorted or repeated indices")
+            }
+        }
+    }
+}
+
+impl From<DimensionMismatch> for SparseFormatError {
+    fn from(err: DimensionMismatch) -> Self {
+        SparseFormatError::LengthMismatch(err)
+    }
+}
+
+pub struct SparseProvider<D> {
+    offsets: Vec<usize>,
+    indices: Vec<u32>,
+    values: Vec<f64>,
+    norms: Vec<f64>,
+    dim: usize,
+    distance: D,
+}
+
+impl<D> SparseProvider<D> {
+    // the usual CSR layout: row i covers offsets[i]..offsets[i + 1]
+    pub fn from_csr(
+        offsets: Vec<usize>,
+        indices: Vec<u32>,
+        values: Vec<f64>,
+        dim: usize,
+        distance: D,
+    ) -> Result<Self, SparseFormatError> {
+        DimensionMismatch::check(indices.len(), values.len())?;
+        let end = offsets.last().copied().unwrap_or(0);
+        DimensionMismatch::check(indices.len(), end)?;
+        for (row, range) in offsets.windows(2).enumerate() {
+            if range[0] > range[1] || range[1] > end {
+                return Err(SparseFormatError::BadOffsets { row });
+            }
+            let row_ixs = &indices[range[0]..range[1]];
+            if let Some(&index) = row_ixs.iter().find(|&&ix| ix as usize >= dim) {
+                return Err(SparseFormatError::IndexOutOfBounds { row, index });
+            }
+            if row_ixs.windows(2).any(|pair| pair[0] >= pair[1]) {
+                return Err(SparseFormatError::Unsorted { row });
+            }
+        }
+        let norms = offsets
+            .windows(2)
+            .map(|range| norm(&values[range[0]..range[1]]))
+            .collect();
+        Ok(SparseProvider {
+            offsets,
+            indices,
+            values,
+            norms,
+            dim,
+            distance,
+        })
+    }
+
+    pub fn from_rows(
+        rows: &[SparseVec],
+        dim: usize,
+        distance: D,
+    ) -> Result<Self, SparseFormatError> {
+        let mut offsets = Vec::with_capacity(rows.len() + 1);
+        offsets.push(0);
+        rows.iter()
+            .for_each(|row| offsets.push(offsets.last().unwrap() + row.nnz()));
+        Self::from_csr(
+            offsets,
+            rows.iter()
+                .flat_map(|row| row.indices.iter().copied())
+                .collect(),
+            rows.iter()
+                .flat_map(|row| row.values.iter().copied())
+                .collect(),
+            dim,
+            distance,
+        )
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn nnz(&self) -> usize {
+        self.values.len()
+    }
+
+    fn rows(&self) -> usize {
+        self.offsets.len().saturating_sub(1)
+    }
+
+    fn row(&self, index: usize) -> SparseRow<'_> {
+        let range = self.offsets[index]..self.offsets[index + 1];
+        SparseRow {
+            indices: &self.indices[range.clone()],
+            values: &self.values[range],
+            norm: self.norms[index],
+        }
+    }
+
+    fn check_indices(&self, row: &SparseRow) -> Result<(), DimensionMismatch> {
+        match row.indices.last() {
+            Some(&last) if last as usize >= self.dim => Err(DimensionMismatch {
+                expected: self.dim,
+                actual: last as usize + 1,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<'a, D> EmbeddingProvider<'a, D, SparseRow<'a>> for SparseProvider<D>
+where
+    D: Distance<SparseRow<'a>> + Copy,
+{
+    fn get_embed(&'a self, index: usize) -> SparseRow<'a> {
+        self.row(index)
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        0..self.rows()
+    }
+
+    fn distance(&self) -> D {
+        self.distance
+    }
+
+    fn hash_embed<H>(&self, index: usize, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        let row = self.row(index);
+        row.indices
+            .iter()
+            .zip(row.values.iter())
+            .for_each(|(ix, v)| {
+                hasher.update(ix.to_be_bytes());
+                hasher.update(v.to_be_bytes());
+            });
+    }
+
+    fn check_embed(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        InvalidEmbeddingError::check(index, self.row(index).values.iter().copied())
+    }
+
+    fn dim(&self) -> Option<usize> {
+        Some(self.dim)
+    }
+
+    fn check_dim(&self, embed: &Embedding<SparseRow<'a>>) -> Result<(), DimensionMismatch> {
+        self.check_indices(&embed.embed)
+    }
+}
+
+impl<'a, D> NearestNeighbors<'a, SparseRow<'a>> for SparseProvider<D>
+where
+    D: for<'r> Distance<SparseRow<'r>>,
+{
+    fn check_query(&self, other: &Embedding<SparseRow<'a>>) -> Result<(), DimensionMismatch> {
+        self.check_indices(&other.embed)
+    }
+
+    fn get_closest<I>(
+        &self,
+        other: &Embedding<SparseRow<'a>>,
+        count: usize,
+        _info: &mut I,
+    ) -> Vec<(usize, DistanceValue)>
+    where
+        I: Info,
+    {
+        let mut dists: Vec<(usize, DistanceCmp)> = (0..self.rows())
+            .map(|ix| {
+                let val = Embedding::wrap(self.row(ix), ix);
+                (ix, self.distance.distance_cmp(&val, other))
+            })
+            .collect();
+        dists.sort_unstable_by_key(|(_, a)| *a);
+        dists
+            .iter()
+            .take(count)
+            .map(|(ix, dist)| (*ix, self.distance.finalize_distance(dist)))
+            .collect()
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{Distance, DistanceCmp, DistanceValue, Embedding};
+
+pub const HISTOGRAM_BUCKETS: usize = 64;
+const HISTOGRAM_OFFSET: i32 = 32;
+
+// bucket i holds values in [2^(i-32), 2^(i-31)), non-positive values land in
+// the first and huge values in the last bucket
+fn bucket(value: DistanceValue) -> usize {
+    if value > 0.0 {
+        (value.log2().floor() as i32 + HISTOGRAM_OFFSET).clamp(0, HISTOGRAM_BUCKETS as i32 - 1)
+            as usize
+    } else {
+        0
+    }
+}
+
+pub fn bucket_bounds(bucket: usize) -> (DistanceValue, DistanceValue) {
+    let exp = bucket as i32 - HISTOGRAM_OFFSET;
+    let base: DistanceValue = 2.0;
+    (base.powi(exp), base.powi(exp + 1))
+}
+
+#[derive(Debug)]
+pub struct DistanceStats {
+    calls: AtomicU64,
+    nanos: AtomicU64,
+    histogram: [AtomicU64; HISTOGRAM_BUCKETS],
+}
+
+impl DistanceStats {
+    pub fn new() -> Self {
+        DistanceStats {
+            calls: AtomicU64::new(0),
+            nanos: AtomicU64::new(0),
+            histogram: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+
+    fn record(&self, elapsed: Duration, value: DistanceValue) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+        self.histogram[bucket(value)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn calls(&self) -> u64 {
+        self.calls.load(Ordering::Relaxed)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+
+    pub fn mean_elapsed(&self) -> Duration {
+        match self.calls() {
+            0 => Duration::ZERO,
+            calls => Duration::from_nanos(self.nanos.load(Ordering::Relaxed) / calls),
+        }
+    }
+
+    pub fn histogram(&self) -> Vec<u64> {
+        self.histogram
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    pub fn reset(&self) {
+        self.calls.store(0, Ordering::Relaxed);
+        self.nanos.store(0, Ordering::Relaxed);
+        self.histogram
+            .iter()
+            .for_each(|count| count.store(0, Ordering::Relaxed));
+    }
+}
+
+impl Default for DistanceStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct DistanceWithStats<'s, D> {
+    distance: D,
+    stats: &'s DistanceStats,
+}
+
+impl<'s, D> DistanceWithStats<'s, D> {
+    pub fn new(distance: D, stats: &'s DistanceStats) -> Self {
+        DistanceWithStats { distance, stats }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.distance
+    }
+
+    pub fn stats(&self) -> &'s DistanceStats {
+        self.stats
+    }
+}
+
+impl<'s, D> Clone for DistanceWithStats<'s, D>
+where
+    D: Copy,
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'s, D> Copy for DistanceWithStats<'s, D> where D: Copy {}
+
+// dot_batch is not forwarded so batches still record every distance
+impl<'s, D, T> Distance<T> for DistanceWithStats<'s, D>
+where
+    D: Distance<T>,
+{
+    fn distance_cmp(&self, a: &Embedding<T>, b: &Embedding<T>) -> DistanceCmp {
+        let start = Instant::now();
+        let res = self.distance.distance_cmp(a, b);
+        let elapsed = start.elapsed();
+        // the histogram shows the user facing values
+        self.stats
+            .record(elapsed, self.distance.finalize_distance(&res));
+        res
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        self.distance.finalize_distance(dist_cmp)
+    }
+
+    fn name(&self) -> &str {
+        self.distance.name()
+    }
+
+    fn is_signed(&self) -> bool {
+        self.distance.is_signed()
+    }
+
+    fn is_metric(&self) -> bool {
+        self.distance.is_metric()
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        self.distance.to_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        self.distance.metric_cmp(value)
+    }
+}
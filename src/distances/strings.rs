@@ -0,0 +1,123 @@
+use digest::Digest;
+
+use crate::{
+    distances::dynamic::DynDistance, info::Info, Distance, DistanceCmp, DistanceValue, Embedding,
+    EmbeddingProvider, NearestNeighbors,
+};
+
+// edits are counted on chars so multi byte characters count once
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b_chars.len()).collect();
+    let mut cur = vec![0; b_chars.len() + 1];
+    for (pos_a, char_a) in a.chars().enumerate() {
+        cur[0] = pos_a + 1;
+        for (pos_b, &char_b) in b_chars.iter().enumerate() {
+            let replace = prev[pos_b] + usize::from(char_a != char_b);
+            cur[pos_b + 1] = replace.min(prev[pos_b + 1] + 1).min(cur[pos_b] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b_chars.len()]
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LevenshteinDistance {}
+
+pub const LEVENSHTEIN_DISTANCE: LevenshteinDistance = LevenshteinDistance {};
+
+impl<'a> Distance<&'a str> for LevenshteinDistance {
+    fn distance_cmp(&self, a: &Embedding<&'a str>, b: &Embedding<&'a str>) -> DistanceCmp {
+        DistanceCmp::of_count(levenshtein(a.embed, b.embed) as u32)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "levenshtein"
+    }
+}
+
+pub fn str_distance<'a>(name: &str) -> Option<DynDistance<'a, &'a str>> {
+    match name {
+        "levenshtein" => Some(DynDistance::new(&LEVENSHTEIN_DISTANCE)),
+        _ => None,
+    }
+}
+
+pub struct StrProvider<'a, D>
+where
+    D: Distance<&'a str>,
+{
+    strings: &'a [String],
+    distance: D,
+}
+
+impl<'a, D> StrProvider<'a, D>
+where
+    D: Distance<&'a str>,
+{
+    pub fn new(strings: &'a [String], distance: D) -> Self {
+        StrProvider { strings, distance }
+    }
+}
+
+impl<'a, D> EmbeddingProvider<'a, D, &'a str> for StrProvider<'a, D>
+where
+    D: Distance<&'a str> + Copy,
+{
+    fn get_embed(&'a self, index: usize) -> &'a str {
+        &self.strings[index]
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        0..self.strings.len()
+    }
+
+    fn distance(&self) -> D {
+        self.distance
+    }
+
+    fn hash_embed<H>(&self, index: usize, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        // the length keeps neighboring strings from running together
+        let value = &self.strings[index];
+        hasher.update(value.len().to_be_bytes());
+        hasher.update(value.as_bytes());
+    }
+}
+
+impl<'a, D> NearestNeighbors<'a, &'a str> for StrProvider<'a, D>
+where
+    D: Distance<&'a str>,
+{
+    fn get_closest<I>(
+        &self,
+        other: &Embedding<&'a str>,
+        count: usize,
+        _info: &mut I,
+    ) -> Vec<(usize, DistanceValue)>
+    where
+        I: Info,
+    {
+        let mut dists: Vec<(usize, DistanceCmp)> = self
+            .strings
+            .iter()
+            .enumerate()
+            .map(|(ix, cur)| {
+                let val = Embedding::wrap(cur.as_str(), ix);
+                (ix, self.distance.distance_cmp(&val, other))
+            })
+            .collect();
+        dists.sort_unstable_by_key(|(_, a)| *a);
+        dists
+            .iter()
+            .take(count)
+            .map(|(ix, dist)| (*ix, self.distance.finalize_distance(dist)))
+            .collect()
+    }
+}
@@ -0,0 +1,454 @@
+use std::marker::PhantomData;
+
+use blake2::Blake2s256;
+use digest::Digest;
+use ndarray::{Array1, ArrayView1, ArrayView2, Axis};
+
+use crate::{
+    distances::{
+        chord_cmp, chord_metric, dynamic::DynDistance, ndarray::*, scalar::Scalar, sqrt_metric,
+        squared_cmp,
+    },
+    DimensionMismatch, Distance, DistanceCmp, DistanceValue, Embedding, EmbeddingProvider,
+    InvalidEmbeddingError,
+};
+
+// a value becomes finish(shift(value) * scale) where the scale is computed
+// once per row from the shifted values
+pub trait RowTransform {
+    fn shift(&self, _position: usize, value: f64) -> f64 {
+        value
+    }
+
+    fn row_scale<I>(&self, _shifted: I) -> f64
+    where
+        I: Iterator<Item = f64>,
+    {
+        1.0
+    }
+
+    fn finish(&self, value: f64) -> f64 {
+        value
+    }
+
+    fn hash_params<H>(&self, hasher: &mut H)
+    where
+        H: Digest;
+}
+
+// applied in order: subtract the mean, scale to unit length, clip
+#[derive(Debug, Clone, Default)]
+pub struct Preprocess {
+    mean: Option<Array1<f64>>,
+    normalize: bool,
+    clip: Option<(f64, f64)>,
+}
+
+impl Preprocess {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn fit_mean<S>(arr: ArrayView2<S>) -> Array1<f64>
+    where
+        S: Scalar,
+    {
+        arr.mapv(|v| v.to_f64())
+            .mean_axis(Axis(0))
+            .unwrap_or_else(|| Array1::zeros(arr.shape()[1]))
+    }
+
+    pub fn center(self, mean: Array1<f64>) -> Self {
+        Preprocess {
+            mean: Some(mean),
+            ..self
+        }
+    }
+
+    pub fn normalize(self) -> Self {
+        Preprocess {
+            normalize: true,
+            ..self
+        }
+    }
+
+    pub fn clip(self, min: f64, max: f64) -> Self {
+        Preprocess {
+            clip: Some((min, max)),
+            ..self
+        }
+    }
+}
+
+impl RowTransform for Preprocess {
+    fn shift(&self, position: usize, value: f64) -> f64 {
+        match &self.mean {
+            Some(mean) => value - mean[position],
+            None => value,
+        }
+    }
+
+    fn row_scale<I>(&self, shifted: I) -> f64
+    where
+        I: Iterator<Item = f64>,
+    {
+        if !self.normalize {
+            return 1.0;
+        }
+        let norm = shifted.map(|v| v * v).sum::<f64>().sqrt();
+        // zero rows stay zero
+        if norm > 0.0 {
+            1.0 / norm
+        } else {
+            1.0
+        }
+    }
+
+    fn finish(&self, value: f64) -> f64 {
+        match self.clip {
+            Some((min, max)) => value.clamp(min, max),
+            None => value,
+        }
+    }
+
+    fn hash_params<H>(&self, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        match &self.mean {
+            Some(mean) => {
+                hasher.update(mean.len().to_be_bytes());
+                mean.iter().for_each(|v| hasher.update(v.to_be_bytes()));
+            }
+            None => hasher.update(b"-"),
+        }
+        hasher.update([u8::from(self.normalize)]);
+        match self.clip {
+            Some((min, max)) => {
+                hasher.update(min.to_be_bytes());
+                hasher.update(max.to_be_bytes());
+            }
+            None => hasher.update(b"-"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TransformedRow<'a, S, P> {
+    pub row: ArrayView1<'a, S>,
+    pub scale: f64,
+    pub transform: &'a P,
+}
+
+impl<S, P> Clone for TransformedRow<'_, S, P> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S, P> Copy for TransformedRow<'_, S, P> {}
+
+impl<'a, S, P> TransformedRow<'a, S, P>
+where
+    S: Scalar,
+    P: RowTransform,
+{
+    // for queries that are not part of a provider
+    pub fn new(row: ArrayView1<'a, S>, transform: &'a P) -> Self {
+        let scale = transform.row_scale(
+            row.iter()
+                .enumerate()
+                .map(|(pos, v)| transform.shift(pos, v.to_f64())),
+        );
+        TransformedRow {
+            row,
+            scale,
+            transform,
+        }
+    }
+
+    pub fn values(self) -> impl Iterator<Item = f64> + 'a {
+        let TransformedRow {
+            row,
+            scale,
+            transform,
+        } = self;
+        row.into_iter()
+            .enumerate()
+            .map(move |(pos, v)| transform.finish(transform.shift(pos, v.to_f64()) * scale))
+    }
+
+    pub fn to_array(&self) -> Array1<f64> {
+        self.values().collect()
+    }
+}
+
+impl<'a, S, P> Distance<TransformedRow<'a, S, P>> for NdDotDistance
+where
+    S: Scalar,
+    P: RowTransform,
+{
+    fn distance_cmp(
+        &self,
+        a: &Embedding<TransformedRow<'a, S, P>>,
+        b: &Embedding<TransformedRow<'a, S, P>>,
+    ) -> DistanceCmp {
+        let res: f64 = a
+            .embed
+            .values()
+            .zip(b.embed.values())
+            .map(|(cur_a, cur_b)| cur_a * cur_b)
+            .sum();
+        DistanceCmp::of((-res).exp() as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "dot"
+    }
+
+    fn is_metric(&self) -> bool {
+        false
+    }
+}
+
+impl<'a, S, P> Distance<TransformedRow<'a, S, P>> for NdL2Distance
+where
+    S: Scalar,
+    P: RowTransform,
+{
+    fn distance_cmp(
+        &self,
+        a: &Embedding<TransformedRow<'a, S, P>>,
+        b: &Embedding<TransformedRow<'a, S, P>>,
+    ) -> DistanceCmp {
+        let res: f64 = a
+            .embed
+            .values()
+            .zip(b.embed.values())
+            .map(|(cur_a, cur_b)| (cur_a - cur_b) * (cur_a - cur_b))
+            .sum();
+        DistanceCmp::of(res as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to().sqrt()
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        sqrt_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        squared_cmp(value)
+    }
+
+    fn name(&self) -> &str {
+        "l2"
+    }
+}
+
+impl<'a, S, P> Distance<TransformedRow<'a, S, P>> for NdL1Distance
+where
+    S: Scalar,
+    P: RowTransform,
+{
+    fn distance_cmp(
+        &self,
+        a: &Embedding<TransformedRow<'a, S, P>>,
+        b: &Embedding<TransformedRow<'a, S, P>>,
+    ) -> DistanceCmp {
+        let res: f64 = a
+            .embed
+            .values()
+            .zip(b.embed.values())
+            .map(|(cur_a, cur_b)| (cur_a - cur_b).abs())
+            .sum();
+        DistanceCmp::of(res as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "l1"
+    }
+}
+
+impl<'a, S, P> Distance<TransformedRow<'a, S, P>> for NdCosineDistance
+where
+    S: Scalar,
+    P: RowTransform,
+{
+    fn distance_cmp(
+        &self,
+        a: &Embedding<TransformedRow<'a, S, P>>,
+        b: &Embedding<TransformedRow<'a, S, P>>,
+    ) -> DistanceCmp {
+        let (dot, norm_a, norm_b) = a.embed.values().zip(b.embed.values()).fold(
+            (0.0, 0.0, 0.0),
+            |(dot, norm_a, norm_b), (cur_a, cur_b)| {
+                (
+                    dot + cur_a * cur_b,
+                    norm_a + cur_a * cur_a,
+                    norm_b + cur_b * cur_b,
+                )
+            },
+        );
+        let denom = (norm_a * norm_b).sqrt();
+        let sim = if denom > 0.0 { dot / denom } else { 0.0 };
+        DistanceCmp::of((1.0 - sim).max(0.0) as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "cosine"
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        chord_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        chord_cmp(value)
+    }
+}
+
+pub fn transformed_distance<'a, S, P>(
+    name: &str,
+) -> Option<DynDistance<'a, TransformedRow<'a, S, P>>>
+where
+    S: Scalar,
+    P: RowTransform,
+{
+    match name {
+        "cosine" => Some(DynDistance::new(&ND_COSINE_DISTANCE)),
+        "dot" => Some(DynDistance::new(&ND_DOT_DISTANCE)),
+        "l1" => Some(DynDistance::new(&ND_L1_DISTANCE)),
+        "l2" => Some(DynDistance::new(&ND_L2_DISTANCE)),
+        _ => None,
+    }
+}
+
+// rows are transformed on every access instead of copying the array, only
+// one scale per row is stored
+pub struct TransformedProvider<'a, E, DI, D, P> {
+    inner: &'a E,
+    transform: &'a P,
+    start: usize,
+    scales: Vec<f64>,
+    distance: D,
+    inner_distance: PhantomData<DI>,
+}
+
+impl<'a, E, DI, D, P> TransformedProvider<'a, E, DI, D, P>
+where
+    P: RowTransform,
+{
+    pub fn new<S>(inner: &'a E, transform: &'a P, distance: D) -> Self
+    where
+        E: EmbeddingProvider<'a, DI, ArrayView1<'a, S>>,
+        DI: Distance<ArrayView1<'a, S>> + Copy,
+        S: Scalar,
+    {
+        let all = inner.all();
+        let scales = all
+            .clone()
+            .map(|ix| {
+                let row = inner.get_embed(ix);
+                transform.row_scale(
+                    row.iter()
+                        .enumerate()
+                        .map(|(pos, v)| transform.shift(pos, v.to_f64())),
+                )
+            })
+            .collect();
+        TransformedProvider {
+            inner,
+            transform,
+            start: all.start,
+            scales,
+            distance,
+            inner_distance: PhantomData,
+        }
+    }
+
+    pub fn transform(&self) -> &'a P {
+        self.transform
+    }
+
+    pub fn inner(&self) -> &'a E {
+        self.inner
+    }
+}
+
+impl<'a, E, DI, D, P, S> EmbeddingProvider<'a, D, TransformedRow<'a, S, P>>
+    for TransformedProvider<'a, E, DI, D, P>
+where
+    E: EmbeddingProvider<'a, DI, ArrayView1<'a, S>>,
+    DI: Distance<ArrayView1<'a, S>> + Copy,
+    D: Distance<TransformedRow<'a, S, P>> + Copy,
+    P: RowTransform,
+    S: Scalar,
+{
+    fn get_embed(&'a self, index: usize) -> TransformedRow<'a, S, P> {
+        TransformedRow {
+            row: self.inner.get_embed(index),
+            scale: self.scales[index - self.start],
+            transform: self.transform,
+        }
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        self.inner.all()
+    }
+
+    fn distance(&self) -> D {
+        self.distance
+    }
+
+    fn generation(&self) -> u64 {
+        self.inner.generation()
+    }
+
+    fn hash_embed<H>(&self, index: usize, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        self.inner.hash_embed(index, hasher)
+    }
+
+    fn check_embed(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        self.inner.check_embed(index)
+    }
+
+    fn dim(&self) -> Option<usize> {
+        self.inner.dim()
+    }
+
+    fn check_dim(
+        &self,
+        embed: &Embedding<TransformedRow<'a, S, P>>,
+    ) -> Result<(), DimensionMismatch> {
+        self.inner.check_dim(&Embedding {
+            embed: embed.embed.row,
+            index: embed.index,
+            generation: embed.generation,
+        })
+    }
+
+    // the same rows under a different transform are a different index
+    fn compute_hash(&self) -> String {
+        let mut hasher = Blake2s256::new();
+        self.transform.hash_params(&mut hasher);
+        hasher.update(self.inner.compute_hash().as_bytes());
+        format!("{hash:x}", hash = hasher.finalize())
+    }
+}
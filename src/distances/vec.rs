@@ -1,81 +1,246 @@
-use crate::{info::Info, Distance, DistanceCmp, Embedding, EmbeddingProvider, NearestNeighbors};
+use crate::{
+    distances::{
+        braycurtis::BRAY_CURTIS_DISTANCE, canberra_term, chord_cmp, chord_metric,
+        dynamic::DynDistance, scalar::Scalar, sqrt_metric, squared_cmp,
+    },
+    info::Info,
+    DimensionMismatch, Distance, DistanceCmp, DistanceValue, Embedding, EmbeddingProvider,
+    InvalidEmbeddingError, NearestNeighbors,
+};
 use digest::Digest;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Copy)]
 pub struct VecDotDistance {}
 
 pub const VEC_DOT_DISTANCE: VecDotDistance = VecDotDistance {};
 
-impl Distance<&Vec<f64>> for VecDotDistance {
-    fn distance_cmp(&self, a: &Embedding<&Vec<f64>>, b: &Embedding<&Vec<f64>>) -> DistanceCmp {
+impl<F> Distance<&Vec<F>> for VecDotDistance
+where
+    F: Scalar,
+{
+    fn distance_cmp(&self, a: &Embedding<&Vec<F>>, b: &Embedding<&Vec<F>>) -> DistanceCmp {
+        let res = F::dot_slice(a.embed, b.embed);
+        DistanceCmp::of((-res).exp() as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "dot"
+    }
+
+    fn is_metric(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VecL2Distance {}
+
+pub const VEC_L2_DISTANCE: VecL2Distance = VecL2Distance {};
+
+impl<F> Distance<&Vec<F>> for VecL2Distance
+where
+    F: Scalar,
+{
+    fn distance_cmp(&self, a: &Embedding<&Vec<F>>, b: &Embedding<&Vec<F>>) -> DistanceCmp {
+        let res = F::l2_slice(a.embed, b.embed);
+        DistanceCmp::of(res as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to().sqrt()
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        sqrt_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        squared_cmp(value)
+    }
+
+    fn name(&self) -> &str {
+        "l2"
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VecL1Distance {}
+
+pub const VEC_L1_DISTANCE: VecL1Distance = VecL1Distance {};
+
+impl<F> Distance<&Vec<F>> for VecL1Distance
+where
+    F: Scalar,
+{
+    fn distance_cmp(&self, a: &Embedding<&Vec<F>>, b: &Embedding<&Vec<F>>) -> DistanceCmp {
         let res: f64 = a
             .embed
             .iter()
             .zip(b.embed.iter())
-            .map(|(&cur_a, &cur_b)| cur_a * cur_b)
+            .map(|(&cur_a, &cur_b)| (cur_a.to_f64() - cur_b.to_f64()).abs())
             .sum();
-        DistanceCmp::of((-res).exp())
+        DistanceCmp::of(res as DistanceValue)
     }
 
-    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> f64 {
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
         dist_cmp.to()
     }
 
     fn name(&self) -> &str {
-        "dot"
+        "l1"
     }
 }
 
 #[derive(Debug, Clone, Copy)]
-pub struct VecL2Distance {}
+pub struct VecCanberraDistance {}
 
-pub const VEC_L2_DISTANCE: VecL2Distance = VecL2Distance {};
+pub const VEC_CANBERRA_DISTANCE: VecCanberraDistance = VecCanberraDistance {};
 
-impl Distance<&Vec<f64>> for VecL2Distance {
-    fn distance_cmp(&self, a: &Embedding<&Vec<f64>>, b: &Embedding<&Vec<f64>>) -> DistanceCmp {
+impl<F> Distance<&Vec<F>> for VecCanberraDistance
+where
+    F: Scalar,
+{
+    fn distance_cmp(&self, a: &Embedding<&Vec<F>>, b: &Embedding<&Vec<F>>) -> DistanceCmp {
         let res: f64 = a
             .embed
             .iter()
             .zip(b.embed.iter())
-            .map(|(&cur_a, &cur_b)| (cur_a - cur_b) * (cur_a - cur_b))
+            .map(|(&cur_a, &cur_b)| canberra_term(cur_a.to_f64(), cur_b.to_f64()))
             .sum();
-        DistanceCmp::of(res)
+        DistanceCmp::of(res as DistanceValue)
     }
 
-    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> f64 {
-        dist_cmp.to().sqrt()
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
     }
 
     fn name(&self) -> &str {
-        "l2"
+        "canberra"
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VecCosineDistance {}
+
+pub const VEC_COSINE_DISTANCE: VecCosineDistance = VecCosineDistance {};
+
+impl<F> Distance<&Vec<F>> for VecCosineDistance
+where
+    F: Scalar,
+{
+    fn distance_cmp(&self, a: &Embedding<&Vec<F>>, b: &Embedding<&Vec<F>>) -> DistanceCmp {
+        let (dot, norm_a, norm_b) = a.embed.iter().zip(b.embed.iter()).fold(
+            (0.0, 0.0, 0.0),
+            |(dot, norm_a, norm_b), (&cur_a, &cur_b)| {
+                let (cur_a, cur_b) = (cur_a.to_f64(), cur_b.to_f64());
+                (
+                    dot + cur_a * cur_b,
+                    norm_a + cur_a * cur_a,
+                    norm_b + cur_b * cur_b,
+                )
+            },
+        );
+        let denom: f64 = (norm_a * norm_b).sqrt();
+        let sim = if denom > 0.0 { dot / denom } else { 0.0 };
+        DistanceCmp::of((1.0 - sim).max(0.0) as DistanceValue)
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to()
+    }
+
+    fn name(&self) -> &str {
+        "cosine"
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        chord_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        chord_cmp(value)
+    }
+}
+
+pub fn vec_distance<'a, F>(name: &str) -> Option<DynDistance<'a, &'a Vec<F>>>
+where
+    F: Scalar,
+{
+    match name {
+        "braycurtis" => Some(DynDistance::new(&BRAY_CURTIS_DISTANCE)),
+        "canberra" => Some(DynDistance::new(&VEC_CANBERRA_DISTANCE)),
+        "cosine" => Some(DynDistance::new(&VEC_COSINE_DISTANCE)),
+        "dot" => Some(DynDistance::new(&VEC_DOT_DISTANCE)),
+        "l1" => Some(DynDistance::new(&VEC_L1_DISTANCE)),
+        "l2" => Some(DynDistance::new(&VEC_L2_DISTANCE)),
+        _ => None,
     }
 }
 
-pub struct VecProvider<'a, D>
+pub struct VecProvider<'a, D, F = f64>
 where
-    D: Distance<&'a Vec<f64>>,
+    D: Distance<&'a Vec<F>>,
+    F: Scalar,
 {
-    embeddings: &'a Vec<Vec<f64>>,
+    embeddings: &'a Vec<Vec<F>>,
     distance: D,
 }
 
-impl<'a, D> VecProvider<'a, D>
+impl<'a, D, F> VecProvider<'a, D, F>
 where
-    D: Distance<&'a Vec<f64>>,
+    D: Distance<&'a Vec<F>>,
+    F: Scalar,
 {
-    pub fn new(embeddings: &'a Vec<Vec<f64>>, distance: D) -> Self {
+    pub fn new(embeddings: &'a Vec<Vec<F>>, distance: D) -> Self {
         VecProvider {
             embeddings,
             distance,
         }
     }
+
+    pub fn try_new(
+        embeddings: &'a Vec<Vec<F>>,
+        distance: D,
+    ) -> Result<Self, InvalidEmbeddingError> {
+        let provider = Self::new(embeddings, distance);
+        (0..embeddings.len()).try_for_each(|ix| provider.check_row(ix))?;
+        Ok(provider)
+    }
+
+    fn check_row(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        check_row(self.embeddings, index)
+    }
+
+    fn check_len(&self, embed: &[F]) -> Result<(), DimensionMismatch> {
+        check_len(self.embeddings, embed)
+    }
 }
 
-impl<'a, D> EmbeddingProvider<'a, D, &'a Vec<f64>> for VecProvider<'a, D>
+fn check_row<F>(embeddings: &[Vec<F>], index: usize) -> Result<(), InvalidEmbeddingError>
 where
-    D: Distance<&'a Vec<f64>> + Copy,
+    F: Scalar,
 {
-    fn get_embed(&'a self, index: usize) -> &'a Vec<f64> {
+    InvalidEmbeddingError::check(index, embeddings[index].iter().map(|v| v.to_f64()))
+}
+
+fn check_len<F>(embeddings: &[Vec<F>], embed: &[F]) -> Result<(), DimensionMismatch> {
+    match embeddings.first() {
+        Some(first) => DimensionMismatch::check(first.len(), embed.len()),
+        None => Ok(()),
+    }
+}
+
+impl<'a, D, F> EmbeddingProvider<'a, D, &'a Vec<F>> for VecProvider<'a, D, F>
+where
+    D: Distance<&'a Vec<F>> + Copy,
+    F: Scalar,
+{
+    fn get_embed(&'a self, index: usize) -> &'a Vec<F> {
         &self.embeddings[index]
     }
 
@@ -93,37 +258,167 @@ where
     {
         self.embeddings[index]
             .iter()
-            .for_each(|v| hasher.update(v.to_be_bytes()));
+            .for_each(|v| v.hash_into(hasher));
+    }
+
+    fn check_embed(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        self.check_row(index)
+    }
+
+    fn dim(&self) -> Option<usize> {
+        self.embeddings.first().map(|first| first.len())
+    }
+
+    fn check_dim(&self, embed: &Embedding<&'a Vec<F>>) -> Result<(), DimensionMismatch> {
+        self.check_len(embed.embed)
     }
 }
 
-impl<'a, D> NearestNeighbors<'a, &'a Vec<f64>> for VecProvider<'a, D>
+impl<'a, D, F> NearestNeighbors<'a, &'a Vec<F>> for VecProvider<'a, D, F>
 where
-    D: Distance<&'a Vec<f64>>,
+    D: Distance<&'a Vec<F>>,
+    F: Scalar,
 {
+    fn check_query(&self, other: &Embedding<&'a Vec<F>>) -> Result<(), DimensionMismatch> {
+        self.check_len(other.embed)
+    }
+
     fn get_closest<I>(
         &self,
-        other: &Embedding<&'a Vec<f64>>,
+        other: &Embedding<&'a Vec<F>>,
         count: usize,
         _info: &mut I,
-    ) -> Vec<(usize, f64)>
+    ) -> Vec<(usize, DistanceValue)>
     where
         I: Info,
     {
-        let mut dists: Vec<(usize, DistanceCmp)> = self
-            .embeddings
-            .iter()
-            .enumerate()
-            .map(|(ix, cur)| {
-                let val = Embedding::wrap(cur, ix);
-                (ix, self.distance.distance_cmp(&val, other))
-            })
-            .collect();
-        dists.sort_unstable_by(|(_, a), (_, b)| a.cmp(b));
-        dists
+        brute_force_closest(self.embeddings, &self.distance, other, count)
+    }
+}
+
+fn brute_force_closest<'r, D, F>(
+    embeddings: &'r [Vec<F>],
+    distance: &D,
+    other: &Embedding<&'r Vec<F>>,
+    count: usize,
+) -> Vec<(usize, DistanceValue)>
+where
+    D: Distance<&'r Vec<F>>,
+{
+    let mut dists: Vec<(usize, DistanceCmp)> = embeddings
+        .iter()
+        .enumerate()
+        .map(|(ix, cur)| {
+            let val = Embedding::wrap(cur, ix);
+            (ix, distance.distance_cmp(&val, other))
+        })
+        .collect();
+    dists.sort_unstable_by_key(|(_, a)| *a);
+    dists
+        .iter()
+        .take(count)
+        .map(|(ix, dist)| (*ix, distance.finalize_distance(dist)))
+        .collect()
+}
+
+// owns its rows so it can be stored next to its trees, cloning shares the
+// rows instead of copying them
+#[derive(Debug, Clone)]
+pub struct OwnedVecProvider<D, F = f64> {
+    embeddings: Arc<Vec<Vec<F>>>,
+    distance: D,
+}
+
+impl<D, F> OwnedVecProvider<D, F>
+where
+    F: Scalar,
+{
+    pub fn new(embeddings: Vec<Vec<F>>, distance: D) -> Self {
+        Self::from_shared(Arc::new(embeddings), distance)
+    }
+
+    pub fn from_shared(embeddings: Arc<Vec<Vec<F>>>, distance: D) -> Self {
+        OwnedVecProvider {
+            embeddings,
+            distance,
+        }
+    }
+
+    pub fn try_new(embeddings: Vec<Vec<F>>, distance: D) -> Result<Self, InvalidEmbeddingError> {
+        let provider = Self::new(embeddings, distance);
+        (0..provider.embeddings.len()).try_for_each(|ix| check_row(&provider.embeddings, ix))?;
+        Ok(provider)
+    }
+
+    pub fn shared(&self) -> &Arc<Vec<Vec<F>>> {
+        &self.embeddings
+    }
+
+    pub fn view<'a>(&'a self) -> VecProvider<'a, D, F>
+    where
+        D: Distance<&'a Vec<F>> + Copy,
+    {
+        VecProvider::new(&self.embeddings, self.distance)
+    }
+}
+
+impl<'a, D, F> EmbeddingProvider<'a, D, &'a Vec<F>> for OwnedVecProvider<D, F>
+where
+    D: Distance<&'a Vec<F>> + Copy,
+    F: Scalar,
+{
+    fn get_embed(&'a self, index: usize) -> &'a Vec<F> {
+        &self.embeddings[index]
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        0..self.embeddings.len()
+    }
+
+    fn distance(&self) -> D {
+        self.distance
+    }
+
+    fn hash_embed<H>(&self, index: usize, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        self.embeddings[index]
             .iter()
-            .take(count)
-            .map(|(ix, dist)| (*ix, self.distance.finalize_distance(dist)))
-            .collect()
+            .for_each(|v| v.hash_into(hasher));
+    }
+
+    fn check_embed(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        check_row(&self.embeddings, index)
+    }
+
+    fn dim(&self) -> Option<usize> {
+        self.embeddings.first().map(|first| first.len())
+    }
+
+    fn check_dim(&self, embed: &Embedding<&'a Vec<F>>) -> Result<(), DimensionMismatch> {
+        check_len(&self.embeddings, embed.embed)
+    }
+}
+
+impl<'a, D, F> NearestNeighbors<'a, &'a Vec<F>> for OwnedVecProvider<D, F>
+where
+    D: for<'r> Distance<&'r Vec<F>>,
+    F: Scalar + 'a,
+{
+    fn check_query(&self, other: &Embedding<&'a Vec<F>>) -> Result<(), DimensionMismatch> {
+        check_len(&self.embeddings, other.embed)
+    }
+
+    fn get_closest<I>(
+        &self,
+        other: &Embedding<&'a Vec<F>>,
+        count: usize,
+        _info: &mut I,
+    ) -> Vec<(usize, DistanceValue)>
+    where
+        I: Info,
+    {
+        brute_force_closest(&self.embeddings, &self.distance, other, count)
     }
 }
@@ -0,0 +1,100 @@
+use ndarray::ArrayView1;
+
+use crate::{
+    distances::{name::DistanceName, sqrt_metric, squared_cmp},
+    Distance, DistanceCmp, DistanceValue, Embedding,
+};
+
+// the weights are borrowed since distances have to be Copy
+#[derive(Debug, Clone, Copy)]
+pub struct WeightedL2Distance<'w> {
+    weights: &'w [f64],
+    name: DistanceName,
+}
+
+impl<'w> WeightedL2Distance<'w> {
+    pub fn new(weights: &'w [f64]) -> Self {
+        WeightedL2Distance {
+            weights,
+            name: DistanceName::hashed("weighted-l2", weights.iter().copied()),
+        }
+    }
+
+    pub fn weights(&self) -> &'w [f64] {
+        self.weights
+    }
+
+    pub fn dim(&self) -> usize {
+        self.weights.len()
+    }
+
+    fn sum<I>(&self, diffs: I) -> DistanceCmp
+    where
+        I: Iterator<Item = f64>,
+    {
+        // negative weights would break the metric so they count as zero
+        let res: f64 = diffs
+            .zip(self.weights.iter())
+            .map(|(diff, &weight)| weight.max(0.0) * diff * diff)
+            .sum();
+        DistanceCmp::of(res as DistanceValue)
+    }
+}
+
+impl<'a, 'w> Distance<ArrayView1<'a, f64>> for WeightedL2Distance<'w> {
+    fn distance_cmp(
+        &self,
+        a: &Embedding<ArrayView1<'a, f64>>,
+        b: &Embedding<ArrayView1<'a, f64>>,
+    ) -> DistanceCmp {
+        self.sum(
+            a.embed
+                .iter()
+                .zip(b.embed.iter())
+                .map(|(&cur_a, &cur_b)| cur_a - cur_b),
+        )
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to().sqrt()
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        sqrt_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        squared_cmp(value)
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+}
+
+impl<'w> Distance<&Vec<f64>> for WeightedL2Distance<'w> {
+    fn distance_cmp(&self, a: &Embedding<&Vec<f64>>, b: &Embedding<&Vec<f64>>) -> DistanceCmp {
+        self.sum(
+            a.embed
+                .iter()
+                .zip(b.embed.iter())
+                .map(|(&cur_a, &cur_b)| cur_a - cur_b),
+        )
+    }
+
+    fn finalize_distance(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        dist_cmp.to().sqrt()
+    }
+
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        sqrt_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        squared_cmp(value)
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+}
@@ -0,0 +1,467 @@
+use std::{
+    collections::BinaryHeap,
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use ndarray::ArrayView2;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    info::{no_info, BaseInfo, Info},
+    kmed::{FannTree, QueryContext},
+    Distance, DistanceCmp, DistanceValue, EmbeddingProvider,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NeighborsError {
+    RowCount { queries: usize, rows: usize },
+    OutOfRange { query: usize, index: usize },
+}
+
+impl fmt::Display for NeighborsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NeighborsError::RowCount { queries, rows } => {
+                write!(f, "expected neighbors for {queries} queries got {rows}")
+            }
+            NeighborsError::OutOfRange { query, index } => {
+                write!(
+                    f,
+                    "neighbor {index} of query {query} is not in the provider"
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroundTruth {
+    pub k: usize,
+    pub neighbors: Vec<Vec<(usize, DistanceValue)>>,
+    provider_hash: String,
+    queries_hash: String,
+    distance_name: String,
+}
+
+impl GroundTruth {
+    fn matches<'a, E, EQ, D, T>(&self, provider: &E, queries: &EQ, k: usize) -> bool
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        EQ: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+    {
+        self.k >= k
+            && self.distance_name == provider.distance().name()
+            && self.provider_hash == provider.compute_hash()
+            && self.queries_hash == queries.compute_hash()
+    }
+
+    fn truncate(mut self, k: usize) -> Self {
+        self.neighbors
+            .iter_mut()
+            .for_each(|neighbors| neighbors.truncate(k));
+        self.k = k;
+        self
+    }
+
+    // published neighbor ids, e.g. the neighbors of an ann-benchmarks dataset
+    // or an ivecs file, negative ids are padding
+    pub fn from_neighbors<'a, E, EQ, D, T>(
+        provider: &'a E,
+        queries: &'a EQ,
+        neighbors: ArrayView2<i32>,
+    ) -> Result<Self, NeighborsError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        EQ: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+    {
+        if neighbors.nrows() != queries.all().len() {
+            return Err(NeighborsError::RowCount {
+                queries: queries.all().len(),
+                rows: neighbors.nrows(),
+            });
+        }
+        let distance = provider.distance();
+        let neighbors: Vec<Vec<(usize, DistanceValue)>> = queries
+            .all()
+            .zip(neighbors.rows())
+            .map(|(query_ix, ids)| {
+                let embed = queries.get(query_ix);
+                ids.iter()
+                    .filter_map(|&ix| usize::try_from(ix).ok())
+                    .map(|ix| {
+                        if !provider.all().contains(&ix) {
+                            return Err(NeighborsError::OutOfRange {
+                                query: query_ix,
+                                index: ix,
+                            });
+                        }
+                        let dist = distance.distance_cmp(&embed, &provider.get(ix));
+                        Ok((ix, distance.finalize_distance(&dist)))
+                    })
+                    .collect()
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(GroundTruth {
+            k: neighbors.iter().map(|ids| ids.len()).min().unwrap_or(0),
+            neighbors,
+            provider_hash: provider.compute_hash(),
+            queries_hash: queries.compute_hash(),
+            distance_name: distance.name().to_string(),
+        })
+    }
+
+    pub fn load(file: &File) -> io::Result<Self> {
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    pub fn save(&self, file: &File) -> io::Result<()> {
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()
+    }
+}
+
+fn brute_force<'a, E, EQ, D, T>(
+    provider: &'a E,
+    queries: &'a EQ,
+    query_ix: usize,
+    k: usize,
+) -> Vec<(usize, DistanceValue)>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    EQ: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+{
+    let distance = provider.distance();
+    let embed = queries.get(query_ix);
+    let mut heap: BinaryHeap<(DistanceCmp, usize)> = BinaryHeap::with_capacity(k + 1);
+    provider.all().for_each(|ix| {
+        let dist = distance.distance_cmp(&embed, &provider.get(ix));
+        if heap.len() < k {
+            heap.push((dist, ix));
+        } else if heap.peek().is_some_and(|&(worst, _)| dist < worst) {
+            heap.pop();
+            heap.push((dist, ix));
+        }
+    });
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|(dist, ix)| (ix, distance.finalize_distance(&dist)))
+        .collect()
+}
+
+pub fn ground_truth<'a, E, EQ, D, T>(provider: &'a E, queries: &'a EQ, k: usize) -> GroundTruth
+where
+    E: EmbeddingProvider<'a, D, T> + Sync,
+    EQ: EmbeddingProvider<'a, D, T> + Sync,
+    D: Distance<T> + Copy,
+    T: 'a,
+{
+    #[cfg(feature = "parallel")]
+    let neighbors = queries
+        .all()
+        .into_par_iter()
+        .map(|query_ix| brute_force(provider, queries, query_ix, k))
+        .collect();
+    #[cfg(not(feature = "parallel"))]
+    let neighbors = queries
+        .all()
+        .map(|query_ix| brute_force(provider, queries, query_ix, k))
+        .collect();
+    GroundTruth {
+        k,
+        neighbors,
+        provider_hash: provider.compute_hash(),
+        queries_hash: queries.compute_hash(),
+        distance_name: provider.distance().name().to_string(),
+    }
+}
+
+pub fn ground_truth_cached<'a, E, EQ, D, T, P>(
+    provider: &'a E,
+    queries: &'a EQ,
+    k: usize,
+    path: P,
+) -> io::Result<GroundTruth>
+where
+    E: EmbeddingProvider<'a, D, T> + Sync,
+    EQ: EmbeddingProvider<'a, D, T> + Sync,
+    D: Distance<T> + Copy,
+    T: 'a,
+    P: AsRef<Path>,
+{
+    // a stale or unreadable cache is simply recomputed
+    if let Ok(truth) = File::open(&path).and_then(|file| GroundTruth::load(&file)) {
+        if truth.matches(provider, queries, k) {
+            return Ok(truth.truncate(k));
+        }
+    }
+    let truth = ground_truth(provider, queries, k);
+    truth.save(&File::create(&path)?)?;
+    Ok(truth)
+}
+
+// the metric names of ann-benchmarks datasets, angular is the cosine distance
+pub fn benchmark_distance(metric: &str) -> Option<&'static str> {
+    match metric {
+        "angular" => Some("cosine"),
+        "euclidean" => Some("l2"),
+        "hamming" => Some("hamming"),
+        _ => None,
+    }
+}
+
+pub fn recall(result: &[(usize, DistanceValue)], truth: &[(usize, DistanceValue)]) -> f64 {
+    let Some(&(_, kth_dist)) = truth.last() else {
+        return 1.0;
+    };
+    // compare by distance so ties at the k-th neighbor count as hits
+    let hits = result
+        .iter()
+        .filter(|&&(_, dist)| dist <= kth_dist + kth_dist.abs() * 1e-6)
+        .count()
+        .min(truth.len());
+    hits as f64 / truth.len() as f64
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct EvalPoint {
+    pub count: usize,
+    pub budget: Option<usize>,
+    pub recall: f64,
+    pub min_recall: f64,
+    pub mean_latency: Duration,
+    pub max_latency: Duration,
+    pub mean_dist_count: f64,
+}
+
+pub fn evaluate<'a, E, EQ, D, T>(
+    tree: &FannTree,
+    provider: &'a E,
+    queries: &'a EQ,
+    truth: &GroundTruth,
+    counts: &[usize],
+) -> Vec<EvalPoint>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    EQ: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+{
+    counts
+        .iter()
+        .map(|&count| evaluate_budget(tree, provider, queries, truth, count, None))
+        .collect()
+}
+
+pub fn evaluate_budget<'a, E, EQ, D, T>(
+    tree: &FannTree,
+    provider: &'a E,
+    queries: &'a EQ,
+    truth: &GroundTruth,
+    count: usize,
+    budget: Option<usize>,
+) -> EvalPoint
+where
+    E: EmbeddingProvider<'a, D, T>,
+    EQ: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+{
+    let embeds: Vec<_> = queries.all().map(|ix| queries.get(ix)).collect();
+    let total = embeds.len().max(1) as f64;
+    let mut ctx = QueryContext::new();
+    ctx.set_budget(budget);
+    let mut info = BaseInfo::new(provider.all().end);
+    let mut recall_sum = 0.0;
+    let mut min_recall: f64 = 1.0;
+    let mut latency_sum = Duration::ZERO;
+    let mut max_latency = Duration::ZERO;
+    let mut dist_sum = 0;
+    embeds
+        .iter()
+        .zip(&truth.neighbors)
+        .for_each(|(embed, expected)| {
+            let start = Instant::now();
+            let res = tree.query_with_ctx(provider, embed, count, &mut no_info(), &mut ctx);
+            let elapsed = start.elapsed();
+            let cur_recall = recall(res, expected);
+            recall_sum += cur_recall;
+            min_recall = min_recall.min(cur_recall);
+            latency_sum += elapsed;
+            max_latency = max_latency.max(elapsed);
+            // count distances in a separate pass to keep the timing clean
+            info.clear();
+            tree.query_with_ctx(provider, embed, count, &mut info, &mut ctx);
+            dist_sum += info.dist_count();
+        });
+    EvalPoint {
+        count,
+        budget,
+        recall: recall_sum / total,
+        min_recall,
+        mean_latency: latency_sum.div_f64(total),
+        max_latency,
+        mean_dist_count: dist_sum as f64 / total,
+    }
+}
+
+// finds the smallest node visit budget whose recall of the top k reaches the
+// target. None means even the unbounded search misses it which only happens
+// for distances that violate the metric axioms
+pub fn tune<'a, E, EQ, D, T>(
+    tree: &FannTree,
+    provider: &'a E,
+    queries: &'a EQ,
+    k: usize,
+    target_recall: f64,
+) -> Option<EvalPoint>
+where
+    E: EmbeddingProvider<'a, D, T> + Sync,
+    EQ: EmbeddingProvider<'a, D, T> + Sync,
+    D: Distance<T> + Copy,
+    T: 'a,
+{
+    let truth = ground_truth(provider, queries, k);
+    let measure = |budget: usize| evaluate_budget(tree, provider, queries, &truth, k, Some(budget));
+    // no search visits more nodes than there are slots
+    let mut upper = tree.slot_counts().0.max(1);
+    let mut best = measure(upper);
+    if best.recall < target_recall {
+        return None;
+    }
+    // a smaller budget stops the same search earlier so recall only drops
+    let mut lower = 0;
+    while upper - lower > 1 {
+        let point = measure(lower + (upper - lower) / 2);
+        if point.recall >= target_recall {
+            upper = point.budget.unwrap();
+            best = point;
+        } else {
+            lower = point.budget.unwrap();
+        }
+    }
+    Some(best)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MetricReport {
+    pub samples: usize,
+    pub triangle_violations: usize,
+    pub symmetry_violations: usize,
+    pub identity_violations: usize,
+    pub negative_distances: usize,
+    pub max_triangle_excess: DistanceValue,
+    pub mean_triangle_excess: DistanceValue,
+    pub max_asymmetry: DistanceValue,
+    pub worst_triangle: Option<(usize, usize, usize)>,
+}
+
+impl MetricReport {
+    pub fn is_metric(&self) -> bool {
+        self.triangle_violations == 0
+            && self.symmetry_violations == 0
+            && self.identity_violations == 0
+            && self.negative_distances == 0
+    }
+
+    pub fn triangle_rate(&self) -> f64 {
+        self.triangle_violations as f64 / self.samples.max(1) as f64
+    }
+}
+
+// splitmix64 so the sampled triples are the same on every run
+fn next_index(state: &mut u64, len: usize) -> usize {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut res = *state;
+    res = (res ^ (res >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    res = (res ^ (res >> 27)).wrapping_mul(0x94d049bb133111eb);
+    ((res ^ (res >> 31)) % len as u64) as usize
+}
+
+// samples triples of rows and checks the values the tree bounds are computed
+// on against the metric axioms, the tree only returns exact results if none
+// are violated
+pub fn validate_metric<'a, E, DP, D, T>(
+    provider: &'a E,
+    distance: D,
+    samples: usize,
+) -> MetricReport
+where
+    E: EmbeddingProvider<'a, DP, T>,
+    DP: Distance<T> + Copy,
+    D: Distance<T>,
+    T: 'a,
+{
+    let all = provider.all();
+    let mut report = MetricReport {
+        samples: 0,
+        triangle_violations: 0,
+        symmetry_violations: 0,
+        identity_violations: 0,
+        negative_distances: 0,
+        max_triangle_excess: 0.0,
+        mean_triangle_excess: 0.0,
+        max_asymmetry: 0.0,
+        worst_triangle: None,
+    };
+    if all.is_empty() {
+        return report;
+    }
+    let dist = |ix_a: usize, ix_b: usize| {
+        distance.to_metric(&distance.distance_cmp(&provider.get(ix_a), &provider.get(ix_b)))
+    };
+    // rounding must not count as a violation
+    let tolerance = |scale: DistanceValue| DistanceValue::EPSILON * 16.0 * scale.abs().max(1.0);
+    let mut state = samples as u64;
+    let mut excess_sum = 0.0;
+    for _ in 0..samples {
+        let (ix_a, ix_b, ix_c) = (
+            all.start + next_index(&mut state, all.len()),
+            all.start + next_index(&mut state, all.len()),
+            all.start + next_index(&mut state, all.len()),
+        );
+        let (d_ab, d_ba, d_bc, d_ac) = (
+            dist(ix_a, ix_b),
+            dist(ix_b, ix_a),
+            dist(ix_b, ix_c),
+            dist(ix_a, ix_c),
+        );
+        let d_aa = dist(ix_a, ix_a);
+        report.samples += 1;
+        if [d_ab, d_bc, d_ac].iter().any(|&cur| cur < 0.0) {
+            report.negative_distances += 1;
+        }
+        if d_aa.abs() > tolerance(d_aa) {
+            report.identity_violations += 1;
+        }
+        let asymmetry = (d_ab - d_ba).abs();
+        if asymmetry > tolerance(d_ab) {
+            report.symmetry_violations += 1;
+            report.max_asymmetry = report.max_asymmetry.max(asymmetry);
+        }
+        let excess = d_ac - (d_ab + d_bc);
+        if excess > tolerance(d_ac) {
+            report.triangle_violations += 1;
+            excess_sum += excess;
+            if excess > report.max_triangle_excess {
+                report.max_triangle_excess = excess;
+                report.worst_triangle = Some((ix_a, ix_b, ix_c));
+            }
+        }
+    }
+    if report.triangle_violations > 0 {
+        report.mean_triangle_excess = excess_sum / report.triangle_violations as DistanceValue;
+    }
+    report
+}
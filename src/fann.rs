@@ -2,10 +2,21 @@ use std::fmt;
 use std::marker::PhantomData;
 
 use crate::{
-    info::Info, Cache, Distance, Embedding, EmbeddingProvider, LocalDistance, NearestNeighbors,
+    ids::{IdMap, IdMapError},
+    info::Info,
+    payload::{PayloadResult, PayloadStore},
+    Cache, DimensionMismatch, Distance, DistanceValue, Embedding, EmbeddingProvider, LocalDistance,
+    NearestNeighbors, ScoreTransform, SearchResult, StreamingNeighbors,
 };
 
+pub mod buffered;
+#[cfg(feature = "hnsw")]
+pub mod hnsw;
 pub mod kmed;
+pub mod namespace;
+pub mod snapshot;
+pub mod wal;
+pub mod window;
 
 #[derive(Debug, Clone)]
 pub struct MisconfiguredTreeError;
@@ -16,6 +27,50 @@ impl fmt::Display for MisconfiguredTreeError {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FingerprintPolicy {
+    #[default]
+    CheckAll,
+    CheckDistanceOnly,
+    CheckShapeOnly,
+    Skip,
+}
+
+impl FingerprintPolicy {
+    pub fn check<'a, E, D, N, T>(
+        &self,
+        tree: &N,
+        provider: &E,
+    ) -> Result<(), MisconfiguredTreeError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        N: Tree<'a, E, D, T>,
+        T: 'a,
+    {
+        let (phash, dname) = tree.fingerprint();
+        let is_valid = match self {
+            FingerprintPolicy::CheckAll => {
+                // trees changed since their last hash refresh have no hash to
+                // compare so they only pass the weaker policies
+                tree.generation() == provider.generation()
+                    && dname == provider.distance().name()
+                    && !phash.is_empty()
+                    && phash == provider.compute_hash()
+            }
+            FingerprintPolicy::CheckDistanceOnly => dname == provider.distance().name(),
+            // the rows may differ but every index must exist
+            FingerprintPolicy::CheckShapeOnly => tree.index_end() <= provider.len(),
+            FingerprintPolicy::Skip => true,
+        };
+        if is_valid {
+            Ok(())
+        } else {
+            Err(MisconfiguredTreeError)
+        }
+    }
+}
+
 pub trait Tree<'a, E, D, T>
 where
     E: EmbeddingProvider<'a, D, T>,
@@ -33,11 +88,35 @@ where
         C: Cache,
         I: Info;
 
+    // rows whose length disagrees with the provider would silently be
+    // truncated by the distances so they fail the build instead
+    fn try_build<C, I>(
+        provider: &'a E,
+        max_node_size: Option<usize>,
+        pre_cluster: Option<usize>,
+        cache: &mut C,
+        info: &mut I,
+    ) -> Result<Self, DimensionMismatch>
+    where
+        Self: Sized,
+        C: Cache,
+        I: Info,
+    {
+        provider.validate_dims()?;
+        Ok(Self::build(
+            provider,
+            max_node_size,
+            pre_cluster,
+            cache,
+            info,
+        ))
+    }
+
     fn draw<I>(
         &self,
         high_ix: usize,
         info: Option<&I>,
-        res: Option<Vec<(usize, f64)>>,
+        res: Option<Vec<(usize, DistanceValue)>>,
         prune: bool,
         radius: bool,
     ) -> String
@@ -49,11 +128,27 @@ where
         count: usize,
         ldist: &LocalDistance<'a, E, D, T>,
         info: &mut I,
-    ) -> Vec<(usize, f64)>
+    ) -> Vec<(usize, DistanceValue)>
     where
         I: Info;
 
     fn fingerprint(&self) -> (&str, &str);
+
+    fn index_end(&self) -> usize;
+
+    fn generation(&self) -> u64 {
+        0
+    }
+
+    fn ids(&self) -> Option<&IdMap> {
+        None
+    }
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 pub struct Fann<'a, E, D, N, T>
@@ -65,6 +160,7 @@ where
 {
     provider: &'a E,
     root: Option<N>,
+    transform: Option<Box<dyn ScoreTransform + Send + Sync>>,
     distance_type: PhantomData<D>,
     embed_type: PhantomData<T>,
 }
@@ -80,6 +176,7 @@ where
         Fann {
             provider,
             root: None,
+            transform: None,
             distance_type: PhantomData,
             embed_type: PhantomData,
         }
@@ -92,25 +189,36 @@ where
     pub fn set_tree(
         &mut self,
         tree: N,
-        ignore_provider: bool,
+        policy: FingerprintPolicy,
     ) -> Result<(), MisconfiguredTreeError> {
-        if !ignore_provider {
-            let (phash, dname) = tree.fingerprint();
-            if dname != self.provider.distance().name() {
-                return Err(MisconfiguredTreeError);
-            }
-            if phash != &self.provider.compute_hash() {
-                return Err(MisconfiguredTreeError);
-            }
-        }
+        policy.check(&tree, self.provider)?;
         self.root = Some(tree);
         Ok(())
     }
 
+    pub fn len(&self) -> usize {
+        self.root.as_ref().map_or(0, |root| root.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn clear_tree(&mut self) {
         self.root = None;
     }
 
+    pub fn set_transform<S>(&mut self, transform: S)
+    where
+        S: ScoreTransform + Send + Sync + 'static,
+    {
+        self.transform = Some(Box::new(transform));
+    }
+
+    pub fn clear_transform(&mut self) {
+        self.transform = None;
+    }
+
     pub fn build<C, I>(
         &mut self,
         max_node_size: Option<usize>,
@@ -130,10 +238,31 @@ where
         ));
     }
 
+    pub fn try_build<C, I>(
+        &mut self,
+        max_node_size: Option<usize>,
+        pre_cluster: Option<usize>,
+        cache: &mut C,
+        info: &mut I,
+    ) -> Result<(), DimensionMismatch>
+    where
+        C: Cache,
+        I: Info,
+    {
+        self.root = Some(N::try_build(
+            self.provider,
+            max_node_size,
+            pre_cluster,
+            cache,
+            info,
+        )?);
+        Ok(())
+    }
+
     pub fn draw<I>(
         &self,
         info: Option<&I>,
-        res: Option<Vec<(usize, f64)>>,
+        res: Option<Vec<(usize, DistanceValue)>>,
         prune: bool,
         radius: bool,
     ) -> String
@@ -141,7 +270,7 @@ where
         I: Info,
     {
         self.root.as_ref().unwrap().draw(
-            self.provider.all().last().unwrap(),
+            self.provider.all().last().unwrap_or(0),
             info,
             res,
             prune,
@@ -150,6 +279,47 @@ where
     }
 }
 
+impl<'a, E, D, N, T> Fann<'a, E, D, N, T>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    N: Tree<'a, E, D, T>,
+    T: 'a,
+{
+    // ids stored with the tree take precedence over ids of the provider
+    pub fn get_closest_ids<I>(
+        &self,
+        other: &'a Embedding<T>,
+        count: usize,
+        info: &mut I,
+    ) -> Result<SearchResult, IdMapError>
+    where
+        I: Info,
+    {
+        let ids = self
+            .root
+            .as_ref()
+            .and_then(|root| root.ids())
+            .or_else(|| self.provider.ids())
+            .ok_or(IdMapError::NoIds)?;
+        self.get_closest_result(other, count, info).with_ids(ids)
+    }
+
+    pub fn get_closest_payloads<I, P>(
+        &self,
+        other: &'a Embedding<T>,
+        count: usize,
+        info: &mut I,
+        payloads: &PayloadStore<P>,
+    ) -> PayloadResult<P>
+    where
+        I: Info,
+        P: Clone,
+    {
+        payloads.attach(&self.get_closest(other, count, info))
+    }
+}
+
 impl<'a, E, D, N, T> NearestNeighbors<'a, T> for Fann<'a, E, D, N, T>
 where
     E: EmbeddingProvider<'a, D, T>,
@@ -157,19 +327,49 @@ where
     N: Tree<'a, E, D, T>,
     T: 'a,
 {
+    fn check_query(&self, other: &Embedding<T>) -> Result<(), DimensionMismatch> {
+        self.provider.check_dim(other)
+    }
+
     fn get_closest<I>(
         &self,
         other: &'a Embedding<T>,
         count: usize,
         info: &mut I,
-    ) -> Vec<(usize, f64)>
+    ) -> Vec<(usize, DistanceValue)>
     where
         I: Info,
     {
         let ldist = LocalDistance::new(self.provider, other);
-        self.get_tree()
+        let mut res = self
+            .get_tree()
             .as_ref()
             .unwrap()
-            .get_closest(count, &ldist, info)
+            .get_closest(count, &ldist, info);
+        if let Some(transform) = &self.transform {
+            transform.apply(&mut res);
+        }
+        res
+    }
+}
+
+impl<'a, E, D, T> StreamingNeighbors<'a, T> for Fann<'a, E, D, kmed::FannTree, T>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+{
+    type Stream<'s>
+        = kmed::NeighborStream<'s, 'a, E, D, T>
+    where
+        Self: 's;
+
+    fn get_closest_stream<'s>(&'s self, other: &'a Embedding<T>) -> Self::Stream<'s> {
+        let ldist = LocalDistance::new(self.provider, other);
+        let stream = self.get_tree().as_ref().unwrap().get_closest_stream(ldist);
+        match &self.transform {
+            Some(transform) => stream.with_transform(transform.as_ref()),
+            None => stream,
+        }
     }
 }
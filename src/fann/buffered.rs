@@ -0,0 +1,139 @@
+use std::{
+    sync::{Arc, Mutex, RwLock},
+    thread::{self, JoinHandle},
+};
+
+use ndarray::ArrayView1;
+
+use crate::{
+    distances::{ndarray::OwnedNdProvider, scalar::Scalar},
+    info::{no_info, Info},
+    kmed::FannTree,
+    Cache, Distance, DistanceCmp, DistanceValue, EmbeddingProvider, LocalDistance, Tree,
+};
+
+struct BufferState {
+    tree: Arc<FannTree>,
+    buffer: Vec<usize>,
+}
+
+pub struct BufferedTree {
+    state: RwLock<BufferState>,
+    merging: Mutex<()>,
+    max_node_size: Option<usize>,
+    pre_cluster: Option<usize>,
+}
+
+impl BufferedTree {
+    pub fn new(tree: FannTree, max_node_size: Option<usize>, pre_cluster: Option<usize>) -> Self {
+        BufferedTree {
+            state: RwLock::new(BufferState {
+                tree: Arc::new(tree),
+                buffer: Vec::new(),
+            }),
+            merging: Mutex::new(()),
+            max_node_size,
+            pre_cluster,
+        }
+    }
+
+    pub fn push(&self, index: usize) {
+        self.state.write().unwrap().buffer.push(index);
+    }
+
+    pub fn buffer_len(&self) -> usize {
+        self.state.read().unwrap().buffer.len()
+    }
+
+    pub fn tree(&self) -> Arc<FannTree> {
+        self.state.read().unwrap().tree.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        let state = self.state.read().unwrap();
+        state.tree.len() + state.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get_closest<'a, E, D, T, I>(
+        &self,
+        count: usize,
+        ldist: &LocalDistance<'a, E, D, T>,
+        info: &mut I,
+    ) -> Vec<(usize, DistanceValue)>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        I: Info,
+    {
+        let state = self.state.read().unwrap();
+        let mut buffered: Vec<(usize, DistanceCmp)> = state
+            .buffer
+            .iter()
+            .copied()
+            .zip(ldist.distance_batch(&state.buffer, info))
+            .collect();
+        buffered.sort_unstable_by_key(|&(_, dist)| dist);
+        let mut res = state.tree.get_closest(count, ldist, info);
+        res.extend(buffered.into_iter().take(count).map(|(ix, dist)| {
+            (
+                state.tree.original_index(ix),
+                ldist.finalize_distance(&dist),
+            )
+        }));
+        res.sort_by(|(_, dist_a), (_, dist_b)| dist_a.total_cmp(dist_b));
+        res.truncate(count);
+        res
+    }
+
+    pub fn merge<'a, E, D, T, C, I>(&self, provider: &'a E, cache: &mut C, info: &mut I) -> usize
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        let _guard = self.merging.lock().unwrap();
+        let (prev, buffer) = {
+            let state = self.state.read().unwrap();
+            if state.buffer.is_empty() {
+                return 0;
+            }
+            (state.tree.clone(), state.buffer.clone())
+        };
+        // the expensive build happens while readers still see the buffer
+        let tree = prev.rebuild_with(
+            &buffer,
+            provider,
+            self.max_node_size,
+            self.pre_cluster,
+            cache,
+            info,
+        );
+        let mut state = self.state.write().unwrap();
+        state.tree = Arc::new(tree);
+        state.buffer.drain(..buffer.len());
+        buffer.len()
+    }
+
+    // merges on a separate thread. the provider shares its rows so the thread
+    // can own it while the caller keeps pushing and querying
+    pub fn spawn_merge<D, F, C>(
+        self: &Arc<Self>,
+        provider: OwnedNdProvider<D, F>,
+        mut cache: C,
+    ) -> JoinHandle<usize>
+    where
+        D: for<'b> Distance<ArrayView1<'b, F>> + Copy + Send + Sync + 'static,
+        F: Scalar + 'static,
+        C: Cache + Send + 'static,
+    {
+        let buffered = Arc::clone(self);
+        thread::spawn(move || buffered.merge(&provider, &mut cache, &mut no_info()))
+    }
+}
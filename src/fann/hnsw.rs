@@ -0,0 +1,250 @@
+use instant_distance::{Builder, HnswMap, Point, Search};
+use ndarray::ArrayView1;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    distances::scalar::Scalar, info::Info, Cache, Distance, DistanceCmp, DistanceValue, Embedding,
+    EmbeddingProvider, LocalDistance, MisconfiguredTreeError, Tree,
+};
+
+pub trait AsPoint {
+    fn as_point(&self) -> Vec<f32>;
+}
+
+impl<F> AsPoint for ArrayView1<'_, F>
+where
+    F: Scalar,
+{
+    fn as_point(&self) -> Vec<f32> {
+        self.iter().map(|&v| v.to_f64() as f32).collect()
+    }
+}
+
+impl<F> AsPoint for &Vec<F>
+where
+    F: Scalar,
+{
+    fn as_point(&self) -> Vec<f32> {
+        self.iter().map(|&v| v.to_f64() as f32).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum PointMetric {
+    Dot,
+    L2,
+    Cosine,
+    L1,
+    Canberra,
+    BrayCurtis,
+}
+
+impl PointMetric {
+    fn for_name(name: &str) -> Self {
+        match name {
+            "dot" => PointMetric::Dot,
+            "cosine" => PointMetric::Cosine,
+            "l1" => PointMetric::L1,
+            "canberra" => PointMetric::Canberra,
+            "braycurtis" => PointMetric::BrayCurtis,
+            _ => PointMetric::L2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HnswPoint {
+    values: Vec<f32>,
+    metric: PointMetric,
+}
+
+impl HnswPoint {
+    fn new<T>(embed: &Embedding<T>, metric: PointMetric) -> Self
+    where
+        T: AsPoint,
+    {
+        HnswPoint {
+            values: embed.embed.as_point(),
+            metric,
+        }
+    }
+}
+
+impl Point for HnswPoint {
+    fn distance(&self, other: &Self) -> f32 {
+        let pairs = self.values.iter().zip(other.values.iter());
+        match self.metric {
+            PointMetric::Dot => -pairs.map(|(a, b)| a * b).sum::<f32>(),
+            PointMetric::L2 => pairs.map(|(a, b)| (a - b) * (a - b)).sum::<f32>().sqrt(),
+            PointMetric::L1 => pairs.map(|(a, b)| (a - b).abs()).sum::<f32>(),
+            PointMetric::Canberra => pairs
+                .map(|(a, b)| match a.abs() + b.abs() {
+                    denom if denom > 0.0 => (a - b).abs() / denom,
+                    _ => 0.0,
+                })
+                .sum::<f32>(),
+            PointMetric::BrayCurtis => {
+                let (diff, total) = pairs.fold((0.0, 0.0), |(diff, total), (a, b)| {
+                    (diff + (a - b).abs(), total + a.abs() + b.abs())
+                });
+                if total > 0.0 {
+                    diff / total
+                } else {
+                    0.0
+                }
+            }
+            PointMetric::Cosine => {
+                let (dot, norm_a, norm_b) = pairs.fold((0.0, 0.0, 0.0), |(dot, na, nb), (a, b)| {
+                    (dot + a * b, na + a * a, nb + b * b)
+                });
+                let denom: f32 = (norm_a * norm_b).sqrt();
+                if denom > 0.0 {
+                    1.0 - dot / denom
+                } else {
+                    1.0
+                }
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HnswTree {
+    map: HnswMap<HnswPoint, usize>,
+    metric: PointMetric,
+    hash: String,
+    distance_name: String,
+}
+
+impl HnswTree {
+    pub fn check_provider<'a, E, D, T>(&self, provider: &E) -> Result<(), MisconfiguredTreeError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+    {
+        if self.distance_name != provider.distance().name() {
+            return Err(MisconfiguredTreeError);
+        }
+        if self.hash != provider.compute_hash() {
+            return Err(MisconfiguredTreeError);
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_slice(bytes)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, serde_json::Error> {
+        serde_json::to_vec(self)
+    }
+}
+
+impl<'a, E, D, T> Tree<'a, E, D, T> for HnswTree
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a + AsPoint,
+{
+    fn build<C, I>(
+        provider: &'a E,
+        max_node_size: Option<usize>,
+        _pre_cluster: Option<usize>,
+        _cache: &mut C,
+        _info: &mut I,
+    ) -> Self
+    where
+        C: Cache,
+        I: Info,
+    {
+        let metric = PointMetric::for_name(provider.distance().name());
+        let indices: Vec<usize> = provider.all().collect();
+        let points: Vec<HnswPoint> = indices
+            .iter()
+            .map(|&ix| HnswPoint::new(&provider.get(ix), metric))
+            .collect();
+        let mut builder = Builder::default();
+        if let Some(ef_search) = max_node_size {
+            builder = builder.ef_search(ef_search);
+        }
+        HnswTree {
+            map: builder.build(points, indices),
+            metric,
+            hash: provider.compute_hash(),
+            distance_name: provider.distance().name().to_string(),
+        }
+    }
+
+    fn draw<I>(
+        &self,
+        _high_ix: usize,
+        info: Option<&I>,
+        res: Option<Vec<(usize, DistanceValue)>>,
+        _prune: bool,
+        _radius: bool,
+    ) -> String
+    where
+        I: Info,
+    {
+        let dist_count = info.map_or(0, |info| info.dist_count());
+        let res = res
+            .unwrap_or_default()
+            .iter()
+            .map(|(ix, _)| format!("*{ix}"))
+            .collect::<Vec<String>>()
+            .join(", ");
+        format!(
+            "(hnsw[n:{n} d:{dist_count}])━({res})",
+            n = self.len(),
+            dist_count = dist_count,
+            res = res,
+        )
+    }
+
+    fn get_closest<I>(
+        &self,
+        count: usize,
+        ldist: &LocalDistance<'a, E, D, T>,
+        info: &mut I,
+    ) -> Vec<(usize, DistanceValue)>
+    where
+        I: Info,
+    {
+        let point = HnswPoint::new(ldist.embed(), self.metric);
+        let mut search = Search::default();
+        let mut res: Vec<(usize, DistanceCmp)> = self
+            .map
+            .search(&point, &mut search)
+            .take(count)
+            .map(|item| {
+                let ix = *item.value;
+                (ix, ldist.distance_cmp(ix, info))
+            })
+            .collect();
+        res.sort_unstable_by_key(|(_, dist)| *dist);
+        res.iter()
+            .map(|(ix, v)| (*ix, ldist.finalize_distance(v)))
+            .collect()
+    }
+
+    fn fingerprint(&self) -> (&str, &str) {
+        (&self.hash, &self.distance_name)
+    }
+
+    fn index_end(&self) -> usize {
+        self.map.values.iter().max().map_or(0, |&ix| ix + 1)
+    }
+
+    fn len(&self) -> usize {
+        HnswTree::len(self)
+    }
+}
@@ -1,186 +1,1537 @@
-use rayon::prelude::*;
+use bitvec::vec::BitVec;
+use blake2::Blake2s256;
+use digest::Digest;
+use log::{debug, warn};
 use serde::{self, Deserialize, Serialize};
 use std::{
-    collections::{HashMap, VecDeque},
-    iter::repeat,
+    cell::RefCell,
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    fmt,
+    marker::PhantomData,
+    sync::{Arc, OnceLock},
 };
+#[cfg(feature = "archive")]
 use zip::{result::ZipError, write::FileOptions};
 
 use crate::{
-    info::Info, Cache, Distance, DistanceCmp, Embedding, EmbeddingProvider, LocalDistance, Tree,
+    cache::no_cache,
+    clustering::{self, KMedoidsParams},
+    distances::{
+        dynamic::DynDistance,
+        registry::{DistanceRegistry, UnknownDistance},
+    },
+    ids::{IdMap, IdMapError},
+    info::{no_info, Info},
+    Cache, DimensionMismatch, Distance, DistanceCmp, DistanceValue, Embedding, EmbeddingProvider,
+    FingerprintPolicy, LocalDistance, MisconfiguredTreeError, Tree,
 };
 
 #[derive(Debug)]
 pub enum TreeLoadError {
+    #[cfg(feature = "archive")]
     ZipError(ZipError),
     SerdeError(serde_json::Error),
+    IoError(std::io::Error),
+    InsertError(InsertError),
 }
 
+impl From<InsertError> for TreeLoadError {
+    fn from(value: InsertError) -> Self {
+        TreeLoadError::InsertError(value)
+    }
+}
+
+impl From<std::io::Error> for TreeLoadError {
+    fn from(value: std::io::Error) -> Self {
+        TreeLoadError::IoError(value)
+    }
+}
+
+#[cfg(feature = "archive")]
 impl From<ZipError> for TreeLoadError {
     fn from(value: ZipError) -> Self {
         TreeLoadError::ZipError(value)
     }
-}
+}
+
+impl From<serde_json::Error> for TreeLoadError {
+    fn from(value: serde_json::Error) -> Self {
+        TreeLoadError::SerdeError(value)
+    }
+}
+
+#[derive(Debug)]
+pub enum TreeWriteError {
+    #[cfg(feature = "archive")]
+    ZipError(ZipError),
+    SerdeError(serde_json::Error),
+    IoError(std::io::Error),
+    InsertError(InsertError),
+}
+
+impl From<InsertError> for TreeWriteError {
+    fn from(value: InsertError) -> Self {
+        TreeWriteError::InsertError(value)
+    }
+}
+
+impl From<std::io::Error> for TreeWriteError {
+    fn from(value: std::io::Error) -> Self {
+        TreeWriteError::IoError(value)
+    }
+}
+
+#[cfg(feature = "archive")]
+impl From<ZipError> for TreeWriteError {
+    fn from(value: ZipError) -> Self {
+        TreeWriteError::ZipError(value)
+    }
+}
+
+impl From<serde_json::Error> for TreeWriteError {
+    fn from(value: serde_json::Error) -> Self {
+        TreeWriteError::SerdeError(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertError {
+    OutOfRange(usize),
+    AlreadyPresent(usize),
+    DimensionMismatch(DimensionMismatch),
+}
+
+impl From<DimensionMismatch> for InsertError {
+    fn from(value: DimensionMismatch) -> Self {
+        InsertError::DimensionMismatch(value)
+    }
+}
+
+impl fmt::Display for InsertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InsertError::OutOfRange(index) => write!(f, "index {index} is not in the provider"),
+            InsertError::AlreadyPresent(index) => write!(f, "index {index} is already in the tree"),
+            InsertError::DimensionMismatch(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvariantError {
+    RadiusTooSmall {
+        index: usize,
+        radius: DistanceValue,
+        required: DistanceValue,
+    },
+    CenterDistMismatch {
+        index: usize,
+        stored: DistanceValue,
+        actual: DistanceValue,
+    },
+    SiblingDistMismatch {
+        index_a: usize,
+        index_b: usize,
+        stored: DistanceValue,
+        actual: DistanceValue,
+    },
+    OutOfRange(usize),
+    RepeatedIndex(usize),
+    UnknownDeleted(usize),
+    SizeMismatch {
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvariantError::RadiusTooSmall {
+                index,
+                radius,
+                required,
+            } => write!(f, "radius {radius} of node {index} is below {required}"),
+            InvariantError::CenterDistMismatch {
+                index,
+                stored,
+                actual,
+            } => write!(
+                f,
+                "node {index} stores parent distance {stored} but is {actual} away"
+            ),
+            InvariantError::SiblingDistMismatch {
+                index_a,
+                index_b,
+                stored,
+                actual,
+            } => write!(
+                f,
+                "siblings {index_a} and {index_b} store distance {stored} but are {actual} apart"
+            ),
+            InvariantError::OutOfRange(index) => {
+                write!(f, "index {index} is not in the provider")
+            }
+            InvariantError::RepeatedIndex(index) => {
+                write!(f, "index {index} appears more than once")
+            }
+            InvariantError::UnknownDeleted(index) => {
+                write!(f, "deleted index {index} is not in the tree")
+            }
+            InvariantError::SizeMismatch { expected, actual } => {
+                write!(f, "expected {expected} indices got {actual}")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "archive")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundtripMismatch {
+    pub query: usize,
+    pub expected: Vec<(usize, DistanceValue)>,
+    pub actual: Vec<(usize, DistanceValue)>,
+}
+
+#[cfg(feature = "archive")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundtripReport {
+    pub queries: usize,
+    pub metadata_matches: bool,
+    pub mismatches: Vec<RoundtripMismatch>,
+}
+
+#[cfg(feature = "archive")]
+impl RoundtripReport {
+    pub fn is_consistent(&self) -> bool {
+        self.metadata_matches && self.mismatches.is_empty()
+    }
+}
+
+mod chunks;
+#[cfg(feature = "archive")]
+mod spill;
+mod stream;
+
+use chunks::CowVec;
+pub use stream::{NeighborStream, StreamStats};
+
+const HIGHLIGHT_A: &str = "*";
+const HIGHLIGHT_B: &str = ":";
+const NO_HIGHLIGHT: &str = "";
+const DEGENERATE_FACTOR: usize = 8;
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct NodeData {
+    centroid_index: usize,
+    radius: DistanceCmp,
+    center_dist: DistanceCmp,
+    child_start: usize,
+    child_end: usize,
+    sibling_start: usize,
+}
+
+enum SearchStep {
+    Visit(usize, DistanceCmp),
+    Outer(usize, usize, DistanceCmp, DistanceCmp),
+    Inner(usize, DistanceCmp, DistanceCmp),
+}
+
+// the bounds add and subtract distances so they are computed on the metric
+// values, the comparison values of l2 for example are squared
+trait MetricBounds {
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue;
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp;
+
+    fn lower_bound(&self, dist: &DistanceCmp, radius: &DistanceCmp) -> DistanceCmp {
+        let res = self.to_metric(dist) - self.to_metric(radius);
+        self.metric_cmp(DistanceValue::max(0.0, res))
+    }
+
+    fn upper_bound(&self, dist: &DistanceCmp, radius: &DistanceCmp) -> DistanceCmp {
+        self.metric_cmp(self.to_metric(dist) + self.to_metric(radius))
+    }
+
+    fn gap(&self, dist_a: &DistanceCmp, dist_b: &DistanceCmp) -> DistanceCmp {
+        self.metric_cmp((self.to_metric(dist_a) - self.to_metric(dist_b)).abs())
+    }
+}
+
+struct DistanceBounds<D, T> {
+    distance: D,
+    embed_type: PhantomData<T>,
+}
+
+impl<D, T> DistanceBounds<D, T>
+where
+    D: Distance<T>,
+{
+    fn new(distance: D) -> Self {
+        DistanceBounds {
+            distance,
+            embed_type: PhantomData,
+        }
+    }
+}
+
+impl<D, T> MetricBounds for DistanceBounds<D, T>
+where
+    D: Distance<T>,
+{
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        self.distance.to_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        self.distance.metric_cmp(value)
+    }
+}
+
+trait QueryDistance: MetricBounds {
+    fn query_dist<I>(&self, index: usize, info: &mut I) -> DistanceCmp
+    where
+        I: Info;
+
+    fn query_dists<I>(&self, indices: &[usize], info: &mut I) -> Vec<DistanceCmp>
+    where
+        I: Info,
+    {
+        indices
+            .iter()
+            .map(|&index| self.query_dist(index, info))
+            .collect()
+    }
+
+    fn finalize_dist(&self, dist_cmp: &DistanceCmp) -> DistanceValue;
+
+    fn is_signed(&self) -> bool;
+
+    fn is_metric(&self) -> bool;
+
+    // the triangle inequality bounds only hold for non-negative metrics
+    fn can_prune(&self) -> bool {
+        !self.is_signed() && self.is_metric()
+    }
+}
+
+impl<'a, E, D, T> MetricBounds for LocalDistance<'a, E, D, T>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+{
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        LocalDistance::to_metric(self, dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        LocalDistance::metric_cmp(self, value)
+    }
+}
+
+impl<'a, E, D, T> QueryDistance for LocalDistance<'a, E, D, T>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+{
+    fn query_dist<I>(&self, index: usize, info: &mut I) -> DistanceCmp
+    where
+        I: Info,
+    {
+        self.distance_cmp(index, info)
+    }
+
+    fn query_dists<I>(&self, indices: &[usize], info: &mut I) -> Vec<DistanceCmp>
+    where
+        I: Info,
+    {
+        self.distance_batch(indices, info)
+    }
+
+    fn finalize_dist(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        self.finalize_distance(dist_cmp)
+    }
+
+    fn is_signed(&self) -> bool {
+        LocalDistance::is_signed(self)
+    }
+
+    fn is_metric(&self) -> bool {
+        LocalDistance::is_metric(self)
+    }
+}
+
+// like LocalDistance but the query may come from a different provider
+struct CrossDistance<'a, 'q, E, D, T> {
+    provider: &'a E,
+    embed: &'q Embedding<T>,
+    distance_type: PhantomData<D>,
+}
+
+impl<'a, E, D, T> MetricBounds for CrossDistance<'a, '_, E, D, T>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+{
+    fn to_metric(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        self.provider.distance().to_metric(dist_cmp)
+    }
+
+    fn metric_cmp(&self, value: DistanceValue) -> DistanceCmp {
+        self.provider.distance().metric_cmp(value)
+    }
+}
+
+impl<'a, E, D, T> QueryDistance for CrossDistance<'a, '_, E, D, T>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+{
+    fn query_dist<I>(&self, index: usize, info: &mut I) -> DistanceCmp
+    where
+        I: Info,
+    {
+        info.log_dist(&Some(index));
+        let distance = self.provider.distance();
+        distance.distance_cmp(self.embed, &self.provider.get(index))
+    }
+
+    fn finalize_dist(&self, dist_cmp: &DistanceCmp) -> DistanceValue {
+        self.provider.distance().finalize_distance(dist_cmp)
+    }
+
+    fn is_signed(&self) -> bool {
+        self.provider.distance().is_signed()
+    }
+
+    fn is_metric(&self) -> bool {
+        self.provider.distance().is_metric()
+    }
+}
+
+#[derive(Clone, Copy)]
+enum JoinPart {
+    Point(usize),
+    Subtree(usize),
+}
+
+enum JoinStep {
+    Within(usize),
+    Between(JoinPart, JoinPart),
+}
+
+thread_local! {
+    static QUERY_CONTEXT: RefCell<QueryContext> = RefCell::new(QueryContext::new());
+}
+
+#[derive(Default)]
+pub struct QueryContext {
+    res: Vec<(usize, DistanceCmp)>,
+    stack: Vec<SearchStep>,
+    inners: Vec<(usize, DistanceCmp, DistanceCmp)>,
+    output: Vec<(usize, DistanceValue)>,
+    budget: Option<usize>,
+}
+
+impl QueryContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(count: usize) -> Self {
+        QueryContext {
+            res: Vec::with_capacity(count + 1),
+            stack: Vec::new(),
+            inners: Vec::new(),
+            output: Vec::with_capacity(count),
+            budget: None,
+        }
+    }
+
+    // stops a search after visiting this many nodes. the result is the best
+    // found so far which trades exactness for speed
+    pub fn set_budget(&mut self, budget: Option<usize>) {
+        self.budget = budget;
+    }
+
+    pub fn budget(&self) -> Option<usize> {
+        self.budget
+    }
+
+    fn clear(&mut self) {
+        self.res.clear();
+        self.stack.clear();
+        self.inners.clear();
+        self.output.clear();
+    }
+}
+
+impl NodeData {
+    fn new(centroid_index: usize, center_dist: DistanceCmp) -> Self {
+        NodeData {
+            centroid_index,
+            radius: DistanceCmp::zero(),
+            center_dist,
+            child_start: 0,
+            child_end: 0,
+            sibling_start: 0,
+        }
+    }
+
+    fn children(&self) -> std::ops::Range<usize> {
+        self.child_start..self.child_end
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.child_start == self.child_end
+    }
+
+    fn get_dist<Q, I>(&self, ldist: &Q, info: &mut I) -> DistanceCmp
+    where
+        Q: QueryDistance,
+        I: Info,
+    {
+        ldist.query_dist(self.centroid_index, info)
+    }
+
+    fn get_dist_min<M>(&self, dist: &DistanceCmp, bounds: &M) -> DistanceCmp
+    where
+        M: MetricBounds,
+    {
+        bounds.lower_bound(dist, &self.radius)
+    }
+
+    fn get_dist_max<M>(&self, bounds: &M) -> DistanceCmp
+    where
+        M: MetricBounds,
+    {
+        bounds.upper_bound(&self.center_dist, &self.radius)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FannTree {
+    nodes: CowVec<NodeData>,
+    sibling_dists: CowVec<DistanceCmp>,
+    size: usize,
+    deleted: HashSet<usize>,
+    max_node_size: usize,
+    pre_cluster: Option<usize>,
+    hash: String,
+    #[serde(default)]
+    generation: u64,
+    distance_name: String,
+    // shared with snapshots, only replaced as a whole
+    permutation: Option<Arc<Vec<usize>>>,
+    #[serde(default)]
+    duplicates: HashMap<usize, Vec<usize>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ids: Option<Arc<IdMap>>,
+    // lengths of nodes and sibling_dists after the last repack
+    #[serde(skip)]
+    packed: (usize, usize),
+    // every index with a node or in a duplicate group, tombstones included.
+    // built on first use so loading a tree does not pay for it
+    #[serde(skip)]
+    members: OnceLock<BitVec>,
+}
+
+impl FannTree {
+    fn get_dist<'a, E, D, T, C, I>(
+        provider: &E,
+        embed_a: &Embedding<T>,
+        embed_b: &Embedding<T>,
+        cache: &mut C,
+        info: &mut I,
+    ) -> DistanceCmp
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        cache.cached_distance(embed_a, embed_b, provider.distance(), info)
+    }
+
+    fn centroid<'a, E, D, T, C, I>(
+        provider: &'a E,
+        all_ixs: &[usize],
+        cache: &mut C,
+        info: &mut I,
+    ) -> usize
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        provider.medoid_of(all_ixs, cache, info)
+    }
+
+    fn kmedoid<'a, E, D, T, C, I>(
+        provider: &'a E,
+        all_ixs: Vec<usize>,
+        init_centroids: Option<Vec<usize>>,
+        k_num: usize,
+        cache: &mut C,
+        info: &mut I,
+    ) -> Vec<(usize, Vec<usize>)>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        if all_ixs.len() <= k_num {
+            return all_ixs.iter().map(|&ix| (ix, Vec::new())).collect();
+        }
+        let size = all_ixs.len();
+        let res = clustering::kmedoids_of(
+            provider,
+            all_ixs,
+            init_centroids,
+            k_num,
+            KMedoidsParams::default(),
+            cache,
+            info,
+        )
+        // k is at least two here and the initial medoids come from all_ixs
+        .expect("valid k-medoids parameters");
+        if !res.converged {
+            warn!(
+                "k-medoids did not converge after {rounds} rounds (size: {size} k: {k_num})",
+                rounds = res.rounds,
+            );
+        }
+        // every point but the medoids landed in the same cluster even though
+        // there were plenty of points to spread out
+        let largest = res.clusters.iter().map(|c| c.members.len()).max();
+        if k_num > 1 && size >= DEGENERATE_FACTOR * k_num && largest == Some(size - k_num + 1) {
+            warn!("degenerate clustering: {size} points collapsed into one of {k_num} clusters");
+        }
+        res.clusters
+            .into_iter()
+            .map(|cluster| (cluster.medoid, cluster.members))
+            .collect()
+    }
+
+    fn remove_from(ixs: &mut Vec<usize>, index: usize) {
+        ixs.retain(|&ix| ix != index);
+    }
+
+    fn build_level<'a, E, D, T, C, I>(
+        provider: &'a E,
+        cache: &mut C,
+        info: &mut I,
+        cur_all_ixs: Vec<usize>,
+        max_node_size: usize,
+        pre_cluster: Option<usize>,
+    ) -> Vec<(usize, Vec<usize>)>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        let num_k = if max_node_size * max_node_size > cur_all_ixs.len() {
+            ((cur_all_ixs.len() as f64).sqrt() as usize).max(1)
+        } else {
+            max_node_size
+        };
+        if num_k == 1 || cur_all_ixs.len() <= num_k {
+            return cur_all_ixs.into_iter().map(|ix| (ix, Vec::new())).collect();
+        }
+        // TODO pre_cluster makes things slower
+        let init_centroids = match pre_cluster {
+            Some(pre_cluster) => {
+                if cur_all_ixs.len() <= pre_cluster * num_k * 2 {
+                    None
+                } else {
+                    Some(
+                        Self::kmedoid(
+                            provider,
+                            cur_all_ixs
+                                .iter()
+                                .take(pre_cluster * num_k)
+                                .copied()
+                                .collect(),
+                            None,
+                            num_k,
+                            cache,
+                            info,
+                        )
+                        .into_iter()
+                        .map(|(cix, _)| cix)
+                        .collect(),
+                    )
+                }
+            }
+            None => None,
+        };
+        Self::kmedoid(provider, cur_all_ixs, init_centroids, num_k, cache, info)
+            .into_iter()
+            .map(|(centroid_ix, mut assignments)| {
+                Self::remove_from(&mut assignments, centroid_ix);
+                (centroid_ix, assignments)
+            })
+            .collect()
+    }
+
+    fn grow<'a, E, D, T, C, I>(
+        &mut self,
+        provider: &'a E,
+        cache: &mut C,
+        info: &mut I,
+        root_ix: usize,
+        all_ixs: Vec<usize>,
+    ) where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        let first_new = self.nodes.len();
+        let mut queue = VecDeque::from([(root_ix, all_ixs)]);
+        let mut children: Vec<(NodeData, Vec<usize>)> = Vec::new();
+        while let Some((node_ix, cur_all_ixs)) = queue.pop_front() {
+            let centroid = provider.get(self.nodes[node_ix].centroid_index);
+            children.extend(
+                Self::build_level(
+                    provider,
+                    cache,
+                    info,
+                    cur_all_ixs,
+                    self.max_node_size,
+                    self.pre_cluster,
+                )
+                .into_iter()
+                .map(|(cix, assignments)| {
+                    let center_dist =
+                        Self::get_dist(provider, &centroid, &provider.get(cix), cache, info);
+                    (NodeData::new(cix, center_dist), assignments)
+                }),
+            );
+            children.sort_unstable_by(|(a, _), (b, _)| a.center_dist.cmp(&b.center_dist).reverse());
+            self.nodes[node_ix].child_start = self.nodes.len();
+            children.drain(..).for_each(|(child, assignments)| {
+                if !assignments.is_empty() {
+                    queue.push_back((self.nodes.len(), assignments));
+                }
+                self.nodes.push(child);
+            });
+            self.nodes[node_ix].child_end = self.nodes.len();
+            self.compute_sibling_dists(node_ix, 0, provider, cache, info);
+        }
+        // children are always stored after their parent
+        let bounds = DistanceBounds::new(provider.distance());
+        for node_ix in (first_new..self.nodes.len()).rev() {
+            self.compute_radius(node_ix, &bounds);
+        }
+        self.compute_radius(root_ix, &bounds);
+    }
+
+    // the current block of the node is overwritten if the new one fits into
+    // the reserved slots or can grow at the end
+    fn compute_sibling_dists<'a, E, D, T, C, I>(
+        &mut self,
+        node_ix: usize,
+        reserved: usize,
+        provider: &'a E,
+        cache: &mut C,
+        info: &mut I,
+    ) where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        let children = self.nodes[node_ix].children();
+        let child_count = children.len();
+        let size = child_count * child_count;
+        let current = self.nodes[node_ix].sibling_start;
+        let sibling_start = if size <= reserved || current + reserved == self.sibling_dists.len() {
+            current
+        } else {
+            self.sibling_dists.len()
+        };
+        self.nodes[node_ix].sibling_start = sibling_start;
+        if sibling_start + size > self.sibling_dists.len() {
+            self.sibling_dists
+                .resize(sibling_start + size, DistanceCmp::zero());
+        }
+        for (pos_a, ix_a) in children.clone().enumerate() {
+            let embed_a = provider.get(self.nodes[ix_a].centroid_index);
+            for (pos_b, ix_b) in children.clone().enumerate().skip(pos_a + 1) {
+                let embed_b = provider.get(self.nodes[ix_b].centroid_index);
+                let dist = Self::get_dist(provider, &embed_a, &embed_b, cache, info);
+                self.sibling_dists[sibling_start + pos_a * child_count + pos_b] = dist;
+                self.sibling_dists[sibling_start + pos_b * child_count + pos_a] = dist;
+            }
+        }
+    }
+
+    fn compute_radius<M>(&mut self, node_ix: usize, bounds: &M)
+    where
+        M: MetricBounds,
+    {
+        // tombstoned leaves stay covered until compact drops them so an insert
+        // can revive them without walking their path
+        self.nodes[node_ix].radius = self
+            .nodes
+            .range(self.nodes[node_ix].children())
+            .map(|child| child.get_dist_max(bounds))
+            .max()
+            .unwrap_or(DistanceCmp::zero());
+    }
+
+    fn subtree_points(&self, node_ix: usize) -> Vec<usize> {
+        let mut points = Vec::new();
+        let mut stack = Vec::from_iter(self.nodes[node_ix].children());
+        while let Some(cur_ix) = stack.pop() {
+            let node = &self.nodes[cur_ix];
+            points.push(node.centroid_index);
+            stack.extend(node.children());
+        }
+        points
+    }
+
+    pub fn insert<'a, E, D, T, C, I>(
+        &mut self,
+        index: usize,
+        provider: &'a E,
+        cache: &mut C,
+        info: &mut I,
+    ) -> Result<(), InsertError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        Self::check_index(index, provider)?;
+        // the radii still cover tombstoned leaves so they come back as is
+        if self.deleted.remove(&index) {
+            return Ok(());
+        }
+        if self.contains(index) {
+            return Err(InsertError::AlreadyPresent(index));
+        }
+        self.insert_node(index, None, provider, cache, info);
+        self.reclaim();
+        Ok(())
+    }
+
+    pub fn check_index<'a, E, D, T>(index: usize, provider: &'a E) -> Result<(), InsertError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+    {
+        if !provider.all().contains(&index) {
+            return Err(InsertError::OutOfRange(index));
+        }
+        Ok(())
+    }
+
+    // a deleted index can be inserted again, a live one cannot
+    pub fn check_insert<'a, E, D, T>(
+        &self,
+        index: usize,
+        provider: &'a E,
+    ) -> Result<(), InsertError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+    {
+        Self::check_index(index, provider)?;
+        if self.contains(index) {
+            return Err(InsertError::AlreadyPresent(index));
+        }
+        Ok(())
+    }
+
+    // free is a parent and the slot right after its children that an
+    // updated leaf left behind. returns the node the index was added to
+    fn insert_node<'a, E, D, T, C, I>(
+        &mut self,
+        index: usize,
+        free: Option<(usize, usize)>,
+        provider: &'a E,
+        cache: &mut C,
+        info: &mut I,
+    ) -> usize
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        self.set_member(index, true);
+        if self.nodes.is_empty() {
+            self.nodes.push(NodeData::new(index, DistanceCmp::zero()));
+            self.size = 1;
+            self.mark_changed(provider);
+            return 0;
+        }
+        let embed = provider.get(index);
+        let mut path = Vec::new();
+        let mut node_ix = 0;
+        loop {
+            path.push(node_ix);
+            let closest = self.nodes[node_ix]
+                .children()
+                .map(|child_ix| {
+                    let child_embed = provider.get(self.nodes[child_ix].centroid_index);
+                    let dist = Self::get_dist(provider, &child_embed, &embed, cache, info);
+                    (dist, child_ix)
+                })
+                .min();
+            match closest {
+                Some((_, child_ix)) if !self.nodes[child_ix].is_leaf() => node_ix = child_ix,
+                _ => break,
+            }
+        }
+        let centroid = provider.get(self.nodes[node_ix].centroid_index);
+        let center_dist = Self::get_dist(provider, &centroid, &embed, cache, info);
+        let children = self.nodes[node_ix].children();
+        let child_count = children.len();
+        // the sibling block of the parent still has room for the updated leaf
+        let reserved = if free == Some((node_ix, children.end)) && child_count > 0 {
+            self.nodes[children.end] = NodeData::new(index, center_dist);
+            (child_count + 1) * (child_count + 1)
+        } else {
+            if children.end != self.nodes.len() || children.is_empty() {
+                // move the children to the end so the new child can be appended
+                let child_start = self.nodes.len();
+                self.nodes.extend_from_within(children.clone());
+                self.nodes[node_ix].child_start = child_start;
+            }
+            self.nodes.push(NodeData::new(index, center_dist));
+            child_count * child_count
+        };
+        self.nodes[node_ix].child_end = self.nodes[node_ix].child_start + child_count + 1;
+        self.size += 1;
+        let children = self.nodes[node_ix].children();
+        if children.len() > self.max_node_size {
+            let points = self.subtree_points(node_ix);
+            self.grow(provider, cache, info, node_ix, points);
+        } else {
+            self.nodes
+                .sort_range_by(children, |a, b| a.center_dist.cmp(&b.center_dist).reverse());
+            self.compute_sibling_dists(node_ix, reserved, provider, cache, info);
+        }
+        path.into_iter().rev().for_each(|path_ix| {
+            self.compute_radius(path_ix, &DistanceBounds::new(provider.distance()))
+        });
+        self.mark_changed(provider);
+        node_ix
+    }
+
+    // moved children, split subtrees and recomputed sibling blocks leave
+    // their old slots behind. repacking once the storage doubled keeps it
+    // within a constant factor of the live tree at amortized constant cost
+    fn reclaim(&mut self) {
+        let (nodes, sibling_dists) = self.packed;
+        if self.nodes.len() > 2 * nodes || self.sibling_dists.len() > 2 * sibling_dists {
+            self.repack();
+        }
+    }
+
+    pub fn slot_counts(&self) -> (usize, usize) {
+        (self.nodes.len(), self.sibling_dists.len())
+    }
+
+    // nodes with fewer than two children cannot partition anything
+    fn node_size(max_node_size: Option<usize>, size: usize) -> usize {
+        max_node_size.unwrap_or(size).max(2)
+    }
+
+    pub fn build_from<'a, E, D, T, C, I>(
+        provider: &'a E,
+        mut all_ixs: Vec<usize>,
+        max_node_size: Option<usize>,
+        pre_cluster: Option<usize>,
+        cache: &mut C,
+        info: &mut I,
+    ) -> Self
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        let max_node_size = Self::node_size(max_node_size, all_ixs.len());
+        let pre_cluster = pre_cluster.filter(|&pre_cluster| pre_cluster > 0);
+        if all_ixs.is_empty() {
+            return Self::empty(provider, max_node_size, pre_cluster);
+        }
+        let root_ix = Self::centroid(provider, &all_ixs, cache, info);
+
+        Self::remove_from(&mut all_ixs, root_ix);
+        let mut tree = Self::with_root(provider, root_ix, max_node_size, pre_cluster);
+        tree.size += all_ixs.len();
+        tree.grow(provider, cache, info, 0, all_ixs);
+        debug!(
+            "built tree (size: {size} nodes: {nodes} max node size: {max_node_size})",
+            size = tree.size,
+            nodes = tree.nodes.len(),
+        );
+        tree.packed = tree.slot_counts();
+        tree
+    }
 
-impl From<serde_json::Error> for TreeLoadError {
-    fn from(value: serde_json::Error) -> Self {
-        TreeLoadError::SerdeError(value)
+    // builds a fresh tree over the live points and the extra indices. the
+    // permutation, ids and duplicate groups carry over so results keep
+    // their original indices
+    pub fn rebuild_with<'a, E, D, T, C, I>(
+        &self,
+        extra: &[usize],
+        provider: &'a E,
+        max_node_size: Option<usize>,
+        pre_cluster: Option<usize>,
+        cache: &mut C,
+        info: &mut I,
+    ) -> Self
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        let duplicates: HashMap<usize, Vec<usize>> = self
+            .duplicates
+            .iter()
+            .filter(|(rep, _)| !self.deleted.contains(rep))
+            .map(|(&rep, dups)| {
+                let dups: Vec<usize> = dups
+                    .iter()
+                    .copied()
+                    .filter(|dup| !self.deleted.contains(dup))
+                    .collect();
+                (rep, dups)
+            })
+            .filter(|(_, dups)| !dups.is_empty())
+            .collect();
+        let mut all_ixs = Vec::with_capacity(self.size + extra.len());
+        if !self.nodes.is_empty() {
+            all_ixs.push(self.nodes[0].centroid_index);
+            all_ixs.extend(self.subtree_points(0));
+        }
+        all_ixs.retain(|ix| !self.deleted.contains(ix));
+        all_ixs.extend(
+            extra
+                .iter()
+                .copied()
+                .filter(|ix| !duplicates.values().any(|dups| dups.contains(ix))),
+        );
+        all_ixs.sort_unstable();
+        all_ixs.dedup();
+        let mut tree = Self::build_from(provider, all_ixs, max_node_size, pre_cluster, cache, info);
+        tree.duplicates = duplicates;
+        tree.members = OnceLock::new();
+        tree.permutation.clone_from(&self.permutation);
+        tree.ids.clone_from(&self.ids);
+        tree
     }
-}
 
-#[derive(Debug)]
-pub enum TreeWriteError {
-    ZipError(ZipError),
-    SerdeError(serde_json::Error),
-}
+    pub fn build_collapsed<'a, E, D, T, C, I>(
+        provider: &'a E,
+        max_node_size: Option<usize>,
+        pre_cluster: Option<usize>,
+        cache: &mut C,
+        info: &mut I,
+    ) -> Self
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        let mut representatives: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut duplicates: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut all_ixs = Vec::new();
+        provider.all().for_each(|ix| {
+            let mut hasher = Blake2s256::new();
+            provider.hash_embed(ix, &mut hasher);
+            match representatives.entry(hasher.finalize().to_vec()) {
+                Entry::Occupied(entry) => duplicates.entry(*entry.get()).or_default().push(ix),
+                Entry::Vacant(entry) => {
+                    entry.insert(ix);
+                    all_ixs.push(ix);
+                }
+            }
+        });
+        let mut tree = Self::build_from(provider, all_ixs, max_node_size, pre_cluster, cache, info);
+        tree.duplicates = duplicates;
+        tree
+    }
 
-impl From<ZipError> for TreeWriteError {
-    fn from(value: ZipError) -> Self {
-        TreeWriteError::ZipError(value)
+    pub fn duplicates_of(&self, index: usize) -> &[usize] {
+        self.duplicates.get(&index).map_or(&[], |dups| dups)
     }
-}
 
-impl From<serde_json::Error> for TreeWriteError {
-    fn from(value: serde_json::Error) -> Self {
-        TreeWriteError::SerdeError(value)
+    pub fn duplicate_count(&self) -> usize {
+        self.duplicates.values().map(|dups| dups.len()).sum()
     }
-}
 
-const HIGHLIGHT_A: &str = "*";
-const HIGHLIGHT_B: &str = ":";
-const NO_HIGHLIGHT: &str = "";
+    fn group(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        std::iter::once(index).chain(
+            self.duplicates_of(index)
+                .iter()
+                .copied()
+                .filter(|dup| !self.deleted.contains(dup)),
+        )
+    }
 
-#[derive(Serialize, Deserialize)]
-struct Child {
-    node: Node,
-    center_dist: DistanceCmp,
-}
+    fn expand_duplicates<V>(&self, values: &mut HashMap<usize, V>)
+    where
+        V: Copy,
+    {
+        self.duplicates.iter().for_each(|(rep, dups)| {
+            if let Some(&value) = values.get(rep) {
+                dups.iter().for_each(|&dup| {
+                    values.insert(dup, value);
+                });
+            }
+        });
+    }
 
-#[derive(Serialize, Deserialize)]
-struct Node {
-    centroid_index: usize,
-    radius: DistanceCmp,
-    children: Vec<Child>,
-}
+    // a representative hands its node over to one of its duplicates,
+    // preferring one that is not deleted
+    fn hand_over(&mut self, index: usize) {
+        let Some(mut dups) = self.duplicates.remove(&index) else {
+            return;
+        };
+        let pos = dups
+            .iter()
+            .position(|dup| !self.deleted.contains(dup))
+            .unwrap_or(0);
+        let new_rep = dups[pos];
+        dups[pos] = index;
+        if let Some(path) = self.find_path(index) {
+            self.nodes[*path.last().unwrap()].centroid_index = new_rep;
+        }
+        self.duplicates.insert(new_rep, dups);
+    }
 
-impl Node {
-    fn new(centroid_index: usize) -> Self {
-        Node {
-            centroid_index,
-            radius: DistanceCmp::zero(),
-            children: Vec::new(),
+    fn detach_duplicate(&mut self, index: usize) -> bool {
+        self.hand_over(index);
+        let found = self.duplicates.iter_mut().find_map(|(&rep, dups)| {
+            dups.iter().position(|&dup| dup == index).map(|pos| {
+                dups.remove(pos);
+                (rep, dups.is_empty())
+            })
+        });
+        match found {
+            Some((rep, is_empty)) => {
+                if is_empty {
+                    self.duplicates.remove(&rep);
+                }
+                true
+            }
+            None => false,
         }
     }
 
-    fn get_embed<'a, E, D, T>(&self, provider: &'a E) -> Embedding<T>
+    fn purge_duplicates(&mut self) {
+        let deleted = &mut self.deleted;
+        let mut purged = Vec::new();
+        self.duplicates.values_mut().for_each(|dups| {
+            dups.retain(|dup| {
+                let is_deleted = deleted.remove(dup);
+                if is_deleted {
+                    purged.push(*dup);
+                }
+                !is_deleted
+            });
+        });
+        purged
+            .into_iter()
+            .for_each(|index| self.set_member(index, false));
+        self.duplicates.retain(|_, dups| !dups.is_empty());
+    }
+
+    fn with_root<'a, E, D, T>(
+        provider: &'a E,
+        root_ix: usize,
+        max_node_size: usize,
+        pre_cluster: Option<usize>,
+    ) -> Self
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+    {
+        let mut tree = Self::empty(provider, max_node_size, pre_cluster);
+        tree.nodes.push(NodeData::new(root_ix, DistanceCmp::zero()));
+        tree.size = 1;
+        tree
+    }
+
+    fn empty<'a, E, D, T>(provider: &'a E, max_node_size: usize, pre_cluster: Option<usize>) -> Self
     where
         E: EmbeddingProvider<'a, D, T>,
         D: Distance<T> + Copy,
         T: 'a,
     {
-        provider.get(self.centroid_index)
+        Self {
+            nodes: CowVec::new(),
+            sibling_dists: CowVec::new(),
+            size: 0,
+            deleted: HashSet::new(),
+            max_node_size,
+            pre_cluster,
+            hash: provider.compute_hash(),
+            generation: provider.generation(),
+            distance_name: provider.distance().name().to_string(),
+            permutation: None,
+            duplicates: HashMap::new(),
+            ids: None,
+            packed: (0, 0),
+            members: OnceLock::new(),
+        }
+    }
+
+    fn members(&self) -> &BitVec {
+        self.members.get_or_init(|| {
+            let mut members = BitVec::new();
+            let mut add = |index: usize| {
+                if members.len() <= index {
+                    members.resize(index + 1, false);
+                }
+                members.set(index, true);
+            };
+            if !self.nodes.is_empty() {
+                add(self.nodes[0].centroid_index);
+                self.subtree_points(0).into_iter().for_each(&mut add);
+            }
+            self.duplicates.values().flatten().copied().for_each(add);
+            members
+        })
     }
 
-    fn is_before_leaf(&self) -> bool {
-        self.children.iter().all(|c| c.node.children.is_empty())
+    fn set_member(&mut self, index: usize, is_member: bool) {
+        let Some(members) = self.members.get_mut() else {
+            return;
+        };
+        if members.len() <= index {
+            if !is_member {
+                return;
+            }
+            members.resize(index + 1, false);
+        }
+        members.set(index, is_member);
     }
 
-    fn get_internal_dist<'a, E, D, T, C, I>(
-        &self,
-        embed: &Embedding<T>,
+    fn find_path(&self, index: usize) -> Option<Vec<usize>> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut path = Vec::new();
+        let mut stack = Vec::from([(0, 0)]);
+        while let Some((node_ix, depth)) = stack.pop() {
+            path.truncate(depth);
+            path.push(node_ix);
+            let node = &self.nodes[node_ix];
+            if node.centroid_index == index {
+                return Some(path);
+            }
+            stack.extend(node.children().map(|child_ix| (child_ix, depth + 1)));
+        }
+        None
+    }
+
+    fn refresh_children<'a, E, D, T, C, I>(
+        &mut self,
+        node_ix: usize,
         provider: &'a E,
         cache: &mut C,
         info: &mut I,
-    ) -> DistanceCmp
-    where
+    ) where
         E: EmbeddingProvider<'a, D, T>,
         D: Distance<T> + Copy,
         T: 'a,
         C: Cache,
         I: Info,
     {
-        let distance = provider.distance();
-        cache.cached_distance(&self.get_embed(provider), embed, distance, info)
+        let centroid = provider.get(self.nodes[node_ix].centroid_index);
+        for child_ix in self.nodes[node_ix].children() {
+            let child_embed = provider.get(self.nodes[child_ix].centroid_index);
+            self.nodes[child_ix].center_dist =
+                Self::get_dist(provider, &centroid, &child_embed, cache, info);
+        }
+        let children = self.nodes[node_ix].children();
+        let child_count = children.len();
+        self.nodes
+            .sort_range_by(children, |a, b| a.center_dist.cmp(&b.center_dist).reverse());
+        self.compute_sibling_dists(node_ix, child_count * child_count, provider, cache, info);
     }
 
-    fn get_dist<'a, E, D, T, I>(
-        &self,
-        ldist: &LocalDistance<'a, E, D, T>,
+    pub fn update<'a, E, D, T, I>(
+        &mut self,
+        index: usize,
+        provider: &'a E,
         info: &mut I,
-    ) -> DistanceCmp
+    ) -> Result<(), InsertError>
     where
         E: EmbeddingProvider<'a, D, T>,
         D: Distance<T> + Copy,
         T: 'a,
         I: Info,
     {
-        ldist.distance_cmp(self.centroid_index, info)
+        Self::check_index(index, provider)?;
+        // cached distances of the old embedding would be stale
+        let cache = &mut no_cache();
+        if self.detach_duplicate(index) {
+            self.insert_node(index, None, provider, cache, info);
+            self.reclaim();
+            return Ok(());
+        }
+        let Some(mut path) = self.find_path(index) else {
+            return self.insert(index, provider, cache, info);
+        };
+        let node_ix = path.pop().unwrap();
+        match path.last().copied() {
+            Some(parent_ix) if self.nodes[node_ix].is_leaf() => {
+                // closing the gap frees the slot after the remaining children
+                let children = self.nodes[parent_ix].children();
+                let child_count = children.len();
+                self.nodes.copy_within(node_ix + 1..children.end, node_ix);
+                self.nodes[parent_ix].child_end -= 1;
+                self.size -= 1;
+                let free = (parent_ix, children.end - 1);
+                if self.insert_node(index, Some(free), provider, cache, info) != parent_ix {
+                    let reserved = child_count * child_count;
+                    self.compute_sibling_dists(parent_ix, reserved, provider, cache, info);
+                    path.into_iter().rev().for_each(|path_ix| {
+                        self.compute_radius(path_ix, &DistanceBounds::new(provider.distance()))
+                    });
+                }
+            }
+            parent_ix => {
+                self.refresh_children(node_ix, provider, cache, info);
+                self.compute_radius(node_ix, &DistanceBounds::new(provider.distance()));
+                if let Some(parent_ix) = parent_ix {
+                    self.refresh_children(parent_ix, provider, cache, info);
+                }
+                path.into_iter().rev().for_each(|path_ix| {
+                    self.compute_radius(path_ix, &DistanceBounds::new(provider.distance()))
+                });
+                self.mark_changed(provider);
+            }
+        }
+        self.reclaim();
+        Ok(())
     }
 
-    fn get_dist_min(&self, dist: &DistanceCmp) -> DistanceCmp {
-        dist.combine(&self.radius, |d, radius| f64::max(0.0, d - radius))
+    // hashing every row would make each mutation linear in the size of the
+    // provider so the hash is dropped until the next refresh_hash. until then
+    // the tree fails FingerprintPolicy::CheckAll
+    fn mark_changed<'a, E, D, T>(&mut self, provider: &'a E)
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+    {
+        self.hash.clear();
+        self.generation = provider.generation();
     }
 
-    fn get_child_dist_max(child: &Child) -> DistanceCmp {
-        child
-            .center_dist
-            .combine(&child.node.radius, |center_dist, radius| {
-                center_dist + radius
-            })
+    pub fn refresh_hash<'a, E, D, T>(&mut self, provider: &'a E)
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+    {
+        self.hash = provider.compute_hash();
+        self.generation = provider.generation();
     }
 
-    fn compute_radius(&mut self) {
-        self.radius = self
-            .children
-            .iter()
-            .map(|child| Node::get_child_dist_max(child))
-            .max()
-            .unwrap_or(DistanceCmp::zero());
+    pub fn has_hash(&self) -> bool {
+        !self.hash.is_empty()
+    }
+
+    pub fn remove(&mut self, index: usize) -> bool {
+        // a tombstone for an unknown index would shrink len and turn a later
+        // insert of it into a no-op
+        if !self.contains(index) {
+            return false;
+        }
+        self.hand_over(index);
+        self.deleted.insert(index)
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        !self.deleted.contains(&index) && self.members().get(index).is_some_and(|bit| *bit)
+    }
+
+    pub fn is_deleted(&self, index: usize) -> bool {
+        self.deleted.contains(&index)
+    }
+
+    pub fn deleted_count(&self) -> usize {
+        self.deleted.len()
     }
 
-    fn add_child<'a, E, D, T, C, I>(
+    pub fn compact<'a, E, D, T, C, I>(
         &mut self,
-        child: Node,
+        threshold: f64,
         provider: &'a E,
         cache: &mut C,
         info: &mut I,
-    ) where
+    ) -> bool
+    where
         E: EmbeddingProvider<'a, D, T>,
         D: Distance<T> + Copy,
         T: 'a,
         C: Cache,
         I: Info,
     {
-        let center_dist = self.get_internal_dist(&child.get_embed(provider), provider, cache, info);
-        self.children.push(Child {
-            node: child,
-            center_dist,
-        });
-        self.children
-            .sort_unstable_by(|a, b| a.center_dist.cmp(&b.center_dist).reverse());
+        if self.deleted.is_empty() || self.deleted.len() as f64 <= threshold * self.size as f64 {
+            return false;
+        }
+        self.purge_duplicates();
+        if self.deleted.is_empty() {
+            return true;
+        }
+        if self.deleted.len() >= self.size {
+            return false;
+        }
+        let mut parents = vec![0; self.nodes.len()];
+        let mut affected = Vec::new();
+        let mut stack = Vec::from([0]);
+        while let Some(node_ix) = stack.pop() {
+            let node = self.nodes[node_ix];
+            if self.deleted.contains(&node.centroid_index) {
+                let mut cur_ix = node_ix;
+                while cur_ix != 0 && self.deleted.contains(&self.nodes[cur_ix].centroid_index) {
+                    cur_ix = parents[cur_ix];
+                }
+                affected.push(cur_ix);
+            }
+            node.children().for_each(|child_ix| {
+                parents[child_ix] = node_ix;
+                stack.push(child_ix);
+            });
+        }
+        if self.deleted.contains(&self.nodes[0].centroid_index) {
+            affected = Vec::from([0]);
+        }
+        let is_nested = |node_ix: usize| {
+            let mut cur_ix = node_ix;
+            while cur_ix != 0 {
+                cur_ix = parents[cur_ix];
+                if affected.contains(&cur_ix) {
+                    return true;
+                }
+            }
+            false
+        };
+        let mut roots: Vec<usize> = affected
+            .iter()
+            .copied()
+            .filter(|&node_ix| !is_nested(node_ix))
+            .collect();
+        roots.sort_unstable();
+        roots.dedup();
+        for root_ix in roots {
+            let mut points = self.subtree_points(root_ix);
+            points.retain(|ix| !self.deleted.contains(ix));
+            if root_ix == 0 && self.deleted.contains(&self.nodes[0].centroid_index) {
+                let new_root = Self::centroid(provider, &points, cache, info);
+                Self::remove_from(&mut points, new_root);
+                self.nodes =
+                    CowVec::from(Vec::from([NodeData::new(new_root, DistanceCmp::zero())]));
+                self.sibling_dists.clear();
+            }
+            self.grow(provider, cache, info, root_ix, points);
+        }
+        self.size -= self.deleted.len();
+        let deleted: Vec<usize> = self.deleted.drain().collect();
+        deleted
+            .into_iter()
+            .for_each(|index| self.set_member(index, false));
+        self.repack();
+        let bounds = DistanceBounds::new(provider.distance());
+        for node_ix in (0..self.nodes.len()).rev() {
+            self.compute_radius(node_ix, &bounds);
+        }
+        true
+    }
+
+    fn repack(&mut self) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut nodes = Vec::with_capacity(self.size);
+        let mut sibling_dists = Vec::with_capacity(self.sibling_dists.len());
+        nodes.push(self.nodes[0]);
+        let mut pos = 0;
+        while pos < nodes.len() {
+            let node = nodes[pos];
+            let child_count = node.children().len();
+            let sibling_end = node.sibling_start + child_count * child_count;
+            nodes[pos].child_start = nodes.len();
+            nodes[pos].sibling_start = sibling_dists.len();
+            nodes.extend(self.nodes.range(node.children()).copied());
+            sibling_dists.extend(
+                self.sibling_dists
+                    .range(node.sibling_start..sibling_end)
+                    .copied(),
+            );
+            nodes[pos].child_end = nodes.len();
+            pos += 1;
+        }
+        self.nodes = CowVec::from(nodes);
+        self.sibling_dists = CowVec::from(sibling_dists);
+        self.packed = self.slot_counts();
+    }
+
+    fn sibling_dist(&self, parent: &NodeData, child_a: usize, child_b: usize) -> DistanceCmp {
+        let child_count = parent.children().len();
+        let pos_a = child_a - parent.child_start;
+        let pos_b = child_b - parent.child_start;
+        self.sibling_dists[parent.sibling_start + pos_a * child_count + pos_b]
     }
 
-    fn get_closest<'a, E, D, T, I>(
+    fn get_closest_node<Q, I>(
         &self,
-        res: &mut Vec<(usize, DistanceCmp)>,
-        own_dist: DistanceCmp,
+        ctx: &mut QueryContext,
+        root_dist: DistanceCmp,
         count: usize,
-        ldist: &LocalDistance<'a, E, D, T>,
+        ldist: &Q,
         info: &mut I,
     ) where
-        E: EmbeddingProvider<'a, D, T>,
-        D: Distance<T> + Copy,
-        T: 'a,
+        Q: QueryDistance,
         I: Info,
     {
-        fn max_dist(res: &Vec<(usize, DistanceCmp)>, count: usize) -> DistanceCmp {
-            let index = count.min(res.len()) - 1;
-            res[index].1
+        fn max_dist(res: &[(usize, DistanceCmp)], count: usize) -> DistanceCmp {
+            // nothing can be pruned until the result is full
+            match count.checked_sub(1).and_then(|index| res.get(index)) {
+                Some(&(_, dist)) => dist,
+                None => DistanceCmp::of(DistanceValue::INFINITY),
+            }
         }
 
         fn add_node(
             res: &mut Vec<(usize, DistanceCmp)>,
-            node: &Node,
+            node: &NodeData,
             distance: DistanceCmp,
             count: usize,
         ) {
@@ -193,60 +1544,115 @@ impl Node {
             res.truncate(count);
         }
 
-        if res.len() < count || own_dist < max_dist(res, count) {
-            add_node(res, self, own_dist, count);
-        }
-        let is_outer = self.radius < own_dist;
-        info.log_scan(self.centroid_index, is_outer);
-        if is_outer {
-            for child in self.children.iter() {
-                let c_dist_est = own_dist.combine(&child.center_dist, |own, center| own - center);
-                if max_dist(res, count) < c_dist_est {
-                    continue;
+        // signed comparisons and semi-metrics visit every node
+        let prune = ldist.can_prune();
+        let QueryContext {
+            res,
+            stack,
+            inners,
+            budget,
+            ..
+        } = ctx;
+        let mut visits = 0;
+        stack.push(SearchStep::Visit(0, root_dist));
+        while let Some(step) = stack.pop() {
+            match step {
+                SearchStep::Visit(node_ix, own_dist) => {
+                    let node = &self.nodes[node_ix];
+                    if (res.len() < count || own_dist < max_dist(res, count))
+                        && !self.deleted.contains(&node.centroid_index)
+                    {
+                        add_node(res, node, own_dist, count);
+                    }
+                    visits += 1;
+                    if budget.is_some_and(|budget| visits >= budget) {
+                        break;
+                    }
+                    let is_outer = node.radius < own_dist;
+                    info.log_scan(node.centroid_index, is_outer);
+                    if is_outer {
+                        stack.extend(node.children().rev().map(|child_ix| {
+                            SearchStep::Outer(child_ix, node_ix, own_dist, DistanceCmp::zero())
+                        }));
+                    } else {
+                        // all children are needed so their distances go in one batch
+                        let centroids: Vec<usize> = self
+                            .nodes
+                            .range(node.children())
+                            .map(|child| child.centroid_index)
+                            .collect();
+                        let cdists = ldist.query_dists(&centroids, info);
+                        inners.extend(node.children().zip(cdists).map(|(child_ix, cdist)| {
+                            let cmin = self.nodes[child_ix].get_dist_min(&cdist, ldist);
+                            (child_ix, cdist, cmin)
+                        }));
+                        inners.sort_unstable_by_key(|(_, _, dist_a)| *dist_a);
+                        stack.extend(inners.drain(..).rev().map(|(child_ix, cdist, cmin)| {
+                            SearchStep::Inner(child_ix, cdist, cmin)
+                        }));
+                    }
                 }
-                let cdist = child.node.get_dist(ldist, info);
-                child.node.get_closest(res, cdist, count, ldist, info);
-            }
-        } else {
-            let mut inners: Vec<(&Node, DistanceCmp, DistanceCmp)> = self
-                .children
-                .iter()
-                .map(|child| {
-                    let cdist = child.node.get_dist(ldist, info);
-                    let cmin = child.node.get_dist_min(&cdist);
-                    (&child.node, cdist, cmin)
-                })
-                .collect();
-            inners.sort_unstable_by(|(_, _, dist_a), (_, _, dist_b)| dist_a.cmp(dist_b));
-            for (cnode, cdist, cmin) in inners.into_iter() {
-                if max_dist(res, count) < cmin {
-                    continue;
+                SearchStep::Outer(child_ix, parent_ix, parent_dist, sibling_est) => {
+                    let child = &self.nodes[child_ix];
+                    // the parent and every visited sibling bound the distance
+                    // to the centroid from below, the radius covers the rest
+                    let c_dist_est = ldist.gap(&parent_dist, &child.center_dist).max(sibling_est);
+                    if prune && max_dist(res, count) < child.get_dist_min(&c_dist_est, ldist) {
+                        continue;
+                    }
+                    let cdist = child.get_dist(ldist, info);
+                    let parent = &self.nodes[parent_ix];
+                    for step in stack.iter_mut().rev() {
+                        match step {
+                            SearchStep::Outer(sib_ix, sib_parent_ix, _, sib_est)
+                                if *sib_parent_ix == parent_ix =>
+                            {
+                                let est = ldist
+                                    .gap(&cdist, &self.sibling_dist(parent, child_ix, *sib_ix));
+                                *sib_est = est.max(*sib_est);
+                            }
+                            _ => break,
+                        }
+                    }
+                    stack.push(SearchStep::Visit(child_ix, cdist));
+                }
+                SearchStep::Inner(child_ix, cdist, cmin) => {
+                    if prune && max_dist(res, count) < cmin {
+                        continue;
+                    }
+                    stack.push(SearchStep::Visit(child_ix, cdist));
                 }
-                cnode.get_closest(res, cdist, count, ldist, info);
             }
         }
     }
 
-    fn draw(
+    fn is_before_leaf(&self, node: &NodeData) -> bool {
+        node.children()
+            .all(|child_ix| self.nodes[child_ix].is_leaf())
+    }
+
+    fn draw_node(
         &self,
+        node_ix: usize,
         pad: usize,
         show_ixs: &HashMap<usize, bool>,
         stats: &HashMap<usize, &str>,
         prune: bool,
         radius: bool,
     ) -> Vec<String> {
-        let highlight = match show_ixs.get(&self.centroid_index) {
+        let node = &self.nodes[node_ix];
+        let highlight = match show_ixs.get(&node.centroid_index) {
             Some(true) => HIGHLIGHT_A,
             Some(false) => HIGHLIGHT_B,
             None => NO_HIGHLIGHT,
         };
         let num = format!(
             "{ix: >pad$}",
-            ix = self.centroid_index,
+            ix = node.centroid_index,
             pad = pad - highlight.len(),
         );
         let rad = if radius {
-            format!("[r:{r}]", r = self.radius.to())
+            format!("[r:{r}]", r = node.radius.to())
         } else {
             "".to_owned()
         };
@@ -256,16 +1662,15 @@ impl Node {
             num = num,
             rad = rad,
         );
-        let children_count = self.children.len();
+        let children_count = node.children().len();
         if children_count == 0 {
             return Vec::from([own]);
         }
-        if !radius && self.is_before_leaf() {
-            let mut chs = self
-                .children
-                .iter()
-                .map(|child| {
-                    let cix = child.node.centroid_index;
+        if !radius && self.is_before_leaf(node) {
+            let mut chs = node
+                .children()
+                .map(|child_ix| {
+                    let cix = self.nodes[child_ix].centroid_index;
                     let chighlight = match show_ixs.get(&cix) {
                         Some(true) => HIGHLIGHT_A,
                         Some(false) => HIGHLIGHT_B,
@@ -275,30 +1680,27 @@ impl Node {
                 })
                 .collect::<Vec<String>>()
                 .join(", ");
-            if prune && !chs.contains(&HIGHLIGHT_A) && !chs.contains(&HIGHLIGHT_B) {
+            if prune && !chs.contains(HIGHLIGHT_A) && !chs.contains(HIGHLIGHT_B) {
                 chs = "...".to_string();
             }
             return Vec::from([format!("{own}━({chs})", own = own, chs = chs)]);
         }
-        let bar: String = repeat(" ").take(own.len()).collect();
+        let bar = " ".repeat(own.len());
         let sown = own.as_str();
         let sbar = bar.as_str();
-        self.children
-            .iter()
-            .map(|child| {
+        node.children()
+            .map(|child_ix| {
                 (
-                    child.node.centroid_index,
-                    child.node.draw(pad, &show_ixs, &stats, prune, radius),
+                    self.nodes[child_ix].centroid_index,
+                    self.draw_node(child_ix, pad, show_ixs, stats, prune, radius),
                 )
             })
             .enumerate()
-            .map(|(cix, (child_ix, mut lines))| {
+            .flat_map(|(cix, (child_ix, mut lines))| {
                 let all_lines = lines.join("");
-                if prune && !all_lines.contains(&HIGHLIGHT_A) && !all_lines.contains(&HIGHLIGHT_B) {
+                if prune && !all_lines.contains(HIGHLIGHT_A) && !all_lines.contains(HIGHLIGHT_B) {
                     lines = Vec::from(["(...)".to_owned()]);
                 }
-                let cix = cix;
-                let child_ix = child_ix;
                 lines.into_iter().enumerate().map(move |(lix, line)| {
                     let start = if lix == 0 && cix == 0 { sown } else { sbar };
                     let mid: String = if lix == 0 {
@@ -330,160 +1732,402 @@ impl Node {
                     format!("{start}{mid}{line}", start = start, mid = mid, line = line)
                 })
             })
-            .flatten()
             .collect::<Vec<String>>()
     }
-}
-
-#[derive(Serialize, Deserialize)]
-pub struct FannTree {
-    root: Node,
-    hash: String,
-    distance_name: String,
-}
 
-impl FannTree {
-    fn get_dist<'a, E, D, T, C, I>(
-        provider: &E,
-        embed_a: &Embedding<T>,
-        embed_b: &Embedding<T>,
+    pub fn check_provider<'a, E, D, T>(&self, provider: &E) -> Result<(), MisconfiguredTreeError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+    {
+        self.check_provider_with(provider, FingerprintPolicy::CheckAll)
+    }
+
+    pub fn check_provider_with<'a, E, D, T>(
+        &self,
+        provider: &E,
+        policy: FingerprintPolicy,
+    ) -> Result<(), MisconfiguredTreeError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+    {
+        policy.check(self, provider)
+    }
+
+    pub fn index_end(&self) -> usize {
+        self.nodes
+            .iter()
+            .map(|node| node.centroid_index)
+            .chain(self.duplicates.values().flatten().copied())
+            .max()
+            .map_or(0, |ix| ix + 1)
+    }
+
+    pub fn invariant_violations<'a, E, D, T, C>(
+        &self,
+        provider: &'a E,
+        cache: &mut C,
+    ) -> Vec<InvariantError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+    {
+        fn is_close(stored: DistanceCmp, actual: DistanceCmp) -> bool {
+            let (stored, actual) = (stored.to(), actual.to());
+            stored == actual
+                || (stored - actual).abs()
+                    <= DistanceValue::EPSILON * 16.0 * stored.abs().max(actual.abs()).max(1.0)
+        }
+
+        let info = &mut no_info();
+        let bounds = DistanceBounds::new(provider.distance());
+        let mut res = Vec::new();
+        let mut seen = HashSet::new();
+        let mut visit = |index: usize, res: &mut Vec<InvariantError>| {
+            if !provider.all().contains(&index) {
+                res.push(InvariantError::OutOfRange(index));
+            } else if !seen.insert(index) {
+                res.push(InvariantError::RepeatedIndex(index));
+            }
+        };
+        let mut stack = Vec::from_iter((!self.nodes.is_empty()).then_some(0));
+        while let Some(node_ix) = stack.pop() {
+            let node = &self.nodes[node_ix];
+            visit(node.centroid_index, &mut res);
+            self.duplicates_of(node.centroid_index)
+                .iter()
+                .for_each(|&dup| visit(dup, &mut res));
+            if !provider.all().contains(&node.centroid_index) {
+                stack.extend(node.children());
+                continue;
+            }
+            let centroid = provider.get(node.centroid_index);
+            // radii only have to cover the bound of each child, whether the
+            // triangle inequality holds is up to the distance
+            for child_ix in node.children() {
+                let child = &self.nodes[child_ix];
+                stack.push(child_ix);
+                if !provider.all().contains(&child.centroid_index) {
+                    continue;
+                }
+                let child_embed = provider.get(child.centroid_index);
+                let actual = Self::get_dist(provider, &centroid, &child_embed, cache, info);
+                if !is_close(child.center_dist, actual) {
+                    res.push(InvariantError::CenterDistMismatch {
+                        index: child.centroid_index,
+                        stored: child.center_dist.to(),
+                        actual: actual.to(),
+                    });
+                }
+                let required = child.get_dist_max(&bounds);
+                let is_removed = child.is_leaf() && self.deleted.contains(&child.centroid_index);
+                if !is_removed && node.radius < required && !is_close(node.radius, required) {
+                    res.push(InvariantError::RadiusTooSmall {
+                        index: node.centroid_index,
+                        radius: node.radius.to(),
+                        required: required.to(),
+                    });
+                }
+                for sib_ix in node.children().filter(|&sib_ix| sib_ix > child_ix) {
+                    let sibling = &self.nodes[sib_ix];
+                    if !provider.all().contains(&sibling.centroid_index) {
+                        continue;
+                    }
+                    let sib_embed = provider.get(sibling.centroid_index);
+                    let actual = Self::get_dist(provider, &child_embed, &sib_embed, cache, info);
+                    let stored = self.sibling_dist(node, child_ix, sib_ix);
+                    if !is_close(stored, actual) {
+                        res.push(InvariantError::SiblingDistMismatch {
+                            index_a: child.centroid_index,
+                            index_b: sibling.centroid_index,
+                            stored: stored.to(),
+                            actual: actual.to(),
+                        });
+                    }
+                }
+            }
+        }
+        let expected = self.size + self.duplicate_count();
+        if seen.len() != expected {
+            res.push(InvariantError::SizeMismatch {
+                expected,
+                actual: seen.len(),
+            });
+        }
+        let mut unknown: Vec<usize> = self
+            .deleted
+            .iter()
+            .copied()
+            .filter(|ix| !seen.contains(ix))
+            .collect();
+        unknown.sort_unstable();
+        res.extend(unknown.into_iter().map(InvariantError::UnknownDeleted));
+        res
+    }
+
+    pub fn check_invariants<'a, E, D, T, C>(
+        &self,
+        provider: &'a E,
         cache: &mut C,
-        info: &mut I,
-    ) -> DistanceCmp
+    ) -> Result<(), InvariantError>
     where
         E: EmbeddingProvider<'a, D, T>,
         D: Distance<T> + Copy,
         T: 'a,
         C: Cache,
+    {
+        match self
+            .invariant_violations(provider, cache)
+            .into_iter()
+            .next()
+        {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    pub fn get_closest_with_ctx<'a, 'c, E, D, T, I>(
+        &self,
+        count: usize,
+        ldist: &LocalDistance<'a, E, D, T>,
+        info: &mut I,
+        ctx: &'c mut QueryContext,
+    ) -> &'c [(usize, DistanceValue)]
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
         I: Info,
     {
-        cache.cached_distance(embed_a, embed_b, provider.distance(), info)
+        self.search_with_ctx(count, ldist, info, ctx)
     }
 
-    fn centroid<'a, E, D, T, C, I>(
+    fn search_with_ctx<'c, Q, I>(
+        &self,
+        count: usize,
+        ldist: &Q,
+        info: &mut I,
+        ctx: &'c mut QueryContext,
+    ) -> &'c [(usize, DistanceValue)]
+    where
+        Q: QueryDistance,
+        I: Info,
+    {
+        ctx.clear();
+        if count == 0 || self.nodes.is_empty() {
+            return &ctx.output;
+        }
+        let root_dist = self.nodes[0].get_dist(ldist, info);
+        self.get_closest_node(ctx, root_dist, count, ldist, info);
+        let QueryContext { res, output, .. } = ctx;
+        if self.duplicates.is_empty() {
+            output.extend(
+                res.iter()
+                    .map(|(ix, v)| (self.original_index(*ix), ldist.finalize_dist(v))),
+            );
+        } else {
+            res.iter().for_each(|(ix, v)| {
+                let dist = ldist.finalize_dist(v);
+                output.extend(self.group(*ix).map(|ix| (self.original_index(ix), dist)));
+            });
+            output.truncate(count);
+        }
+        output
+    }
+
+    pub fn join<'a, E, EB, D, T, I>(
+        &self,
         provider: &'a E,
-        all_ixs: &Vec<usize>,
-        cache: &mut C,
+        provider_b: &'a EB,
+        count: usize,
         info: &mut I,
-    ) -> usize
+    ) -> Vec<Vec<(usize, DistanceValue)>>
     where
         E: EmbeddingProvider<'a, D, T>,
+        EB: EmbeddingProvider<'a, D, T>,
         D: Distance<T> + Copy,
         T: 'a,
-        C: Cache,
         I: Info,
     {
-        let (res_ix, _) =
-            all_ixs
-                .iter()
-                .fold((None, DistanceCmp::of(f64::INFINITY)), |best, &ix| {
-                    let (best_ix, best_dist) = best;
-                    let embed = provider.get(ix);
-                    let cur_dist: DistanceCmp =
-                        all_ixs.iter().fold(DistanceCmp::zero(), |res, &oix| {
-                            if oix == ix || res > best_dist {
-                                res
-                            } else {
-                                let oembed = provider.get(oix);
-                                res.combine(
-                                    &Self::get_dist(provider, &embed, &oembed, cache, info),
-                                    |cur, dist| cur + dist,
-                                )
-                            }
-                        });
-                    if best_ix.is_none() || cur_dist < best_dist {
-                        (Some(ix), cur_dist)
-                    } else {
-                        best
-                    }
-                });
-        res_ix.unwrap()
+        let mut ctx = QueryContext::with_capacity(count);
+        provider_b
+            .all()
+            .map(|index_b| {
+                self.query_with_ctx(provider, &provider_b.get(index_b), count, info, &mut ctx)
+                    .to_vec()
+            })
+            .collect()
     }
 
-    fn kmedoid<'a, E, D, T, C, I>(
+    pub fn query_with_ctx<'a, 'c, E, D, T, I>(
+        &self,
         provider: &'a E,
-        all_ixs: Vec<usize>,
-        init_centroids: Option<Vec<usize>>,
-        k_num: usize,
-        cache: &mut C,
+        embed: &Embedding<T>,
+        count: usize,
         info: &mut I,
-    ) -> Vec<(usize, Vec<usize>)>
+        ctx: &'c mut QueryContext,
+    ) -> &'c [(usize, DistanceValue)]
     where
         E: EmbeddingProvider<'a, D, T>,
         D: Distance<T> + Copy,
         T: 'a,
-        C: Cache,
         I: Info,
     {
-        if all_ixs.len() <= k_num {
-            return all_ixs.iter().map(|&ix| (ix, Vec::new())).collect();
-        }
-        let buff_size = 10;
-        let mut rounds = 1000;
-        let mut buff: VecDeque<Vec<usize>> = VecDeque::with_capacity(buff_size);
-        if let Some(init_centroids) = init_centroids {
-            buff.push_front(init_centroids);
-        } else {
-            buff.push_front(all_ixs[..k_num].to_vec());
+        let cdist = CrossDistance {
+            provider,
+            embed,
+            distance_type: PhantomData,
+        };
+        self.search_with_ctx(count, &cdist, info, ctx)
+    }
+
+    pub fn try_query_with_ctx<'a, 'c, E, D, T, I>(
+        &self,
+        provider: &'a E,
+        embed: &Embedding<T>,
+        count: usize,
+        info: &mut I,
+        ctx: &'c mut QueryContext,
+    ) -> Result<&'c [(usize, DistanceValue)], DimensionMismatch>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        I: Info,
+    {
+        provider.check_dim(embed)?;
+        Ok(self.query_with_ctx(provider, embed, count, info, ctx))
+    }
+
+    fn cut_labels<F>(&self, is_cut: F) -> HashMap<usize, usize>
+    where
+        F: Fn(&NodeData, usize) -> bool,
+    {
+        if self.nodes.is_empty() {
+            return HashMap::new();
         }
-        let mut done = false;
-        loop {
-            let centroids: Vec<usize> = buff.get(0).unwrap().clone();
-            let mut res: Vec<(usize, Vec<usize>)> =
-                centroids.iter().map(|&ix| (ix, Vec::from([ix]))).collect();
-            all_ixs
-                .iter()
-                .filter(|&ix| !centroids.contains(ix))
-                .for_each(|&ix| {
-                    let embed = provider.get(ix);
-                    let (_, best) = res
-                        .iter_mut()
-                        .min_by(|(a, _), (b, _)| {
-                            let dist_a =
-                                Self::get_dist(provider, &embed, &provider.get(*a), cache, info);
-                            let dist_b =
-                                Self::get_dist(provider, &embed, &provider.get(*b), cache, info);
-                            dist_a.cmp(&dist_b)
-                        })
+        let mut node_labels: HashMap<usize, usize> = HashMap::new();
+        let mut redirects: HashMap<usize, usize> = HashMap::new();
+        let mut labels = HashMap::with_capacity(self.size);
+        let mut stack = Vec::from([(0, 0, None)]);
+        while let Some((node_ix, depth, label)) = stack.pop() {
+            let node = &self.nodes[node_ix];
+            let label = match label {
+                Some(label) => Some(label),
+                None if node.is_leaf() || is_cut(node, depth) => {
+                    let label = node_labels.len();
+                    node_labels.insert(node_ix, label);
+                    Some(label)
+                }
+                None => {
+                    // the centroid joins the cluster of its closest child
+                    let closest = node
+                        .children()
+                        .min_by_key(|&child_ix| self.nodes[child_ix].center_dist)
                         .unwrap();
-                    best.push(ix);
-                });
-            if done {
-                return res;
-            }
-            rounds -= 1;
-            if rounds <= 0 {
-                eprintln!("exhausted iteration steps");
-                return res;
-            }
-            let new_cs: Vec<usize> = res
-                .iter()
-                .map(|(_, assignments)| Self::centroid(provider, assignments, cache, info))
-                .collect();
-            if buff.par_iter().any(|old_cs| *old_cs == new_cs) {
-                // TODO use par for actually useful things
-                done = true;
+                    redirects.insert(node_ix, closest);
+                    None
+                }
+            };
+            if let Some(label) = label {
+                labels.insert(node.centroid_index, label);
             }
-            while buff.len() >= buff_size {
-                buff.pop_back();
+            stack.extend(
+                node.children()
+                    .rev()
+                    .map(|child_ix| (child_ix, depth + 1, label)),
+            );
+        }
+        redirects.iter().for_each(|(&node_ix, &child_ix)| {
+            let mut cur_ix = child_ix;
+            while let Some(&next_ix) = redirects.get(&cur_ix) {
+                cur_ix = next_ix;
             }
-            buff.push_front(new_cs.clone());
+            labels.insert(self.nodes[node_ix].centroid_index, node_labels[&cur_ix]);
+        });
+        self.expand_duplicates(&mut labels);
+        labels
+            .into_iter()
+            .filter(|(ix, _)| !self.deleted.contains(ix))
+            .map(|(ix, label)| (self.original_index(ix), label))
+            .collect()
+    }
+
+    pub fn cluster_labels(&self, depth: usize) -> HashMap<usize, usize> {
+        self.cut_labels(|_, node_depth| node_depth >= depth)
+    }
+
+    pub fn cluster_labels_by_radius(&self, max_radius: DistanceCmp) -> HashMap<usize, usize> {
+        self.cut_labels(|node, _| node.radius <= max_radius)
+    }
+
+    // scores are ratios of metric distances, ratios of comparison values
+    // like squared l2 would exaggerate far points. the node radius is not
+    // used as the scale since the outliers themselves define it
+    pub fn outlier_scores<'a, E, D, T, I>(
+        &self,
+        provider: &'a E,
+        info: &mut I,
+    ) -> HashMap<usize, DistanceValue>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        I: Info,
+    {
+        if self.nodes.is_empty() {
+            return HashMap::new();
+        }
+        let distance = provider.distance();
+        let mut scores = HashMap::with_capacity(self.size);
+        scores.insert(self.nodes[0].centroid_index, 0.0);
+        let mut stack = Vec::from([0]);
+        while let Some(node_ix) = stack.pop() {
+            let node = &self.nodes[node_ix];
+            info.log_scan(node.centroid_index, false);
+            let mean_dist = self
+                .nodes
+                .range(node.children())
+                .map(|child| distance.to_metric(&child.center_dist))
+                .sum::<DistanceValue>()
+                / node.children().len().max(1) as DistanceValue;
+            let parent_score = scores[&node.centroid_index];
+            self.nodes.range(node.children()).for_each(|child| {
+                // how far a point is from its medoid compared to its siblings
+                let score = if mean_dist > 0.0 {
+                    distance.to_metric(&child.center_dist) / mean_dist
+                } else {
+                    0.0
+                };
+                // points of an outlying subtree stay outliers
+                scores.insert(child.centroid_index, score.max(parent_score));
+            });
+            stack.extend(node.children());
         }
+        self.expand_duplicates(&mut scores);
+        scores
+            .into_iter()
+            .filter(|(ix, _)| !self.deleted.contains(ix))
+            .map(|(ix, score)| (self.original_index(ix), score))
+            .collect()
     }
 
-    fn remove(ixs: &mut Vec<usize>, index: usize) {
-        ixs.retain(|&ix| ix != index);
+    fn join_parts(&self, node_ix: usize) -> impl Iterator<Item = JoinPart> + '_ {
+        std::iter::once(JoinPart::Point(node_ix))
+            .chain(self.nodes[node_ix].children().map(JoinPart::Subtree))
     }
 
-    fn build_level<'a, E, D, T, C, I>(
+    fn metric_radii<'a, E, D, T, C, I>(
+        &self,
         provider: &'a E,
         cache: &mut C,
         info: &mut I,
-        cur_root_ix: usize,
-        cur_all_ixs: Vec<usize>,
-        max_node_size: usize,
-        pre_cluster: Option<usize>,
-    ) -> Node
+    ) -> Vec<DistanceValue>
     where
         E: EmbeddingProvider<'a, D, T>,
         D: Distance<T> + Copy,
@@ -491,66 +2135,274 @@ impl FannTree {
         C: Cache,
         I: Info,
     {
-        let mut node = Node::new(cur_root_ix);
-        let num_k = if max_node_size * max_node_size > cur_all_ixs.len() {
-            ((cur_all_ixs.len() as f64).sqrt() as usize).max(1)
-        } else {
-            max_node_size
+        // the stored radii can be stale after mutations so they are measured
+        // again in the metric the bounds are computed on
+        let distance = provider.distance();
+        let mut radii = vec![0.0; self.nodes.len()];
+        let mut stack = Vec::from([0]);
+        while let Some(node_ix) = stack.pop() {
+            let node = &self.nodes[node_ix];
+            let centroid = provider.get(node.centroid_index);
+            radii[node_ix] = self
+                .subtree_points(node_ix)
+                .into_iter()
+                .map(|ix| {
+                    let dist = Self::get_dist(provider, &centroid, &provider.get(ix), cache, info);
+                    distance.to_metric(&dist)
+                })
+                .fold(0.0, DistanceValue::max);
+            stack.extend(node.children());
+        }
+        radii
+    }
+
+    pub fn find_duplicates<'a, E, D, T, C, I>(
+        &self,
+        epsilon: DistanceValue,
+        provider: &'a E,
+        cache: &mut C,
+        info: &mut I,
+    ) -> Vec<(usize, usize, DistanceValue)>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        if self.nodes.is_empty() {
+            return Vec::new();
+        }
+        let distance = provider.distance();
+        // semi-metrics break the triangle inequality so every pair is compared
+        let prune = !distance.is_signed() && distance.is_metric();
+        let radii = self.metric_radii(provider, cache, info);
+        let join_radius = |part: JoinPart| match part {
+            JoinPart::Point(_) => 0.0,
+            JoinPart::Subtree(node_ix) => radii[node_ix],
         };
-        if num_k == 1 || cur_all_ixs.len() <= num_k {
-            cur_all_ixs.iter().for_each(|&ix| {
-                let mut cnode = Node::new(ix);
-                cnode.compute_radius();
-                node.add_child(cnode, provider, cache, info);
-            });
-        } else {
-            // TODO pre_cluster makes things slower
-            let init_centroids = match pre_cluster {
-                Some(pre_cluster) => {
-                    if cur_all_ixs.len() <= pre_cluster * num_k * 2 {
-                        None
-                    } else {
-                        Some(
-                            Self::kmedoid(
-                                provider,
-                                cur_all_ixs
-                                    .iter()
-                                    .take(pre_cluster * num_k)
-                                    .map(|&cix| cix)
-                                    .collect(),
-                                None,
-                                num_k,
-                                cache,
-                                info,
-                            )
-                            .into_iter()
-                            .map(|(cix, _)| cix)
-                            .collect(),
-                        )
+        let mut res = Vec::new();
+        let mut stack = Vec::from([JoinStep::Within(0)]);
+        while let Some(step) = stack.pop() {
+            match step {
+                JoinStep::Within(node_ix) => {
+                    let parts: Vec<JoinPart> = self.join_parts(node_ix).collect();
+                    for (pos, &part_a) in parts.iter().enumerate() {
+                        if let JoinPart::Subtree(child_ix) = part_a {
+                            stack.push(JoinStep::Within(child_ix));
+                        }
+                        stack.extend(
+                            parts[pos + 1..]
+                                .iter()
+                                .map(|&part_b| JoinStep::Between(part_a, part_b)),
+                        );
                     }
                 }
-                None => None,
-            };
-            Self::kmedoid(provider, cur_all_ixs, init_centroids, num_k, cache, info)
-                .into_iter()
-                .for_each(|(centroid_ix, mut assignments)| {
-                    Self::remove(&mut assignments, centroid_ix);
-                    let child_node = Self::build_level(
+                JoinStep::Between(part_a, part_b) => {
+                    let (JoinPart::Point(ix_a) | JoinPart::Subtree(ix_a)) = part_a;
+                    let (JoinPart::Point(ix_b) | JoinPart::Subtree(ix_b)) = part_b;
+                    let node_a = &self.nodes[ix_a];
+                    let node_b = &self.nodes[ix_b];
+                    let dist = Self::get_dist(
                         provider,
+                        &provider.get(node_a.centroid_index),
+                        &provider.get(node_b.centroid_index),
                         cache,
                         info,
-                        centroid_ix,
-                        assignments,
-                        max_node_size,
-                        pre_cluster,
                     );
-                    node.add_child(child_node, provider, cache, info);
-                });
+                    let value = distance.finalize_distance(&dist);
+                    let radius_a = join_radius(part_a);
+                    let radius_b = join_radius(part_b);
+                    // the bound is computed on the metric and compared with
+                    // epsilon after mapping it back to a finalized distance
+                    let lower = (distance.to_metric(&dist) - radius_a - radius_b).max(0.0);
+                    if prune && distance.finalize_distance(&distance.metric_cmp(lower)) > epsilon {
+                        continue;
+                    }
+                    match (part_a, part_b) {
+                        (JoinPart::Point(_), JoinPart::Point(_)) => {
+                            if value <= epsilon
+                                && !self.deleted.contains(&node_a.centroid_index)
+                                && !self.deleted.contains(&node_b.centroid_index)
+                            {
+                                for ix_a in self.group(node_a.centroid_index) {
+                                    for ix_b in self.group(node_b.centroid_index) {
+                                        let orig_a = self.original_index(ix_a);
+                                        let orig_b = self.original_index(ix_b);
+                                        res.push((orig_a.min(orig_b), orig_a.max(orig_b), value));
+                                    }
+                                }
+                            }
+                        }
+                        (JoinPart::Subtree(_), _) if radius_a >= radius_b => {
+                            stack.extend(
+                                self.join_parts(ix_a)
+                                    .map(|part| JoinStep::Between(part, part_b)),
+                            );
+                        }
+                        (_, JoinPart::Subtree(_)) => {
+                            stack.extend(
+                                self.join_parts(ix_b)
+                                    .map(|part| JoinStep::Between(part_a, part)),
+                            );
+                        }
+                        (JoinPart::Subtree(_), JoinPart::Point(_)) => {
+                            stack.extend(
+                                self.join_parts(ix_a)
+                                    .map(|part| JoinStep::Between(part, part_b)),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        for &rep in self.duplicates.keys() {
+            let embed = provider.get(rep);
+            let value = distance.finalize_distance(&distance.distance_cmp(&embed, &embed));
+            if value > epsilon || self.deleted.contains(&rep) {
+                continue;
+            }
+            let group: Vec<usize> = self.group(rep).map(|ix| self.original_index(ix)).collect();
+            for (pos, &orig_a) in group.iter().enumerate() {
+                for &orig_b in &group[pos + 1..] {
+                    res.push((orig_a.min(orig_b), orig_a.max(orig_b), value));
+                }
+            }
+        }
+        res.sort_unstable_by(|(a_0, a_1, _), (b_0, b_1, _)| (a_0, a_1).cmp(&(b_0, b_1)));
+        res
+    }
+
+    pub fn layout_order(&self) -> Vec<usize> {
+        if self.nodes.is_empty() {
+            return Vec::new();
+        }
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut stack = Vec::from([0]);
+        while let Some(node_ix) = stack.pop() {
+            let node = &self.nodes[node_ix];
+            order.push(node.centroid_index);
+            order.extend(self.duplicates_of(node.centroid_index));
+            stack.extend(node.children().rev());
+        }
+        order
+    }
+
+    pub fn reorder<'a, E, D, T>(
+        &mut self,
+        order: &[usize],
+        provider: &E,
+    ) -> Result<(), MisconfiguredTreeError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+    {
+        let total = self.size + self.duplicate_count();
+        if order.len() != total || provider.len() != total {
+            return Err(MisconfiguredTreeError);
+        }
+        let mut new_ixs = vec![0; order.len()];
+        order
+            .iter()
+            .enumerate()
+            .for_each(|(new_ix, &old_ix)| new_ixs[old_ix] = new_ix);
+        self.nodes
+            .iter_mut()
+            .for_each(|node| node.centroid_index = new_ixs[node.centroid_index]);
+        self.deleted = self.deleted.iter().map(|&ix| new_ixs[ix]).collect();
+        self.members = OnceLock::new();
+        self.duplicates = self
+            .duplicates
+            .iter()
+            .map(|(&rep, dups)| (new_ixs[rep], dups.iter().map(|&ix| new_ixs[ix]).collect()))
+            .collect();
+        self.permutation = Some(Arc::new(match self.permutation.take() {
+            Some(prev) => order.iter().map(|&ix| prev[ix]).collect(),
+            None => order.to_vec(),
+        }));
+        self.hash = provider.compute_hash();
+        self.generation = provider.generation();
+        Ok(())
+    }
+
+    pub fn permutation(&self) -> Option<&[usize]> {
+        self.permutation
+            .as_ref()
+            .map(|permutation| permutation.as_slice())
+    }
+
+    // ids are looked up by original index so they survive reordering
+    pub fn set_ids(&mut self, ids: IdMap) -> Result<(), IdMapError> {
+        let expected = match &self.permutation {
+            Some(permutation) => permutation.len(),
+            None => self.index_end(),
+        };
+        if ids.len() < expected {
+            return Err(IdMapError::TooFewIds {
+                expected,
+                actual: ids.len(),
+            });
+        }
+        self.ids = Some(Arc::new(ids));
+        Ok(())
+    }
+
+    pub fn ids(&self) -> Option<&IdMap> {
+        self.ids.as_deref()
+    }
+
+    // rows inserted after the build need their ids pushed here
+    pub fn ids_mut(&mut self) -> Option<&mut IdMap> {
+        self.ids.as_mut().map(Arc::make_mut)
+    }
+
+    pub fn clear_ids(&mut self) -> Option<IdMap> {
+        self.ids.take().map(Arc::unwrap_or_clone)
+    }
+
+    // rows appended after a reorder are not part of the permutation and keep
+    // their index
+    pub fn original_index(&self, index: usize) -> usize {
+        match &self.permutation {
+            Some(permutation) => permutation.get(index).copied().unwrap_or(index),
+            None => index,
         }
-        node.compute_radius();
-        node
     }
 
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn distance_name(&self) -> &str {
+        &self.distance_name
+    }
+
+    pub fn resolve_distance<'a, T>(
+        &self,
+        registry: &DistanceRegistry<'a, T>,
+    ) -> Result<DynDistance<'a, T>, UnknownDistance> {
+        registry.resolve(&self.distance_name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.size + self.duplicate_count() - self.deleted.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, TreeLoadError> {
+        let res: Self = serde_json::from_slice(bytes)?;
+        Ok(res)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, TreeWriteError> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    #[cfg(feature = "archive")]
     pub fn load(file: &std::fs::File) -> Result<Self, TreeLoadError> {
         let mut archive = zip::ZipArchive::new(file)?;
         let zip_file = archive.by_name("tree.json")?;
@@ -558,6 +2410,7 @@ impl FannTree {
         Ok(res)
     }
 
+    #[cfg(feature = "archive")]
     pub fn save(&self, file: &std::fs::File) -> Result<(), TreeWriteError> {
         let mut zip = zip::ZipWriter::new(file);
         let options = FileOptions::default()
@@ -567,6 +2420,60 @@ impl FannTree {
         serde_json::to_writer(zip, self)?;
         Ok(())
     }
+
+    #[cfg(feature = "archive")]
+    pub fn verify_roundtrip<'a, E, EQ, D, T>(
+        &self,
+        file: &std::fs::File,
+        provider: &'a E,
+        queries: &'a EQ,
+        count: usize,
+    ) -> Result<RoundtripReport, TreeLoadError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        EQ: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+    {
+        let loaded = Self::load(file)?;
+        let metadata_matches = self.size == loaded.size
+            && self.hash == loaded.hash
+            && self.generation == loaded.generation
+            && self.distance_name == loaded.distance_name
+            && self.deleted == loaded.deleted
+            && self.duplicates == loaded.duplicates
+            && self.permutation == loaded.permutation
+            && self.ids == loaded.ids;
+        let mut ctx = QueryContext::new();
+        let mut loaded_ctx = QueryContext::new();
+        // distances have to match bit for bit so compare them exactly
+        let mismatches = queries
+            .all()
+            .filter_map(|query| {
+                let embed = queries.get(query);
+                let expected =
+                    self.query_with_ctx(provider, &embed, count, &mut no_info(), &mut ctx);
+                let actual =
+                    loaded.query_with_ctx(provider, &embed, count, &mut no_info(), &mut loaded_ctx);
+                let is_same = expected.len() == actual.len()
+                    && expected.iter().zip(actual.iter()).all(
+                        |(&(ix_a, dist_a), &(ix_b, dist_b))| {
+                            ix_a == ix_b && dist_a.to_bits() == dist_b.to_bits()
+                        },
+                    );
+                (!is_same).then(|| RoundtripMismatch {
+                    query,
+                    expected: expected.to_vec(),
+                    actual: actual.to_vec(),
+                })
+            })
+            .collect();
+        Ok(RoundtripReport {
+            queries: queries.len(),
+            metadata_matches,
+            mismatches,
+        })
+    }
 }
 
 impl<'a, E, D, T> Tree<'a, E, D, T> for FannTree
@@ -586,40 +2493,30 @@ where
         C: Cache,
         I: Info,
     {
-        let mut all_ixs: Vec<usize> = provider.all().collect();
-        let max_node_size = match max_node_size {
-            Some(max_node_size) => max_node_size,
-            None => all_ixs.len(),
-        };
-        let root_ix = Self::centroid(provider, &all_ixs, cache, info);
-
-        Self::remove(&mut all_ixs, root_ix);
-        Self {
-            root: Self::build_level(
-                provider,
-                cache,
-                info,
-                root_ix,
-                all_ixs,
-                max_node_size,
-                pre_cluster,
-            ),
-            hash: provider.compute_hash(),
-            distance_name: provider.distance().name().to_string(),
-        }
+        Self::build_from(
+            provider,
+            provider.all().collect(),
+            max_node_size,
+            pre_cluster,
+            cache,
+            info,
+        )
     }
 
     fn draw<I>(
         &self,
         high_ix: usize,
         info: Option<&I>,
-        res: Option<Vec<(usize, f64)>>,
+        res: Option<Vec<(usize, DistanceValue)>>,
         prune: bool,
         radius: bool,
     ) -> String
     where
         I: Info,
     {
+        if self.nodes.is_empty() {
+            return String::new();
+        }
         let pad = format!("{high_ix}", high_ix = high_ix).len();
         let show_ixs: HashMap<usize, bool> = {
             let mut show_ixs = HashMap::new();
@@ -642,8 +2539,7 @@ where
             }
             stats
         };
-        self.root
-            .draw(pad, &show_ixs, &stats, prune, radius)
+        self.draw_node(0, pad, &show_ixs, &stats, prune, radius)
             .join("\n")
     }
 
@@ -652,20 +2548,39 @@ where
         count: usize,
         ldist: &LocalDistance<'a, E, D, T>,
         info: &mut I,
-    ) -> Vec<(usize, f64)>
+    ) -> Vec<(usize, DistanceValue)>
     where
         I: Info,
     {
-        let mut res: Vec<(usize, DistanceCmp)> = Vec::with_capacity(count + 1);
-        let root_dist = self.root.get_dist(ldist, info);
-        self.root
-            .get_closest(&mut res, root_dist, count, ldist, info);
-        res.iter()
-            .map(|(ix, v)| (*ix, ldist.finalize_distance(v)))
-            .collect()
+        QUERY_CONTEXT.with(|cell| match cell.try_borrow_mut() {
+            Ok(mut ctx) => self
+                .get_closest_with_ctx(count, ldist, info, &mut ctx)
+                .to_vec(),
+            // a search from inside a distance or info callback of another
+            // search on this thread cannot reuse the buffers
+            Err(_) => self
+                .get_closest_with_ctx(count, ldist, info, &mut QueryContext::new())
+                .to_vec(),
+        })
     }
 
     fn fingerprint(&self) -> (&str, &str) {
         (&self.hash, &self.distance_name)
     }
+
+    fn index_end(&self) -> usize {
+        FannTree::index_end(self)
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn ids(&self) -> Option<&IdMap> {
+        self.ids.as_deref()
+    }
+
+    fn len(&self) -> usize {
+        FannTree::len(self)
+    }
 }
@@ -0,0 +1,181 @@
+use std::{
+    ops::{Index, IndexMut, Range},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const CHUNK_SIZE: usize = 1024;
+
+// a vector split into shared chunks. clones only copy the chunk pointers and
+// a write copies the chunk it touches if a clone still holds on to it
+#[derive(Debug, Clone)]
+pub struct CowVec<T> {
+    chunks: Vec<Arc<Vec<T>>>,
+    len: usize,
+}
+
+impl<T> CowVec<T>
+where
+    T: Clone,
+{
+    pub fn new() -> Self {
+        CowVec {
+            chunks: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, value: T) {
+        if self.len.is_multiple_of(CHUNK_SIZE) {
+            self.chunks.push(Arc::new(Vec::with_capacity(CHUNK_SIZE)));
+        }
+        Arc::make_mut(self.chunks.last_mut().unwrap()).push(value);
+        self.len += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+        self.len = 0;
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        self.chunks.truncate(len.div_ceil(CHUNK_SIZE));
+        let last_len = len - (self.chunks.len().max(1) - 1) * CHUNK_SIZE;
+        if let Some(last) = self.chunks.last_mut() {
+            Arc::make_mut(last).truncate(last_len);
+        }
+        self.len = len;
+    }
+
+    pub fn resize(&mut self, len: usize, value: T) {
+        self.truncate(len);
+        while self.len < len {
+            self.push(value.clone());
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.chunks.iter().flat_map(|chunk| chunk.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.chunks
+            .iter_mut()
+            .flat_map(|chunk| Arc::make_mut(chunk).iter_mut())
+    }
+
+    // ranges can span chunks so there is no contiguous slice to hand out
+    pub fn range(&self, range: Range<usize>) -> impl Iterator<Item = &T> {
+        range.map(move |ix| &self[ix])
+    }
+
+    pub fn extend_from_within(&mut self, range: Range<usize>) {
+        range.for_each(|ix| self.push(self[ix].clone()));
+    }
+
+    pub fn copy_within(&mut self, src: Range<usize>, dest: usize) {
+        if dest <= src.start {
+            src.enumerate()
+                .for_each(|(pos, ix)| self[dest + pos] = self[ix].clone());
+        } else {
+            src.enumerate()
+                .rev()
+                .for_each(|(pos, ix)| self[dest + pos] = self[ix].clone());
+        }
+    }
+
+    pub fn sort_range_by<F>(&mut self, range: Range<usize>, compare: F)
+    where
+        F: FnMut(&T, &T) -> std::cmp::Ordering,
+    {
+        let mut values: Vec<T> = self.range(range.clone()).cloned().collect();
+        values.sort_unstable_by(compare);
+        range.zip(values).for_each(|(ix, value)| self[ix] = value);
+    }
+}
+
+impl<T> Default for CowVec<T>
+where
+    T: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> From<Vec<T>> for CowVec<T>
+where
+    T: Clone,
+{
+    fn from(values: Vec<T>) -> Self {
+        let mut res = Self::new();
+        res.extend(values);
+        res
+    }
+}
+
+impl<T> Extend<T> for CowVec<T>
+where
+    T: Clone,
+{
+    fn extend<I>(&mut self, values: I)
+    where
+        I: IntoIterator<Item = T>,
+    {
+        values.into_iter().for_each(|value| self.push(value));
+    }
+}
+
+impl<T> Index<usize> for CowVec<T> {
+    type Output = T;
+
+    fn index(&self, ix: usize) -> &T {
+        &self.chunks[ix / CHUNK_SIZE][ix % CHUNK_SIZE]
+    }
+}
+
+impl<T> IndexMut<usize> for CowVec<T>
+where
+    T: Clone,
+{
+    fn index_mut(&mut self, ix: usize) -> &mut T {
+        &mut Arc::make_mut(&mut self.chunks[ix / CHUNK_SIZE])[ix % CHUNK_SIZE]
+    }
+}
+
+// stored as a plain sequence so the format does not depend on the chunks
+impl<T> Serialize for CowVec<T>
+where
+    T: Clone + Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de, T> Deserialize<'de> for CowVec<T>
+where
+    T: Clone + Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::deserialize(deserializer).map(Self::from)
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use serde::{ser::Error, ser::SerializeSeq, Serialize, Serializer};
+use zip::write::FileOptions;
+
+use super::{CowVec, DistanceBounds, FannTree, NodeData, TreeWriteError};
+use crate::{info::Info, Cache, Distance, DistanceCmp, EmbeddingProvider};
+
+struct SpilledPart {
+    path: Option<PathBuf>,
+    node_base: usize,
+    sibling_base: usize,
+}
+
+impl SpilledPart {
+    fn load(&self) -> Result<Option<FannTree>, String> {
+        match &self.path {
+            Some(path) => {
+                let file = File::open(path).map_err(|err| err.to_string())?;
+                let tree =
+                    serde_json::from_reader(BufReader::new(file)).map_err(|err| err.to_string())?;
+                Ok(Some(tree))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn adjust(&self, node: &NodeData, own_ix: usize) -> NodeData {
+        let map_ix = |ix: usize| {
+            if ix == 0 {
+                own_ix
+            } else {
+                self.node_base + ix - 1
+            }
+        };
+        let mut res = *node;
+        if !node.is_leaf() {
+            res.child_start = map_ix(node.child_start);
+            res.child_end = map_ix(node.child_end - 1) + 1;
+            res.sibling_start = node.sibling_start + self.sibling_base;
+        }
+        res
+    }
+}
+
+struct SpilledNodes<'a> {
+    head: &'a CowVec<NodeData>,
+    parts: &'a [SpilledPart],
+}
+
+impl Serialize for SpilledNodes<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(None)?;
+        for node in self.head.iter() {
+            seq.serialize_element(node)?;
+        }
+        for (pos, part) in self.parts.iter().enumerate() {
+            if let Some(sub) = part.load().map_err(S::Error::custom)? {
+                for node in sub.nodes.range(1..sub.nodes.len()) {
+                    seq.serialize_element(&part.adjust(node, pos + 1))?;
+                }
+            }
+        }
+        seq.end()
+    }
+}
+
+struct SpilledSiblings<'a> {
+    head: &'a CowVec<DistanceCmp>,
+    parts: &'a [SpilledPart],
+}
+
+impl Serialize for SpilledSiblings<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(None)?;
+        for dist in self.head.iter() {
+            seq.serialize_element(dist)?;
+        }
+        for part in self.parts {
+            if let Some(sub) = part.load().map_err(S::Error::custom)? {
+                for dist in sub.sibling_dists.iter() {
+                    seq.serialize_element(dist)?;
+                }
+            }
+        }
+        seq.end()
+    }
+}
+
+// mirrors the fields of FannTree so the archive can be loaded as usual
+#[derive(Serialize)]
+struct StitchedTree<'a> {
+    nodes: SpilledNodes<'a>,
+    sibling_dists: SpilledSiblings<'a>,
+    size: usize,
+    deleted: HashSet<usize>,
+    max_node_size: usize,
+    pre_cluster: Option<usize>,
+    hash: &'a str,
+    generation: u64,
+    distance_name: &'a str,
+    permutation: Option<Vec<usize>>,
+}
+
+impl FannTree {
+    fn spill_part(&self, dir: &Path, pos: usize) -> Result<PathBuf, TreeWriteError> {
+        let path = dir.join(format!("part-{pos}.json"));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        Ok(path)
+    }
+
+    pub fn build_spilled<'a, E, D, T, C, I>(
+        provider: &'a E,
+        max_node_size: Option<usize>,
+        pre_cluster: Option<usize>,
+        cache: &mut C,
+        info: &mut I,
+        dir: &Path,
+        file: &File,
+    ) -> Result<(), TreeWriteError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        let mut all_ixs: Vec<usize> = provider.all().collect();
+        let size = all_ixs.len();
+        let max_node_size = Self::node_size(max_node_size, size);
+        let pre_cluster = pre_cluster.filter(|&pre_cluster| pre_cluster > 0);
+        if all_ixs.is_empty() {
+            return Self::empty(provider, max_node_size, pre_cluster).save(file);
+        }
+        let root_ix = Self::centroid(provider, &all_ixs, cache, info);
+        Self::remove_from(&mut all_ixs, root_ix);
+        let mut top = Self::with_root(provider, root_ix, max_node_size, pre_cluster);
+        top.size = size;
+        let root = provider.get(root_ix);
+        let mut children: Vec<(NodeData, Vec<usize>)> =
+            Self::build_level(provider, cache, info, all_ixs, max_node_size, pre_cluster)
+                .into_iter()
+                .map(|(cix, assignments)| {
+                    let center_dist =
+                        Self::get_dist(provider, &root, &provider.get(cix), cache, info);
+                    (NodeData::new(cix, center_dist), assignments)
+                })
+                .collect();
+        children.sort_unstable_by(|(a, _), (b, _)| a.center_dist.cmp(&b.center_dist).reverse());
+        top.nodes[0].child_start = 1;
+        top.nodes[0].child_end = children.len() + 1;
+        top.nodes.extend(children.iter().map(|(child, _)| *child));
+        top.compute_sibling_dists(0, 0, provider, cache, info);
+
+        let mut parts = Vec::with_capacity(children.len());
+        let mut node_base = top.nodes.len();
+        let mut sibling_base = top.sibling_dists.len();
+        for (pos, (child, assignments)) in children.into_iter().enumerate() {
+            if assignments.is_empty() {
+                parts.push(SpilledPart {
+                    path: None,
+                    node_base,
+                    sibling_base,
+                });
+                continue;
+            }
+            let mut sub = FannTree {
+                nodes: CowVec::from(Vec::from([child])),
+                sibling_dists: CowVec::new(),
+                size: assignments.len() + 1,
+                deleted: HashSet::new(),
+                max_node_size,
+                pre_cluster,
+                hash: String::new(),
+                generation: top.generation,
+                distance_name: String::new(),
+                permutation: None,
+                duplicates: HashMap::new(),
+                ids: None,
+                packed: (0, 0),
+                members: OnceLock::new(),
+            };
+            sub.grow(provider, cache, info, 0, assignments);
+            let part = SpilledPart {
+                path: Some(sub.spill_part(dir, pos)?),
+                node_base,
+                sibling_base,
+            };
+            top.nodes[pos + 1] = part.adjust(&sub.nodes[0], pos + 1);
+            node_base += sub.nodes.len() - 1;
+            sibling_base += sub.sibling_dists.len();
+            parts.push(part);
+        }
+        top.compute_radius(0, &DistanceBounds::new(provider.distance()));
+
+        let stitched = StitchedTree {
+            nodes: SpilledNodes {
+                head: &top.nodes,
+                parts: &parts,
+            },
+            sibling_dists: SpilledSiblings {
+                head: &top.sibling_dists,
+                parts: &parts,
+            },
+            size,
+            deleted: HashSet::new(),
+            max_node_size,
+            pre_cluster,
+            hash: &top.hash,
+            generation: top.generation,
+            distance_name: &top.distance_name,
+            permutation: None,
+        };
+        let mut zip = zip::ZipWriter::new(file);
+        let options = FileOptions::default()
+            .compression_method(zip::CompressionMethod::Bzip2)
+            .unix_permissions(0o755);
+        zip.start_file("tree.json", options)?;
+        serde_json::to_writer(&mut zip, &stitched)?;
+        zip.finish()?;
+        for part in parts {
+            if let Some(path) = part.path {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
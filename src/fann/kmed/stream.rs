@@ -0,0 +1,184 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+};
+
+use super::{FannTree, MetricBounds, QueryDistance};
+use crate::{
+    info::no_info, Distance, DistanceCmp, DistanceValue, EmbeddingProvider, LocalDistance,
+    ScoreTransform,
+};
+
+// points sort first so they are emitted as soon as nothing can beat them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Pending {
+    Point(usize),
+    Node(usize),
+    // a child whose distance is only bounded through its parent
+    Child(usize),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    pub distances: usize,
+    pub expanded: usize,
+    pub emitted: usize,
+}
+
+pub struct NeighborStream<'s, 'a, E, D, T>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+{
+    tree: &'s FannTree,
+    ldist: LocalDistance<'a, E, D, T>,
+    heap: BinaryHeap<Reverse<(DistanceCmp, Pending, DistanceCmp)>>,
+    ready: VecDeque<(usize, DistanceValue)>,
+    transform: Option<&'s (dyn ScoreTransform + Send + Sync)>,
+    exhaustive: bool,
+    stats: StreamStats,
+}
+
+impl<'s, 'a, E, D, T> NeighborStream<'s, 'a, E, D, T>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+{
+    fn new(tree: &'s FannTree, ldist: LocalDistance<'a, E, D, T>) -> Self {
+        let exhaustive = !ldist.can_prune();
+        let mut stream = NeighborStream {
+            tree,
+            ldist,
+            heap: BinaryHeap::new(),
+            ready: VecDeque::new(),
+            transform: None,
+            exhaustive,
+            stats: StreamStats::default(),
+        };
+        if !tree.nodes.is_empty() {
+            let root_dist = stream.query_dist(0);
+            stream.push_node(0, root_dist);
+        }
+        stream
+    }
+
+    pub fn with_transform(self, transform: &'s (dyn ScoreTransform + Send + Sync)) -> Self {
+        NeighborStream {
+            transform: Some(transform),
+            ..self
+        }
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        self.stats
+    }
+
+    // subtrees that have not been looked at yet, everything left when the
+    // consumer stops was pruned
+    pub fn unexplored(&self) -> usize {
+        self.heap
+            .iter()
+            .filter(|Reverse((_, pending, _))| !matches!(pending, Pending::Point(_)))
+            .count()
+    }
+
+    fn query_dist(&mut self, node_ix: usize) -> DistanceCmp {
+        self.stats.distances += 1;
+        self.tree.nodes[node_ix].get_dist(&self.ldist, &mut no_info())
+    }
+
+    // bounds only hold for non-negative metrics so signed comparisons and
+    // semi-metrics expand every subtree before emitting anything
+    fn bound(&self, bound: DistanceCmp) -> DistanceCmp {
+        if self.exhaustive {
+            DistanceCmp::of_signed(DistanceValue::NEG_INFINITY)
+        } else {
+            bound
+        }
+    }
+
+    fn push_node(&mut self, node_ix: usize, dist: DistanceCmp) {
+        let bound = self.bound(self.tree.nodes[node_ix].get_dist_min(&dist, &self.ldist));
+        self.heap
+            .push(Reverse((bound, Pending::Node(node_ix), dist)));
+    }
+
+    fn expand(&mut self, node_ix: usize, dist: DistanceCmp) {
+        self.stats.expanded += 1;
+        let tree = self.tree;
+        let node = &tree.nodes[node_ix];
+        if !tree.deleted.contains(&node.centroid_index) {
+            self.heap
+                .push(Reverse((dist, Pending::Point(node.centroid_index), dist)));
+        }
+        if node.radius < dist {
+            node.children().for_each(|child_ix| {
+                let child = &tree.nodes[child_ix];
+                let c_dist_est = self.ldist.gap(&dist, &child.center_dist);
+                let bound = self.bound(child.get_dist_min(&c_dist_est, &self.ldist));
+                self.heap.push(Reverse((
+                    bound,
+                    Pending::Child(child_ix),
+                    DistanceCmp::zero(),
+                )));
+            });
+        } else {
+            node.children().for_each(|child_ix| {
+                let cdist = self.query_dist(child_ix);
+                self.push_node(child_ix, cdist);
+            });
+        }
+    }
+}
+
+impl<'s, 'a, E, D, T> Iterator for NeighborStream<'s, 'a, E, D, T>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+{
+    type Item = (usize, DistanceValue);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.ready.is_empty() {
+            let Reverse((_, pending, dist)) = self.heap.pop()?;
+            match pending {
+                Pending::Point(index) => {
+                    let tree = self.tree;
+                    let dist = self.ldist.finalize_dist(&dist);
+                    let dist = match self.transform {
+                        Some(transform) => transform.score(dist),
+                        None => dist,
+                    };
+                    self.ready.extend(
+                        tree.group(index)
+                            .map(|index| (tree.original_index(index), dist)),
+                    );
+                }
+                Pending::Node(node_ix) => self.expand(node_ix, dist),
+                Pending::Child(child_ix) => {
+                    let cdist = self.query_dist(child_ix);
+                    self.push_node(child_ix, cdist);
+                }
+            }
+        }
+        self.stats.emitted += 1;
+        self.ready.pop_front()
+    }
+}
+
+impl FannTree {
+    pub fn get_closest_stream<'s, 'a, E, D, T>(
+        &'s self,
+        ldist: LocalDistance<'a, E, D, T>,
+    ) -> NeighborStream<'s, 'a, E, D, T>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+    {
+        NeighborStream::new(self, ldist)
+    }
+}
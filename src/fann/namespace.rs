@@ -0,0 +1,197 @@
+use std::{collections::BTreeMap, ops::Range};
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "archive")]
+use zip::write::FileOptions;
+
+#[cfg(feature = "archive")]
+use crate::kmed::{TreeLoadError, TreeWriteError};
+use crate::{
+    distances::{
+        dynamic::DynDistance,
+        registry::{DistanceRegistry, UnknownDistance},
+    },
+    info::Info,
+    kmed::FannTree,
+    Cache, Distance, DistanceValue, EmbeddingProvider, LocalDistance, Tree,
+};
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Namespace {
+    range: Range<usize>,
+    tree: FannTree,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Settings {
+    max_node_size: Option<usize>,
+    pre_cluster: Option<usize>,
+}
+
+pub struct NamespacedTrees {
+    settings: Settings,
+    namespaces: BTreeMap<String, Namespace>,
+}
+
+impl NamespacedTrees {
+    pub fn new(max_node_size: Option<usize>, pre_cluster: Option<usize>) -> Self {
+        NamespacedTrees {
+            settings: Settings {
+                max_node_size,
+                pre_cluster,
+            },
+            namespaces: BTreeMap::new(),
+        }
+    }
+
+    pub fn build<'a, E, D, T, C, I>(
+        &mut self,
+        name: &str,
+        provider: &'a E,
+        range: Range<usize>,
+        cache: &mut C,
+        info: &mut I,
+    ) where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        let tree = FannTree::build_from(
+            provider,
+            range.clone().collect(),
+            self.settings.max_node_size,
+            self.settings.pre_cluster,
+            cache,
+            info,
+        );
+        self.namespaces
+            .insert(name.to_string(), Namespace { range, tree });
+    }
+
+    pub fn rebuild<'a, E, D, T, C, I>(
+        &mut self,
+        name: &str,
+        provider: &'a E,
+        cache: &mut C,
+        info: &mut I,
+    ) -> bool
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        let Some(range) = self.range(name) else {
+            return false;
+        };
+        self.build(name, provider, range, cache, info);
+        true
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<FannTree> {
+        self.namespaces.remove(name).map(|namespace| namespace.tree)
+    }
+
+    pub fn get_closest<'a, E, D, T, I>(
+        &self,
+        name: &str,
+        count: usize,
+        ldist: &LocalDistance<'a, E, D, T>,
+        info: &mut I,
+    ) -> Option<Vec<(usize, DistanceValue)>>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        I: Info,
+    {
+        self.namespaces
+            .get(name)
+            .map(|namespace| namespace.tree.get_closest(count, ldist, info))
+    }
+
+    pub fn tree(&self, name: &str) -> Option<&FannTree> {
+        self.namespaces.get(name).map(|namespace| &namespace.tree)
+    }
+
+    pub fn tree_mut(&mut self, name: &str) -> Option<&mut FannTree> {
+        self.namespaces
+            .get_mut(name)
+            .map(|namespace| &mut namespace.tree)
+    }
+
+    pub fn range(&self, name: &str) -> Option<Range<usize>> {
+        self.namespaces
+            .get(name)
+            .map(|namespace| namespace.range.clone())
+    }
+
+    // fails on the first namespace whose distance the registry does not know
+    pub fn resolve_distances<'a, T>(
+        &self,
+        registry: &DistanceRegistry<'a, T>,
+    ) -> Result<BTreeMap<String, DynDistance<'a, T>>, UnknownDistance> {
+        self.namespaces
+            .iter()
+            .map(|(name, namespace)| {
+                let distance = namespace.tree.resolve_distance(registry)?;
+                Ok((name.clone(), distance))
+            })
+            .collect()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.namespaces.keys().map(|name| name.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.namespaces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.namespaces.is_empty()
+    }
+
+    #[cfg(feature = "archive")]
+    pub fn load(file: &std::fs::File) -> Result<Self, TreeLoadError> {
+        let mut archive = zip::ZipArchive::new(file)?;
+        let settings: Settings = serde_json::from_reader(archive.by_name("settings.json")?)?;
+        let mut namespaces = BTreeMap::new();
+        for ix in 0..archive.len() {
+            let zip_file = archive.by_index(ix)?;
+            let Some(name) = zip_file
+                .name()
+                .strip_prefix("namespaces/")
+                .and_then(|name| name.strip_suffix(".json"))
+                .map(|name| name.to_string())
+            else {
+                continue;
+            };
+            let namespace: Namespace = serde_json::from_reader(zip_file)?;
+            namespaces.insert(name, namespace);
+        }
+        Ok(NamespacedTrees {
+            settings,
+            namespaces,
+        })
+    }
+
+    #[cfg(feature = "archive")]
+    pub fn save(&self, file: &std::fs::File) -> Result<(), TreeWriteError> {
+        let mut zip = zip::ZipWriter::new(file);
+        let options = FileOptions::default()
+            .compression_method(zip::CompressionMethod::Bzip2)
+            .unix_permissions(0o755);
+        zip.start_file("settings.json", options)?;
+        serde_json::to_writer(&mut zip, &self.settings)?;
+        for (name, namespace) in self.namespaces.iter() {
+            zip.start_file(format!("namespaces/{name}.json"), options)?;
+            serde_json::to_writer(&mut zip, namespace)?;
+        }
+        zip.finish()?;
+        Ok(())
+    }
+}
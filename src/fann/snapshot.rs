@@ -0,0 +1,49 @@
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::kmed::FannTree;
+
+pub struct SnapshotTree {
+    current: RwLock<(u64, Arc<FannTree>)>,
+    writer: Mutex<()>,
+}
+
+impl SnapshotTree {
+    pub fn new(tree: FannTree) -> Self {
+        SnapshotTree {
+            current: RwLock::new((0, Arc::new(tree))),
+            writer: Mutex::new(()),
+        }
+    }
+
+    pub fn snapshot(&self) -> Arc<FannTree> {
+        self.current.read().unwrap().1.clone()
+    }
+
+    pub fn versioned_snapshot(&self) -> (u64, Arc<FannTree>) {
+        let (version, tree) = &*self.current.read().unwrap();
+        (*version, tree.clone())
+    }
+
+    pub fn version(&self) -> u64 {
+        self.current.read().unwrap().0
+    }
+
+    pub fn write<F, R>(&self, update: F) -> R
+    where
+        F: FnOnce(&mut FannTree) -> R,
+    {
+        // the lock guards no data and a panicking update never publishes its
+        // tree so a poisoned lock is safe to take over
+        let _guard = self
+            .writer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // readers keep using the old version until the new one is published.
+        // the clone shares all chunks and only the ones written to get copied
+        let (version, prev) = self.versioned_snapshot();
+        let mut next = FannTree::clone(&prev);
+        let res = update(&mut next);
+        *self.current.write().unwrap() = (version + 1, Arc::new(next));
+        res
+    }
+}
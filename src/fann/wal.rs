@@ -0,0 +1,215 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    info::Info,
+    kmed::{FannTree, InsertError, TreeLoadError, TreeWriteError},
+    Cache, Distance, EmbeddingProvider,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WalEntry {
+    Insert(usize),
+    Remove(usize),
+    Update(usize),
+}
+
+pub struct WriteAheadLog {
+    path: PathBuf,
+    file: File,
+}
+
+impl WriteAheadLog {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(WriteAheadLog {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    // the entries and the length of the log up to its last complete line
+    fn scan(path: &Path) -> Result<(Vec<WalEntry>, u64), TreeLoadError> {
+        let mut entries = Vec::new();
+        let mut valid_len = 0;
+        if !path.exists() {
+            return Ok((entries, valid_len));
+        }
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let len = reader.read_until(b'\n', &mut line)?;
+            // a crash while appending can only leave the last line without
+            // its newline, even if the entry itself is complete
+            if len == 0 || line.last() != Some(&b'\n') {
+                break;
+            }
+            let entry = line.trim_ascii();
+            if !entry.is_empty() {
+                entries.push(serde_json::from_slice(entry)?);
+            }
+            valid_len += len as u64;
+        }
+        Ok((entries, valid_len))
+    }
+
+    pub fn read(path: &Path) -> Result<Vec<WalEntry>, TreeLoadError> {
+        Self::scan(path).map(|(entries, _)| entries)
+    }
+
+    // cuts off an incomplete last line, appending after it would glue the
+    // next entry onto the fragment and make the log unreadable
+    pub fn recover(path: &Path) -> Result<(Self, Vec<WalEntry>), TreeLoadError> {
+        let (entries, valid_len) = Self::scan(path)?;
+        let wal = Self::open(path)?;
+        if wal.file.metadata()?.len() > valid_len {
+            wal.file.set_len(valid_len)?;
+            wal.file.sync_all()?;
+        }
+        Ok((wal, entries))
+    }
+
+    pub fn append(&mut self, entry: WalEntry) -> Result<(), TreeWriteError> {
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    pub fn truncate(&mut self) -> Result<(), TreeWriteError> {
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl FannTree {
+    pub fn replay<'a, E, D, T, C, I>(
+        &mut self,
+        entries: &[WalEntry],
+        provider: &'a E,
+        cache: &mut C,
+        info: &mut I,
+    ) -> Result<(), InsertError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        entries.iter().try_for_each(|entry| match *entry {
+            WalEntry::Insert(index) => match self.insert(index, provider, cache, info) {
+                // entries might already be part of the saved tree
+                Err(InsertError::AlreadyPresent(_)) => Ok(()),
+                res => res,
+            },
+            WalEntry::Remove(index) => {
+                self.remove(index);
+                Ok(())
+            }
+            WalEntry::Update(index) => self.update(index, provider, info),
+        })
+    }
+}
+
+pub struct LoggedTree {
+    tree: FannTree,
+    wal: WriteAheadLog,
+}
+
+impl LoggedTree {
+    pub fn new(tree: FannTree, wal: WriteAheadLog) -> Self {
+        LoggedTree { tree, wal }
+    }
+
+    pub fn tree(&self) -> &FannTree {
+        &self.tree
+    }
+
+    pub fn insert<'a, E, D, T, C, I>(
+        &mut self,
+        index: usize,
+        provider: &'a E,
+        cache: &mut C,
+        info: &mut I,
+    ) -> Result<(), TreeWriteError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        // invalid entries must not reach the log or replaying it would fail
+        self.tree.check_insert(index, provider)?;
+        self.wal.append(WalEntry::Insert(index))?;
+        self.tree.insert(index, provider, cache, info)?;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<bool, TreeWriteError> {
+        if !self.tree.contains(index) {
+            return Ok(false);
+        }
+        self.wal.append(WalEntry::Remove(index))?;
+        Ok(self.tree.remove(index))
+    }
+
+    pub fn update<'a, E, D, T, I>(
+        &mut self,
+        index: usize,
+        provider: &'a E,
+        info: &mut I,
+    ) -> Result<(), TreeWriteError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        I: Info,
+    {
+        FannTree::check_index(index, provider)?;
+        self.wal.append(WalEntry::Update(index))?;
+        self.tree.update(index, provider, info)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "archive")]
+    pub fn save(&mut self, file: &File) -> Result<(), TreeWriteError> {
+        self.tree.save(file)?;
+        file.sync_all()?;
+        self.wal.truncate()
+    }
+
+    #[cfg(feature = "archive")]
+    pub fn load<'a, E, D, T, C, I>(
+        file: &File,
+        wal_path: &Path,
+        provider: &'a E,
+        cache: &mut C,
+        info: &mut I,
+    ) -> Result<Self, TreeLoadError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        let mut tree = FannTree::load(file)?;
+        let (wal, entries) = WriteAheadLog::recover(wal_path)?;
+        tree.replay(&entries, provider, cache, info)?;
+        Ok(LoggedTree { tree, wal })
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    info::Info,
+    kmed::{FannTree, InsertError},
+    Cache, Distance, DistanceValue, EmbeddingProvider, LocalDistance, Tree,
+};
+
+struct Segment {
+    tree: FannTree,
+    indices: Vec<usize>,
+    newest: u64,
+}
+
+pub struct SlidingWindow {
+    segments: VecDeque<Segment>,
+    timestamps: HashMap<usize, u64>,
+    max_age: u64,
+    segment_size: usize,
+    max_node_size: usize,
+    pre_cluster: Option<usize>,
+}
+
+impl SlidingWindow {
+    pub fn new(
+        max_age: u64,
+        segment_size: usize,
+        max_node_size: usize,
+        pre_cluster: Option<usize>,
+    ) -> Self {
+        SlidingWindow {
+            segments: VecDeque::new(),
+            timestamps: HashMap::new(),
+            max_age,
+            segment_size: segment_size.max(1),
+            max_node_size: max_node_size.max(2),
+            pre_cluster,
+        }
+    }
+
+    fn build_segment<'a, E, D, T, C, I>(
+        &self,
+        provider: &'a E,
+        indices: Vec<usize>,
+        cache: &mut C,
+        info: &mut I,
+    ) -> FannTree
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        FannTree::build_from(
+            provider,
+            indices,
+            Some(self.max_node_size),
+            self.pre_cluster,
+            cache,
+            info,
+        )
+    }
+
+    pub fn push<'a, E, D, T, C, I>(
+        &mut self,
+        index: usize,
+        timestamp: u64,
+        provider: &'a E,
+        cache: &mut C,
+        info: &mut I,
+    ) -> Result<(), InsertError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        C: Cache,
+        I: Info,
+    {
+        FannTree::check_index(index, provider)?;
+        if self.timestamps.contains_key(&index) {
+            return Err(InsertError::AlreadyPresent(index));
+        }
+        let is_full = self
+            .segments
+            .back()
+            .is_none_or(|hot| hot.indices.len() >= self.segment_size);
+        if is_full {
+            // rebuild the full hot tree so it doesn't keep the insert structure
+            if let Some(hot) = self.segments.back() {
+                let tree = self.build_segment(provider, hot.indices.clone(), cache, info);
+                self.segments.back_mut().unwrap().tree = tree;
+            }
+            let tree = self.build_segment(provider, Vec::from([index]), cache, info);
+            self.segments.push_back(Segment {
+                tree,
+                indices: Vec::from([index]),
+                newest: timestamp,
+            });
+        } else {
+            let hot = self.segments.back_mut().unwrap();
+            hot.tree.insert(index, provider, cache, info)?;
+            hot.indices.push(index);
+            hot.newest = hot.newest.max(timestamp);
+        }
+        self.timestamps.insert(index, timestamp);
+        Ok(())
+    }
+
+    pub fn expire(&mut self, now: u64) -> Vec<usize> {
+        let mut expired = Vec::new();
+        while let Some(oldest) = self.segments.front() {
+            if now.saturating_sub(oldest.newest) <= self.max_age {
+                break;
+            }
+            let oldest = self.segments.pop_front().unwrap();
+            oldest.indices.iter().for_each(|ix| {
+                self.timestamps.remove(ix);
+            });
+            expired.extend(oldest.indices);
+        }
+        expired
+    }
+
+    pub fn get_closest<'a, E, D, T, I>(
+        &self,
+        count: usize,
+        ldist: &LocalDistance<'a, E, D, T>,
+        info: &mut I,
+    ) -> Vec<(usize, DistanceValue)>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+        T: 'a,
+        I: Info,
+    {
+        let mut res: Vec<(usize, DistanceValue)> = self
+            .segments
+            .iter()
+            .flat_map(|segment| segment.tree.get_closest(count, ldist, info))
+            .collect();
+        res.sort_by(|(_, dist_a), (_, dist_b)| dist_a.total_cmp(dist_b));
+        res.truncate(count);
+        res
+    }
+
+    pub fn timestamp(&self, index: usize) -> Option<u64> {
+        self.timestamps.get(&index).copied()
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,198 @@
+use std::sync::Arc;
+
+use arrow_array::{cast::AsArray, types::Float64Type, Array, ArrayRef, RecordBatch, UInt64Array};
+use arrow_flight::{
+    decode::FlightRecordBatchStream,
+    encode::FlightDataEncoderBuilder,
+    error::FlightError,
+    flight_service_server::{FlightService, FlightServiceServer},
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightInfo,
+    HandshakeRequest, HandshakeResponse, PollInfo, PutResult, SchemaResult, Ticket,
+};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+use ndarray::ArrayView1;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::{index::OwnedNdIndex, kmed::QueryContext, DistanceValue};
+
+#[cfg(not(feature = "f32"))]
+type DistanceArray = arrow_array::Float64Array;
+#[cfg(feature = "f32")]
+type DistanceArray = arrow_array::Float32Array;
+
+#[cfg(not(feature = "f32"))]
+const DISTANCE_TYPE: DataType = DataType::Float64;
+#[cfg(feature = "f32")]
+const DISTANCE_TYPE: DataType = DataType::Float32;
+
+struct BatchQuery<'a>(&'a OwnedNdIndex);
+
+impl BatchQuery<'_> {
+    fn query_batch(&self, batch: &RecordBatch, count: usize) -> Result<RecordBatch, ArrowError> {
+        let column = batch
+            .column_by_name("query")
+            .ok_or_else(|| ArrowError::SchemaError("missing column: query".to_string()))?;
+        let list = column.as_fixed_size_list_opt().ok_or_else(|| {
+            ArrowError::SchemaError("query must be a fixed size list".to_string())
+        })?;
+        let index = self.0;
+        let dim = list.value_length() as usize;
+        if dim != index.dim() {
+            return Err(ArrowError::InvalidArgumentError(format!(
+                "expected queries of length {expected} got {dim}",
+                expected = index.dim(),
+            )));
+        }
+        let values = list
+            .values()
+            .as_primitive_opt::<Float64Type>()
+            .ok_or_else(|| ArrowError::SchemaError("query values must be f64".to_string()))?
+            .values();
+        let mut query_ixs: Vec<u64> = Vec::with_capacity(list.len() * count);
+        let mut indices: Vec<u64> = Vec::with_capacity(list.len() * count);
+        let mut distances: Vec<DistanceValue> = Vec::with_capacity(list.len() * count);
+        let mut ctx = QueryContext::with_capacity(count);
+        for row in 0..list.len() {
+            if list.is_null(row) {
+                continue;
+            }
+            let offset = list.value_offset(row) as usize;
+            let embed = ArrayView1::from(&values[offset..offset + dim]);
+            index
+                .query_with_ctx(embed, count, &mut ctx)
+                .iter()
+                .for_each(|&(ix, dist)| {
+                    query_ixs.push(row as u64);
+                    indices.push(ix as u64);
+                    distances.push(dist);
+                });
+        }
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt64Array::from(query_ixs)),
+            Arc::new(UInt64Array::from(indices)),
+            Arc::new(DistanceArray::from(distances)),
+        ];
+        RecordBatch::try_new(result_schema(), columns)
+    }
+}
+
+pub fn result_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("query", DataType::UInt64, false),
+        Field::new("index", DataType::UInt64, false),
+        Field::new("distance", DISTANCE_TYPE, false),
+    ]))
+}
+
+pub struct FannFlightService {
+    index: Arc<OwnedNdIndex>,
+    count: usize,
+}
+
+impl FannFlightService {
+    pub fn new(index: OwnedNdIndex, count: usize) -> Self {
+        FannFlightService {
+            index: Arc::new(index),
+            count,
+        }
+    }
+
+    pub fn into_server(self) -> FlightServiceServer<Self> {
+        FlightServiceServer::new(self)
+    }
+}
+
+#[tonic::async_trait]
+impl FlightService for FannFlightService {
+    type HandshakeStream = BoxStream<'static, Result<HandshakeResponse, Status>>;
+    type ListFlightsStream = BoxStream<'static, Result<FlightInfo, Status>>;
+    type DoGetStream = BoxStream<'static, Result<FlightData, Status>>;
+    type DoPutStream = BoxStream<'static, Result<PutResult, Status>>;
+    type DoExchangeStream = BoxStream<'static, Result<FlightData, Status>>;
+    type DoActionStream = BoxStream<'static, Result<arrow_flight::Result, Status>>;
+    type ListActionsStream = BoxStream<'static, Result<ActionType, Status>>;
+
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(Status::unimplemented("handshake"))
+    }
+
+    async fn list_flights(
+        &self,
+        _request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        Err(Status::unimplemented("list_flights"))
+    }
+
+    async fn get_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        Err(Status::unimplemented("get_flight_info"))
+    }
+
+    async fn poll_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<PollInfo>, Status> {
+        Err(Status::unimplemented("poll_flight_info"))
+    }
+
+    async fn get_schema(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        Err(Status::unimplemented("get_schema"))
+    }
+
+    async fn do_get(
+        &self,
+        _request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        Err(Status::unimplemented("do_get"))
+    }
+
+    async fn do_put(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        Err(Status::unimplemented("do_put"))
+    }
+
+    async fn do_exchange(
+        &self,
+        request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        let index = self.index.clone();
+        let count = self.count;
+        let queries = FlightRecordBatchStream::new_from_flight_data(
+            request.into_inner().map_err(FlightError::from),
+        );
+        let results = queries.map(move |batch| {
+            let batch = batch?;
+            Ok(BatchQuery(&index).query_batch(&batch, count)?)
+        });
+        let stream = FlightDataEncoderBuilder::new()
+            .with_schema(result_schema())
+            .build(results)
+            .map_err(Status::from);
+        Ok(Response::new(stream.boxed()))
+    }
+
+    async fn do_action(
+        &self,
+        _request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        Err(Status::unimplemented("do_action"))
+    }
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        Err(Status::unimplemented("list_actions"))
+    }
+}
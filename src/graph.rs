@@ -0,0 +1,86 @@
+use crate::{info::Info, kmed::FannTree, Distance, DistanceValue, EmbeddingProvider};
+
+#[derive(Debug, Clone)]
+pub struct KnnGraph {
+    pub size: usize,
+    pub rows: Vec<usize>,
+    pub cols: Vec<usize>,
+    pub values: Vec<DistanceValue>,
+}
+
+impl KnnGraph {
+    pub fn from_lists(lists: &[Vec<(usize, DistanceValue)>], offset: usize) -> Self {
+        let edges: usize = lists.iter().map(|neighbors| neighbors.len()).sum();
+        let mut graph = KnnGraph {
+            size: lists.len(),
+            rows: Vec::with_capacity(edges),
+            cols: Vec::with_capacity(edges),
+            values: Vec::with_capacity(edges),
+        };
+        lists.iter().enumerate().for_each(|(row, neighbors)| {
+            neighbors.iter().for_each(|&(ix, dist)| {
+                graph.rows.push(row);
+                graph.cols.push(ix - offset);
+                graph.values.push(dist);
+            });
+        });
+        graph
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = (usize, usize, DistanceValue)> + '_ {
+        self.rows
+            .iter()
+            .zip(self.cols.iter())
+            .zip(self.values.iter())
+            .map(|((&row, &col), &value)| (row, col, value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+pub fn knn_lists<'a, E, D, T, I>(
+    tree: &FannTree,
+    provider: &'a E,
+    k: usize,
+    info: &mut I,
+) -> Vec<Vec<(usize, DistanceValue)>>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+    I: Info,
+{
+    // every point finds itself so ask for one more and drop it
+    tree.join(provider, provider, k + 1, info)
+        .into_iter()
+        .zip(provider.all())
+        .map(|(neighbors, own_ix)| {
+            neighbors
+                .into_iter()
+                .filter(|&(ix, _)| ix != own_ix)
+                .take(k)
+                .collect()
+        })
+        .collect()
+}
+
+pub fn knn_graph<'a, E, D, T, I>(
+    tree: &FannTree,
+    provider: &'a E,
+    k: usize,
+    info: &mut I,
+) -> KnnGraph
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+    T: 'a,
+    I: Info,
+{
+    KnnGraph::from_lists(&knn_lists(tree, provider, k, info), provider.all().start)
+}
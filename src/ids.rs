@@ -0,0 +1,259 @@
+use std::{collections::HashMap, fmt, ops::Range};
+
+use digest::Digest;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    DimensionMismatch, Distance, DistanceValue, Embedding, EmbeddingProvider, InvalidEmbeddingError,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ExternalId {
+    Num(u64),
+    Str(String),
+}
+
+impl fmt::Display for ExternalId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExternalId::Num(id) => write!(f, "{id}"),
+            ExternalId::Str(id) => write!(f, "{id}"),
+        }
+    }
+}
+
+impl From<u64> for ExternalId {
+    fn from(value: u64) -> Self {
+        ExternalId::Num(value)
+    }
+}
+
+impl From<String> for ExternalId {
+    fn from(value: String) -> Self {
+        ExternalId::Str(value)
+    }
+}
+
+impl From<&str> for ExternalId {
+    fn from(value: &str) -> Self {
+        ExternalId::Str(value.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdMapError {
+    DuplicateId(ExternalId),
+    MissingId(usize),
+    NoIds,
+    TooFewIds { expected: usize, actual: usize },
+}
+
+impl fmt::Display for IdMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IdMapError::DuplicateId(id) => write!(f, "duplicate id {id}"),
+            IdMapError::MissingId(index) => write!(f, "no id for index {index}"),
+            IdMapError::NoIds => write!(f, "no ids attached"),
+            IdMapError::TooFewIds { expected, actual } => {
+                write!(f, "expected at least {expected} ids got {actual}")
+            }
+        }
+    }
+}
+
+// the position of an id is the row index it belongs to, rows keep their
+// original index through reordering so the map never needs to be permuted
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "Vec<ExternalId>", into = "Vec<ExternalId>")]
+pub struct IdMap {
+    ids: Vec<ExternalId>,
+    lookup: HashMap<ExternalId, usize>,
+}
+
+impl IdMap {
+    pub fn new() -> Self {
+        IdMap::default()
+    }
+
+    pub fn from_ids<I, V>(ids: I) -> Result<Self, IdMapError>
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<ExternalId>,
+    {
+        let mut res = IdMap::new();
+        ids.into_iter()
+            .try_for_each(|id| res.push(id).map(|_| ()))?;
+        Ok(res)
+    }
+
+    pub fn push<V>(&mut self, id: V) -> Result<usize, IdMapError>
+    where
+        V: Into<ExternalId>,
+    {
+        let id = id.into();
+        let index = self.ids.len();
+        if self.lookup.contains_key(&id) {
+            return Err(IdMapError::DuplicateId(id));
+        }
+        self.lookup.insert(id.clone(), index);
+        self.ids.push(id);
+        Ok(index)
+    }
+
+    pub fn id(&self, index: usize) -> Option<&ExternalId> {
+        self.ids.get(index)
+    }
+
+    pub fn index_of<V>(&self, id: V) -> Option<usize>
+    where
+        V: Into<ExternalId>,
+    {
+        self.lookup.get(&id.into()).copied()
+    }
+
+    pub fn ids(&self) -> &[ExternalId] {
+        &self.ids
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    // matches a provider that was built over the same subrange of rows
+    pub fn slice(&self, range: Range<usize>) -> IdMap {
+        self.select(range)
+    }
+
+    pub fn select<I>(&self, indices: I) -> IdMap
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        let ids: Vec<ExternalId> = indices
+            .into_iter()
+            .map(|index| self.ids[index].clone())
+            .collect();
+        let lookup = ids
+            .iter()
+            .enumerate()
+            .map(|(index, id)| (id.clone(), index))
+            .collect();
+        IdMap { ids, lookup }
+    }
+
+    pub fn resolve<I>(&self, indices: I) -> Result<Vec<ExternalId>, IdMapError>
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        indices
+            .into_iter()
+            .map(|index| self.id(index).cloned().ok_or(IdMapError::MissingId(index)))
+            .collect()
+    }
+
+    pub fn label(
+        &self,
+        results: &[(usize, DistanceValue)],
+    ) -> Result<Vec<(ExternalId, DistanceValue)>, IdMapError> {
+        let ids = self.resolve(results.iter().map(|&(index, _)| index))?;
+        Ok(ids
+            .into_iter()
+            .zip(results.iter().map(|&(_, dist)| dist))
+            .collect())
+    }
+}
+
+impl PartialEq for IdMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.ids == other.ids
+    }
+}
+
+impl TryFrom<Vec<ExternalId>> for IdMap {
+    type Error = IdMapError;
+
+    fn try_from(value: Vec<ExternalId>) -> Result<Self, Self::Error> {
+        IdMap::from_ids(value)
+    }
+}
+
+impl From<IdMap> for Vec<ExternalId> {
+    fn from(value: IdMap) -> Self {
+        value.ids
+    }
+}
+
+pub struct IdProvider<'a, E> {
+    inner: &'a E,
+    ids: IdMap,
+}
+
+impl<'a, E> IdProvider<'a, E> {
+    pub fn new<D, T>(inner: &'a E, ids: IdMap) -> Result<Self, IdMapError>
+    where
+        E: EmbeddingProvider<'a, D, T>,
+        D: Distance<T> + Copy,
+    {
+        let expected = inner.all().end;
+        if ids.len() < expected {
+            return Err(IdMapError::TooFewIds {
+                expected,
+                actual: ids.len(),
+            });
+        }
+        Ok(IdProvider { inner, ids })
+    }
+
+    pub fn inner(&self) -> &'a E {
+        self.inner
+    }
+}
+
+impl<'a, E, D, T> EmbeddingProvider<'a, D, T> for IdProvider<'a, E>
+where
+    E: EmbeddingProvider<'a, D, T>,
+    D: Distance<T> + Copy,
+{
+    fn get_embed(&'a self, index: usize) -> T {
+        self.inner.get_embed(index)
+    }
+
+    fn all(&self) -> std::ops::Range<usize> {
+        self.inner.all()
+    }
+
+    fn distance(&self) -> D {
+        self.inner.distance()
+    }
+
+    fn generation(&self) -> u64 {
+        self.inner.generation()
+    }
+
+    fn hash_embed<H>(&self, index: usize, hasher: &mut H)
+    where
+        H: Digest,
+    {
+        self.inner.hash_embed(index, hasher)
+    }
+
+    fn check_embed(&self, index: usize) -> Result<(), InvalidEmbeddingError> {
+        self.inner.check_embed(index)
+    }
+
+    fn dim(&self) -> Option<usize> {
+        self.inner.dim()
+    }
+
+    fn check_dim(&self, embed: &Embedding<T>) -> Result<(), DimensionMismatch> {
+        self.inner.check_dim(embed)
+    }
+
+    fn ids(&self) -> Option<&IdMap> {
+        Some(&self.ids)
+    }
+}
@@ -0,0 +1,466 @@
+use std::fmt;
+
+use ndarray::{Array2, ArrayView1, ArrayView2, Axis};
+
+use crate::{
+    cache::DistanceCache,
+    distances::{
+        dynamic::DynDistance,
+        ndarray::{nd_distance, NdDotDistance, NdL2Distance, NdProvider},
+        registry::{DistanceRegistry, UnknownDistance},
+    },
+    ids::{IdMap, IdMapError},
+    info::no_info,
+    kmed::{FannTree, InsertError, QueryContext, TreeLoadError, TreeWriteError},
+    payload::{PayloadResult, PayloadStore},
+    DimensionMismatch, Distance, DistanceValue, Embedding, FingerprintPolicy,
+    InvalidEmbeddingError, LocalDistance, MisconfiguredTreeError, SearchResult, Tree,
+};
+
+const DEFAULT_CACHE_SIZE: usize = 100000;
+
+#[derive(Debug)]
+pub enum IndexLoadError {
+    TreeLoadError(TreeLoadError),
+    MisconfiguredTreeError(MisconfiguredTreeError),
+    UnknownDistance(String),
+    InvalidEmbedding(InvalidEmbeddingError),
+    DimensionMismatch(DimensionMismatch),
+}
+
+impl From<TreeLoadError> for IndexLoadError {
+    fn from(value: TreeLoadError) -> Self {
+        IndexLoadError::TreeLoadError(value)
+    }
+}
+
+impl From<MisconfiguredTreeError> for IndexLoadError {
+    fn from(value: MisconfiguredTreeError) -> Self {
+        IndexLoadError::MisconfiguredTreeError(value)
+    }
+}
+
+impl From<InvalidEmbeddingError> for IndexLoadError {
+    fn from(value: InvalidEmbeddingError) -> Self {
+        IndexLoadError::InvalidEmbedding(value)
+    }
+}
+
+impl From<UnknownDistance> for IndexLoadError {
+    fn from(value: UnknownDistance) -> Self {
+        IndexLoadError::UnknownDistance(value.name)
+    }
+}
+
+impl From<DimensionMismatch> for IndexLoadError {
+    fn from(value: DimensionMismatch) -> Self {
+        IndexLoadError::DimensionMismatch(value)
+    }
+}
+
+impl fmt::Display for IndexLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IndexLoadError::TreeLoadError(err) => write!(f, "could not load tree: {err:?}"),
+            IndexLoadError::MisconfiguredTreeError(err) => write!(f, "{err}"),
+            IndexLoadError::UnknownDistance(name) => write!(f, "unknown distance: {name}"),
+            IndexLoadError::InvalidEmbedding(err) => write!(f, "{err}"),
+            IndexLoadError::DimensionMismatch(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BuildParams {
+    pub max_node_size: Option<usize>,
+    pub pre_cluster: Option<usize>,
+    pub cache_size: usize,
+}
+
+impl Default for BuildParams {
+    fn default() -> Self {
+        BuildParams {
+            max_node_size: None,
+            pre_cluster: None,
+            cache_size: DEFAULT_CACHE_SIZE,
+        }
+    }
+}
+
+pub struct NdIndex<'a, D> {
+    arr: ArrayView2<'a, f64>,
+    distance: D,
+    tree: FannTree,
+}
+
+pub type L2Index<'a> = NdIndex<'a, NdL2Distance>;
+// exp(-dot) is not a metric so every query visits the whole tree, an
+// L2Index over mips::MipsTransform rows finds the same neighbors with pruning
+pub type DotIndex<'a> = NdIndex<'a, NdDotDistance>;
+
+impl<'a, D> NdIndex<'a, D>
+where
+    D: for<'b> Distance<ArrayView1<'b, f64>> + Copy,
+{
+    pub fn build(
+        arr: ArrayView2<'a, f64>,
+        distance: D,
+        max_node_size: Option<usize>,
+        pre_cluster: Option<usize>,
+    ) -> Self {
+        let provider = NdProvider::new(arr.view(), distance);
+        let mut cache = DistanceCache::new(DEFAULT_CACHE_SIZE);
+        let tree = FannTree::build(
+            &provider,
+            max_node_size,
+            pre_cluster,
+            &mut cache,
+            &mut no_info(),
+        );
+        NdIndex {
+            arr,
+            distance,
+            tree,
+        }
+    }
+
+    #[cfg(feature = "archive")]
+    pub fn load(
+        arr: ArrayView2<'a, f64>,
+        distance: D,
+        file: &std::fs::File,
+    ) -> Result<Self, IndexLoadError> {
+        Self::with_tree(arr, distance, FannTree::load(file)?)
+    }
+
+    pub fn from_bytes(
+        arr: ArrayView2<'a, f64>,
+        distance: D,
+        bytes: &[u8],
+    ) -> Result<Self, IndexLoadError> {
+        Self::with_tree(arr, distance, FannTree::from_bytes(bytes)?)
+    }
+
+    fn with_tree(
+        arr: ArrayView2<'a, f64>,
+        distance: D,
+        tree: FannTree,
+    ) -> Result<Self, IndexLoadError> {
+        tree.check_provider(&NdProvider::new(arr.view(), distance))?;
+        Ok(NdIndex {
+            arr,
+            distance,
+            tree,
+        })
+    }
+
+    #[cfg(feature = "archive")]
+    pub fn save(&self, file: &std::fs::File) -> Result<(), TreeWriteError> {
+        self.tree.save(file)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, TreeWriteError> {
+        self.tree.to_bytes()
+    }
+
+    pub fn query(&self, embed: ArrayView1<f64>, count: usize) -> Vec<(usize, DistanceValue)> {
+        let provider = self.provider();
+        let embed = Embedding::as_embedding(embed.view());
+        let ldist = LocalDistance::new(&provider, &embed);
+        self.tree.get_closest(count, &ldist, &mut no_info())
+    }
+
+    pub fn try_query(
+        &self,
+        embed: ArrayView1<f64>,
+        count: usize,
+    ) -> Result<Vec<(usize, DistanceValue)>, DimensionMismatch> {
+        DimensionMismatch::check(self.arr.shape()[1], embed.len())?;
+        Ok(self.query(embed, count))
+    }
+
+    pub fn query_with_ctx<'c>(
+        &self,
+        embed: ArrayView1<f64>,
+        count: usize,
+        ctx: &'c mut QueryContext,
+    ) -> &'c [(usize, DistanceValue)] {
+        let provider = self.provider();
+        let embed = Embedding::as_embedding(embed.view());
+        let ldist = LocalDistance::new(&provider, &embed);
+        self.tree
+            .get_closest_with_ctx(count, &ldist, &mut no_info(), ctx)
+    }
+
+    pub fn provider(&self) -> NdProvider<'_, D> {
+        NdProvider::new(self.arr.view(), self.distance)
+    }
+}
+
+pub struct OwnedNdIndex {
+    arr: Array2<f64>,
+    distance: String,
+    generation: u64,
+    tree: FannTree,
+    // row of each original index once the tree was reordered
+    positions: Option<Vec<usize>>,
+}
+
+impl OwnedNdIndex {
+    fn positions(tree: &FannTree) -> Result<Option<Vec<usize>>, MisconfiguredTreeError> {
+        let Some(permutation) = tree.permutation() else {
+            return Ok(None);
+        };
+        let mut positions = vec![usize::MAX; permutation.len()];
+        for (pos, &ix) in permutation.iter().enumerate() {
+            match positions.get_mut(ix) {
+                Some(slot) if *slot == usize::MAX => *slot = pos,
+                _ => return Err(MisconfiguredTreeError),
+            }
+        }
+        Ok(Some(positions))
+    }
+
+    fn check_distance(distance: &str) -> Result<(), IndexLoadError> {
+        DistanceRegistry::<ArrayView1<f64>>::nd().resolve(distance)?;
+        Ok(())
+    }
+
+    pub fn build(
+        arr: Array2<f64>,
+        distance: &str,
+        max_node_size: Option<usize>,
+        pre_cluster: Option<usize>,
+    ) -> Result<Self, IndexLoadError> {
+        Self::build_with(
+            arr,
+            distance,
+            BuildParams {
+                max_node_size,
+                pre_cluster,
+                ..BuildParams::default()
+            },
+        )
+    }
+
+    pub fn from_embeddings<I, R>(
+        embeddings: I,
+        distance: &str,
+        params: BuildParams,
+    ) -> Result<Self, IndexLoadError>
+    where
+        I: IntoIterator<Item = R>,
+        R: AsRef<[f64]>,
+    {
+        let mut dim = None;
+        let mut rows = 0;
+        let mut values = Vec::new();
+        for embed in embeddings {
+            let embed = embed.as_ref();
+            DimensionMismatch::check(*dim.get_or_insert(embed.len()), embed.len())?;
+            values.extend_from_slice(embed);
+            rows += 1;
+        }
+        let arr = Array2::from_shape_vec((rows, dim.unwrap_or(0)), values).unwrap();
+        Self::build_with(arr, distance, params)
+    }
+
+    pub fn build_with(
+        arr: Array2<f64>,
+        distance: &str,
+        params: BuildParams,
+    ) -> Result<Self, IndexLoadError> {
+        Self::check_distance(distance)?;
+        let provider = NdProvider::try_new(arr.view(), nd_distance(distance).unwrap())?;
+        let mut cache = DistanceCache::new(params.cache_size.max(1));
+        let tree = FannTree::build(
+            &provider,
+            params.max_node_size,
+            params.pre_cluster,
+            &mut cache,
+            &mut no_info(),
+        );
+        Ok(OwnedNdIndex {
+            arr,
+            distance: distance.to_string(),
+            generation: 0,
+            tree,
+            positions: None,
+        })
+    }
+
+    pub fn with_tree(
+        arr: Array2<f64>,
+        distance: &str,
+        tree: FannTree,
+    ) -> Result<Self, IndexLoadError> {
+        Self::with_tree_checked(arr, distance, tree, FingerprintPolicy::CheckAll)
+    }
+
+    // uses the distance the tree was built with
+    pub fn from_tree(arr: Array2<f64>, tree: FannTree) -> Result<Self, IndexLoadError> {
+        let distance = tree.distance_name().to_string();
+        Self::with_tree(arr, &distance, tree)
+    }
+
+    pub fn with_tree_checked(
+        arr: Array2<f64>,
+        distance: &str,
+        tree: FannTree,
+        policy: FingerprintPolicy,
+    ) -> Result<Self, IndexLoadError> {
+        Self::check_distance(distance)?;
+        let positions = Self::positions(&tree)?;
+        let arr = match tree.permutation() {
+            Some(permutation) => {
+                if permutation.len() != arr.shape()[0] {
+                    return Err(MisconfiguredTreeError.into());
+                }
+                arr.select(Axis(0), permutation)
+            }
+            None => arr,
+        };
+        let generation = tree.generation();
+        tree.check_provider_with(
+            &NdProvider::with_generation(arr.view(), nd_distance(distance).unwrap(), generation),
+            policy,
+        )?;
+        Ok(OwnedNdIndex {
+            arr,
+            distance: distance.to_string(),
+            generation,
+            tree,
+            positions,
+        })
+    }
+
+    fn provider(&self) -> NdProvider<'_, DynDistance<'_, ArrayView1<'_, f64>>> {
+        NdProvider::with_generation(
+            self.arr.view(),
+            nd_distance(&self.distance).unwrap(),
+            self.generation,
+        )
+    }
+
+    pub fn update(&mut self, index: usize, embed: ArrayView1<f64>) -> Result<(), InsertError> {
+        let index = match &self.positions {
+            Some(positions) => positions.get(index).copied(),
+            None => (index < self.arr.nrows()).then_some(index),
+        }
+        .ok_or(InsertError::OutOfRange(index))?;
+        // assign would panic on a row of the wrong length
+        DimensionMismatch::check(self.dim(), embed.len())?;
+        self.arr.row_mut(index).assign(&embed);
+        self.generation += 1;
+        let provider = NdProvider::with_generation(
+            self.arr.view(),
+            nd_distance(&self.distance).unwrap(),
+            self.generation,
+        );
+        self.tree.update(index, &provider, &mut no_info())
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn tree(&self) -> &FannTree {
+        &self.tree
+    }
+
+    pub fn set_ids(&mut self, ids: IdMap) -> Result<(), IdMapError> {
+        self.tree.set_ids(ids)
+    }
+
+    pub fn ids(&self) -> Option<&IdMap> {
+        self.tree.ids()
+    }
+
+    pub fn reorder(&mut self) -> Result<(), MisconfiguredTreeError> {
+        let order = self.tree.layout_order();
+        // the rows are only replaced once the tree accepted the order
+        if order.len() != self.arr.nrows() {
+            return Err(MisconfiguredTreeError);
+        }
+        let arr = self.arr.select(Axis(0), &order);
+        let provider = NdProvider::with_generation(
+            arr.view(),
+            nd_distance(&self.distance).unwrap(),
+            self.generation,
+        );
+        self.tree.reorder(&order, &provider)?;
+        self.arr = arr;
+        self.positions = Self::positions(&self.tree)?;
+        Ok(())
+    }
+
+    pub fn arr(&self) -> ArrayView2<'_, f64> {
+        self.arr.view()
+    }
+
+    pub fn distance_name(&self) -> &str {
+        &self.distance
+    }
+
+    pub fn dim(&self) -> usize {
+        self.arr.shape()[1]
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn query(&self, embed: ArrayView1<f64>, count: usize) -> Vec<(usize, DistanceValue)> {
+        let provider = self.provider();
+        let embed = Embedding::as_embedding(embed.view());
+        let ldist = LocalDistance::new(&provider, &embed);
+        self.tree.get_closest(count, &ldist, &mut no_info())
+    }
+
+    pub fn try_query(
+        &self,
+        embed: ArrayView1<f64>,
+        count: usize,
+    ) -> Result<Vec<(usize, DistanceValue)>, DimensionMismatch> {
+        DimensionMismatch::check(self.dim(), embed.len())?;
+        Ok(self.query(embed, count))
+    }
+
+    pub fn query_with_ctx<'c>(
+        &self,
+        embed: ArrayView1<f64>,
+        count: usize,
+        ctx: &'c mut QueryContext,
+    ) -> &'c [(usize, DistanceValue)] {
+        let provider = self.provider();
+        let embed = Embedding::as_embedding(embed.view());
+        let ldist = LocalDistance::new(&provider, &embed);
+        self.tree
+            .get_closest_with_ctx(count, &ldist, &mut no_info(), ctx)
+    }
+
+    pub fn query_ids(
+        &self,
+        embed: ArrayView1<f64>,
+        count: usize,
+    ) -> Result<SearchResult, IdMapError> {
+        let ids = self.ids().ok_or(IdMapError::NoIds)?;
+        SearchResult::from(self.query(embed, count)).with_ids(ids)
+    }
+
+    pub fn query_payloads<P>(
+        &self,
+        embed: ArrayView1<f64>,
+        count: usize,
+        payloads: &PayloadStore<P>,
+    ) -> PayloadResult<P>
+    where
+        P: Clone,
+    {
+        payloads.attach(&self.query(embed, count))
+    }
+}
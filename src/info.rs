@@ -1,7 +1,6 @@
 use std::collections::{hash_map::IntoIter, HashMap};
 
 use bitvec::vec::BitVec;
-use polars::export::num::ToPrimitive;
 
 pub trait Info {
     fn log_cache_access(&mut self, is_miss: bool);
@@ -11,7 +10,7 @@ pub trait Info {
     fn cache_hits_miss(&self) -> (u64, u64);
     fn cache_hit_rate(&self) -> f64 {
         let (hits, miss) = self.cache_hits_miss();
-        hits.to_f64().unwrap() / (hits + miss).to_f64().unwrap()
+        hits as f64 / (hits + miss) as f64
     }
 
     fn scan_map(&self) -> IntoIter<usize, &str>;
@@ -0,0 +1,27 @@
+#[cfg(feature = "csv")]
+pub mod csv;
+#[cfg(feature = "archive")]
+pub mod graph;
+#[cfg(feature = "hdf5")]
+pub mod hdf5;
+pub mod jsonl;
+pub mod npy;
+#[cfg(feature = "archive")]
+pub mod npz;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod vecs;
+
+#[cfg(feature = "csv")]
+pub use self::csv::load_embed_csv;
+#[cfg(feature = "archive")]
+pub use graph::write_graph_npz;
+#[cfg(feature = "hdf5")]
+pub use hdf5::read_ann_benchmarks;
+pub use jsonl::read_jsonl;
+pub use npy::{npy_layout, read_npy, write_npy};
+#[cfg(feature = "archive")]
+pub use npz::{read_npz, read_npz_all};
+#[cfg(feature = "parquet")]
+pub use parquet::{read_parquet, read_parquet_chunked};
+pub use vecs::{read_bvecs, read_fvecs, read_ivecs};
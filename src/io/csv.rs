@@ -0,0 +1,123 @@
+use std::{fmt, fs::File, io::Read, path::Path};
+
+use ::csv::{ReaderBuilder, StringRecord};
+use ndarray::Array2;
+
+#[derive(Debug)]
+pub enum CsvError {
+    IoError(std::io::Error),
+    ParseError(::csv::Error),
+    FormatError(String),
+}
+
+impl From<std::io::Error> for CsvError {
+    fn from(value: std::io::Error) -> Self {
+        CsvError::IoError(value)
+    }
+}
+
+impl From<::csv::Error> for CsvError {
+    fn from(value: ::csv::Error) -> Self {
+        CsvError::ParseError(value)
+    }
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CsvError::IoError(err) => write!(f, "{err}"),
+            CsvError::ParseError(err) => write!(f, "invalid csv file: {err}"),
+            CsvError::FormatError(msg) => write!(f, "invalid csv file: {msg}"),
+        }
+    }
+}
+
+fn format_error<T>(msg: String) -> Result<T, CsvError> {
+    Err(CsvError::FormatError(msg))
+}
+
+fn select_columns(
+    headers: &StringRecord,
+    first: Option<&StringRecord>,
+    columns: &[&str],
+) -> Result<Vec<usize>, CsvError> {
+    if !columns.is_empty() {
+        return columns
+            .iter()
+            .map(
+                |&column| match headers.iter().position(|name| name == column) {
+                    Some(pos) => Ok(pos),
+                    None => format_error(format!("missing column {column}")),
+                },
+            )
+            .collect();
+    }
+    // without explicit columns every column that is numeric in the first row
+    // is used, except for numeric ids
+    let Some(first) = first else {
+        return Ok(Vec::new());
+    };
+    Ok(first
+        .iter()
+        .zip(headers.iter())
+        .enumerate()
+        .filter(|(_, (value, name))| value.trim().parse::<f64>().is_ok() && !is_id_column(name))
+        .map(|(pos, _)| pos)
+        .collect())
+}
+
+fn is_id_column(name: &str) -> bool {
+    let name = name.trim().to_lowercase();
+    name.is_empty() || name == "id" || name == "index" || name.ends_with("_id")
+}
+
+// the first line must name the columns, an empty selection picks the
+// numeric columns
+pub fn load_embed_csv_from<R>(
+    reader: R,
+    delimiter: u8,
+    columns: &[&str],
+) -> Result<Array2<f64>, CsvError>
+where
+    R: Read,
+{
+    let mut reader = ReaderBuilder::new()
+        .delimiter(delimiter)
+        .from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let mut records = reader.records();
+    let first = records.next().transpose()?;
+    let selected = select_columns(&headers, first.as_ref(), columns)?;
+    let mut values = Vec::new();
+    let mut rows = 0;
+    for record in first.into_iter().map(Ok).chain(records) {
+        let record = record?;
+        for &pos in &selected {
+            let value = record.get(pos).unwrap_or("");
+            match value.trim().parse::<f64>() {
+                Ok(value) => values.push(value),
+                Err(_) => {
+                    return format_error(format!(
+                        "invalid number {value:?} in row {rows} column {column}",
+                        column = &headers[pos],
+                    ))
+                }
+            }
+        }
+        rows += 1;
+    }
+    Array2::from_shape_vec((rows, selected.len()), values)
+        .or_else(|err| format_error(err.to_string()))
+}
+
+// files ending in .tsv or .tab are tab separated
+pub fn load_embed_csv<P>(path: P, columns: &[&str]) -> Result<Array2<f64>, CsvError>
+where
+    P: AsRef<Path>,
+{
+    let delimiter = match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+        Some("tsv" | "tab") => b'\t',
+        _ => b',',
+    };
+    load_embed_csv_from(File::open(path)?, delimiter, columns)
+}
@@ -0,0 +1,80 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use zip::write::FileOptions;
+
+use super::npy::{write_array_to, NpyError};
+use crate::graph::KnnGraph;
+
+#[cfg(not(feature = "f32"))]
+const VALUE_DESCR: &str = "<f8";
+#[cfg(feature = "f32")]
+const VALUE_DESCR: &str = "<f4";
+
+fn index_bytes(ixs: &[usize]) -> impl Iterator<Item = [u8; 8]> + '_ {
+    ixs.iter().map(|&ix| (ix as i64).to_le_bytes())
+}
+
+// same layout as scipy.sparse.save_npz so scipy.sparse.load_npz can read it
+pub fn write_graph_npz_to<W>(writer: W, graph: &KnnGraph) -> Result<(), NpyError>
+where
+    W: Write + io::Seek,
+{
+    let mut zip = zip::ZipWriter::new(writer);
+    let options = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    zip.start_file("row.npy", options)
+        .map_err(io::Error::from)?;
+    write_array_to(&mut zip, "<i8", &[graph.len()], index_bytes(&graph.rows))?;
+    zip.start_file("col.npy", options)
+        .map_err(io::Error::from)?;
+    write_array_to(&mut zip, "<i8", &[graph.len()], index_bytes(&graph.cols))?;
+    zip.start_file("data.npy", options)
+        .map_err(io::Error::from)?;
+    write_array_to(
+        &mut zip,
+        VALUE_DESCR,
+        &[graph.len()],
+        graph.values.iter().map(|v| v.to_le_bytes()),
+    )?;
+    zip.start_file("shape.npy", options)
+        .map_err(io::Error::from)?;
+    write_array_to(
+        &mut zip,
+        "<i8",
+        &[2],
+        index_bytes(&[graph.size, graph.size]),
+    )?;
+    zip.start_file("format.npy", options)
+        .map_err(io::Error::from)?;
+    write_array_to(&mut zip, "|S3", &[], [b"coo"])?;
+    zip.finish().map_err(io::Error::from)?;
+    Ok(())
+}
+
+pub fn write_graph_npz<P>(path: P, graph: &KnnGraph) -> Result<(), NpyError>
+where
+    P: AsRef<Path>,
+{
+    write_graph_npz_to(BufWriter::new(File::create(path)?), graph)
+}
+
+pub fn write_graph_csv_to<W>(mut writer: W, graph: &KnnGraph) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    writeln!(writer, "source,target,distance")?;
+    for (row, col, value) in graph.edges() {
+        writeln!(writer, "{row},{col},{value}")?;
+    }
+    writer.flush()
+}
+
+pub fn write_graph_csv<P>(path: P, graph: &KnnGraph) -> Result<(), io::Error>
+where
+    P: AsRef<Path>,
+{
+    write_graph_csv_to(BufWriter::new(File::create(path)?), graph)
+}
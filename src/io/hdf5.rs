@@ -0,0 +1,100 @@
+use std::{fmt, path::Path};
+
+use hdf5_pure::{File, FormatError};
+use ndarray::Array2;
+
+#[derive(Debug)]
+pub enum Hdf5Error {
+    Hdf5Error(hdf5_pure::Error),
+    FormatError(String),
+}
+
+impl From<hdf5_pure::Error> for Hdf5Error {
+    fn from(value: hdf5_pure::Error) -> Self {
+        Hdf5Error::Hdf5Error(value)
+    }
+}
+
+impl fmt::Display for Hdf5Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Hdf5Error::Hdf5Error(err) => write!(f, "{err}"),
+            Hdf5Error::FormatError(msg) => write!(f, "invalid ann-benchmarks file: {msg}"),
+        }
+    }
+}
+
+fn format_error<T>(msg: &str) -> Result<T, Hdf5Error> {
+    Err(Hdf5Error::FormatError(msg.to_string()))
+}
+
+// the layout of the ann-benchmarks datasets, neighbors holds the ids of the
+// true nearest neighbors in train for every row of test
+#[derive(Debug, Clone)]
+pub struct AnnBenchmarks {
+    pub train: Array2<f64>,
+    pub test: Array2<f64>,
+    pub neighbors: Array2<i32>,
+    pub distances: Option<Array2<f64>>,
+    pub distance: String,
+}
+
+impl AnnBenchmarks {
+    // the name of the matching distance of this crate if there is one
+    pub fn distance_name(&self) -> Option<&'static str> {
+        match self.distance.as_str() {
+            "euclidean" => Some("l2"),
+            "angular" => Some("cosine"),
+            _ => None,
+        }
+    }
+}
+
+fn shape_of(file: &File, name: &str) -> Result<(usize, usize), Hdf5Error> {
+    match file.dataset(name)?.shape()?[..] {
+        [rows, cols] => Ok((rows as usize, cols as usize)),
+        _ => format_error(&format!("{name} must have two dimensions")),
+    }
+}
+
+fn read_matrix(file: &File, name: &str) -> Result<Array2<f64>, Hdf5Error> {
+    let shape = shape_of(file, name)?;
+    Array2::from_shape_vec(shape, file.dataset(name)?.read_f64()?)
+        .or_else(|err| format_error(&err.to_string()))
+}
+
+pub fn read_ann_benchmarks<P>(path: P) -> Result<AnnBenchmarks, Hdf5Error>
+where
+    P: AsRef<Path>,
+{
+    let file = File::open(path)?;
+    let train = read_matrix(&file, "train")?;
+    let test = read_matrix(&file, "test")?;
+    if train.shape()[1] != test.shape()[1] {
+        return format_error("train and test have different dimensions");
+    }
+    let shape = shape_of(&file, "neighbors")?;
+    if shape.0 != test.shape()[0] {
+        return format_error("neighbors must have one row per test row");
+    }
+    let neighbors = Array2::from_shape_vec(shape, file.dataset("neighbors")?.read_i32()?)
+        .or_else(|err| format_error(&err.to_string()))?;
+    // older files only carry the neighbor ids
+    let distances = match file.dataset("distances") {
+        Ok(_) => Some(read_matrix(&file, "distances")?),
+        Err(hdf5_pure::Error::Format(FormatError::PathNotFound(_))) => None,
+        Err(err) => return Err(err.into()),
+    };
+    let attrs = file.root().attrs()?;
+    let distance = match attrs.get("distance").and_then(|value| value.as_str()) {
+        Some(distance) => distance.to_string(),
+        None => return format_error("missing distance attribute"),
+    };
+    Ok(AnnBenchmarks {
+        train,
+        test,
+        neighbors,
+        distances,
+        distance,
+    })
+}
@@ -0,0 +1,145 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use serde_json::Value;
+
+#[derive(Debug)]
+pub enum JsonlError {
+    IoError(std::io::Error),
+    JsonError(usize, serde_json::Error),
+    FormatError(usize, String),
+}
+
+impl From<std::io::Error> for JsonlError {
+    fn from(value: std::io::Error) -> Self {
+        JsonlError::IoError(value)
+    }
+}
+
+impl fmt::Display for JsonlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JsonlError::IoError(err) => write!(f, "{err}"),
+            JsonlError::JsonError(line, err) => write!(f, "invalid json in line {line}: {err}"),
+            JsonlError::FormatError(line, msg) => write!(f, "invalid line {line}: {msg}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct JsonlEmbeddings {
+    pub embeddings: Vec<Vec<f64>>,
+    pub ids: Option<Vec<String>>,
+}
+
+impl JsonlEmbeddings {
+    pub fn dim(&self) -> Option<usize> {
+        self.embeddings.first().map(|embed| embed.len())
+    }
+
+    pub fn id_map(&self) -> HashMap<&str, usize> {
+        self.ids
+            .iter()
+            .flatten()
+            .enumerate()
+            .map(|(ix, id)| (id.as_str(), ix))
+            .collect()
+    }
+}
+
+// fields starting with a slash are json pointers into nested objects
+fn lookup<'v>(value: &'v Value, field: &str) -> Option<&'v Value> {
+    if field.starts_with('/') {
+        value.pointer(field)
+    } else {
+        value.get(field)
+    }
+}
+
+fn parse_embed(value: &Value, field: &str) -> Result<Vec<f64>, String> {
+    let Some(values) = lookup(value, field).and_then(|embed| embed.as_array()) else {
+        return Err(format!("missing array {field}"));
+    };
+    values
+        .iter()
+        .map(|v| {
+            v.as_f64()
+                .ok_or_else(|| format!("{field} contains non-number {v}"))
+        })
+        .collect()
+}
+
+fn parse_id(value: &Value, id_field: &str) -> Result<String, String> {
+    match lookup(value, id_field) {
+        Some(Value::String(id)) => Ok(id.clone()),
+        Some(Value::Number(id)) => Ok(id.to_string()),
+        Some(other) => Err(format!("{id_field} must be a string or number got {other}")),
+        None => Err(format!("missing id {id_field}")),
+    }
+}
+
+// reads one object per line, lines are counted from one and blank lines are
+// skipped
+pub fn read_jsonl_from<R>(
+    reader: R,
+    field: &str,
+    id_field: Option<&str>,
+) -> Result<JsonlEmbeddings, JsonlError>
+where
+    R: BufRead,
+{
+    let mut embeddings: Vec<Vec<f64>> = Vec::new();
+    let mut ids = id_field.map(|_| Vec::new());
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (pos, line) in reader.lines().enumerate() {
+        let (line, line_no) = (line?, pos + 1);
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value: Value =
+            serde_json::from_str(&line).map_err(|err| JsonlError::JsonError(line_no, err))?;
+        let embed =
+            parse_embed(&value, field).map_err(|msg| JsonlError::FormatError(line_no, msg))?;
+        if let Some(first) = embeddings.first() {
+            if first.len() != embed.len() {
+                return Err(JsonlError::FormatError(
+                    line_no,
+                    format!(
+                        "expected embedding of length {expected} got {actual}",
+                        expected = first.len(),
+                        actual = embed.len(),
+                    ),
+                ));
+            }
+        }
+        if let (Some(ids), Some(id_field)) = (ids.as_mut(), id_field) {
+            let id =
+                parse_id(&value, id_field).map_err(|msg| JsonlError::FormatError(line_no, msg))?;
+            if let Some(prev) = seen.insert(id.clone(), line_no) {
+                return Err(JsonlError::FormatError(
+                    line_no,
+                    format!("duplicate id {id} first seen in line {prev}"),
+                ));
+            }
+            ids.push(id);
+        }
+        embeddings.push(embed);
+    }
+    Ok(JsonlEmbeddings { embeddings, ids })
+}
+
+pub fn read_jsonl<P>(
+    path: P,
+    field: &str,
+    id_field: Option<&str>,
+) -> Result<JsonlEmbeddings, JsonlError>
+where
+    P: AsRef<Path>,
+{
+    read_jsonl_from(BufReader::new(File::open(path)?), field, id_field)
+}
@@ -0,0 +1,284 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, Write},
+    path::Path,
+};
+
+use ndarray::{Array2, ArrayView2, ShapeBuilder};
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+// the shape in the header is not trusted with a larger allocation up front
+const READ_CAPACITY: usize = 1 << 20;
+
+#[derive(Debug)]
+pub enum NpyError {
+    IoError(std::io::Error),
+    FormatError(String),
+}
+
+impl From<std::io::Error> for NpyError {
+    fn from(value: std::io::Error) -> Self {
+        NpyError::IoError(value)
+    }
+}
+
+impl fmt::Display for NpyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NpyError::IoError(err) => write!(f, "{err}"),
+            NpyError::FormatError(msg) => write!(f, "invalid npy file: {msg}"),
+        }
+    }
+}
+
+fn format_error<T>(msg: &str) -> Result<T, NpyError> {
+    Err(NpyError::FormatError(msg.to_string()))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum DType {
+    F4,
+    F8,
+}
+
+impl DType {
+    fn size(&self) -> usize {
+        match self {
+            DType::F4 => 4,
+            DType::F8 => 8,
+        }
+    }
+}
+
+struct Header {
+    dtype: DType,
+    big_endian: bool,
+    fortran_order: bool,
+    shape: Vec<usize>,
+}
+
+fn header_value<'h>(header: &'h str, key: &str) -> Result<&'h str, NpyError> {
+    let pattern = format!("'{key}':");
+    let start = match header.find(&pattern) {
+        Some(start) => start + pattern.len(),
+        None => return format_error(&format!("missing key {key}")),
+    };
+    let rest = header[start..].trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')').map(|end| end + 1)
+    } else {
+        rest.find(',').or_else(|| rest.find('}'))
+    };
+    match end {
+        Some(end) => Ok(rest[..end].trim()),
+        None => format_error(&format!("malformed value for {key}")),
+    }
+}
+
+fn parse_header(header: &str) -> Result<Header, NpyError> {
+    let descr = header_value(header, "descr")?.trim_matches(|c| c == '\'' || c == '"');
+    let (big_endian, dtype) = match descr {
+        "<f4" | "=f4" => (false, DType::F4),
+        ">f4" => (true, DType::F4),
+        "<f8" | "=f8" => (false, DType::F8),
+        ">f8" => (true, DType::F8),
+        _ => return format_error(&format!("unsupported dtype {descr}")),
+    };
+    let fortran_order = match header_value(header, "fortran_order")? {
+        "True" => true,
+        "False" => false,
+        other => return format_error(&format!("invalid fortran_order {other}")),
+    };
+    let shape = header_value(header, "shape")?
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(|dim| dim.trim())
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse::<usize>())
+        .collect::<Result<Vec<usize>, _>>()
+        .or_else(|_| format_error("invalid shape"))?;
+    Ok(Header {
+        dtype,
+        big_endian,
+        fortran_order,
+        shape,
+    })
+}
+
+fn read_header<R>(reader: &mut R) -> Result<Header, NpyError>
+where
+    R: Read,
+{
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic[..6] != MAGIC {
+        return format_error("missing magic string");
+    }
+    let header_len = match magic[6] {
+        1 => {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        }
+        2 | 3 => {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        }
+        version => return format_error(&format!("unsupported version {version}")),
+    };
+    let mut header = vec![0u8; header_len];
+    reader.read_exact(&mut header)?;
+    let header = String::from_utf8_lossy(&header);
+    parse_header(&header)
+}
+
+// rows, columns, and the byte length of the values
+fn dims(header: &Header) -> Result<(usize, usize, usize), NpyError> {
+    let (rows, cols) = match header.shape[..] {
+        [rows] => (rows, 1),
+        [rows, cols] => (rows, cols),
+        _ => return format_error("expected one or two dimensions"),
+    };
+    match rows
+        .checked_mul(cols)
+        .and_then(|len| len.checked_mul(header.dtype.size()))
+    {
+        Some(len) => Ok((rows, cols, len)),
+        None => format_error(&format!("shape ({rows}, {cols}) is too large")),
+    }
+}
+
+fn decode(chunk: &[u8], dtype: DType, big_endian: bool) -> f64 {
+    match (dtype, big_endian) {
+        (DType::F4, false) => f32::from_le_bytes(chunk.try_into().unwrap()) as f64,
+        (DType::F4, true) => f32::from_be_bytes(chunk.try_into().unwrap()) as f64,
+        (DType::F8, false) => f64::from_le_bytes(chunk.try_into().unwrap()),
+        (DType::F8, true) => f64::from_be_bytes(chunk.try_into().unwrap()),
+    }
+}
+
+pub fn read_npy_from<R>(mut reader: R) -> Result<Array2<f64>, NpyError>
+where
+    R: Read,
+{
+    let header = read_header(&mut reader)?;
+    let (rows, cols, len) = dims(&header)?;
+    let size = header.dtype.size();
+    let mut buff = Vec::with_capacity(len.min(READ_CAPACITY));
+    reader.take(len as u64).read_to_end(&mut buff)?;
+    if buff.len() < len {
+        return format_error(&format!(
+            "expected {len} bytes of values but got {}",
+            buff.len()
+        ));
+    }
+    let values: Vec<f64> = buff
+        .chunks_exact(size)
+        .map(|chunk| decode(chunk, header.dtype, header.big_endian))
+        .collect();
+    let arr = Array2::from_shape_vec(
+        (rows, cols).strides(if header.fortran_order {
+            (1, rows)
+        } else {
+            (cols, 1)
+        }),
+        values,
+    )
+    .or_else(|err| format_error(&err.to_string()))?;
+    Ok(arr.as_standard_layout().into_owned())
+}
+
+pub fn read_npy<P>(path: P) -> Result<Array2<f64>, NpyError>
+where
+    P: AsRef<Path>,
+{
+    read_npy_from(BufReader::new(File::open(path)?))
+}
+
+// where the values of a file start so rows can be read without loading the
+// whole array
+#[derive(Debug, Clone, Copy)]
+pub struct NpyLayout {
+    pub offset: u64,
+    pub rows: usize,
+    pub cols: usize,
+    pub value_size: usize,
+    pub big_endian: bool,
+}
+
+pub fn npy_layout<P>(path: P) -> Result<NpyLayout, NpyError>
+where
+    P: AsRef<Path>,
+{
+    let mut file = File::open(path)?;
+    let header = read_header(&mut file)?;
+    if header.fortran_order {
+        return format_error("rows of fortran ordered arrays are not contiguous");
+    }
+    let (rows, cols, _) = dims(&header)?;
+    Ok(NpyLayout {
+        offset: file.stream_position()?,
+        rows,
+        cols,
+        value_size: header.dtype.size(),
+        big_endian: header.big_endian,
+    })
+}
+
+pub(super) fn write_array_to<W, I, B>(
+    writer: &mut W,
+    descr: &str,
+    shape: &[usize],
+    values: I,
+) -> Result<(), NpyError>
+where
+    W: Write,
+    I: IntoIterator<Item = B>,
+    B: AsRef<[u8]>,
+{
+    let shape = match shape {
+        [dim] => format!("({dim},)"),
+        dims => format!(
+            "({})",
+            dims.iter()
+                .map(|dim| dim.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!("{{'descr': '{descr}', 'fortran_order': False, 'shape': {shape}, }}");
+    let total = MAGIC.len() + 4 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - total % 64) % 64));
+    header.push('\n');
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for v in values {
+        writer.write_all(v.as_ref())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_npy_to<W>(mut writer: W, arr: ArrayView2<f64>) -> Result<(), NpyError>
+where
+    W: Write,
+{
+    let (rows, cols) = arr.dim();
+    write_array_to(
+        &mut writer,
+        "<f8",
+        &[rows, cols],
+        arr.iter().map(|v| v.to_le_bytes()),
+    )
+}
+
+pub fn write_npy<P>(path: P, arr: ArrayView2<f64>) -> Result<(), NpyError>
+where
+    P: AsRef<Path>,
+{
+    write_npy_to(BufWriter::new(File::create(path)?), arr)
+}
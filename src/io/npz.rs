@@ -0,0 +1,84 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufReader, Read, Seek},
+    path::Path,
+};
+
+use ndarray::Array2;
+use zip::{result::ZipError, ZipArchive};
+
+use super::npy::{read_npy_from, NpyError};
+
+// numpy.savez stores every array as <name>.npy, positional arrays are
+// called arr_0, arr_1, ...
+fn array_names<R>(archive: &ZipArchive<R>) -> Vec<String>
+where
+    R: Read + Seek,
+{
+    let mut names: Vec<String> = archive
+        .file_names()
+        .filter_map(|name| name.strip_suffix(".npy"))
+        .map(|name| name.to_string())
+        .collect();
+    names.sort_unstable();
+    names
+}
+
+// without a name the archive must contain exactly one array
+pub fn read_npz_from<R>(reader: R, name: Option<&str>) -> Result<Array2<f64>, NpyError>
+where
+    R: Read + Seek,
+{
+    let mut archive = ZipArchive::new(reader).map_err(io::Error::from)?;
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => match &array_names(&archive)[..] {
+            [name] => name.clone(),
+            names => {
+                return Err(NpyError::FormatError(format!(
+                    "expected a single array, choose one of: {names}",
+                    names = names.join(", "),
+                )))
+            }
+        },
+    };
+    let file = match archive.by_name(&format!("{name}.npy")) {
+        Ok(file) => file,
+        Err(ZipError::FileNotFound) => {
+            return Err(NpyError::FormatError(format!("missing array {name}")))
+        }
+        Err(err) => return Err(io::Error::from(err).into()),
+    };
+    read_npy_from(file)
+}
+
+pub fn read_npz<P>(path: P, name: Option<&str>) -> Result<Array2<f64>, NpyError>
+where
+    P: AsRef<Path>,
+{
+    read_npz_from(BufReader::new(File::open(path)?), name)
+}
+
+pub fn read_npz_all_from<R>(reader: R) -> Result<BTreeMap<String, Array2<f64>>, NpyError>
+where
+    R: Read + Seek,
+{
+    let mut archive = ZipArchive::new(reader).map_err(io::Error::from)?;
+    array_names(&archive)
+        .into_iter()
+        .map(|name| {
+            let file = archive
+                .by_name(&format!("{name}.npy"))
+                .map_err(io::Error::from)?;
+            Ok((name, read_npy_from(file)?))
+        })
+        .collect()
+}
+
+pub fn read_npz_all<P>(path: P) -> Result<BTreeMap<String, Array2<f64>>, NpyError>
+where
+    P: AsRef<Path>,
+{
+    read_npz_all_from(BufReader::new(File::open(path)?))
+}
@@ -0,0 +1,194 @@
+use std::{collections::VecDeque, fmt, fs::File, marker::PhantomData, path::Path};
+
+use ndarray::Array2;
+use polars::{
+    io::{mmap::MmapBytesReader, parquet::BatchedParquetReader, SerReader},
+    prelude::{DataFrame, Float64Type, ParquetReader, PolarsError},
+};
+
+use crate::distances::{ndarray::OwnedNdProvider, scalar::Scalar};
+
+pub const PARQUET_CHUNK_SIZE: usize = 1 << 16;
+
+#[derive(Debug)]
+pub enum ParquetError {
+    IoError(std::io::Error),
+    PolarsError(PolarsError),
+    FormatError(String),
+}
+
+impl From<std::io::Error> for ParquetError {
+    fn from(value: std::io::Error) -> Self {
+        ParquetError::IoError(value)
+    }
+}
+
+impl From<PolarsError> for ParquetError {
+    fn from(value: PolarsError) -> Self {
+        ParquetError::PolarsError(value)
+    }
+}
+
+impl fmt::Display for ParquetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParquetError::IoError(err) => write!(f, "{err}"),
+            ParquetError::PolarsError(err) => write!(f, "invalid parquet file: {err}"),
+            ParquetError::FormatError(msg) => write!(f, "invalid parquet file: {msg}"),
+        }
+    }
+}
+
+// every column becomes one dimension of the embeddings
+pub fn read_parquet_from<R>(reader: R) -> Result<Array2<f64>, ParquetError>
+where
+    R: MmapBytesReader,
+{
+    let df = ParquetReader::new(reader).finish()?;
+    Ok(df.to_ndarray::<Float64Type>()?)
+}
+
+pub fn read_parquet<P>(path: P) -> Result<Array2<f64>, ParquetError>
+where
+    P: AsRef<Path>,
+{
+    read_parquet_from(File::open(path)?)
+}
+
+fn to_array<F>(df: &DataFrame) -> Result<Array2<F>, ParquetError>
+where
+    F: Scalar,
+{
+    Ok(df.to_ndarray::<Float64Type>()?.mapv(F::from_f64))
+}
+
+pub struct ParquetChunks<F> {
+    batches: BatchedParquetReader,
+    pending: VecDeque<DataFrame>,
+    dim: usize,
+    _scalar: PhantomData<F>,
+}
+
+impl<F> ParquetChunks<F> {
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+}
+
+impl<F> Iterator for ParquetChunks<F>
+where
+    F: Scalar,
+{
+    type Item = Result<Array2<F>, ParquetError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // one row group is fetched at a time
+        while self.pending.is_empty() {
+            match self.batches.next_batches(1) {
+                Ok(Some(dfs)) => self.pending.extend(dfs),
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+        self.pending.pop_front().map(|df| to_array(&df))
+    }
+}
+
+// row groups are read lazily and split into chunks of roughly chunk_size
+// rows, an empty selection reads every column
+pub fn parquet_chunks_from<R, F>(
+    reader: R,
+    columns: &[&str],
+    chunk_size: usize,
+) -> Result<ParquetChunks<F>, ParquetError>
+where
+    R: MmapBytesReader + 'static,
+{
+    let mut reader = ParquetReader::new(reader);
+    let schema = reader.schema()?;
+    let projection = columns
+        .iter()
+        .map(|&column| match schema.get_full(column) {
+            Some((pos, _, _)) => Ok(pos),
+            None => Err(ParquetError::FormatError(format!(
+                "missing column {column}"
+            ))),
+        })
+        .collect::<Result<Vec<usize>, ParquetError>>()?;
+    let dim = if projection.is_empty() {
+        schema.len()
+    } else {
+        projection.len()
+    };
+    let chunk_size = chunk_size.max(1);
+    let batches = reader
+        .with_projection((!projection.is_empty()).then_some(projection))
+        .batched(chunk_size)?;
+    Ok(ParquetChunks {
+        batches,
+        pending: VecDeque::new(),
+        dim,
+        _scalar: PhantomData,
+    })
+}
+
+pub fn parquet_chunks<P, F>(
+    path: P,
+    columns: &[&str],
+    chunk_size: usize,
+) -> Result<ParquetChunks<F>, ParquetError>
+where
+    P: AsRef<Path>,
+{
+    parquet_chunks_from(File::open(path)?, columns, chunk_size)
+}
+
+// only one chunk at a time is held as a data frame
+pub fn read_parquet_chunked_from<R, F>(
+    reader: R,
+    columns: &[&str],
+    chunk_size: usize,
+) -> Result<Array2<F>, ParquetError>
+where
+    R: MmapBytesReader + 'static,
+    F: Scalar,
+{
+    let chunks = parquet_chunks_from::<R, F>(reader, columns, chunk_size)?;
+    let dim = chunks.dim();
+    let mut values = Vec::new();
+    let mut rows = 0;
+    for chunk in chunks {
+        let chunk = chunk?;
+        rows += chunk.nrows();
+        values.extend(chunk.iter().copied());
+    }
+    Array2::from_shape_vec((rows, dim), values)
+        .map_err(|err| ParquetError::FormatError(err.to_string()))
+}
+
+pub fn read_parquet_chunked<P, F>(
+    path: P,
+    columns: &[&str],
+    chunk_size: usize,
+) -> Result<Array2<F>, ParquetError>
+where
+    P: AsRef<Path>,
+    F: Scalar,
+{
+    read_parquet_chunked_from(File::open(path)?, columns, chunk_size)
+}
+
+pub fn parquet_provider<P, D, F>(
+    path: P,
+    columns: &[&str],
+    distance: D,
+) -> Result<OwnedNdProvider<D, F>, ParquetError>
+where
+    P: AsRef<Path>,
+    F: Scalar,
+{
+    Ok(OwnedNdProvider::new(
+        read_parquet_chunked(path, columns, PARQUET_CHUNK_SIZE)?,
+        distance,
+    ))
+}
@@ -0,0 +1,140 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{BufReader, ErrorKind, Read},
+    path::Path,
+};
+
+use ndarray::Array2;
+
+#[derive(Debug)]
+pub enum VecsError {
+    IoError(std::io::Error),
+    FormatError(String),
+}
+
+impl From<std::io::Error> for VecsError {
+    fn from(value: std::io::Error) -> Self {
+        VecsError::IoError(value)
+    }
+}
+
+impl fmt::Display for VecsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VecsError::IoError(err) => write!(f, "{err}"),
+            VecsError::FormatError(msg) => write!(f, "invalid vecs file: {msg}"),
+        }
+    }
+}
+
+fn format_error<T>(msg: &str) -> Result<T, VecsError> {
+    Err(VecsError::FormatError(msg.to_string()))
+}
+
+// a clean end of file is only allowed between vectors
+fn read_dim<R>(reader: &mut R, row: usize) -> Result<Option<usize>, VecsError>
+where
+    R: Read,
+{
+    let mut buff = [0u8; 4];
+    let mut filled = 0;
+    while filled < buff.len() {
+        match reader.read(&mut buff[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return format_error(&format!("truncated length of vector {row}")),
+            Ok(count) => filled += count,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    match usize::try_from(i32::from_le_bytes(buff)) {
+        Ok(dim) => Ok(Some(dim)),
+        Err(_) => format_error(&format!("negative length of vector {row}")),
+    }
+}
+
+// every vector is stored as its little endian i32 length followed by its
+// values, all vectors of a file must have the same length
+fn read_vecs_from<R, T, const SIZE: usize>(
+    mut reader: R,
+    max_rows: Option<usize>,
+    decode: fn([u8; SIZE]) -> T,
+) -> Result<Array2<T>, VecsError>
+where
+    R: Read,
+{
+    let mut dim = None;
+    let mut rows = 0;
+    let mut values = Vec::new();
+    let mut buff = Vec::new();
+    while max_rows.is_none_or(|max_rows| rows < max_rows) {
+        let Some(cur_dim) = read_dim(&mut reader, rows)? else {
+            break;
+        };
+        let expected = *dim.get_or_insert(cur_dim);
+        if cur_dim != expected {
+            return format_error(&format!(
+                "vector {rows} has length {cur_dim} instead of {expected}"
+            ));
+        }
+        buff.resize(cur_dim * SIZE, 0);
+        reader
+            .read_exact(&mut buff)
+            .or_else(|err| match err.kind() {
+                ErrorKind::UnexpectedEof => format_error(&format!("truncated vector {rows}")),
+                _ => Err(err.into()),
+            })?;
+        values.extend(
+            buff.chunks_exact(SIZE)
+                .map(|chunk| decode(chunk.try_into().unwrap())),
+        );
+        rows += 1;
+    }
+    Array2::from_shape_vec((rows, dim.unwrap_or(0)), values)
+        .or_else(|err| format_error(&err.to_string()))
+}
+
+pub fn read_fvecs_from<R>(reader: R, max_rows: Option<usize>) -> Result<Array2<f32>, VecsError>
+where
+    R: Read,
+{
+    read_vecs_from(reader, max_rows, f32::from_le_bytes)
+}
+
+// bytes are widened to f32 so the result works with the float distances
+pub fn read_bvecs_from<R>(reader: R, max_rows: Option<usize>) -> Result<Array2<f32>, VecsError>
+where
+    R: Read,
+{
+    read_vecs_from(reader, max_rows, |[byte]: [u8; 1]| byte as f32)
+}
+
+// usually the ground truth neighbors of the query set
+pub fn read_ivecs_from<R>(reader: R, max_rows: Option<usize>) -> Result<Array2<i32>, VecsError>
+where
+    R: Read,
+{
+    read_vecs_from(reader, max_rows, i32::from_le_bytes)
+}
+
+pub fn read_fvecs<P>(path: P) -> Result<Array2<f32>, VecsError>
+where
+    P: AsRef<Path>,
+{
+    read_fvecs_from(BufReader::new(File::open(path)?), None)
+}
+
+pub fn read_bvecs<P>(path: P) -> Result<Array2<f32>, VecsError>
+where
+    P: AsRef<Path>,
+{
+    read_bvecs_from(BufReader::new(File::open(path)?), None)
+}
+
+pub fn read_ivecs<P>(path: P) -> Result<Array2<i32>, VecsError>
+where
+    P: AsRef<Path>,
+{
+    read_ivecs_from(BufReader::new(File::open(path)?), None)
+}
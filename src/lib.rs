@@ -1,6 +1,23 @@
 pub mod cache;
+pub mod clustering;
+#[cfg(feature = "datafusion")]
+pub mod datafusion;
+pub mod density;
 pub mod distances;
+pub mod evaluate;
+#[cfg(feature = "flight")]
+pub mod flight;
+pub mod graph;
+pub mod ids;
+pub mod index;
 pub mod info;
+pub mod io;
+pub mod ml;
+#[cfg(feature = "node")]
+pub mod node;
+pub mod payload;
+#[cfg(feature = "python")]
+pub mod python;
 
 mod fann;
 pub use fann::*;
@@ -1,27 +1,42 @@
-use clap::{arg, Parser};
-use fann::distances::vec::{VecProvider, VEC_DOT_DISTANCE};
+use clap::Parser;
+use fann::distances::vec::{vec_distance, VecProvider};
 use fann::info::{no_info, BaseInfo, Info};
+use fann::io::{load_embed_csv, parquet::PARQUET_CHUNK_SIZE, read_parquet_chunked};
 use fann::kmed::FannTree;
+use log::{info, LevelFilter, Log, Metadata, Record};
 use std::time::Instant;
 
 use fann::cache::DistanceCache;
-use ndarray::{s, Array2, ArrayView2};
-use polars::io::prelude::*;
-use polars::prelude::Float64Type;
+use ndarray::{s, ArrayView1, ArrayView2, CowArray};
 
-use fann::distances::ndarray::{NdProvider, ND_DOT_DISTANCE};
-use fann::{Embedding, EmbeddingProvider, Fann, NearestNeighbors};
-
-fn load_embed(path: &str) -> Array2<f64> {
-    let mut file = std::fs::File::open(path).unwrap();
-    let df = ParquetReader::new(&mut file).finish().unwrap();
-    df.to_ndarray::<Float64Type>().unwrap()
-}
+use fann::distances::{
+    mips::MipsTransform,
+    ndarray::{nd_distance, NdProvider, ND_DOT_DISTANCE, ND_L2_DISTANCE},
+};
+use fann::{Distance, Embedding, EmbeddingProvider, Fann, FingerprintPolicy, NearestNeighbors};
 
 fn to_vec_vec(arr: ArrayView2<f64>) -> Vec<Vec<f64>> {
     arr.rows().into_iter().map(|row| row.to_vec()).collect()
 }
 
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
@@ -35,6 +50,14 @@ struct Args {
     force: bool,
     #[arg(short, long, default_value_t = false)]
     info: bool,
+    #[arg(short, long, default_value_t = String::from("dot"))]
+    distance: String,
+    #[arg(short, long, default_value_t = false)]
+    verbose: bool,
+    // comma separated columns, defaults to all numeric columns for csv or tsv
+    // files and to all columns for parquet files
+    #[arg(long)]
+    columns: Option<String>,
 }
 
 fn main() {
@@ -43,19 +66,52 @@ fn main() {
     let pre_cluster = args.precluster;
     let force = args.force;
     let print_info = args.info;
-    println!("size: {} pre_cluster: {:?}", total_size, pre_cluster);
+    let distance_name = args.distance.as_str();
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(if args.verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    info!("size: {} pre_cluster: {:?}", total_size, pre_cluster);
 
     let t_load = Instant::now();
-    let df = load_embed(args.file.as_str());
-    println!("load took {:?}", t_load.elapsed());
-    println!("{shape:?}", shape = df.shape());
+    let columns: Vec<&str> = args.columns.as_deref().map_or(Vec::new(), |columns| {
+        columns.split(',').map(str::trim).collect()
+    });
+    let df = if [".csv", ".tsv", ".tab"]
+        .iter()
+        .any(|ext| args.file.ends_with(ext))
+    {
+        load_embed_csv(args.file.as_str(), &columns).unwrap()
+    } else {
+        read_parquet_chunked(args.file.as_str(), &columns, PARQUET_CHUNK_SIZE).unwrap()
+    };
+    info!("load took {:?}", t_load.elapsed());
+    info!("{shape:?}", shape = df.shape());
     let mut info = BaseInfo::new(total_size);
 
-    let provider = NdProvider::new(df.slice(s![0..total_size, ..]), ND_DOT_DISTANCE);
+    // exp(-dot) is not a metric and would be searched exhaustively so dot
+    // runs as l2 on the mips augmented rows
+    let is_dot = distance_name == Distance::<ArrayView1<f64>>::name(&ND_DOT_DISTANCE);
+    let mips = is_dot.then(|| MipsTransform::fit(df.slice(s![0..total_size, ..])));
+    let rows = match &mips {
+        Some(mips) => CowArray::from(mips.augment(df.slice(s![0..total_size, ..]))),
+        None => CowArray::from(df.slice(s![0..total_size, ..])),
+    };
+    let tree_distance = if is_dot {
+        Distance::<ArrayView1<f64>>::name(&ND_L2_DISTANCE)
+    } else {
+        distance_name
+    };
+    let nd_dist = nd_distance(tree_distance).expect("unknown distance");
+    let vec_dist = vec_distance(distance_name).expect("unknown distance");
+    let provider = NdProvider::new(rows.view(), nd_dist);
+    let dot_provider = NdProvider::new(df.slice(s![0..total_size, ..]), ND_DOT_DISTANCE);
     let vv = to_vec_vec(df.slice(s![0..total_size, ..]));
-    let vv_provider = VecProvider::new(&vv, VEC_DOT_DISTANCE);
+    let vv_provider = VecProvider::new(&vv, vec_dist);
 
-    println!("{size:?}", size = provider.all());
+    info!("{size:?}", size = provider.all());
 
     let mut fann = Fann::new(&provider);
     let t_build = Instant::now();
@@ -64,10 +120,10 @@ fn main() {
     if !force && tfile.exists() {
         fann.set_tree(
             FannTree::load(&std::fs::File::open(tfile).unwrap()).unwrap(),
-            false,
+            FingerprintPolicy::CheckAll,
         )
         .unwrap();
-        println!("load took {:?}", t_build.elapsed());
+        info!("load took {:?}", t_build.elapsed());
     } else {
         let mut cache = DistanceCache::new(100000);
         fann.build(None, pre_cluster, &mut cache, &mut info);
@@ -76,9 +132,9 @@ fn main() {
             .unwrap()
             .save(&std::fs::File::create(tfile).unwrap())
             .unwrap();
-        println!("build took {:?}", t_build.elapsed());
+        info!("build took {:?}", t_build.elapsed());
         let (hits, miss) = info.cache_hits_miss();
-        println!(
+        info!(
             "cache[rate: {:.2}% hits: {} miss: {} total: {}]",
             info.cache_hit_rate() * 100.0,
             hits,
@@ -90,13 +146,28 @@ fn main() {
 
     let embed_v = df.row(total_size);
     let embed = Embedding::as_embedding(embed_v);
+    let query = match &mips {
+        Some(mips) => CowArray::from(mips.augment_query(embed_v)),
+        None => CowArray::from(embed_v),
+    };
+    let tree_embed = Embedding::as_embedding(query.view());
 
     let t_search = Instant::now();
-    let closest = fann.get_closest(&embed, 10, &mut info);
-    println!("search took {:?}", t_search.elapsed());
-    println!("{:?}", closest);
+    let closest = fann.get_closest(&tree_embed, 10, &mut info);
+    info!("search took {:?}", t_search.elapsed());
+    match &mips {
+        // in the exp(-dot) space of the baselines
+        Some(mips) => println!(
+            "{:?}",
+            mips.to_dots(&closest, embed_v)
+                .into_iter()
+                .map(|(ix, dot)| (ix, (-dot).exp()))
+                .collect::<Vec<_>>()
+        ),
+        None => println!("{:?}", closest),
+    }
 
-    println!("cache[total: {}]", info.dist_count());
+    info!("cache[total: {}]", info.dist_count());
     if print_info {
         println!(
             "{draw}",
@@ -104,16 +175,18 @@ fn main() {
         );
     }
 
-    let t_base_search = Instant::now();
-    let base_closest = provider.get_closest(&embed, 10, &mut no_info());
-    println!("baseline search took {:?}", t_base_search.elapsed());
-    println!("{:?}", base_closest);
+    if is_dot {
+        let t_base_search = Instant::now();
+        let base_closest = dot_provider.get_closest(&embed, 10, &mut no_info());
+        info!("baseline search took {:?}", t_base_search.elapsed());
+        println!("{:?}", base_closest);
+    }
 
     let vv_embed_v = embed_v.to_vec();
     let vv_embed = Embedding::as_embedding(&vv_embed_v);
 
     let t_vv_base_search = Instant::now();
     let vv_base_closest = vv_provider.get_closest(&vv_embed, 10, &mut no_info());
-    println!("vv baseline search took {:?}", t_vv_base_search.elapsed());
+    info!("vv baseline search took {:?}", t_vv_base_search.elapsed());
     println!("{:?}", vv_base_closest);
 }
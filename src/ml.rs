@@ -0,0 +1,115 @@
+use std::hash::Hash;
+
+use crate::{info::Info, DistanceValue, Embedding, NearestNeighbors};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Weighting {
+    Uniform,
+    Distance,
+}
+
+fn weights(neighbors: &[(usize, DistanceValue)], weighting: Weighting) -> Vec<DistanceValue> {
+    match weighting {
+        Weighting::Uniform => vec![1.0; neighbors.len()],
+        Weighting::Distance => {
+            // exact matches take all the weight
+            if neighbors.iter().any(|&(_, dist)| dist <= 0.0) {
+                neighbors
+                    .iter()
+                    .map(|&(_, dist)| if dist <= 0.0 { 1.0 } else { 0.0 })
+                    .collect()
+            } else {
+                neighbors.iter().map(|&(_, dist)| 1.0 / dist).collect()
+            }
+        }
+    }
+}
+
+pub struct KnnClassifier<'l, L> {
+    labels: &'l [L],
+    weighting: Weighting,
+}
+
+impl<'l, L> KnnClassifier<'l, L>
+where
+    L: Eq + Hash + Clone,
+{
+    pub fn new(labels: &'l [L], weighting: Weighting) -> Self {
+        KnnClassifier { labels, weighting }
+    }
+
+    pub fn vote(&self, neighbors: &[(usize, DistanceValue)]) -> Option<L> {
+        let mut votes: Vec<(&L, DistanceValue)> = Vec::new();
+        neighbors
+            .iter()
+            .zip(weights(neighbors, self.weighting))
+            .for_each(|(&(ix, _), weight)| {
+                let label = &self.labels[ix];
+                match votes.iter_mut().find(|(other, _)| *other == label) {
+                    Some((_, total)) => *total += weight,
+                    None => votes.push((label, weight)),
+                }
+            });
+        // ties go to the label with the closest neighbor
+        votes
+            .into_iter()
+            .rev()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(label, _)| label.clone())
+    }
+
+    pub fn predict<'a, N, T, I>(
+        &self,
+        neighbors: &N,
+        query: &'a Embedding<T>,
+        k: usize,
+        info: &mut I,
+    ) -> Option<L>
+    where
+        N: NearestNeighbors<'a, T>,
+        T: 'a,
+        I: Info,
+    {
+        self.vote(&neighbors.get_closest(query, k, info))
+    }
+}
+
+pub struct KnnRegressor<'l> {
+    targets: &'l [DistanceValue],
+    weighting: Weighting,
+}
+
+impl<'l> KnnRegressor<'l> {
+    pub fn new(targets: &'l [DistanceValue], weighting: Weighting) -> Self {
+        KnnRegressor { targets, weighting }
+    }
+
+    pub fn average(&self, neighbors: &[(usize, DistanceValue)]) -> Option<DistanceValue> {
+        let weights = weights(neighbors, self.weighting);
+        let total: DistanceValue = weights.iter().sum();
+        if total <= 0.0 {
+            return None;
+        }
+        let sum: DistanceValue = neighbors
+            .iter()
+            .zip(weights)
+            .map(|(&(ix, _), weight)| self.targets[ix] * weight)
+            .sum();
+        Some(sum / total)
+    }
+
+    pub fn predict<'a, N, T, I>(
+        &self,
+        neighbors: &N,
+        query: &'a Embedding<T>,
+        k: usize,
+        info: &mut I,
+    ) -> Option<DistanceValue>
+    where
+        N: NearestNeighbors<'a, T>,
+        T: 'a,
+        I: Info,
+    {
+        self.average(&neighbors.get_closest(query, k, info))
+    }
+}
@@ -0,0 +1,115 @@
+use napi::{
+    bindgen_prelude::{Float64Array, Uint32Array},
+    Error, Result, Status,
+};
+use napi_derive::napi;
+use ndarray::{Array2, ArrayView1};
+
+use crate::{index::OwnedNdIndex, kmed::FannTree, DistanceValue};
+
+#[cfg(not(feature = "f32"))]
+type DistanceArray = Float64Array;
+#[cfg(feature = "f32")]
+type DistanceArray = napi::bindgen_prelude::Float32Array;
+
+#[napi(object)]
+pub struct QueryResult {
+    pub indices: Uint32Array,
+    pub distances: DistanceArray,
+}
+
+#[napi(js_name = "Index")]
+pub struct NodeIndex {
+    index: OwnedNdIndex,
+}
+
+fn to_array(data: &[f64], dim: u32) -> Result<Array2<f64>> {
+    let dim = dim as usize;
+    if dim == 0 || !data.len().is_multiple_of(dim) {
+        return Err(Error::new(
+            Status::InvalidArg,
+            format!(
+                "data of length {len} is not divisible by {dim}",
+                len = data.len()
+            ),
+        ));
+    }
+    Array2::from_shape_vec((data.len() / dim, dim), data.to_vec())
+        .map_err(|err| Error::new(Status::InvalidArg, err.to_string()))
+}
+
+fn invalid<E: ToString>(err: E) -> Error {
+    Error::new(Status::InvalidArg, err.to_string())
+}
+
+fn io_error<E: std::fmt::Debug>(err: E) -> Error {
+    Error::new(Status::GenericFailure, format!("{err:?}"))
+}
+
+#[napi]
+impl NodeIndex {
+    #[napi(factory)]
+    pub fn build(
+        data: Float64Array,
+        dim: u32,
+        distance: Option<String>,
+        max_node_size: Option<u32>,
+        pre_cluster: Option<u32>,
+    ) -> Result<Self> {
+        let arr = to_array(&data, dim)?;
+        let distance = distance.unwrap_or_else(|| "l2".to_string());
+        let index = OwnedNdIndex::build(
+            arr,
+            &distance,
+            max_node_size.map(|v| v as usize),
+            pre_cluster.map(|v| v as usize),
+        )
+        .map_err(invalid)?;
+        Ok(NodeIndex { index })
+    }
+
+    #[napi(factory)]
+    pub fn load(
+        data: Float64Array,
+        dim: u32,
+        path: String,
+        distance: Option<String>,
+    ) -> Result<Self> {
+        let arr = to_array(&data, dim)?;
+        let distance = distance.unwrap_or_else(|| "l2".to_string());
+        let file = std::fs::File::open(path).map_err(io_error)?;
+        let tree = FannTree::load(&file).map_err(io_error)?;
+        let index = OwnedNdIndex::with_tree(arr, &distance, tree).map_err(invalid)?;
+        Ok(NodeIndex { index })
+    }
+
+    #[napi]
+    pub fn save(&self, path: String) -> Result<()> {
+        let file = std::fs::File::create(path).map_err(io_error)?;
+        self.index.tree().save(&file).map_err(io_error)
+    }
+
+    #[napi]
+    pub fn query(&self, embed: Float64Array, count: u32) -> Result<QueryResult> {
+        let res = self
+            .index
+            .try_query(ArrayView1::from(&embed[..]), count as usize)
+            .map_err(invalid)?;
+        let indices: Vec<u32> = res.iter().map(|&(ix, _)| ix as u32).collect();
+        let distances: Vec<DistanceValue> = res.iter().map(|&(_, dist)| dist).collect();
+        Ok(QueryResult {
+            indices: indices.into(),
+            distances: distances.into(),
+        })
+    }
+
+    #[napi(getter)]
+    pub fn length(&self) -> u32 {
+        self.index.len() as u32
+    }
+
+    #[napi(getter)]
+    pub fn dim(&self) -> u32 {
+        self.index.dim() as u32
+    }
+}
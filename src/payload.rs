@@ -0,0 +1,187 @@
+use std::ops::Range;
+
+#[cfg(feature = "archive")]
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "archive")]
+use zip::{result::ZipError, write::FileOptions};
+
+#[cfg(feature = "archive")]
+use crate::kmed::{FannTree, TreeLoadError, TreeWriteError};
+use crate::DistanceValue;
+
+#[cfg(feature = "archive")]
+const PAYLOADS_FILE: &str = "payloads.json";
+
+// payloads are looked up by original index like the ids of an IdMap, rows
+// without a payload are stored as null
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PayloadStore<P = serde_json::Value> {
+    payloads: Vec<Option<P>>,
+}
+
+impl<P> Default for PayloadStore<P> {
+    fn default() -> Self {
+        PayloadStore {
+            payloads: Vec::new(),
+        }
+    }
+}
+
+impl<P> PayloadStore<P> {
+    pub fn new() -> Self {
+        PayloadStore::default()
+    }
+
+    pub fn from_payloads<I>(payloads: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+    {
+        PayloadStore {
+            payloads: payloads.into_iter().map(Some).collect(),
+        }
+    }
+
+    pub fn push(&mut self, payload: P) -> usize {
+        self.payloads.push(Some(payload));
+        self.payloads.len() - 1
+    }
+
+    pub fn set(&mut self, index: usize, payload: P) -> Option<P> {
+        if index >= self.payloads.len() {
+            self.payloads.resize_with(index + 1, || None);
+        }
+        self.payloads[index].replace(payload)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<P> {
+        self.payloads
+            .get_mut(index)
+            .and_then(|payload| payload.take())
+    }
+
+    pub fn get(&self, index: usize) -> Option<&P> {
+        self.payloads
+            .get(index)
+            .and_then(|payload| payload.as_ref())
+    }
+
+    pub fn len(&self) -> usize {
+        self.payloads.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.payloads.is_empty()
+    }
+
+    pub fn attach(&self, results: &[(usize, DistanceValue)]) -> PayloadResult<P>
+    where
+        P: Clone,
+    {
+        PayloadResult {
+            neighbors: results
+                .iter()
+                .map(|&(index, distance)| PayloadNeighbor {
+                    index,
+                    distance,
+                    payload: self.get(index).cloned(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<P> PayloadStore<P>
+where
+    P: Clone,
+{
+    // matches a provider that was built over the same subrange of rows
+    pub fn slice(&self, range: Range<usize>) -> PayloadStore<P> {
+        PayloadStore {
+            payloads: range.map(|index| self.get(index).cloned()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PayloadNeighbor<P> {
+    pub index: usize,
+    pub distance: DistanceValue,
+    pub payload: Option<P>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PayloadResult<P = serde_json::Value> {
+    pub neighbors: Vec<PayloadNeighbor<P>>,
+}
+
+impl<P> PayloadResult<P> {
+    pub fn len(&self) -> usize {
+        self.neighbors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.neighbors.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, PayloadNeighbor<P>> {
+        self.neighbors.iter()
+    }
+
+    pub fn indices(&self) -> Vec<usize> {
+        self.neighbors.iter().map(|n| n.index).collect()
+    }
+
+    pub fn payloads(&self) -> impl Iterator<Item = Option<&P>> {
+        self.neighbors.iter().map(|n| n.payload.as_ref())
+    }
+}
+
+impl<P> IntoIterator for PayloadResult<P> {
+    type Item = PayloadNeighbor<P>;
+    type IntoIter = std::vec::IntoIter<PayloadNeighbor<P>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.neighbors.into_iter()
+    }
+}
+
+// the payloads are a separate entry next to tree.json so FannTree::load
+// keeps working on the same file
+#[cfg(feature = "archive")]
+impl FannTree {
+    pub fn save_with_payloads<P>(
+        &self,
+        file: &std::fs::File,
+        payloads: &PayloadStore<P>,
+    ) -> Result<(), TreeWriteError>
+    where
+        P: Serialize,
+    {
+        let mut zip = zip::ZipWriter::new(file);
+        let options = FileOptions::default()
+            .compression_method(zip::CompressionMethod::Bzip2)
+            .unix_permissions(0o755);
+        zip.start_file("tree.json", options)?;
+        serde_json::to_writer(&mut zip, self)?;
+        zip.start_file(PAYLOADS_FILE, options)?;
+        serde_json::to_writer(&mut zip, payloads)?;
+        zip.finish()?;
+        Ok(())
+    }
+
+    // files saved without payloads yield an empty store
+    pub fn load_payloads<P>(file: &std::fs::File) -> Result<PayloadStore<P>, TreeLoadError>
+    where
+        P: DeserializeOwned,
+    {
+        let mut archive = zip::ZipArchive::new(file)?;
+        let res = match archive.by_name(PAYLOADS_FILE) {
+            Ok(zip_file) => serde_json::from_reader(zip_file)?,
+            Err(ZipError::FileNotFound) => PayloadStore::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(res)
+    }
+}
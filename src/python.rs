@@ -0,0 +1,76 @@
+use numpy::{PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::{
+    exceptions::{PyIOError, PyValueError},
+    prelude::*,
+};
+
+use crate::{index::OwnedNdIndex, kmed::FannTree, DistanceValue};
+
+#[pyclass(name = "Index")]
+pub struct PyIndex {
+    index: OwnedNdIndex,
+}
+
+#[pymethods]
+impl PyIndex {
+    #[staticmethod]
+    #[pyo3(signature = (arr, distance="l2", max_node_size=None, pre_cluster=None))]
+    fn build(
+        py: Python,
+        arr: PyReadonlyArray2<f64>,
+        distance: &str,
+        max_node_size: Option<usize>,
+        pre_cluster: Option<usize>,
+    ) -> PyResult<Self> {
+        let arr = arr.as_array().to_owned();
+        let index = py
+            .allow_threads(|| OwnedNdIndex::build(arr, distance, max_node_size, pre_cluster))
+            .map_err(|err| PyValueError::new_err(err.to_string()))?;
+        Ok(PyIndex { index })
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (arr, path, distance="l2"))]
+    fn load(arr: PyReadonlyArray2<f64>, path: &str, distance: &str) -> PyResult<Self> {
+        let file = std::fs::File::open(path)?;
+        let tree = FannTree::load(&file)
+            .map_err(|err| PyIOError::new_err(format!("could not load tree: {err:?}")))?;
+        let index = OwnedNdIndex::with_tree(arr.as_array().to_owned(), distance, tree)
+            .map_err(|err| PyValueError::new_err(err.to_string()))?;
+        Ok(PyIndex { index })
+    }
+
+    fn save(&self, path: &str) -> PyResult<()> {
+        let file = std::fs::File::create(path)?;
+        self.index
+            .tree()
+            .save(&file)
+            .map_err(|err| PyIOError::new_err(format!("could not save tree: {err:?}")))
+    }
+
+    fn query(
+        &self,
+        py: Python,
+        embed: PyReadonlyArray1<f64>,
+        count: usize,
+    ) -> PyResult<Vec<(usize, DistanceValue)>> {
+        let embed = embed.as_array();
+        py.allow_threads(|| self.index.try_query(embed, count))
+            .map_err(|err| PyValueError::new_err(err.to_string()))
+    }
+
+    fn reorder(&mut self, py: Python) -> PyResult<()> {
+        py.allow_threads(|| self.index.reorder())
+            .map_err(|err| PyValueError::new_err(err.to_string()))
+    }
+
+    fn __len__(&self) -> usize {
+        self.index.len()
+    }
+}
+
+#[pymodule]
+fn fann(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyIndex>()?;
+    Ok(())
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use std::sync::Arc;
+
+use fann::{
+    buffered::BufferedTree,
+    cache::{no_cache, DistanceCache},
+    distances::ndarray::{NdProvider, OwnedNdProvider, ND_L2_DISTANCE},
+    info::no_info,
+    kmed::FannTree,
+    Embedding, LocalDistance, Tree,
+};
+use ndarray::{concatenate, s, Axis};
+
+#[test]
+fn merges_keep_the_original_indices_of_a_reordered_tree() {
+    let arr = common::random_arr(300, 4, 19);
+    let base = arr.slice(s![0..250, ..]);
+    let base_provider = NdProvider::new(base, ND_L2_DISTANCE);
+    let mut tree = FannTree::build(
+        &base_provider,
+        Some(5),
+        None,
+        &mut no_cache(),
+        &mut no_info(),
+    );
+    let order = tree.layout_order();
+    let reordered = base.select(Axis(0), &order);
+    let reordered_provider = NdProvider::new(reordered.view(), ND_L2_DISTANCE);
+    tree.reorder(&order, &reordered_provider).unwrap();
+    // new rows are appended after the reordered ones
+    let rows = concatenate![Axis(0), reordered, arr.slice(s![250.., ..])];
+    let provider = OwnedNdProvider::new(rows, ND_L2_DISTANCE);
+    let buffered = Arc::new(BufferedTree::new(tree, Some(5), None));
+    (250..300).for_each(|ix| buffered.push(ix));
+    let assert_closest = |buffered: &BufferedTree| {
+        (0..300).for_each(|ix| {
+            let query = provider.view();
+            let embed = Embedding::as_embedding(arr.row(ix));
+            let ldist = LocalDistance::new(&query, &embed);
+            let res = buffered.get_closest(1, &ldist, &mut no_info());
+            assert_eq!(res[0].0, ix);
+        })
+    };
+    assert_closest(&buffered);
+    let merged = buffered
+        .spawn_merge(provider.clone(), DistanceCache::new(10000))
+        .join()
+        .unwrap();
+    assert_eq!(merged, 50);
+    assert_eq!(buffered.buffer_len(), 0);
+    assert_eq!(buffered.len(), 300);
+    assert_closest(&buffered);
+    buffered
+        .tree()
+        .check_invariants(&provider, &mut no_cache())
+        .unwrap();
+}
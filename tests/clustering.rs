@@ -0,0 +1,96 @@
+mod common;
+
+use fann::{
+    cache::DistanceCache,
+    clustering::{kmedoids, kmedoids_of, KMedoidsError, KMedoidsParams},
+    distances::ndarray::{NdProvider, ND_L2_DISTANCE},
+    info::no_info,
+    kmed::FannTree,
+    Tree,
+};
+
+#[test]
+fn kmedoids_rejects_invalid_parameters() {
+    let arr = common::random_arr(50, 3, 21);
+    let provider = NdProvider::new(arr.view(), ND_L2_DISTANCE);
+    let mut cache = DistanceCache::new(10000);
+    let params = KMedoidsParams::default();
+    let res = kmedoids(&provider, 0, params, &mut cache, &mut no_info());
+    assert_eq!(res.err(), Some(KMedoidsError::NoClusters));
+    let all_ixs: Vec<usize> = (0..50).collect();
+    let res = kmedoids_of(
+        &provider,
+        all_ixs.clone(),
+        Some(vec![1, 2]),
+        3,
+        params,
+        &mut cache,
+        &mut no_info(),
+    );
+    assert_eq!(
+        res.err(),
+        Some(KMedoidsError::InitMedoids {
+            expected: 3,
+            actual: 2
+        })
+    );
+    let res = kmedoids_of(
+        &provider,
+        all_ixs.clone(),
+        Some(vec![1, 2, 60]),
+        3,
+        params,
+        &mut cache,
+        &mut no_info(),
+    );
+    assert_eq!(res.err(), Some(KMedoidsError::UnknownMedoid(60)));
+    let res = kmedoids_of(
+        &provider,
+        all_ixs,
+        Some(vec![1, 2, 3]),
+        3,
+        params,
+        &mut cache,
+        &mut no_info(),
+    )
+    .unwrap();
+    assert_eq!(res.clusters.len(), 3);
+    assert_eq!(
+        res.clusters.iter().map(|c| c.members.len()).sum::<usize>(),
+        50
+    );
+    // nothing to cluster needs no clusters
+    let res = kmedoids_of(
+        &provider,
+        Vec::new(),
+        None,
+        0,
+        params,
+        &mut cache,
+        &mut no_info(),
+    )
+    .unwrap();
+    assert!(res.clusters.is_empty());
+}
+
+#[test]
+fn planted_outlier_scores_highest() {
+    let mut arr = common::random_arr(300, 3, 22);
+    arr.row_mut(123).fill(20.0);
+    let provider = NdProvider::new(arr.view(), ND_L2_DISTANCE);
+    let tree = FannTree::build(
+        &provider,
+        Some(6),
+        None,
+        &mut DistanceCache::new(100000),
+        &mut no_info(),
+    );
+    let scores = tree.outlier_scores(&provider, &mut no_info());
+    assert_eq!(scores.len(), 300);
+    assert!(scores.values().all(|&score| score >= 0.0));
+    let (&top, _) = scores
+        .iter()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .unwrap();
+    assert_eq!(top, 123);
+}
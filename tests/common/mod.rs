@@ -0,0 +1,12 @@
+use ndarray::Array2;
+
+// the same generator as the benches so failures reproduce
+pub fn random_arr(rows: usize, cols: usize, seed: u64) -> Array2<f64> {
+    let mut state = seed;
+    Array2::from_shape_simple_fn((rows, cols), || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((state >> 33) as f64) / (1u64 << 31) as f64 - 0.5
+    })
+}
@@ -0,0 +1,48 @@
+use std::{fs, num::NonZeroUsize, path::PathBuf};
+
+use fann::distances::disk::{DiskError, DiskProvider, FileRows, RowSource, DISK_L2_DISTANCE};
+
+fn temp_file(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fann-{name}-{pid}", pid = std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir.join("rows.fvecs")
+}
+
+fn fvecs(rows: &[(i32, [f32; 3])]) -> Vec<u8> {
+    rows.iter()
+        .flat_map(|(dim, row)| {
+            dim.to_le_bytes()
+                .into_iter()
+                .chain(row.iter().flat_map(|v| v.to_le_bytes()))
+        })
+        .collect()
+}
+
+#[test]
+fn fvecs_rows_check_their_prefix() {
+    let path = temp_file("disk-prefix");
+    fs::write(
+        &path,
+        fvecs(&[
+            (3, [1.0, 2.0, 3.0]),
+            (4, [4.0, 5.0, 6.0]),
+            (3, [7.0, 8.0, 9.0]),
+        ]),
+    )
+    .unwrap();
+    let rows = FileRows::open_fvecs(&path).unwrap();
+    let mut row: Vec<f64> = Vec::new();
+    rows.fetch(2, &mut row).unwrap();
+    assert_eq!(row, vec![7.0, 8.0, 9.0]);
+    assert!(matches!(
+        rows.fetch(1, &mut row),
+        Err(DiskError::FormatError(_))
+    ));
+    let provider: DiskProvider<_, _, f64> =
+        DiskProvider::new(rows, NonZeroUsize::new(2).unwrap(), DISK_L2_DISTANCE);
+    assert!(matches!(
+        provider.try_validate(),
+        Err(DiskError::FormatError(_))
+    ));
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,81 @@
+#![cfg(feature = "hdf5")]
+
+mod common;
+
+use fann::{
+    distances::ndarray::{NdProvider, ND_L2_DISTANCE},
+    evaluate::{GroundTruth, NeighborsError},
+    info::no_info,
+    io::read_ann_benchmarks,
+    Embedding, NearestNeighbors,
+};
+use hdf5_pure::{AttrValue, FileBuilder};
+use ndarray::{Array2, ArrayView2};
+
+fn to_f32(arr: ArrayView2<f64>) -> Vec<f32> {
+    arr.iter().map(|&v| v as f32).collect()
+}
+
+#[test]
+fn ann_benchmarks_neighbors_become_ground_truth() {
+    let train = common::random_arr(50, 3, 17);
+    let test = common::random_arr(4, 3, 19);
+    let distance = ND_L2_DISTANCE;
+    let mut neighbors: Vec<i32> = Vec::new();
+    for row in test.rows() {
+        let provider = NdProvider::new(train.view(), distance);
+        let embed = Embedding::as_embedding(row.view());
+        let closest = provider.get_closest(&embed, 3, &mut no_info());
+        neighbors.extend(closest.iter().map(|&(ix, _)| ix as i32));
+    }
+    let path = std::env::temp_dir().join(format!("fann-ann-{pid}.hdf5", pid = std::process::id()));
+    let mut builder = FileBuilder::new();
+    builder.set_attr("distance", AttrValue::String("euclidean".into()));
+    builder
+        .create_dataset("train")
+        .with_f32_data(&to_f32(train.view()))
+        .with_shape(&[50, 3]);
+    builder
+        .create_dataset("test")
+        .with_f32_data(&to_f32(test.view()))
+        .with_shape(&[4, 3]);
+    builder
+        .create_dataset("neighbors")
+        .with_i32_data(&neighbors)
+        .with_shape(&[4, 3]);
+    builder.write(&path).unwrap();
+
+    let dataset = read_ann_benchmarks(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(dataset.distance_name(), Some("l2"));
+    assert!(dataset.distances.is_none());
+    assert_eq!(dataset.train.shape(), &[50, 3]);
+    let provider = NdProvider::new(dataset.train.view(), distance);
+    let queries = NdProvider::new(dataset.test.view(), distance);
+    let truth = GroundTruth::from_neighbors(&provider, &queries, dataset.neighbors.view()).unwrap();
+    assert_eq!(truth.k, 3);
+    let ids: Vec<i32> = truth
+        .neighbors
+        .iter()
+        .flat_map(|row| row.iter().map(|&(ix, _)| ix as i32))
+        .collect();
+    assert_eq!(ids, neighbors);
+
+    let mut unknown = dataset.neighbors.clone();
+    unknown[[2, 1]] = 50;
+    assert_eq!(
+        GroundTruth::from_neighbors(&provider, &queries, unknown.view()).unwrap_err(),
+        NeighborsError::OutOfRange {
+            query: 2,
+            index: 50
+        }
+    );
+    let missing = Array2::<i32>::zeros((3, 3));
+    assert_eq!(
+        GroundTruth::from_neighbors(&provider, &queries, missing.view()).unwrap_err(),
+        NeighborsError::RowCount {
+            queries: 4,
+            rows: 3
+        }
+    );
+}
@@ -0,0 +1,30 @@
+mod common;
+
+use fann::{index::OwnedNdIndex, kmed::InsertError, DimensionMismatch};
+use ndarray::arr1;
+
+#[test]
+fn updates_follow_the_reordered_rows() {
+    let arr = common::random_arr(80, 3, 13);
+    let mut index = OwnedNdIndex::build(arr, "l2", Some(4), None).unwrap();
+    index.reorder().unwrap();
+    let embed = arr1(&[5.0, -5.0, 5.0]);
+    index.update(17, embed.view()).unwrap();
+    let pos = (0..80)
+        .find(|&pos| index.tree().original_index(pos) == 17)
+        .unwrap();
+    assert_eq!(index.arr().row(pos), embed.view());
+    assert_eq!(index.query(embed.view(), 1)[0].0, 17);
+    assert_eq!(
+        index.update(80, embed.view()),
+        Err(InsertError::OutOfRange(80))
+    );
+    assert_eq!(
+        index.update(17, arr1(&[1.0, 2.0]).view()),
+        Err(InsertError::DimensionMismatch(DimensionMismatch {
+            expected: 3,
+            actual: 2
+        }))
+    );
+    assert_eq!(index.arr().row(pos), embed.view());
+}
@@ -0,0 +1,156 @@
+use fann::io::{
+    jsonl::{read_jsonl_from, JsonlError},
+    npy::{read_npy_from, write_npy_to, NpyError},
+    vecs::{read_bvecs_from, read_fvecs_from, read_ivecs_from, VecsError},
+};
+use ndarray::{array, Array2};
+
+fn npy_bytes(header: &str, values: &[f64]) -> Vec<u8> {
+    let mut res = b"\x93NUMPY\x01\x00".to_vec();
+    res.extend((header.len() as u16).to_le_bytes());
+    res.extend(header.as_bytes());
+    res.extend(values.iter().flat_map(|v| v.to_le_bytes()));
+    res
+}
+
+#[test]
+fn npy_round_trips() {
+    let arr = array![[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]];
+    let mut bytes = Vec::new();
+    write_npy_to(&mut bytes, arr.view()).unwrap();
+    assert_eq!(read_npy_from(bytes.as_slice()).unwrap(), arr);
+}
+
+#[test]
+fn npy_reads_fortran_order() {
+    let bytes = npy_bytes(
+        "{'descr': '<f8', 'fortran_order': True, 'shape': (2, 3), }\n",
+        &[1.0, 4.0, 2.0, 5.0, 3.0, 6.0],
+    );
+    let arr: Array2<f64> = read_npy_from(bytes.as_slice()).unwrap();
+    assert_eq!(arr, array![[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]);
+}
+
+#[test]
+fn npy_rejects_malformed_input() {
+    let is_format_error = |bytes: Vec<u8>| {
+        matches!(
+            read_npy_from(bytes.as_slice()),
+            Err(NpyError::FormatError(_))
+        )
+    };
+    // the values end before the shape says they do
+    assert!(is_format_error(npy_bytes(
+        "{'descr': '<f8', 'fortran_order': False, 'shape': (2, 3), }\n",
+        &[1.0, 2.0, 3.0, 4.0],
+    )));
+    // the byte length does not fit a usize and must not be allocated
+    assert!(is_format_error(npy_bytes(
+        &format!(
+            "{{'descr': '<f8', 'fortran_order': False, 'shape': ({max}, {max}), }}\n",
+            max = usize::MAX,
+        ),
+        &[],
+    )));
+    // a large but valid shape is read through a capped buffer
+    assert!(is_format_error(npy_bytes(
+        "{'descr': '<f8', 'fortran_order': False, 'shape': (1000000000, 1000), }\n",
+        &[1.0],
+    )));
+    assert!(is_format_error(npy_bytes(
+        "{'descr': '<i8', 'fortran_order': False, 'shape': (1, 1), }\n",
+        &[1.0],
+    )));
+}
+
+fn vecs_bytes(rows: &[(i32, &[u8])]) -> Vec<u8> {
+    rows.iter()
+        .flat_map(|(dim, values)| dim.to_le_bytes().into_iter().chain(values.iter().copied()))
+        .collect()
+}
+
+fn f32_bytes(values: &[f32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+#[test]
+fn vecs_read_every_row() {
+    let bytes = vecs_bytes(&[
+        (2, &f32_bytes(&[1.0, 2.0])),
+        (2, &f32_bytes(&[3.0, 4.0])),
+        (2, &f32_bytes(&[5.0, 6.0])),
+    ]);
+    assert_eq!(
+        read_fvecs_from(bytes.as_slice(), None).unwrap(),
+        array![[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]]
+    );
+    assert_eq!(
+        read_fvecs_from(bytes.as_slice(), Some(2)).unwrap(),
+        array![[1.0, 2.0], [3.0, 4.0]]
+    );
+    let bytes = vecs_bytes(&[(3, &[1, 2, 255])]);
+    assert_eq!(
+        read_bvecs_from(bytes.as_slice(), None).unwrap(),
+        array![[1.0, 2.0, 255.0]]
+    );
+    let ints: Vec<u8> = [7i32, -1].iter().flat_map(|v| v.to_le_bytes()).collect();
+    let bytes = vecs_bytes(&[(2, &ints)]);
+    assert_eq!(
+        read_ivecs_from(bytes.as_slice(), None).unwrap(),
+        array![[7, -1]]
+    );
+    assert_eq!(read_fvecs_from(&[][..], None).unwrap().dim(), (0, 0));
+}
+
+#[test]
+fn vecs_reject_malformed_input() {
+    let is_format_error = |bytes: Vec<u8>| {
+        matches!(
+            read_fvecs_from(bytes.as_slice(), None),
+            Err(VecsError::FormatError(_))
+        )
+    };
+    let row = f32_bytes(&[1.0, 2.0]);
+    // truncated vector
+    assert!(is_format_error(vecs_bytes(&[(2, &row), (2, &row[..6])])));
+    // truncated length
+    let mut bytes = vecs_bytes(&[(2, &row)]);
+    bytes.extend([2, 0]);
+    assert!(is_format_error(bytes));
+    assert!(is_format_error(vecs_bytes(&[(2, &row), (-2, &row)])));
+    assert!(is_format_error(vecs_bytes(&[(2, &row), (1, &row[..4])])));
+}
+
+#[test]
+fn jsonl_reads_nested_fields_and_ids() {
+    let lines = concat!(
+        "{\"id\": \"a\", \"data\": {\"embed\": [1, 2.5]}}\n",
+        "\n",
+        "{\"id\": 7, \"data\": {\"embed\": [3, 4]}}\n",
+    );
+    let res = read_jsonl_from(lines.as_bytes(), "/data/embed", Some("id")).unwrap();
+    assert_eq!(res.embeddings, vec![vec![1.0, 2.5], vec![3.0, 4.0]]);
+    assert_eq!(res.ids, Some(vec!["a".to_string(), "7".to_string()]));
+    assert_eq!(res.id_map()["7"], 1);
+    assert_eq!(res.dim(), Some(2));
+}
+
+#[test]
+fn jsonl_rejects_malformed_input() {
+    let line_of = |lines: &str| match read_jsonl_from(lines.as_bytes(), "embed", Some("id")) {
+        Err(JsonlError::FormatError(line, _)) => Some(line),
+        Err(JsonlError::JsonError(line, _)) => Some(line),
+        _ => None,
+    };
+    assert_eq!(
+        line_of("{\"id\": 1, \"embed\": [1]}\n\n{\"id\": 1, \"embed\": [2]}\n"),
+        Some(3)
+    );
+    assert_eq!(
+        line_of("{\"id\": 1, \"embed\": [1]}\n{\"id\": 2, \"embed\": [2, 3]}\n"),
+        Some(2)
+    );
+    assert_eq!(line_of("{\"id\": 1, \"embed\": [\"x\"]}\n"), Some(1));
+    assert_eq!(line_of("{\"id\": [1], \"embed\": [1]}\n"), Some(1));
+    assert_eq!(line_of("{\"id\": 1, \"embed\": [1]\n"), Some(1));
+}
@@ -0,0 +1,188 @@
+mod common;
+
+use fann::{
+    cache::{no_cache, DistanceCache},
+    distances::ndarray::{NdProvider, ND_L2_DISTANCE},
+    info::no_info,
+    kmed::{FannTree, InsertError},
+    Embedding, FingerprintPolicy, LocalDistance, Tree,
+};
+
+#[test]
+fn inserts_drop_the_hash_until_refreshed() {
+    let arr = common::random_arr(120, 4, 5);
+    let provider = NdProvider::new(arr.view(), ND_L2_DISTANCE);
+    let cache = &mut DistanceCache::new(10000);
+    let mut tree = FannTree::build_from(
+        &provider,
+        (0..100).collect(),
+        Some(5),
+        None,
+        cache,
+        &mut no_info(),
+    );
+    assert!(tree.has_hash());
+    (100..120).for_each(|ix| tree.insert(ix, &provider, cache, &mut no_info()).unwrap());
+    assert!(!tree.has_hash());
+    // without a hash any rows with the same distance would pass
+    assert!(tree.check_provider(&provider).is_err());
+    let other = common::random_arr(120, 4, 6);
+    let other_provider = NdProvider::new(other.view(), ND_L2_DISTANCE);
+    assert!(tree.check_provider(&other_provider).is_err());
+    tree.check_provider_with(&provider, FingerprintPolicy::CheckShapeOnly)
+        .unwrap();
+    tree.refresh_hash(&provider);
+    assert!(tree.has_hash());
+    tree.check_provider(&provider).unwrap();
+    tree.check_invariants(&provider, &mut no_cache()).unwrap();
+}
+
+#[test]
+fn mutations_reclaim_their_slots() {
+    let arr = common::random_arr(1500, 4, 7);
+    let provider = NdProvider::new(arr.view(), ND_L2_DISTANCE);
+    let cache = &mut DistanceCache::new(10000);
+    let max_node_size = 5;
+    let mut tree = FannTree::build_from(
+        &provider,
+        (0..10).collect(),
+        Some(max_node_size),
+        None,
+        cache,
+        &mut no_info(),
+    );
+    (10..1500).for_each(|ix| tree.insert(ix, &provider, cache, &mut no_info()).unwrap());
+    (0..1500)
+        .step_by(3)
+        .for_each(|ix| tree.update(ix, &provider, &mut no_info()).unwrap());
+    let (nodes, sibling_dists) = tree.slot_counts();
+    assert!(nodes <= 2 * tree.len(), "{nodes} node slots");
+    assert!(
+        sibling_dists <= 2 * tree.len() * max_node_size,
+        "{sibling_dists} sibling slots"
+    );
+    tree.check_invariants(&provider, &mut no_cache()).unwrap();
+}
+
+#[test]
+fn inserts_reject_live_and_unknown_indices() {
+    let arr = common::random_arr(50, 4, 9);
+    let provider = NdProvider::new(arr.view(), ND_L2_DISTANCE);
+    let cache = &mut no_cache();
+    let mut tree = FannTree::build_from(
+        &provider,
+        (0..40).collect(),
+        Some(4),
+        None,
+        cache,
+        &mut no_info(),
+    );
+    assert_eq!(
+        tree.insert(7, &provider, cache, &mut no_info()),
+        Err(InsertError::AlreadyPresent(7))
+    );
+    assert_eq!(
+        tree.insert(50, &provider, cache, &mut no_info()),
+        Err(InsertError::OutOfRange(50))
+    );
+    assert_eq!(
+        tree.update(50, &provider, &mut no_info()),
+        Err(InsertError::OutOfRange(50))
+    );
+    assert!(tree.remove(7));
+    assert!(!tree.remove(7));
+    // indices that were never inserted leave no tombstone behind
+    assert!(!tree.remove(45));
+    assert_eq!(tree.len(), 39);
+    assert!(!tree.contains(45));
+    tree.insert(7, &provider, cache, &mut no_info()).unwrap();
+    tree.insert(45, &provider, cache, &mut no_info()).unwrap();
+    assert!(tree.contains(45));
+    assert_eq!(tree.len(), 41);
+    tree.check_invariants(&provider, &mut no_cache()).unwrap();
+}
+
+#[test]
+fn repeated_updates_reuse_their_slots() {
+    let arr = common::random_arr(60, 4, 11);
+    let provider = NdProvider::new(arr.view(), ND_L2_DISTANCE);
+    let mut tree = FannTree::build_from(
+        &provider,
+        (0..60).collect(),
+        Some(4),
+        None,
+        &mut no_cache(),
+        &mut no_info(),
+    );
+    (0..60).for_each(|ix| {
+        tree.update(ix, &provider, &mut no_info()).unwrap();
+        let slots = tree.slot_counts();
+        (0..5).for_each(|_| {
+            tree.update(ix, &provider, &mut no_info()).unwrap();
+            assert_eq!(tree.slot_counts(), slots, "update of {ix} took new slots");
+        });
+    });
+    assert_eq!(tree.len(), 60);
+    tree.check_invariants(&provider, &mut no_cache()).unwrap();
+}
+
+#[test]
+fn reinserted_points_are_covered_again() {
+    let arr = common::random_arr(300, 4, 17);
+    let provider = NdProvider::new(arr.view(), ND_L2_DISTANCE);
+    let cache = &mut DistanceCache::new(10000);
+    let mut tree = FannTree::build_from(
+        &provider,
+        (0..250).collect(),
+        Some(4),
+        None,
+        cache,
+        &mut no_info(),
+    );
+    (0..250).step_by(3).for_each(|ix| assert!(tree.remove(ix)));
+    // inserts recompute the radii on their path without the tombstones
+    (250..300).for_each(|ix| tree.insert(ix, &provider, cache, &mut no_info()).unwrap());
+    (0..250)
+        .step_by(3)
+        .for_each(|ix| tree.insert(ix, &provider, cache, &mut no_info()).unwrap());
+    tree.check_invariants(&provider, &mut no_cache()).unwrap();
+    (0..250).step_by(3).for_each(|ix| {
+        // the query provider borrows the embedding so it is created per query
+        let query = NdProvider::new(arr.view(), ND_L2_DISTANCE);
+        let embed = Embedding::as_embedding(arr.row(ix));
+        let ldist = LocalDistance::new(&query, &embed);
+        let res = FannTree::get_closest(&tree, 1, &ldist, &mut no_info());
+        assert_eq!(res[0].0, ix);
+    });
+}
+
+#[test]
+fn membership_follows_compaction_and_reloads() {
+    let arr = common::random_arr(120, 4, 23);
+    let provider = NdProvider::new(arr.view(), ND_L2_DISTANCE);
+    let cache = &mut DistanceCache::new(10000);
+    let mut tree = FannTree::build_from(
+        &provider,
+        (0..100).collect(),
+        Some(4),
+        None,
+        cache,
+        &mut no_info(),
+    );
+    assert!(tree.contains(42));
+    (0..100).step_by(4).for_each(|ix| assert!(tree.remove(ix)));
+    assert!(tree.compact(0.0, &provider, cache, &mut no_info()));
+    assert!(!tree.contains(40));
+    assert!(!tree.is_deleted(40));
+    tree.insert(40, &provider, cache, &mut no_info()).unwrap();
+    tree.insert(110, &provider, cache, &mut no_info()).unwrap();
+    let loaded = FannTree::from_bytes(&tree.to_bytes().unwrap()).unwrap();
+    [&tree, &loaded].into_iter().for_each(|tree| {
+        assert!(tree.contains(40));
+        assert!(tree.contains(110));
+        assert!(tree.contains(41));
+        assert!(!tree.contains(44));
+        assert!(!tree.contains(111));
+    });
+    tree.check_invariants(&provider, &mut no_cache()).unwrap();
+}
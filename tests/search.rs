@@ -0,0 +1,332 @@
+mod common;
+
+use std::collections::{hash_map::IntoIter, HashMap};
+
+use fann::{
+    cache::DistanceCache,
+    distances::{
+        bits::{pack_signs, BitProvider, HAMMING_DISTANCE},
+        mips::MipsTransform,
+        ndarray::{
+            NdProvider, OwnedNdProvider, ND_COSINE_DISTANCE, ND_DOT_DISTANCE, ND_L1_DISTANCE,
+            ND_L2_DISTANCE,
+        },
+    },
+    evaluate::{evaluate_budget, ground_truth, tune},
+    info::{no_info, BaseInfo, Info},
+    kmed::FannTree,
+    Distance, DistanceCmp, DistanceValue, Embedding, EmbeddingProvider, LocalDistance,
+    NearestNeighbors, Tree,
+};
+use ndarray::{ArrayView1, ArrayView2};
+
+const COUNT: usize = 10;
+
+fn indices(res: &[(usize, DistanceValue)]) -> Vec<usize> {
+    res.iter().map(|(ix, _)| *ix).collect()
+}
+
+fn assert_exact<D>(distance: D)
+where
+    D: for<'a> Distance<ArrayView1<'a, f64>> + Copy + Sync,
+{
+    let arr = common::random_arr(600, 6, 1);
+    let queries = common::random_arr(40, 6, 2);
+    for max_node_size in [3, 8, 25] {
+        let tree = FannTree::build(
+            &NdProvider::new(arr.view(), distance),
+            Some(max_node_size),
+            None,
+            &mut DistanceCache::new(100000),
+            &mut no_info(),
+        );
+        for query in queries.rows() {
+            // the provider borrows the query so it is created per query
+            let provider = NdProvider::new(arr.view(), distance);
+            let embed = Embedding::as_embedding(query.view());
+            let expected = provider.get_closest(&embed, COUNT, &mut no_info());
+            let ldist = LocalDistance::new(&provider, &embed);
+            let res = FannTree::get_closest(&tree, COUNT, &ldist, &mut no_info());
+            assert_eq!(indices(&res), indices(&expected), "{}", distance.name());
+            res.iter()
+                .zip(expected.iter())
+                .for_each(|((_, dist), (_, exp))| assert!((dist - exp).abs() < 1e-9));
+            let streamed: Vec<(usize, DistanceValue)> =
+                tree.get_closest_stream(ldist).take(COUNT).collect();
+            assert_eq!(
+                indices(&streamed),
+                indices(&expected),
+                "{}",
+                distance.name()
+            );
+        }
+    }
+}
+
+#[test]
+fn l2_search_is_exact() {
+    assert_exact(ND_L2_DISTANCE);
+}
+
+#[test]
+fn l1_search_is_exact() {
+    assert_exact(ND_L1_DISTANCE);
+}
+
+#[test]
+fn cosine_search_is_exact() {
+    assert_exact(ND_COSINE_DISTANCE);
+}
+
+// not a metric so nothing may be pruned
+#[test]
+fn dot_search_is_exact() {
+    assert_exact(ND_DOT_DISTANCE);
+}
+
+// counts are compared without rounding, ties can order indices differently
+#[test]
+fn hamming_search_keeps_exact_counts() {
+    let arr = common::random_arr(600, 90, 3);
+    let queries = common::random_arr(40, 90, 4);
+    let tree = FannTree::build(
+        &BitProvider::from_signs(arr.view(), HAMMING_DISTANCE),
+        Some(8),
+        None,
+        &mut DistanceCache::new(100000),
+        &mut no_info(),
+    );
+    for query in queries.rows() {
+        let provider = BitProvider::from_signs(arr.view(), HAMMING_DISTANCE);
+        let words = pack_signs(query);
+        let embed = Embedding::as_embedding(words.as_slice());
+        let expected = provider.get_closest(&embed, COUNT, &mut no_info());
+        let ldist = LocalDistance::new(&provider, &embed);
+        let res = FannTree::get_closest(&tree, COUNT, &ldist, &mut no_info());
+        let dists = |res: &[(usize, DistanceValue)]| -> Vec<DistanceValue> {
+            res.iter().map(|&(_, dist)| dist).collect()
+        };
+        assert_eq!(dists(&res), dists(&expected));
+        assert!(res.iter().all(|&(_, dist)| dist.fract() == 0.0));
+    }
+}
+
+#[test]
+fn tuning_finds_the_smallest_visit_budget() {
+    let arr = common::random_arr(600, 6, 1);
+    let queries = common::random_arr(40, 6, 2);
+    let provider = NdProvider::new(arr.view(), ND_L2_DISTANCE);
+    let query_provider = NdProvider::new(queries.view(), ND_L2_DISTANCE);
+    let tree = FannTree::build(
+        &provider,
+        Some(8),
+        None,
+        &mut DistanceCache::new(100000),
+        &mut no_info(),
+    );
+    let point = tune(&tree, &provider, &query_provider, COUNT, 0.8).unwrap();
+    let budget = point.budget.unwrap();
+    assert!(point.recall >= 0.8);
+    assert!(budget < tree.slot_counts().0, "{budget} visits");
+    let truth = ground_truth(&provider, &query_provider, COUNT);
+    let below = evaluate_budget(
+        &tree,
+        &provider,
+        &query_provider,
+        &truth,
+        COUNT,
+        Some(budget - 1),
+    );
+    assert!(below.recall < 0.8);
+    let exact = evaluate_budget(&tree, &provider, &query_provider, &truth, COUNT, None);
+    assert_eq!(exact.recall, 1.0);
+}
+
+fn assert_batch_agrees<D>(distance: D)
+where
+    D: for<'a> Distance<ArrayView1<'a, f64>> + Copy,
+{
+    let arr = common::random_arr(200, 6, 3);
+    let queries = common::random_arr(5, 6, 4);
+    let owned = OwnedNdProvider::new(arr.clone(), distance);
+    // scattered rows are gathered and a contiguous run is used as a view
+    let scattered: Vec<usize> = (0..200).step_by(3).collect();
+    let contiguous: Vec<usize> = (40..90).collect();
+    for query in queries.rows() {
+        for indices in [&scattered, &contiguous] {
+            let provider = NdProvider::new(arr.view(), distance);
+            let embed = Embedding::as_embedding(query.view());
+            let expected: Vec<DistanceValue> = indices
+                .iter()
+                .map(|&ix| distance.distance_cmp(&embed, &provider.get(ix)).to())
+                .collect();
+            let check = |batch: Vec<DistanceCmp>| {
+                batch.iter().zip(&expected).for_each(|(dist, exp)| {
+                    assert!(
+                        (dist.to() - exp).abs() <= 1e-6 * exp.abs().max(1.0),
+                        "{name} {dist} {exp}",
+                        name = distance.name(),
+                        dist = dist.to(),
+                    )
+                })
+            };
+            check(provider.dist_batch(&embed, indices));
+            // the view borrows the owned rows so it gets its own query
+            let view = owned.view();
+            let embed = Embedding::as_embedding(query.view());
+            check(view.dist_batch(&embed, indices));
+        }
+    }
+}
+
+#[test]
+fn batched_distances_match_single_ones() {
+    assert_batch_agrees(ND_L2_DISTANCE);
+    assert_batch_agrees(ND_COSINE_DISTANCE);
+    assert_batch_agrees(ND_DOT_DISTANCE);
+}
+
+fn assert_duplicates_exact<D>(distance: D, epsilon: DistanceValue)
+where
+    D: for<'a> Distance<ArrayView1<'a, f64>> + Copy,
+{
+    let arr = common::random_arr(300, 3, 5);
+    let provider = NdProvider::new(arr.view(), distance);
+    let tree = FannTree::build(
+        &provider,
+        Some(6),
+        None,
+        &mut DistanceCache::new(100000),
+        &mut no_info(),
+    );
+    let mut expected = Vec::new();
+    for ix_a in 0..300 {
+        for ix_b in ix_a + 1..300 {
+            let dist = distance.distance_cmp(&provider.get(ix_a), &provider.get(ix_b));
+            if distance.finalize_distance(&dist) <= epsilon {
+                expected.push((ix_a, ix_b));
+            }
+        }
+    }
+    assert!(!expected.is_empty(), "{}", distance.name());
+    let res: Vec<(usize, usize)> = tree
+        .find_duplicates(
+            epsilon,
+            &provider,
+            &mut DistanceCache::new(100000),
+            &mut no_info(),
+        )
+        .into_iter()
+        .map(|(ix_a, ix_b, _)| (ix_a, ix_b))
+        .collect();
+    assert_eq!(res, expected, "{}", distance.name());
+}
+
+#[test]
+fn duplicates_match_brute_force() {
+    assert_duplicates_exact(ND_L2_DISTANCE, 0.05);
+    assert_duplicates_exact(ND_COSINE_DISTANCE, 0.002);
+}
+
+#[test]
+fn mips_matches_dot_and_prunes() {
+    let arr = common::random_arr(600, 6, 6);
+    let queries = common::random_arr(20, 6, 7);
+    let mips = MipsTransform::fit(arr.view());
+    let augmented = mips.augment(arr.view());
+    let provider = NdProvider::new(augmented.view(), ND_L2_DISTANCE);
+    let tree = FannTree::build(
+        &provider,
+        Some(8),
+        None,
+        &mut DistanceCache::new(100000),
+        &mut no_info(),
+    );
+    for query in queries.rows() {
+        let dot_provider = NdProvider::new(arr.view(), ND_DOT_DISTANCE);
+        let embed = Embedding::as_embedding(query.view());
+        let expected = dot_provider.get_closest(&embed, COUNT, &mut no_info());
+        let provider = NdProvider::new(augmented.view(), ND_L2_DISTANCE);
+        let augmented_query = mips.augment_query(query.view());
+        let embed = Embedding::as_embedding(augmented_query.view());
+        let ldist = LocalDistance::new(&provider, &embed);
+        let mut info = BaseInfo::new(600);
+        let res = FannTree::get_closest(&tree, COUNT, &ldist, &mut info);
+        assert_eq!(indices(&res), indices(&expected));
+        assert!(info.dist_count() < 600, "{} distances", info.dist_count());
+    }
+}
+
+// searches the same tree again from inside the first search
+struct NestedInfo<'t> {
+    tree: &'t FannTree,
+    arr: ArrayView2<'t, f64>,
+    nested: Option<Vec<(usize, DistanceValue)>>,
+}
+
+impl Info for NestedInfo<'_> {
+    fn log_cache_access(&mut self, _is_miss: bool) {}
+
+    fn log_scan(&mut self, _index: usize, _is_outer: bool) {
+        if self.nested.is_none() {
+            // the provider has to borrow rows that live as long as the call
+            let arr = self.arr.to_owned();
+            let provider = NdProvider::new(arr.view(), ND_L2_DISTANCE);
+            let embed = Embedding::as_embedding(arr.row(0));
+            let ldist = LocalDistance::new(&provider, &embed);
+            self.nested = Some(FannTree::get_closest(
+                self.tree,
+                COUNT,
+                &ldist,
+                &mut no_info(),
+            ));
+        }
+    }
+
+    fn log_dist(&mut self, _index: &Option<usize>) {}
+
+    fn cache_hits_miss(&self) -> (u64, u64) {
+        (0, 0)
+    }
+
+    fn scan_map(&self) -> IntoIter<usize, &str> {
+        HashMap::new().into_iter()
+    }
+
+    fn dist_vec(&self) -> Vec<usize> {
+        Vec::new()
+    }
+
+    fn dist_count(&self) -> usize {
+        0
+    }
+
+    fn clear(&mut self) {}
+}
+
+#[test]
+fn nested_searches_do_not_share_buffers() {
+    let arr = common::random_arr(300, 4, 8);
+    let provider = NdProvider::new(arr.view(), ND_L2_DISTANCE);
+    let tree = FannTree::build(
+        &provider,
+        Some(6),
+        None,
+        &mut DistanceCache::new(100000),
+        &mut no_info(),
+    );
+    let embed = Embedding::as_embedding(arr.row(1));
+    let ldist = LocalDistance::new(&provider, &embed);
+    let mut info = NestedInfo {
+        tree: &tree,
+        arr: arr.view(),
+        nested: None,
+    };
+    let res = FannTree::get_closest(&tree, COUNT, &ldist, &mut info);
+    assert_eq!(res[0].0, 1);
+    assert_eq!(info.nested.unwrap()[0].0, 0);
+    assert_eq!(
+        indices(&res),
+        indices(&FannTree::get_closest(&tree, COUNT, &ldist, &mut no_info()))
+    );
+}
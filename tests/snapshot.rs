@@ -0,0 +1,73 @@
+mod common;
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use fann::{
+    cache::{no_cache, DistanceCache},
+    distances::ndarray::{NdProvider, ND_L2_DISTANCE},
+    info::no_info,
+    kmed::FannTree,
+    snapshot::SnapshotTree,
+};
+
+#[test]
+fn snapshots_keep_their_version_across_writes() {
+    let arr = common::random_arr(1500, 4, 11);
+    let provider = NdProvider::new(arr.view(), ND_L2_DISTANCE);
+    let cache = &mut DistanceCache::new(10000);
+    let tree = FannTree::build_from(
+        &provider,
+        (0..1200).collect(),
+        Some(6),
+        None,
+        cache,
+        &mut no_info(),
+    );
+    let snapshots = SnapshotTree::new(tree);
+    let before = snapshots.snapshot();
+    let expected = serde_json::to_string(&*before).unwrap();
+    snapshots.write(|tree| {
+        (1200..1500).for_each(|ix| tree.insert(ix, &provider, cache, &mut no_info()).unwrap());
+        (0..1200).step_by(7).for_each(|ix| assert!(tree.remove(ix)));
+    });
+    assert_eq!(serde_json::to_string(&*before).unwrap(), expected);
+    before.check_invariants(&provider, &mut no_cache()).unwrap();
+    let after = snapshots.snapshot();
+    assert_eq!(after.len(), 1500 - 1200usize.div_ceil(7));
+    after.check_invariants(&provider, &mut no_cache()).unwrap();
+}
+
+#[test]
+fn panicking_writes_leave_the_tree_usable() {
+    let arr = common::random_arr(200, 4, 13);
+    let provider = NdProvider::new(arr.view(), ND_L2_DISTANCE);
+    let tree = FannTree::build_from(
+        &provider,
+        (0..150).collect(),
+        Some(5),
+        None,
+        &mut no_cache(),
+        &mut no_info(),
+    );
+    let snapshots = SnapshotTree::new(tree);
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        snapshots.write(|tree| {
+            tree.insert(150, &provider, &mut no_cache(), &mut no_info())
+                .unwrap();
+            panic!("update failed");
+        })
+    }));
+    assert!(res.is_err());
+    assert_eq!(snapshots.version(), 0);
+    assert_eq!(snapshots.snapshot().len(), 150);
+    snapshots.write(|tree| {
+        (150..200).for_each(|ix| {
+            tree.insert(ix, &provider, &mut no_cache(), &mut no_info())
+                .unwrap()
+        })
+    });
+    assert_eq!(snapshots.version(), 1);
+    let tree = snapshots.snapshot();
+    assert_eq!(tree.len(), 200);
+    tree.check_invariants(&provider, &mut no_cache()).unwrap();
+}
@@ -0,0 +1,91 @@
+#![cfg(feature = "archive")]
+
+mod common;
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+
+use fann::{
+    cache::{no_cache, DistanceCache},
+    distances::ndarray::{NdProvider, ND_L2_DISTANCE},
+    info::no_info,
+    kmed::FannTree,
+    wal::{LoggedTree, WalEntry, WriteAheadLog},
+};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fann-{name}-{pid}", pid = std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn tear(path: &PathBuf, fragment: &[u8]) {
+    let mut file = OpenOptions::new().append(true).open(path).unwrap();
+    file.write_all(fragment).unwrap();
+}
+
+#[test]
+fn torn_last_line_is_cut_off() {
+    let dir = temp_dir("wal-torn");
+    let tree_path = dir.join("tree.zip");
+    let wal_path = dir.join("tree.wal");
+    let arr = common::random_arr(80, 4, 3);
+    let provider = NdProvider::new(arr.view(), ND_L2_DISTANCE);
+    let cache = &mut DistanceCache::new(10000);
+    let tree = FannTree::build_from(
+        &provider,
+        (0..60).collect(),
+        Some(4),
+        None,
+        cache,
+        &mut no_info(),
+    );
+    tree.save(&File::create(&tree_path).unwrap()).unwrap();
+
+    let mut logged = LoggedTree::new(tree, WriteAheadLog::open(&wal_path).unwrap());
+    (60..70).for_each(|ix| logged.insert(ix, &provider, cache, &mut no_info()).unwrap());
+    logged.remove(3).unwrap();
+    drop(logged);
+    // a crash in the middle of appending the next entry
+    tear(&wal_path, b"{\"Inse");
+
+    let logged = LoggedTree::load(
+        &File::open(&tree_path).unwrap(),
+        &wal_path,
+        &provider,
+        cache,
+        &mut no_info(),
+    )
+    .unwrap();
+    assert!((60..70).all(|ix| logged.tree().contains(ix)));
+    assert!(!logged.tree().contains(3));
+    logged
+        .tree()
+        .check_invariants(&provider, &mut no_cache())
+        .unwrap();
+
+    let mut logged = logged;
+    logged.insert(70, &provider, cache, &mut no_info()).unwrap();
+    drop(logged);
+    let entries = WriteAheadLog::read(&wal_path).unwrap();
+    assert_eq!(entries.len(), 12);
+    assert_eq!(entries.last(), Some(&WalEntry::Insert(70)));
+
+    // a complete entry without its newline was not acknowledged either
+    tear(&wal_path, b"{\"Insert\":71}");
+    let logged = LoggedTree::load(
+        &File::open(&tree_path).unwrap(),
+        &wal_path,
+        &provider,
+        cache,
+        &mut no_info(),
+    )
+    .unwrap();
+    assert!(logged.tree().contains(70));
+    assert!(!logged.tree().contains(71));
+    assert_eq!(WriteAheadLog::read(&wal_path).unwrap().len(), 12);
+    fs::remove_dir_all(&dir).unwrap();
+}